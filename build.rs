@@ -217,6 +217,30 @@ fn main() {
 	"cargo:rustc-link-lib={}=plist-2.0",
 	location_determinator
     );
+
+    // Record the versions of the native libraries being linked against
+    if let Some(version) = pkg_config_version("libimobiledevice-1.0") {
+        println!("cargo:rustc-env=LIBIMOBILEDEVICE_VERSION={}", version);
+    }
+    if let Some(version) = pkg_config_version("libusbmuxd-2.0") {
+        println!("cargo:rustc-env=LIBUSBMUXD_VERSION={}", version);
+    }
+}
+
+fn pkg_config_version(library: &str) -> Option<String> {
+    let output = std::process::Command::new("pkg-config")
+        .arg("--modversion")
+        .arg(library)
+        .output()
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    let version = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    if version.is_empty() {
+        return None;
+    }
+    Some(version)
 }
 
 fn repo_setup(url: &str) {
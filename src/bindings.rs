@@ -15,3 +15,12 @@ include!(concat!(env!("OUT_DIR"), "/bindings.rs"));
 
 #[cfg(not(feature = "pls-generate"))]
 include!("pregenerated.rs");
+
+// Newer than the pregenerated bindings, so only declared for libraries known to have it
+#[cfg(all(feature = "unmount-image", not(feature = "pls-generate")))]
+extern "C" {
+    pub fn mobile_image_mounter_unmount_image(
+        client: mobile_image_mounter_client_t,
+        mount_path: *const ::std::os::raw::c_char,
+    ) -> mobile_image_mounter_error_t;
+}
@@ -1,15 +1,42 @@
 // jkcoxson
 
 use std::convert::TryInto;
+use std::io::{self, Read, Write};
 use std::marker::PhantomData;
+use std::net::TcpStream;
 use std::os::raw::c_char;
+use std::time::Duration;
 
 use crate::bindings as unsafe_bindings;
 use crate::error::IdeviceError;
 use crate::idevice::Device;
 
+use log::info;
+
+/// How long each side of a bridge is polled before switching to the other, in milliseconds
+const BRIDGE_POLL_MS: u32 = 10;
+/// The most bytes moved in one step of a bridge
+const BRIDGE_BUFFER_SIZE: usize = 0x4000;
+
+/// Opens a raw TCP connection to a port on the device through usbmuxd.
+/// The connection implements `Read` and `Write`, so it can be used like a socket
+/// or bridged to a local one with `copy_bidirectional`.
+/// # Arguments
+/// * `device` - The device to connect to
+/// * `port` - The port on the device to connect to
+/// # Returns
+/// A handle for the connection
+///
+/// ***Verified:*** False
+pub fn connect_tcp(device: &Device, port: u16) -> Result<DeviceConnection<'_>, IdeviceError> {
+    DeviceConnection::connect(device, port)
+}
+
 pub struct DeviceConnection<'a> {
     pub(crate) pointer: *mut unsafe_bindings::idevice_connection_private,
+    /// Whether the connection should be closed when this struct is dropped.
+    /// Connections handed out by a service client are owned by that client.
+    pub(crate) owned: bool,
     phantom: PhantomData<&'a Device>,
 }
 
@@ -20,7 +47,7 @@ pub enum DeviceConnectionType {
     Network,
 }
 
-impl DeviceConnection<'_> {
+impl<'a> DeviceConnection<'a> {
     /// Create a connection to an iOS device
     /// This is NOT a lockdown connection, for things like debugging use a specific service
     /// # Arguments
@@ -30,9 +57,10 @@ impl DeviceConnection<'_> {
     /// A handle for the connection
     ///
     /// ***Verified:*** False
-    pub fn connect(device: Device, port: u16) -> Result<Self, IdeviceError> {
+    pub fn connect(device: &'a Device, port: u16) -> Result<Self, IdeviceError> {
         let mut to_fill = unsafe { std::mem::zeroed() };
 
+        info!("Connecting to port {} on {}", port, device.get_udid());
         let result =
             unsafe { unsafe_bindings::idevice_connect(device.pointer, port, &mut to_fill) }.into();
 
@@ -42,25 +70,36 @@ impl DeviceConnection<'_> {
 
         Ok(DeviceConnection {
             pointer: to_fill,
+            owned: true,
             phantom: std::marker::PhantomData,
         })
     }
 
-    /// Sends data to the device
+    /// Wraps a connection owned by a service client, which must not be closed when dropped
+    pub(crate) fn borrowed(pointer: *mut unsafe_bindings::idevice_connection_private) -> Self {
+        DeviceConnection {
+            pointer,
+            owned: false,
+            phantom: PhantomData,
+        }
+    }
+
+    /// Sends data to the device.
+    /// The device may accept fewer bytes than given, check the returned count.
     /// # Arguments
     /// * `data` - The data to send
     /// # Returns
     /// The number of bytes sent
     ///
     /// ***Verified:*** False
-    pub fn send(&self, data: Vec<u8>) -> Result<u32, IdeviceError> {
-        let mut to_fill = unsafe { std::mem::zeroed() };
+    pub fn send(&mut self, data: &[u8]) -> Result<usize, IdeviceError> {
+        let mut sent = 0;
         let result = unsafe {
             unsafe_bindings::idevice_connection_send(
                 self.pointer,
                 data.as_ptr() as *const c_char,
                 data.len().try_into().unwrap(),
-                &mut to_fill,
+                &mut sent,
             )
         }
         .into();
@@ -69,47 +108,24 @@ impl DeviceConnection<'_> {
             return Err(result);
         }
 
-        Ok(to_fill)
+        Ok(sent as usize)
     }
 
-    /// Receives data from the device
+    /// Receives data from the device.
+    /// Returns as soon as any data arrives, so the result may be shorter than `max`.
     /// # Arguments
-    /// * `len` - The number of bytes to receive
-    /// * `timeout` - The timeout in milliseconds
+    /// * `max` - The most bytes to receive
+    /// * `timeout` - The timeout in milliseconds. If 0, this will block until data arrives.
     /// # Returns
-    /// The received data
+    /// The received data, or a Timeout error if nothing arrived in time.
+    /// The data is empty if the device closed the connection.
     ///
     /// ***Verified:*** False
-    pub fn recieve(&self, len: u32, timeout: u32) -> Result<c_char, IdeviceError> {
-        let mut buffer = unsafe { std::mem::zeroed() };
-        let mut recieved = unsafe { std::mem::zeroed() };
-
-        let result = match timeout > 0 {
-            true => unsafe {
-                unsafe_bindings::idevice_connection_receive_timeout(
-                    self.pointer,
-                    &mut buffer,
-                    len.try_into().unwrap(),
-                    &mut recieved,
-                    timeout.try_into().unwrap(),
-                )
-            },
-            false => unsafe {
-                unsafe_bindings::idevice_connection_receive(
-                    self.pointer,
-                    &mut buffer,
-                    len.try_into().unwrap(),
-                    &mut recieved,
-                )
-            },
-        }
-        .into();
-
-        if result != IdeviceError::Success {
-            return Err(result);
-        }
-
-        Ok(buffer) // idk if this is correct
+    pub fn receive(&mut self, max: usize, timeout: u32) -> Result<Vec<u8>, IdeviceError> {
+        let mut buffer = vec![0u8; max];
+        let received = self.receive_into(&mut buffer, timeout)?.unwrap_or(0);
+        buffer.truncate(received);
+        Ok(buffer)
     }
 
     /// Toggles SSL on the connection
@@ -153,6 +169,46 @@ impl DeviceConnection<'_> {
         Ok(())
     }
 
+    /// Copies data between the connection and a local socket until either side closes.
+    /// Both sides are polled on the calling thread, so this blocks until the bridge ends.
+    /// # Arguments
+    /// * `stream` - The local socket to bridge to the device
+    /// # Returns
+    /// The number of bytes sent to the device and the number of bytes received from it
+    ///
+    /// ***Verified:*** False
+    pub fn copy_bidirectional(&mut self, stream: &mut TcpStream) -> Result<(u64, u64), io::Error> {
+        bridge(self, stream)
+    }
+
+    /// Receives into a buffer, blocking until data arrives if the timeout is 0.
+    /// Returns `None` if the device closed the connection.
+    fn receive_into(&self, buffer: &mut [u8], timeout: u32) -> Result<Option<usize>, IdeviceError> {
+        let mut received = 0;
+        let result = match timeout > 0 {
+            true => unsafe {
+                unsafe_bindings::idevice_connection_receive_timeout(
+                    self.pointer,
+                    buffer.as_mut_ptr() as *mut c_char,
+                    buffer.len() as u32,
+                    &mut received,
+                    timeout,
+                )
+            },
+            false => unsafe {
+                unsafe_bindings::idevice_connection_receive(
+                    self.pointer,
+                    buffer.as_mut_ptr() as *mut c_char,
+                    buffer.len() as u32,
+                    &mut received,
+                )
+            },
+        }
+        .into();
+
+        receive_outcome(result, received, || socket_closed(self.get_fd()))
+    }
+
     /// Gets the file descriptor of the connection
     /// # Arguments
     /// *none*
@@ -167,9 +223,129 @@ impl DeviceConnection<'_> {
     }
 }
 
+impl Read for DeviceConnection<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        // A closed connection is the end of the stream
+        Ok(self.receive_into(buf, 0)?.unwrap_or(0))
+    }
+}
+
+impl Write for DeviceConnection<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        Ok(self.send(buf)?)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// The device side of a bridge, so the bridge can be tested without a device
+trait BridgeEnd: Write {
+    /// Receives into a buffer, returning `None` once the other end has closed
+    fn receive_into(&self, buffer: &mut [u8], timeout: u32) -> Result<Option<usize>, IdeviceError>;
+}
+
+impl BridgeEnd for DeviceConnection<'_> {
+    fn receive_into(&self, buffer: &mut [u8], timeout: u32) -> Result<Option<usize>, IdeviceError> {
+        DeviceConnection::receive_into(self, buffer, timeout)
+    }
+}
+
+/// Polls the socket and the connection in turn, copying whatever arrives to the other side
+fn bridge<C: BridgeEnd>(
+    connection: &mut C,
+    stream: &mut TcpStream,
+) -> Result<(u64, u64), io::Error> {
+    stream.set_read_timeout(Some(Duration::from_millis(BRIDGE_POLL_MS as u64)))?;
+    let mut to_device = 0;
+    let mut from_device = 0;
+    let mut buffer = vec![0u8; BRIDGE_BUFFER_SIZE];
+
+    loop {
+        match stream.read(&mut buffer) {
+            Ok(0) => break,
+            Ok(read) => {
+                connection.write_all(&buffer[..read])?;
+                to_device += read as u64;
+            }
+            Err(e)
+                if matches!(
+                    e.kind(),
+                    io::ErrorKind::WouldBlock
+                        | io::ErrorKind::TimedOut
+                        | io::ErrorKind::Interrupted
+                ) => {}
+            Err(e) => return Err(e),
+        }
+
+        match connection.receive_into(&mut buffer, BRIDGE_POLL_MS) {
+            Ok(Some(received)) => {
+                stream.write_all(&buffer[..received])?;
+                from_device += received as u64;
+            }
+            Ok(None) => break,
+            Err(IdeviceError::Timeout) => {}
+            Err(e) => return Err(e.into()),
+        }
+    }
+
+    info!(
+        "Bridge closed after sending {} bytes and receiving {}",
+        to_device, from_device
+    );
+    Ok((to_device, from_device))
+}
+
+/// Interprets the result of a receive.
+/// libimobiledevice reports a closed connection as an unknown error,
+/// so `closed` is only asked when nothing arrived and the receive failed that way.
+fn receive_outcome(
+    result: IdeviceError,
+    received: u32,
+    closed: impl FnOnce() -> bool,
+) -> Result<Option<usize>, IdeviceError> {
+    // A timeout can still deliver part of the requested data
+    if result == IdeviceError::Success || received > 0 {
+        return Ok(Some(received as usize));
+    }
+    if result == IdeviceError::UnknownError && closed() {
+        return Ok(None);
+    }
+    Err(result)
+}
+
+/// Checks whether the peer of a socket has closed it, without consuming any data
+#[cfg(unix)]
+fn socket_closed(fd: i32) -> bool {
+    if fd < 0 {
+        return true;
+    }
+    let mut byte = 0u8;
+    let peeked = unsafe {
+        libc::recv(
+            fd,
+            &mut byte as *mut u8 as *mut libc::c_void,
+            1,
+            libc::MSG_PEEK | libc::MSG_DONTWAIT,
+        )
+    };
+    peeked == 0
+}
+
+/// Checks whether the peer of a socket has closed it, without consuming any data
+#[cfg(not(unix))]
+fn socket_closed(fd: i32) -> bool {
+    // Without a portable peek, any failure on a connection is treated as a close
+    let _ = fd;
+    true
+}
+
 impl Drop for DeviceConnection<'_> {
     fn drop(&mut self) {
-        unsafe { unsafe_bindings::idevice_disconnect(self.pointer) };
+        if self.owned {
+            unsafe { unsafe_bindings::idevice_disconnect(self.pointer) };
+        }
     }
 }
 
@@ -182,3 +358,116 @@ impl From<u32> for DeviceConnectionType {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn receive_outcome_keeps_partial_data() {
+        assert_eq!(
+            receive_outcome(IdeviceError::Success, 4, || false),
+            Ok(Some(4))
+        );
+        assert_eq!(
+            receive_outcome(IdeviceError::Timeout, 2, || false),
+            Ok(Some(2))
+        );
+        assert_eq!(
+            receive_outcome(IdeviceError::Timeout, 0, || true),
+            Err(IdeviceError::Timeout)
+        );
+    }
+
+    #[test]
+    fn receive_outcome_reports_close() {
+        assert_eq!(
+            receive_outcome(IdeviceError::UnknownError, 0, || true),
+            Ok(None)
+        );
+        assert_eq!(
+            receive_outcome(IdeviceError::UnknownError, 0, || false),
+            Err(IdeviceError::UnknownError)
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn socket_closed_on_loopback() {
+        use std::net::TcpListener;
+        use std::os::unix::io::AsRawFd;
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (mut server, _) = listener.accept().unwrap();
+
+        // Open and idle, then open with data waiting
+        assert!(!socket_closed(client.as_raw_fd()));
+        server.write_all(b"ping").unwrap();
+        std::thread::sleep(Duration::from_millis(50));
+        assert!(!socket_closed(client.as_raw_fd()));
+
+        // The peeked data is still there to read
+        let mut echoed = [0u8; 4];
+        client.read_exact(&mut echoed).unwrap();
+        assert_eq!(&echoed, b"ping");
+
+        drop(server);
+        std::thread::sleep(Duration::from_millis(50));
+        assert!(socket_closed(client.as_raw_fd()));
+    }
+
+    /// Stands in for a device port that echoes everything sent to it
+    #[derive(Default)]
+    struct EchoEnd {
+        pending: std::cell::RefCell<std::collections::VecDeque<u8>>,
+    }
+
+    impl Write for EchoEnd {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.pending.borrow_mut().extend(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl BridgeEnd for EchoEnd {
+        fn receive_into(
+            &self,
+            buffer: &mut [u8],
+            _timeout: u32,
+        ) -> Result<Option<usize>, IdeviceError> {
+            let mut pending = self.pending.borrow_mut();
+            if pending.is_empty() {
+                return Err(IdeviceError::Timeout);
+            }
+            let len = pending.len().min(buffer.len());
+            for (byte, echoed) in buffer.iter_mut().zip(pending.drain(..len)) {
+                *byte = echoed;
+            }
+            Ok(Some(len))
+        }
+    }
+
+    #[test]
+    fn bridge_echoes_bytes() {
+        use std::net::{Shutdown, TcpListener};
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (mut server, _) = listener.accept().unwrap();
+        let bridged = std::thread::spawn(move || bridge(&mut EchoEnd::default(), &mut server));
+
+        client.write_all(b"hello device").unwrap();
+        let mut echoed = [0u8; 12];
+        client.read_exact(&mut echoed).unwrap();
+        assert_eq!(&echoed, b"hello device");
+
+        // Closing the local side ends the bridge
+        client.shutdown(Shutdown::Write).unwrap();
+        assert_eq!(bridged.join().unwrap().unwrap(), (12, 12));
+    }
+}
@@ -1,6 +1,8 @@
 // jkcoxson
 // Enums for libimobiledevice's error codes
 
+use std::fmt;
+
 #[derive(PartialEq, Debug)]
 pub enum LockdowndError {
     Success,
@@ -150,6 +152,72 @@ impl From<LockdowndError> for String {
     }
 }
 
+impl fmt::Display for LockdowndError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let message = match self {
+            LockdowndError::Success => "operation succeeded",
+            LockdowndError::InvalidArg => "invalid argument",
+            LockdowndError::InvalidConf => "invalid configuration",
+            LockdowndError::PlistError => "malformed or unexpected plist",
+            LockdowndError::PairingFailed => "pairing failed",
+            LockdowndError::SslError => "SSL error",
+            LockdowndError::DictError => "dictionary error",
+            LockdowndError::RecieveTimeout => "timed out waiting for a response",
+            LockdowndError::MuxError => "usbmuxd connection error",
+            LockdowndError::NoRunningSession => "no lockdownd session is running",
+            LockdowndError::InvalidResponse => "invalid response from lockdownd",
+            LockdowndError::MissingKey => "the requested key is missing",
+            LockdowndError::MissingValue => "the requested value is missing",
+            LockdowndError::GetProhibited => "getting this value is prohibited",
+            LockdowndError::SetProhibited => "setting this value is prohibited",
+            LockdowndError::RemoveProhibited => "removing this value is prohibited",
+            LockdowndError::ImmutableValue => "the value is immutable",
+            LockdowndError::PasswordProtected => {
+                "the device is password protected, unlock it and try again"
+            }
+            LockdowndError::UserDeniedPairing => "the user denied the pairing request",
+            LockdowndError::PairingDialogueRepsonsePending => {
+                "waiting for the user to respond to the pairing dialog"
+            }
+            LockdowndError::MissingHostId => "the host ID is missing",
+            LockdowndError::InvalidHostId => "the host ID is invalid",
+            LockdowndError::SessionActive => "a session is already active",
+            LockdowndError::SessionInactive => "the session is inactive",
+            LockdowndError::MissingSessionId => "the session ID is missing",
+            LockdowndError::InvalidSessionId => "the session ID is invalid",
+            LockdowndError::MissingService => "the service is missing",
+            LockdowndError::InvalidService => "the service is invalid",
+            LockdowndError::ServiceLimit => "the service limit has been reached",
+            LockdowndError::MissingPairRecord => "the pair record is missing",
+            LockdowndError::SavePairRecordFailed => "failed to save the pair record",
+            LockdowndError::InvalidPairRecord => "the pair record is invalid",
+            LockdowndError::InvalidActivationRecord => "the activation record is invalid",
+            LockdowndError::MissingActivationRecord => "the activation record is missing",
+            LockdowndError::ServiceProhibited => "starting this service is prohibited",
+            LockdowndError::EscrowLocked => "the escrow bag is locked",
+            LockdowndError::PairingProhibitedOverThisConnection => {
+                "pairing is prohibited over this connection"
+            }
+            LockdowndError::FmipProtected => "the device is protected by Find My iPhone",
+            LockdowndError::McProtected => "the device is protected by a configuration profile",
+            LockdowndError::McChallengeRequired => "a configuration profile challenge is required",
+            LockdowndError::UnknownError => "unknown error",
+            LockdowndError::MissingObjectDepenency => {
+                "an object this one depends on has been freed"
+            }
+        };
+        write!(f, "{}", message)
+    }
+}
+
+impl std::error::Error for LockdowndError {}
+
+impl From<LockdowndError> for std::io::Error {
+    fn from(error: LockdowndError) -> std::io::Error {
+        std::io::Error::other(error)
+    }
+}
+
 #[derive(PartialEq, Debug)]
 pub enum IdeviceError {
     Success,
@@ -192,6 +260,63 @@ impl From<IdeviceError> for String {
     }
 }
 
+impl fmt::Display for IdeviceError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let message = match self {
+            IdeviceError::Success => "operation succeeded",
+            IdeviceError::InvalidArg => "invalid argument",
+            IdeviceError::UnknownError => "unknown error",
+            IdeviceError::NoDevice => "no device found with the requested udid",
+            IdeviceError::NotEnoughData => "not enough data was received",
+            IdeviceError::ConnRefused => "the device refused the connection",
+            IdeviceError::SslError => "SSL error",
+            IdeviceError::Timeout => "operation timed out",
+        };
+        write!(f, "{}", message)
+    }
+}
+
+impl std::error::Error for IdeviceError {}
+
+impl From<IdeviceError> for std::io::Error {
+    fn from(error: IdeviceError) -> std::io::Error {
+        std::io::Error::other(error)
+    }
+}
+
+/// Errors from building a device with `DeviceBuilder`
+#[derive(PartialEq, Debug)]
+pub enum DeviceBuildError {
+    MissingUdid,
+    InvalidUdid,
+    MissingConnection,
+}
+
+impl From<DeviceBuildError> for String {
+    fn from(e: DeviceBuildError) -> String {
+        match e {
+            DeviceBuildError::MissingUdid => "MissingUdid".to_string(),
+            DeviceBuildError::InvalidUdid => "InvalidUdid".to_string(),
+            DeviceBuildError::MissingConnection => "MissingConnection".to_string(),
+        }
+    }
+}
+
+impl fmt::Display for DeviceBuildError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let message = match self {
+            DeviceBuildError::MissingUdid => "no udid was given for the device",
+            DeviceBuildError::InvalidUdid => "the udid contains a nul byte",
+            DeviceBuildError::MissingConnection => {
+                "no connection was given, use usb(mux_id) or network(ip)"
+            }
+        };
+        write!(f, "{}", message)
+    }
+}
+
+impl std::error::Error for DeviceBuildError {}
+
 #[derive(PartialEq, Debug)]
 pub enum UserPrefError {
     Success,
@@ -234,6 +359,34 @@ impl From<UserPrefError> for String {
     }
 }
 
+impl fmt::Display for UserPrefError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let message = match self {
+            UserPrefError::Success => "operation succeeded",
+            UserPrefError::InvalidArg => "invalid argument",
+            UserPrefError::NoEnt => "no such file or directory",
+            UserPrefError::InvalidConf => "invalid configuration",
+            UserPrefError::SslError => "SSL error",
+            UserPrefError::ReadError => "read error",
+            UserPrefError::WriteError => "write error",
+            UserPrefError::UnknownError => "unknown error",
+        };
+        write!(f, "{}", message)
+    }
+}
+
+impl std::error::Error for UserPrefError {}
+
+impl From<UserPrefError> for std::io::Error {
+    fn from(error: UserPrefError) -> std::io::Error {
+        let kind = match error {
+            UserPrefError::NoEnt => std::io::ErrorKind::NotFound,
+            _ => std::io::ErrorKind::Other,
+        };
+        std::io::Error::new(kind, error)
+    }
+}
+
 #[derive(PartialEq, Debug)]
 pub enum PropertyListServiceError {
     Success,
@@ -276,6 +429,30 @@ impl From<PropertyListServiceError> for String {
     }
 }
 
+impl fmt::Display for PropertyListServiceError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let message = match self {
+            PropertyListServiceError::Success => "operation succeeded",
+            PropertyListServiceError::InvalidArg => "invalid argument",
+            PropertyListServiceError::PlistError => "malformed or unexpected plist",
+            PropertyListServiceError::MuxError => "usbmuxd connection error",
+            PropertyListServiceError::SslError => "SSL error",
+            PropertyListServiceError::RecieveTimeout => "timed out waiting for a response",
+            PropertyListServiceError::NotEnoughData => "not enough data was received",
+            PropertyListServiceError::UnknownError => "unknown error",
+        };
+        write!(f, "{}", message)
+    }
+}
+
+impl std::error::Error for PropertyListServiceError {}
+
+impl From<PropertyListServiceError> for std::io::Error {
+    fn from(error: PropertyListServiceError) -> std::io::Error {
+        std::io::Error::other(error)
+    }
+}
+
 #[derive(PartialEq, Debug)]
 pub enum ServiceError {
     Success,
@@ -318,6 +495,30 @@ impl From<ServiceError> for String {
     }
 }
 
+impl fmt::Display for ServiceError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let message = match self {
+            ServiceError::Success => "operation succeeded",
+            ServiceError::InvalidArg => "invalid argument",
+            ServiceError::MuxError => "usbmuxd connection error",
+            ServiceError::SslError => "SSL error",
+            ServiceError::StartServiceError => "the service could not be started",
+            ServiceError::NotEnoughData => "not enough data was received",
+            ServiceError::Timeout => "operation timed out",
+            ServiceError::UnknownError => "unknown error",
+        };
+        write!(f, "{}", message)
+    }
+}
+
+impl std::error::Error for ServiceError {}
+
+impl From<ServiceError> for std::io::Error {
+    fn from(error: ServiceError) -> std::io::Error {
+        std::io::Error::other(error)
+    }
+}
+
 #[derive(PartialEq, Debug)]
 pub enum InstProxyError {
     Success,
@@ -387,7 +588,8 @@ pub enum InstProxyError {
     MissingBundleVersion,
     UnknownError,
     // Internal 
-    MissingObjectDepenency
+    MissingObjectDepenency,
+    AppNotFound,
 }
 
 impl From<i32> for InstProxyError {
@@ -535,10 +737,101 @@ impl From<InstProxyError> for String {
             InstProxyError::DictCreationFailed => "DictCreationFailed".to_string(),
             InstProxyError::InstallProhibited => "InstallProhibited".to_string(),
             InstProxyError::MissingObjectDepenency => "MissingObjectDependency".to_string(),
+            InstProxyError::AppNotFound => "AppNotFound".to_string(),
         }
     }
 }
 
+impl fmt::Display for InstProxyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let message = match self {
+            InstProxyError::Success => "operation succeeded",
+            InstProxyError::InvalidArg => "invalid argument",
+            InstProxyError::PlistError => "malformed or unexpected plist",
+            InstProxyError::ConnFailed => "connection to the service failed",
+            InstProxyError::OpInProgress => "another operation is already in progress",
+            InstProxyError::OpFailed => "the operation failed",
+            InstProxyError::RecieveTimeout => "timed out waiting for a response",
+            InstProxyError::AlreadyArchived => "already archived",
+            InstProxyError::ApiInternalerror => "internal installation API error",
+            InstProxyError::ApplicationAlreadyInstalled => "application already installed",
+            InstProxyError::ApplicationMoveFailed => "application move failed",
+            InstProxyError::ApplicationSinfCaptureFailed => "application sinf capture failed",
+            InstProxyError::ApplicationSandboxFailed => "application sandbox failed",
+            InstProxyError::ApplicationVerificationFailed => "application verification failed",
+            InstProxyError::ArchiveDestructionFailed => "archive destruction failed",
+            InstProxyError::BundleVerificationFailed => "bundle verification failed",
+            InstProxyError::CarrierBundleCopyFailed => "carrier bundle copy failed",
+            InstProxyError::CarrierBundleDirectoryCreationFailed => {
+                "carrier bundle directory creation failed"
+            }
+            InstProxyError::CarrierBundleMissingSupportedSims => {
+                "carrier bundle missing supported sims"
+            }
+            InstProxyError::CommCenterNotificationFailed => "comm center notification failed",
+            InstProxyError::ContainerCreationFailed => "container creation failed",
+            InstProxyError::ContainerP0wnFailed => "failed to take ownership of the container",
+            InstProxyError::ContainerRemovalFailed => "container removal failed",
+            InstProxyError::EmbeddedProfileInstallFailed => "embedded profile install failed",
+            InstProxyError::ExecutableTwiddleFailed => "executable twiddle failed",
+            InstProxyError::ExistenceCheckFailed => "existence check failed",
+            InstProxyError::InstallMapUpdateFailed => "install map update failed",
+            InstProxyError::ManifestCaptureFailed => "manifest capture failed",
+            InstProxyError::MapGenerationFailed => "map generation failed",
+            InstProxyError::MissingBundleExecutable => "missing bundle executable",
+            InstProxyError::MissingBundleIdentifier => "missing bundle identifier",
+            InstProxyError::MissingBundlePath => "missing bundle path",
+            InstProxyError::MissingContainer => "missing container",
+            InstProxyError::NotificationFailed => "notification failed",
+            InstProxyError::PackageExtractionFailed => "package extraction failed",
+            InstProxyError::PackageInspectionFailed => "package inspection failed",
+            InstProxyError::PackageMoveFailed => "package move failed",
+            InstProxyError::PathConversionFailed => "path conversion failed",
+            InstProxyError::RestoreConversionFailed => "restore conversion failed",
+            InstProxyError::SeatbeltProfileRemovalFailed => "seatbelt profile removal failed",
+            InstProxyError::StageCreationFailed => "stage creation failed",
+            InstProxyError::SymlinkFailed => "symlink failed",
+            InstProxyError::UnknownCommand => "unknown command",
+            InstProxyError::ItunesArtworkCaptureFailed => "failed to capture iTunes artwork",
+            InstProxyError::ItunesMetadataCaptureFailed => "failed to capture iTunes metadata",
+            InstProxyError::DeviceOsVersionTooLow => "the device OS version is too low",
+            InstProxyError::DeviceFamilyNotSupported => "device family not supported",
+            InstProxyError::PackagePatchFailed => "package patch failed",
+            InstProxyError::IncorrectArchitecture => "incorrect architecture",
+            InstProxyError::PluginCopyFailed => "plugin copy failed",
+            InstProxyError::BreadcrumbFailed => "breadcrumb failed",
+            InstProxyError::BreadcrumbUnlockFailed => "breadcrumb unlock failed",
+            InstProxyError::GeoJsonCaptureFailed => "failed to capture GeoJSON",
+            InstProxyError::NewsstandArtworkCaptureFailed => "newsstand artwork capture failed",
+            InstProxyError::MissingCommand => "missing command",
+            InstProxyError::NotEntitled => "the client is not entitled to perform this operation",
+            InstProxyError::MissingPackagePath => "missing package path",
+            InstProxyError::MissingContainerPath => "missing container path",
+            InstProxyError::MissingApplicationIdentifier => "missing application identifier",
+            InstProxyError::MissingAttributeValue => "missing attribute value",
+            InstProxyError::LookupFailed => "lookup failed",
+            InstProxyError::DictCreationFailed => "dict creation failed",
+            InstProxyError::InstallProhibited => "install prohibited",
+            InstProxyError::UninstallProhibited => "uninstall prohibited",
+            InstProxyError::MissingBundleVersion => "missing bundle version",
+            InstProxyError::UnknownError => "unknown error",
+            InstProxyError::MissingObjectDepenency => {
+                "an object this one depends on has been freed"
+            }
+            InstProxyError::AppNotFound => "no app with that bundle identifier is installed",
+        };
+        write!(f, "{}", message)
+    }
+}
+
+impl std::error::Error for InstProxyError {}
+
+impl From<InstProxyError> for std::io::Error {
+    fn from(error: InstProxyError) -> std::io::Error {
+        std::io::Error::other(error)
+    }
+}
+
 #[derive(PartialEq, Debug)]
 pub enum DebugServerError {
     Success,
@@ -554,11 +847,11 @@ impl From<i32> for DebugServerError {
     fn from(value: i32) -> DebugServerError {
         match value {
             0 => DebugServerError::Success,
-            1 => DebugServerError::InvalidArg,
-            2 => DebugServerError::MuxError,
-            3 => DebugServerError::SslError,
-            4 => DebugServerError::ResponseError,
-            5 => DebugServerError::Timeout,
+            -1 => DebugServerError::InvalidArg,
+            -2 => DebugServerError::MuxError,
+            -3 => DebugServerError::SslError,
+            -4 => DebugServerError::ResponseError,
+            -5 => DebugServerError::Timeout,
             _ => DebugServerError::UnknownError
         }
     }
@@ -578,6 +871,29 @@ impl From<DebugServerError> for String {
     }
 }
 
+impl fmt::Display for DebugServerError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let message = match self {
+            DebugServerError::Success => "operation succeeded",
+            DebugServerError::InvalidArg => "invalid argument",
+            DebugServerError::MuxError => "usbmuxd connection error",
+            DebugServerError::SslError => "SSL error",
+            DebugServerError::ResponseError => "the debug server returned an error response",
+            DebugServerError::Timeout => "operation timed out",
+            DebugServerError::UnknownError => "unknown error",
+        };
+        write!(f, "{}", message)
+    }
+}
+
+impl std::error::Error for DebugServerError {}
+
+impl From<DebugServerError> for std::io::Error {
+    fn from(error: DebugServerError) -> std::io::Error {
+        std::io::Error::other(error)
+    }
+}
+
 #[derive(PartialEq, Debug)]
 pub enum WebInspectorError {
     Success,
@@ -620,6 +936,30 @@ impl From<WebInspectorError> for String {
     }
 }
 
+impl fmt::Display for WebInspectorError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let message = match self {
+            WebInspectorError::Success => "operation succeeded",
+            WebInspectorError::InvalidArg => "invalid argument",
+            WebInspectorError::PlistError => "malformed or unexpected plist",
+            WebInspectorError::MuxError => "usbmuxd connection error",
+            WebInspectorError::SslError => "SSL error",
+            WebInspectorError::ReceiveTimeout => "timed out waiting for a response",
+            WebInspectorError::NotEnoughData => "not enough data was received",
+            WebInspectorError::UnknownError => "unknown error",
+        };
+        write!(f, "{}", message)
+    }
+}
+
+impl std::error::Error for WebInspectorError {}
+
+impl From<WebInspectorError> for std::io::Error {
+    fn from(error: WebInspectorError) -> std::io::Error {
+        std::io::Error::other(error)
+    }
+}
+
 #[derive(PartialEq, Debug)]
 pub enum SyslogRelayError {
     Success,
@@ -659,6 +999,29 @@ impl From<SyslogRelayError> for String {
     }
 }
 
+impl fmt::Display for SyslogRelayError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let message = match self {
+            SyslogRelayError::Success => "operation succeeded",
+            SyslogRelayError::InvalidArg => "invalid argument",
+            SyslogRelayError::MuxError => "usbmuxd connection error",
+            SyslogRelayError::SslError => "SSL error",
+            SyslogRelayError::NotEnoughData => "not enough data was received",
+            SyslogRelayError::Timeout => "operation timed out",
+            SyslogRelayError::UnknownError => "unknown error",
+        };
+        write!(f, "{}", message)
+    }
+}
+
+impl std::error::Error for SyslogRelayError {}
+
+impl From<SyslogRelayError> for std::io::Error {
+    fn from(error: SyslogRelayError) -> std::io::Error {
+        std::io::Error::other(error)
+    }
+}
+
 #[derive(PartialEq, Debug)]
 pub enum ScreenshotrError {
     Success,
@@ -701,6 +1064,30 @@ impl From<ScreenshotrError> for String {
     }
 }
 
+impl fmt::Display for ScreenshotrError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let message = match self {
+            ScreenshotrError::Success => "operation succeeded",
+            ScreenshotrError::InvalidArg => "invalid argument",
+            ScreenshotrError::PlistError => "malformed or unexpected plist",
+            ScreenshotrError::MuxError => "usbmuxd connection error",
+            ScreenshotrError::SslError => "SSL error",
+            ScreenshotrError::ReceiveTimeout => "timed out waiting for a response",
+            ScreenshotrError::BadVersion => "the service reported an unsupported protocol version",
+            ScreenshotrError::UnknownError => "unknown error",
+        };
+        write!(f, "{}", message)
+    }
+}
+
+impl std::error::Error for ScreenshotrError {}
+
+impl From<ScreenshotrError> for std::io::Error {
+    fn from(error: ScreenshotrError) -> std::io::Error {
+        std::io::Error::other(error)
+    }
+}
+
 #[derive(PartialEq, Debug)]
 pub enum SbservicesError {
     Success,
@@ -734,6 +1121,27 @@ impl From<SbservicesError> for String {
     }
 }
 
+impl fmt::Display for SbservicesError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let message = match self {
+            SbservicesError::Success => "operation succeeded",
+            SbservicesError::InvalidArg => "invalid argument",
+            SbservicesError::PlistError => "malformed or unexpected plist",
+            SbservicesError::ConnFailed => "connection to the service failed",
+            SbservicesError::UnknownError => "unknown error",
+        };
+        write!(f, "{}", message)
+    }
+}
+
+impl std::error::Error for SbservicesError {}
+
+impl From<SbservicesError> for std::io::Error {
+    fn from(error: SbservicesError) -> std::io::Error {
+        std::io::Error::other(error)
+    }
+}
+
 #[derive(PartialEq, Debug)]
 pub enum ReverseProxyError {
     Success,
@@ -776,6 +1184,30 @@ impl From<ReverseProxyError> for String {
     }
 }
 
+impl fmt::Display for ReverseProxyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let message = match self {
+            ReverseProxyError::Success => "operation succeeded",
+            ReverseProxyError::InvalidArg => "invalid argument",
+            ReverseProxyError::PlistError => "malformed or unexpected plist",
+            ReverseProxyError::MuxError => "usbmuxd connection error",
+            ReverseProxyError::SslError => "SSL error",
+            ReverseProxyError::NotEnoughData => "not enough data was received",
+            ReverseProxyError::Timeout => "operation timed out",
+            ReverseProxyError::UnknownError => "unknown error",
+        };
+        write!(f, "{}", message)
+    }
+}
+
+impl std::error::Error for ReverseProxyError {}
+
+impl From<ReverseProxyError> for std::io::Error {
+    fn from(error: ReverseProxyError) -> std::io::Error {
+        std::io::Error::other(error)
+    }
+}
+
 #[derive(PartialEq, Debug)]
 pub enum RestoredError {
     Success,
@@ -815,6 +1247,29 @@ impl From<RestoredError> for String {
     }
 }
 
+impl fmt::Display for RestoredError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let message = match self {
+            RestoredError::Success => "operation succeeded",
+            RestoredError::InvalidArg => "invalid argument",
+            RestoredError::PlistError => "malformed or unexpected plist",
+            RestoredError::MuxError => "usbmuxd connection error",
+            RestoredError::NotEnoughData => "not enough data was received",
+            RestoredError::RecieveTimeout => "timed out waiting for a response",
+            RestoredError::UnknownError => "unknown error",
+        };
+        write!(f, "{}", message)
+    }
+}
+
+impl std::error::Error for RestoredError {}
+
+impl From<RestoredError> for std::io::Error {
+    fn from(error: RestoredError) -> std::io::Error {
+        std::io::Error::other(error)
+    }
+}
+
 #[derive(PartialEq, Debug)]
 pub enum PreboardError {
     Success,
@@ -860,6 +1315,31 @@ impl From<PreboardError> for String {
     }
 }
 
+impl fmt::Display for PreboardError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let message = match self {
+            PreboardError::Success => "operation succeeded",
+            PreboardError::InvalidArg => "invalid argument",
+            PreboardError::PlistError => "malformed or unexpected plist",
+            PreboardError::MuxError => "usbmuxd connection error",
+            PreboardError::SslError => "SSL error",
+            PreboardError::NotEnoughData => "not enough data was received",
+            PreboardError::Timeout => "operation timed out",
+            PreboardError::OpInProgress => "another operation is already in progress",
+            PreboardError::UnknownError => "unknown error",
+        };
+        write!(f, "{}", message)
+    }
+}
+
+impl std::error::Error for PreboardError {}
+
+impl From<PreboardError> for std::io::Error {
+    fn from(error: PreboardError) -> std::io::Error {
+        std::io::Error::other(error)
+    }
+}
+
 #[derive(PartialEq, Debug)]
 pub enum NpError {
     Success,
@@ -893,6 +1373,27 @@ impl From<NpError> for String {
     }
 }
 
+impl fmt::Display for NpError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let message = match self {
+            NpError::Success => "operation succeeded",
+            NpError::InvalidArg => "invalid argument",
+            NpError::PlistError => "malformed or unexpected plist",
+            NpError::ConnFailed => "connection to the service failed",
+            NpError::UnknownError => "unknown error",
+        };
+        write!(f, "{}", message)
+    }
+}
+
+impl std::error::Error for NpError {}
+
+impl From<NpError> for std::io::Error {
+    fn from(error: NpError) -> std::io::Error {
+        std::io::Error::other(error)
+    }
+}
+
 #[derive(PartialEq, Debug)]
 pub enum MobileSyncError {
     Success,
@@ -947,6 +1448,34 @@ impl From<MobileSyncError> for String {
     }
 }
 
+impl fmt::Display for MobileSyncError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let message = match self {
+            MobileSyncError::Success => "operation succeeded",
+            MobileSyncError::InvalidArg => "invalid argument",
+            MobileSyncError::PlistError => "malformed or unexpected plist",
+            MobileSyncError::MuxError => "usbmuxd connection error",
+            MobileSyncError::SslError => "SSL error",
+            MobileSyncError::ReceiveTimeout => "timed out waiting for a response",
+            MobileSyncError::BadVersion => "the service reported an unsupported protocol version",
+            MobileSyncError::SyncRefused => "the device refused to sync",
+            MobileSyncError::Cancelled => "the sync was cancelled",
+            MobileSyncError::WrongDirection => "the sync direction is wrong",
+            MobileSyncError::NotReady => "the device is not ready",
+            MobileSyncError::UnknownError => "unknown error",
+        };
+        write!(f, "{}", message)
+    }
+}
+
+impl std::error::Error for MobileSyncError {}
+
+impl From<MobileSyncError> for std::io::Error {
+    fn from(error: MobileSyncError) -> std::io::Error {
+        std::io::Error::other(error)
+    }
+}
+
 #[derive(PartialEq, Debug)]
 pub enum MobileBackup2Error {
     Success,
@@ -995,6 +1524,34 @@ impl From<MobileBackup2Error> for String {
     }
 }
 
+impl fmt::Display for MobileBackup2Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let message = match self {
+            MobileBackup2Error::Success => "operation succeeded",
+            MobileBackup2Error::InvalidArg => "invalid argument",
+            MobileBackup2Error::PlistError => "malformed or unexpected plist",
+            MobileBackup2Error::MuxError => "usbmuxd connection error",
+            MobileBackup2Error::SslError => "SSL error",
+            MobileBackup2Error::RecieveTimeout => "timed out waiting for a response",
+            MobileBackup2Error::BadVersion => {
+                "the service reported an unsupported protocol version"
+            }
+            MobileBackup2Error::ReplyNotOk => "the device did not reply with OK",
+            MobileBackup2Error::NoCommonVersion => "no common protocol version with the device",
+            MobileBackup2Error::UnknownError => "unknown error",
+        };
+        write!(f, "{}", message)
+    }
+}
+
+impl std::error::Error for MobileBackup2Error {}
+
+impl From<MobileBackup2Error> for std::io::Error {
+    fn from(error: MobileBackup2Error) -> std::io::Error {
+        std::io::Error::other(error)
+    }
+}
+
 #[derive(PartialEq, Debug)]
 pub enum MobileBackupError {
     Success,
@@ -1040,6 +1597,31 @@ impl From<MobileBackupError> for String {
     }
 }
 
+impl fmt::Display for MobileBackupError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let message = match self {
+            MobileBackupError::Success => "operation succeeded",
+            MobileBackupError::InvalidArg => "invalid argument",
+            MobileBackupError::PlistError => "malformed or unexpected plist",
+            MobileBackupError::MuxError => "usbmuxd connection error",
+            MobileBackupError::SslError => "SSL error",
+            MobileBackupError::ReceiveTimeout => "timed out waiting for a response",
+            MobileBackupError::BadVersion => "the service reported an unsupported protocol version",
+            MobileBackupError::ReplyNotOk => "the device did not reply with OK",
+            MobileBackupError::UnknownError => "unknown error",
+        };
+        write!(f, "{}", message)
+    }
+}
+
+impl std::error::Error for MobileBackupError {}
+
+impl From<MobileBackupError> for std::io::Error {
+    fn from(error: MobileBackupError) -> std::io::Error {
+        std::io::Error::other(error)
+    }
+}
+
 #[derive(PartialEq, Debug)]
 pub enum MobileActivationError {
     Success,
@@ -1079,6 +1661,29 @@ impl From<MobileActivationError> for String {
     }
 }
 
+impl fmt::Display for MobileActivationError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let message = match self {
+            MobileActivationError::Success => "operation succeeded",
+            MobileActivationError::InvalidArg => "invalid argument",
+            MobileActivationError::PlistError => "malformed or unexpected plist",
+            MobileActivationError::MuxError => "usbmuxd connection error",
+            MobileActivationError::UnknownRequest => "the service did not recognize the request",
+            MobileActivationError::RequestFailed => "the request failed on the device",
+            MobileActivationError::UnknownError => "unknown error",
+        };
+        write!(f, "{}", message)
+    }
+}
+
+impl std::error::Error for MobileActivationError {}
+
+impl From<MobileActivationError> for std::io::Error {
+    fn from(error: MobileActivationError) -> std::io::Error {
+        std::io::Error::other(error)
+    }
+}
+
 #[derive(PartialEq, Debug)]
 pub enum MobileImageMounterError {
     Success,
@@ -1087,6 +1692,7 @@ pub enum MobileImageMounterError {
     ConnFailed,
     CommandFailed,
     DeviceLocked,
+    NotSupported,
     UnknownError,
     // Internal errors
     DmgNotFound,
@@ -1103,6 +1709,7 @@ impl From<i32> for MobileImageMounterError {
             -3 => MobileImageMounterError::ConnFailed,
             -4 => MobileImageMounterError::CommandFailed,
             -5 => MobileImageMounterError::DeviceLocked,
+            -6 => MobileImageMounterError::NotSupported,
             -100 => MobileImageMounterError::DmgNotFound,
             -101 => MobileImageMounterError::SignatureNotFound,
             -102 => MobileImageMounterError::MissingObjectDepenency,
@@ -1120,6 +1727,7 @@ impl From<MobileImageMounterError> for String {
             MobileImageMounterError::ConnFailed => "ConnFailed".to_string(),
             MobileImageMounterError::CommandFailed => "CommandFailed".to_string(),
             MobileImageMounterError::DeviceLocked => "DeviceLocked".to_string(),
+            MobileImageMounterError::NotSupported => "NotSupported".to_string(),
             MobileImageMounterError::DmgNotFound => "DmgNotFound".to_string(),
             MobileImageMounterError::SignatureNotFound => "SignatureNotFound".to_string(),
             MobileImageMounterError::MissingObjectDepenency => "MissingObjectDepenency".to_string(),
@@ -1128,6 +1736,37 @@ impl From<MobileImageMounterError> for String {
     }
 }
 
+impl fmt::Display for MobileImageMounterError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let message = match self {
+            MobileImageMounterError::Success => "operation succeeded",
+            MobileImageMounterError::InvalidArg => "invalid argument",
+            MobileImageMounterError::PlistError => "malformed or unexpected plist",
+            MobileImageMounterError::ConnFailed => "connection to the service failed",
+            MobileImageMounterError::CommandFailed => "the command failed on the device",
+            MobileImageMounterError::DeviceLocked => "the device is locked",
+            MobileImageMounterError::NotSupported => {
+                "the command is not supported on this iOS version"
+            }
+            MobileImageMounterError::UnknownError => "unknown error",
+            MobileImageMounterError::DmgNotFound => "disk image not found",
+            MobileImageMounterError::SignatureNotFound => "disk image signature not found",
+            MobileImageMounterError::MissingObjectDepenency => {
+                "an object this one depends on has been freed"
+            }
+        };
+        write!(f, "{}", message)
+    }
+}
+
+impl std::error::Error for MobileImageMounterError {}
+
+impl From<MobileImageMounterError> for std::io::Error {
+    fn from(error: MobileImageMounterError) -> std::io::Error {
+        std::io::Error::other(error)
+    }
+}
+
 #[derive(PartialEq, Debug)]
 pub enum MisagentError {
     Success,
@@ -1164,6 +1803,28 @@ impl From<MisagentError> for String {
     }
 }
 
+impl fmt::Display for MisagentError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let message = match self {
+            MisagentError::Success => "operation succeeded",
+            MisagentError::InvalidArg => "invalid argument",
+            MisagentError::PlistError => "malformed or unexpected plist",
+            MisagentError::ConnFailed => "connection to the service failed",
+            MisagentError::RequestFailed => "the request failed on the device",
+            MisagentError::UnknownError => "unknown error",
+        };
+        write!(f, "{}", message)
+    }
+}
+
+impl std::error::Error for MisagentError {}
+
+impl From<MisagentError> for std::io::Error {
+    fn from(error: MisagentError) -> std::io::Error {
+        std::io::Error::other(error)
+    }
+}
+
 #[derive(PartialEq, Debug)]
 pub enum HouseArrestError {
     Success,
@@ -1200,6 +1861,28 @@ impl From<HouseArrestError> for String {
     }
 }
 
+impl fmt::Display for HouseArrestError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let message = match self {
+            HouseArrestError::Success => "operation succeeded",
+            HouseArrestError::InvalidArg => "invalid argument",
+            HouseArrestError::PlistError => "malformed or unexpected plist",
+            HouseArrestError::ConnFailed => "connection to the service failed",
+            HouseArrestError::InvalidMode => "invalid house arrest mode",
+            HouseArrestError::UnknownError => "unknown error",
+        };
+        write!(f, "{}", message)
+    }
+}
+
+impl std::error::Error for HouseArrestError {}
+
+impl From<HouseArrestError> for std::io::Error {
+    fn from(error: HouseArrestError) -> std::io::Error {
+        std::io::Error::other(error)
+    }
+}
+
 #[derive(PartialEq, Debug)]
 pub enum HeartbeatError {
     Success,
@@ -1242,6 +1925,30 @@ impl From<HeartbeatError> for String {
     }
 }
 
+impl fmt::Display for HeartbeatError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let message = match self {
+            HeartbeatError::Success => "operation succeeded",
+            HeartbeatError::InvalidArg => "invalid argument",
+            HeartbeatError::PlistError => "malformed or unexpected plist",
+            HeartbeatError::MuxError => "usbmuxd connection error",
+            HeartbeatError::SslError => "SSL error",
+            HeartbeatError::NotEnoughData => "not enough data was received",
+            HeartbeatError::Timeout => "operation timed out",
+            HeartbeatError::UnknownError => "unknown error",
+        };
+        write!(f, "{}", message)
+    }
+}
+
+impl std::error::Error for HeartbeatError {}
+
+impl From<HeartbeatError> for std::io::Error {
+    fn from(error: HeartbeatError) -> std::io::Error {
+        std::io::Error::other(error)
+    }
+}
+
 #[derive(PartialEq, Debug)]
 pub enum FileRelayError {
     Success,
@@ -1284,6 +1991,34 @@ impl From<FileRelayError> for String {
     }
 }
 
+impl fmt::Display for FileRelayError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let message = match self {
+            FileRelayError::Success => "operation succeeded",
+            FileRelayError::InvalidArg => "invalid argument",
+            FileRelayError::PlistError => "malformed or unexpected plist",
+            FileRelayError::MuxError => "usbmuxd connection error",
+            FileRelayError::InvalidSource => "invalid file relay source",
+            FileRelayError::StagingEmpty => "no data was staged for the requested sources",
+            FileRelayError::PermissionDenied => "permission denied",
+            FileRelayError::UnknownError => "unknown error",
+        };
+        write!(f, "{}", message)
+    }
+}
+
+impl std::error::Error for FileRelayError {}
+
+impl From<FileRelayError> for std::io::Error {
+    fn from(error: FileRelayError) -> std::io::Error {
+        let kind = match error {
+            FileRelayError::PermissionDenied => std::io::ErrorKind::PermissionDenied,
+            _ => std::io::ErrorKind::Other,
+        };
+        std::io::Error::new(kind, error)
+    }
+}
+
 #[derive(PartialEq, Debug)]
 pub enum DiagnosticsRelayError {
     Success,
@@ -1320,6 +2055,28 @@ impl From<DiagnosticsRelayError> for String {
     }
 }
 
+impl fmt::Display for DiagnosticsRelayError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let message = match self {
+            DiagnosticsRelayError::Success => "operation succeeded",
+            DiagnosticsRelayError::InvalidArg => "invalid argument",
+            DiagnosticsRelayError::PlistError => "malformed or unexpected plist",
+            DiagnosticsRelayError::MuxError => "usbmuxd connection error",
+            DiagnosticsRelayError::UnknownRequest => "the service did not recognize the request",
+            DiagnosticsRelayError::UnknownError => "unknown error",
+        };
+        write!(f, "{}", message)
+    }
+}
+
+impl std::error::Error for DiagnosticsRelayError {}
+
+impl From<DiagnosticsRelayError> for std::io::Error {
+    fn from(error: DiagnosticsRelayError) -> std::io::Error {
+        std::io::Error::other(error)
+    }
+}
+
 #[derive(PartialEq, Debug)]
 pub enum CompanionProxyError {
     Success,
@@ -1333,6 +2090,7 @@ pub enum CompanionProxyError {
     NoDevices,
     UnsupportedKey,
     TimeoutReply,
+    NoCompanion,
     UnknownError,
 }
 
@@ -1369,11 +2127,43 @@ impl From<CompanionProxyError> for String {
             CompanionProxyError::NoDevices => "NoDevices".to_string(),
             CompanionProxyError::UnsupportedKey => "UnsupportedKey".to_string(),
             CompanionProxyError::TimeoutReply => "TimeoutReply".to_string(),
+            CompanionProxyError::NoCompanion => "NoCompanion".to_string(),
             CompanionProxyError::UnknownError => "UnknownError".to_string(),
         }
     }
 }
 
+impl fmt::Display for CompanionProxyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let message = match self {
+            CompanionProxyError::Success => "operation succeeded",
+            CompanionProxyError::InvalidArg => "invalid argument",
+            CompanionProxyError::PlistError => "malformed or unexpected plist",
+            CompanionProxyError::MuxError => "usbmuxd connection error",
+            CompanionProxyError::SslError => "SSL error",
+            CompanionProxyError::NotEnoughData => "not enough data was received",
+            CompanionProxyError::Timeout => "operation timed out",
+            CompanionProxyError::OpInProgress => "another operation is already in progress",
+            CompanionProxyError::NoDevices => "no companion device is paired",
+            CompanionProxyError::UnsupportedKey => "the registry key is not supported",
+            CompanionProxyError::TimeoutReply => {
+                "timed out waiting for a reply from the companion device"
+            }
+            CompanionProxyError::NoCompanion => "no paired companion device is connected",
+            CompanionProxyError::UnknownError => "unknown error",
+        };
+        write!(f, "{}", message)
+    }
+}
+
+impl std::error::Error for CompanionProxyError {}
+
+impl From<CompanionProxyError> for std::io::Error {
+    fn from(error: CompanionProxyError) -> std::io::Error {
+        std::io::Error::other(error)
+    }
+}
+
 #[derive(PartialEq, Debug)]
 pub enum AfcError {
     Success,
@@ -1405,6 +2195,8 @@ pub enum AfcError {
     NotEnoughData,
     DirNotEmpty,
     ForceSignedType,
+    /// A code not known to this version of the crate
+    Unknown(i32),
 }
 
 impl From<i32> for AfcError {
@@ -1438,7 +2230,8 @@ impl From<i32> for AfcError {
             31 => AfcError::NoMem,
             32 => AfcError::NotEnoughData,
             33 => AfcError::DirNotEmpty,
-            _ => AfcError::ForceSignedType,
+            -1 => AfcError::ForceSignedType,
+            _ => AfcError::Unknown(value),
         }
     }
 }
@@ -1475,6 +2268,436 @@ impl From<AfcError> for String {
             AfcError::NotEnoughData => "NotEnoughData".to_string(),
             AfcError::DirNotEmpty => "DirNotEmpty".to_string(),
             AfcError::ForceSignedType => "ForceSignedType".to_string(),
+            AfcError::Unknown(code) => format!("Unknown({})", code),
         }
     }
 }
+
+impl fmt::Display for AfcError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let message = match self {
+            AfcError::Success => "operation succeeded",
+            AfcError::UnknownError => "unknown error",
+            AfcError::OpHeaderInvalid => "invalid AFC operation header",
+            AfcError::NoResources => "the device is out of resources",
+            AfcError::ReadError => "read error",
+            AfcError::WriteError => "write error",
+            AfcError::UnknownPacketType => "unknown AFC packet type",
+            AfcError::InvalidArg => "invalid argument",
+            AfcError::ObjectNotFound => "no such file or directory",
+            AfcError::ObjectIsDir => "the path is a directory",
+            AfcError::PermDenied => "permission denied",
+            AfcError::ServiceNotConnected => "the AFC service is not connected",
+            AfcError::OpTimeout => "operation timed out",
+            AfcError::TooMuchData => "too much data",
+            AfcError::EndOfData => "end of data",
+            AfcError::OpNotSupported => "operation not supported",
+            AfcError::ObjectExists => "the path already exists",
+            AfcError::ObjectBusy => "the file is busy",
+            AfcError::NoSpaceLeft => "no space left on the device",
+            AfcError::OpWouldBlock => "operation would block",
+            AfcError::IoError => "input/output error",
+            AfcError::OpInterrupted => "operation interrupted",
+            AfcError::OpInProgress => "another operation is already in progress",
+            AfcError::InternalError => "internal AFC error",
+            AfcError::MuxError => "usbmuxd connection error",
+            AfcError::NoMem => "out of memory",
+            AfcError::NotEnoughData => "not enough data was received",
+            AfcError::DirNotEmpty => "directory not empty",
+            AfcError::ForceSignedType => "unrecognized AFC error code",
+            AfcError::Unknown(code) => return write!(f, "unrecognized AFC error code {}", code),
+        };
+        write!(f, "{}", message)
+    }
+}
+
+impl std::error::Error for AfcError {}
+
+impl From<AfcError> for std::io::Error {
+    fn from(error: AfcError) -> std::io::Error {
+        let kind = match error {
+            AfcError::ObjectNotFound => std::io::ErrorKind::NotFound,
+            AfcError::PermDenied => std::io::ErrorKind::PermissionDenied,
+            _ => std::io::ErrorKind::Other,
+        };
+        std::io::Error::new(kind, error)
+    }
+}
+
+/// A crate-wide error that wraps the error of any service
+/// Useful for propagating errors from several services with `?`
+#[derive(Debug)]
+pub enum Error {
+    Lockdownd(LockdowndError),
+    Idevice(IdeviceError),
+    UserPref(UserPrefError),
+    PropertyListService(PropertyListServiceError),
+    Service(ServiceError),
+    InstProxy(InstProxyError),
+    DebugServer(DebugServerError),
+    WebInspector(WebInspectorError),
+    SyslogRelay(SyslogRelayError),
+    Screenshotr(ScreenshotrError),
+    Sbservices(SbservicesError),
+    ReverseProxy(ReverseProxyError),
+    Restored(RestoredError),
+    Preboard(PreboardError),
+    Np(NpError),
+    MobileSync(MobileSyncError),
+    MobileBackup2(MobileBackup2Error),
+    MobileBackup(MobileBackupError),
+    MobileActivation(MobileActivationError),
+    MobileImageMounter(MobileImageMounterError),
+    Misagent(MisagentError),
+    HouseArrest(HouseArrestError),
+    Heartbeat(HeartbeatError),
+    FileRelay(FileRelayError),
+    DiagnosticsRelay(DiagnosticsRelayError),
+    CompanionProxy(CompanionProxyError),
+    Afc(AfcError),
+    Io(std::io::Error),
+}
+
+impl From<LockdowndError> for Error {
+    fn from(error: LockdowndError) -> Error {
+        Error::Lockdownd(error)
+    }
+}
+
+impl From<IdeviceError> for Error {
+    fn from(error: IdeviceError) -> Error {
+        Error::Idevice(error)
+    }
+}
+
+impl From<UserPrefError> for Error {
+    fn from(error: UserPrefError) -> Error {
+        Error::UserPref(error)
+    }
+}
+
+impl From<PropertyListServiceError> for Error {
+    fn from(error: PropertyListServiceError) -> Error {
+        Error::PropertyListService(error)
+    }
+}
+
+impl From<ServiceError> for Error {
+    fn from(error: ServiceError) -> Error {
+        Error::Service(error)
+    }
+}
+
+impl From<InstProxyError> for Error {
+    fn from(error: InstProxyError) -> Error {
+        Error::InstProxy(error)
+    }
+}
+
+impl From<DebugServerError> for Error {
+    fn from(error: DebugServerError) -> Error {
+        Error::DebugServer(error)
+    }
+}
+
+impl From<WebInspectorError> for Error {
+    fn from(error: WebInspectorError) -> Error {
+        Error::WebInspector(error)
+    }
+}
+
+impl From<SyslogRelayError> for Error {
+    fn from(error: SyslogRelayError) -> Error {
+        Error::SyslogRelay(error)
+    }
+}
+
+impl From<ScreenshotrError> for Error {
+    fn from(error: ScreenshotrError) -> Error {
+        Error::Screenshotr(error)
+    }
+}
+
+impl From<SbservicesError> for Error {
+    fn from(error: SbservicesError) -> Error {
+        Error::Sbservices(error)
+    }
+}
+
+impl From<ReverseProxyError> for Error {
+    fn from(error: ReverseProxyError) -> Error {
+        Error::ReverseProxy(error)
+    }
+}
+
+impl From<RestoredError> for Error {
+    fn from(error: RestoredError) -> Error {
+        Error::Restored(error)
+    }
+}
+
+impl From<PreboardError> for Error {
+    fn from(error: PreboardError) -> Error {
+        Error::Preboard(error)
+    }
+}
+
+impl From<NpError> for Error {
+    fn from(error: NpError) -> Error {
+        Error::Np(error)
+    }
+}
+
+impl From<MobileSyncError> for Error {
+    fn from(error: MobileSyncError) -> Error {
+        Error::MobileSync(error)
+    }
+}
+
+impl From<MobileBackup2Error> for Error {
+    fn from(error: MobileBackup2Error) -> Error {
+        Error::MobileBackup2(error)
+    }
+}
+
+impl From<MobileBackupError> for Error {
+    fn from(error: MobileBackupError) -> Error {
+        Error::MobileBackup(error)
+    }
+}
+
+impl From<MobileActivationError> for Error {
+    fn from(error: MobileActivationError) -> Error {
+        Error::MobileActivation(error)
+    }
+}
+
+impl From<MobileImageMounterError> for Error {
+    fn from(error: MobileImageMounterError) -> Error {
+        Error::MobileImageMounter(error)
+    }
+}
+
+impl From<MisagentError> for Error {
+    fn from(error: MisagentError) -> Error {
+        Error::Misagent(error)
+    }
+}
+
+impl From<HouseArrestError> for Error {
+    fn from(error: HouseArrestError) -> Error {
+        Error::HouseArrest(error)
+    }
+}
+
+impl From<HeartbeatError> for Error {
+    fn from(error: HeartbeatError) -> Error {
+        Error::Heartbeat(error)
+    }
+}
+
+impl From<FileRelayError> for Error {
+    fn from(error: FileRelayError) -> Error {
+        Error::FileRelay(error)
+    }
+}
+
+impl From<DiagnosticsRelayError> for Error {
+    fn from(error: DiagnosticsRelayError) -> Error {
+        Error::DiagnosticsRelay(error)
+    }
+}
+
+impl From<CompanionProxyError> for Error {
+    fn from(error: CompanionProxyError) -> Error {
+        Error::CompanionProxy(error)
+    }
+}
+
+impl From<AfcError> for Error {
+    fn from(error: AfcError) -> Error {
+        Error::Afc(error)
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(error: std::io::Error) -> Error {
+        Error::Io(error)
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Lockdownd(e) => write!(f, "lockdownd: {}", e),
+            Error::Idevice(e) => write!(f, "idevice: {}", e),
+            Error::UserPref(e) => write!(f, "user_pref: {}", e),
+            Error::PropertyListService(e) => write!(f, "property_list_service: {}", e),
+            Error::Service(e) => write!(f, "service: {}", e),
+            Error::InstProxy(e) => write!(f, "inst_proxy: {}", e),
+            Error::DebugServer(e) => write!(f, "debug_server: {}", e),
+            Error::WebInspector(e) => write!(f, "web_inspector: {}", e),
+            Error::SyslogRelay(e) => write!(f, "syslog_relay: {}", e),
+            Error::Screenshotr(e) => write!(f, "screenshotr: {}", e),
+            Error::Sbservices(e) => write!(f, "sbservices: {}", e),
+            Error::ReverseProxy(e) => write!(f, "reverse_proxy: {}", e),
+            Error::Restored(e) => write!(f, "restored: {}", e),
+            Error::Preboard(e) => write!(f, "preboard: {}", e),
+            Error::Np(e) => write!(f, "np: {}", e),
+            Error::MobileSync(e) => write!(f, "mobile_sync: {}", e),
+            Error::MobileBackup2(e) => write!(f, "mobile_backup2: {}", e),
+            Error::MobileBackup(e) => write!(f, "mobile_backup: {}", e),
+            Error::MobileActivation(e) => write!(f, "mobile_activation: {}", e),
+            Error::MobileImageMounter(e) => write!(f, "mobile_image_mounter: {}", e),
+            Error::Misagent(e) => write!(f, "misagent: {}", e),
+            Error::HouseArrest(e) => write!(f, "house_arrest: {}", e),
+            Error::Heartbeat(e) => write!(f, "heartbeat: {}", e),
+            Error::FileRelay(e) => write!(f, "file_relay: {}", e),
+            Error::DiagnosticsRelay(e) => write!(f, "diagnostics_relay: {}", e),
+            Error::CompanionProxy(e) => write!(f, "companion_proxy: {}", e),
+            Error::Afc(e) => write!(f, "afc: {}", e),
+            Error::Io(e) => write!(f, "io: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Lockdownd(e) => Some(e),
+            Error::Idevice(e) => Some(e),
+            Error::UserPref(e) => Some(e),
+            Error::PropertyListService(e) => Some(e),
+            Error::Service(e) => Some(e),
+            Error::InstProxy(e) => Some(e),
+            Error::DebugServer(e) => Some(e),
+            Error::WebInspector(e) => Some(e),
+            Error::SyslogRelay(e) => Some(e),
+            Error::Screenshotr(e) => Some(e),
+            Error::Sbservices(e) => Some(e),
+            Error::ReverseProxy(e) => Some(e),
+            Error::Restored(e) => Some(e),
+            Error::Preboard(e) => Some(e),
+            Error::Np(e) => Some(e),
+            Error::MobileSync(e) => Some(e),
+            Error::MobileBackup2(e) => Some(e),
+            Error::MobileBackup(e) => Some(e),
+            Error::MobileActivation(e) => Some(e),
+            Error::MobileImageMounter(e) => Some(e),
+            Error::Misagent(e) => Some(e),
+            Error::HouseArrest(e) => Some(e),
+            Error::Heartbeat(e) => Some(e),
+            Error::FileRelay(e) => Some(e),
+            Error::DiagnosticsRelay(e) => Some(e),
+            Error::CompanionProxy(e) => Some(e),
+            Error::Afc(e) => Some(e),
+            Error::Io(e) => Some(e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn display_describes_variants() {
+        assert_eq!(
+            AfcError::ObjectNotFound.to_string(),
+            "no such file or directory"
+        );
+        assert_eq!(IdeviceError::Timeout.to_string(), "operation timed out");
+        assert_eq!(
+            LockdowndError::PasswordProtected.to_string(),
+            "the device is password protected, unlock it and try again"
+        );
+        assert_eq!(
+            AfcError::Unknown(99).to_string(),
+            "unrecognized AFC error code 99"
+        );
+    }
+
+    #[test]
+    fn errors_box_into_dyn_error() {
+        let error: Box<dyn std::error::Error> = Box::new(FileRelayError::StagingEmpty);
+        assert_eq!(error.to_string(), FileRelayError::StagingEmpty.to_string());
+        let wrapped = Error::from(IdeviceError::NoDevice);
+        assert_eq!(
+            wrapped.to_string(),
+            format!("idevice: {}", IdeviceError::NoDevice)
+        );
+    }
+
+    #[test]
+    fn io_errors_keep_kind_and_source() {
+        use std::io::ErrorKind;
+
+        let error = std::io::Error::from(AfcError::ObjectNotFound);
+        assert_eq!(error.kind(), ErrorKind::NotFound);
+        assert_eq!(
+            error.get_ref().unwrap().downcast_ref::<AfcError>(),
+            Some(&AfcError::ObjectNotFound)
+        );
+        assert_eq!(
+            std::io::Error::from(AfcError::PermDenied).kind(),
+            ErrorKind::PermissionDenied
+        );
+        assert_eq!(
+            std::io::Error::from(AfcError::IoError).kind(),
+            ErrorKind::Other
+        );
+        assert_eq!(
+            std::io::Error::from(UserPrefError::NoEnt).kind(),
+            ErrorKind::NotFound
+        );
+        assert_eq!(
+            std::io::Error::from(LockdowndError::MuxError).kind(),
+            ErrorKind::Other
+        );
+    }
+
+    #[test]
+    fn afc_codes_map_to_variants() {
+        assert_eq!(AfcError::from(0), AfcError::Success);
+        assert_eq!(AfcError::from(8), AfcError::ObjectNotFound);
+        assert_eq!(AfcError::from(20), AfcError::IoError);
+        assert_eq!(AfcError::from(33), AfcError::DirNotEmpty);
+        assert_eq!(AfcError::from(-1), AfcError::ForceSignedType);
+        // Codes between the documented ranges aren't collapsed into UnknownError
+        assert_eq!(AfcError::from(25), AfcError::Unknown(25));
+        assert_eq!(AfcError::from(99), AfcError::Unknown(99));
+    }
+
+    /// Stands in for a lockdownd call
+    fn pair(result: Result<(), LockdowndError>) -> Result<(), LockdowndError> {
+        result
+    }
+
+    /// Stands in for an AFC call
+    fn read(result: Result<Vec<u8>, AfcError>) -> Result<Vec<u8>, AfcError> {
+        result
+    }
+
+    /// Uses `?` on two different services, which only compiles with the `From` impls
+    fn pair_and_read(
+        paired: Result<(), LockdowndError>,
+        data: Result<Vec<u8>, AfcError>,
+    ) -> Result<usize, Error> {
+        pair(paired)?;
+        let data = read(data)?;
+        Ok(data.len())
+    }
+
+    #[test]
+    fn service_errors_compose_with_question_mark() {
+        assert_eq!(pair_and_read(Ok(()), Ok(vec![1, 2, 3])).unwrap(), 3);
+        assert!(matches!(
+            pair_and_read(Err(LockdowndError::PasswordProtected), Ok(vec![])),
+            Err(Error::Lockdownd(LockdowndError::PasswordProtected))
+        ));
+        assert!(matches!(
+            pair_and_read(Ok(()), Err(AfcError::ObjectNotFound)),
+            Err(Error::Afc(AfcError::ObjectNotFound))
+        ));
+    }
+}
@@ -2,20 +2,21 @@
 
 use crate::bindings as unsafe_bindings;
 use crate::bindings::idevice_info_t;
-use crate::callback::IDeviceEventCallback;
 use crate::error::{
-    self, DebugServerError, HeartbeatError, IdeviceError, InstProxyError, LockdowndError,
-    MobileImageMounterError,
+    self, DebugServerError, DeviceBuildError, HeartbeatError, IdeviceError, InstProxyError,
+    LockdowndError, MobileImageMounterError,
 };
 use crate::services::heartbeat::HeartbeatClient;
 use crate::services::lockdownd::{LockdowndClient, LockdowndService};
 use crate::services::mobile_image_mounter::MobileImageMounter;
 use core::fmt;
-use libc::c_void;
+use libc::{c_int, c_void};
 use log::{info, trace, warn};
 use std::ffi::CStr;
 use std::net::IpAddr;
 use std::os::raw::c_char;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender};
 use std::{fmt::Debug, fmt::Formatter, ptr::null_mut};
 
 /// Get a list of UDIDs
@@ -26,32 +27,14 @@ use std::{fmt::Debug, fmt::Formatter, ptr::null_mut};
 ///
 /// ***Verified:*** False
 pub fn get_udid_list() -> Result<Vec<String>, IdeviceError> {
-    let mut device_list: *mut idevice_info_t = null_mut();
-    let mut device_count: i32 = 0;
-    info!("Getting all devices from the muxer");
-    let result: error::IdeviceError = unsafe {
-        unsafe_bindings::idevice_get_device_list_extended(&mut device_list, &mut device_count)
-    }
-    .into();
-    if result != error::IdeviceError::Success {
-        return Err(result);
-    }
-
-    // Create slice of mutable references to idevice_info_t from device_list and device_count
-    info!("Getting device list from slice");
-    let device_list_slice =
-        unsafe { std::slice::from_raw_parts_mut(device_list, device_count as usize) };
-
-    let mut to_return = vec![];
-    for device in device_list_slice {
-        to_return.push(unsafe {
-            std::ffi::CStr::from_ptr((*(*device)).udid)
-                .to_string_lossy()
-                .into_owned()
-        });
-    }
+    let device_list = DeviceList::new()?;
     info!("Returning device list");
-    Ok(to_return)
+    Ok(entry_udids(device_list.entries()))
+}
+
+/// Collects the UDIDs of device list entries, skipping malformed ones
+fn entry_udids(entries: &[idevice_info_t]) -> Vec<String> {
+    entries.iter().filter_map(entry_udid).collect()
 }
 
 /// Gets all devices detected by usbmuxd
@@ -63,62 +46,273 @@ pub fn get_udid_list() -> Result<Vec<String>, IdeviceError> {
 ///
 /// ***Verified:*** False
 pub fn get_devices() -> Result<Vec<Device>, IdeviceError> {
-    let mut device_list: *mut idevice_info_t = null_mut();
-    let mut device_count: i32 = 0;
-    info!("Getting device list from the muxer");
-    let result: error::IdeviceError = unsafe {
-        unsafe_bindings::idevice_get_device_list_extended(&mut device_list, &mut device_count)
+    let device_list = DeviceList::new()?;
+    info!("Returning device structs");
+    Ok(device_list.iter().collect())
+}
+
+/// The first device usbmuxd reports, for tests that need a connected device
+#[cfg(test)]
+pub(crate) fn test_device() -> Device {
+    get_devices()
+        .unwrap()
+        .into_iter()
+        .next()
+        .expect("No device connected")
+}
+
+/// Gets all devices connected to usbmuxd over USB
+/// Network entries are skipped before a device is created for them
+/// # Arguments
+/// *none*
+/// # Returns
+/// A vector of devices
+///
+/// ***Verified:*** False
+pub fn get_usb_devices() -> Result<Vec<Device>, IdeviceError> {
+    let device_list = DeviceList::new()?;
+    info!("Returning USB device structs");
+    Ok(device_list.usb_devices().collect())
+}
+
+/// Gets all devices that usbmuxd can reach over the network
+/// USB entries are skipped before a device is created for them
+/// # Arguments
+/// *none*
+/// # Returns
+/// A vector of devices
+///
+/// ***Verified:*** False
+pub fn get_network_devices() -> Result<Vec<Device>, IdeviceError> {
+    let device_list = DeviceList::new()?;
+    info!("Returning network device structs");
+    Ok(device_list.network_devices().collect())
+}
+
+/// The list of devices known to usbmuxd at the time of the request.
+/// Owns the list allocated by libimobiledevice and frees it when dropped.
+/// Devices are only created as the list is iterated, so the list can be walked multiple times.
+pub struct DeviceList {
+    pointer: *mut idevice_info_t,
+    count: usize,
+}
+
+impl DeviceList {
+    /// Fetches the current device list from usbmuxd
+    /// # Arguments
+    /// *none*
+    /// # Returns
+    /// A struct owning the device list
+    ///
+    /// ***Verified:*** False
+    pub fn new() -> Result<Self, IdeviceError> {
+        let mut device_list: *mut idevice_info_t = null_mut();
+        let mut device_count: i32 = 0;
+        info!("Getting device list from the muxer");
+        let result: error::IdeviceError = unsafe {
+            unsafe_bindings::idevice_get_device_list_extended(&mut device_list, &mut device_count)
+        }
+        .into();
+
+        if result != error::IdeviceError::Success {
+            return Err(result);
+        }
+
+        // usbmuxd can report success with no list while it restarts
+        if device_list.is_null() || device_count <= 0 {
+            trace!("The muxer returned an empty device list");
+            return Ok(DeviceList {
+                pointer: device_list,
+                count: 0,
+            });
+        }
+
+        Ok(DeviceList {
+            pointer: device_list,
+            count: device_count as usize,
+        })
     }
-    .into();
 
-    if result != error::IdeviceError::Success {
-        return Err(result);
+    /// Returns the number of entries in the list
+    pub fn len(&self) -> usize {
+        self.count
     }
 
-    info!("Determining devices from slice");
-    // Create slice of mutable references to idevice_info_t from device_list and device_count
-    let device_list_slice =
-        unsafe { std::slice::from_raw_parts_mut(device_list, device_count as usize) };
+    /// Returns whether the list has no entries
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
 
-    let mut to_return = vec![];
-    for i in device_list_slice.iter_mut() {
-        let network = unsafe {
-            if (*(*i)).conn_type == 1 {
-                false
-            } else {
-                true
+    /// Creates an iterator that creates a device for each entry in the list.
+    /// Entries that fail to create a device are skipped.
+    pub fn iter(&self) -> DeviceListIter<'_> {
+        DeviceListIter {
+            entries: self.entries().iter(),
+            network: None,
+        }
+    }
+
+    /// Creates an iterator that only creates devices for USB entries
+    pub fn usb_devices(&self) -> DeviceListIter<'_> {
+        DeviceListIter {
+            entries: self.entries().iter(),
+            network: Some(false),
+        }
+    }
+
+    /// Creates an iterator that only creates devices for network entries
+    pub fn network_devices(&self) -> DeviceListIter<'_> {
+        DeviceListIter {
+            entries: self.entries().iter(),
+            network: Some(true),
+        }
+    }
+
+    /// The raw entries of the list
+    pub(crate) fn entries(&self) -> &[idevice_info_t] {
+        if self.pointer.is_null() || self.count == 0 {
+            return &[];
+        }
+        unsafe { std::slice::from_raw_parts(self.pointer, self.count) }
+    }
+}
+
+impl<'a> IntoIterator for &'a DeviceList {
+    type Item = Device;
+    type IntoIter = DeviceListIter<'a>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+impl Drop for DeviceList {
+    fn drop(&mut self) {
+        info!("Freeing device list");
+        unsafe {
+            unsafe_bindings::idevice_device_list_extended_free(self.pointer);
+        }
+    }
+}
+
+/// An iterator over a `DeviceList`, creating devices lazily
+pub struct DeviceListIter<'a> {
+    entries: std::slice::Iter<'a, idevice_info_t>,
+    // Only entries with this connection kind are turned into devices
+    network: Option<bool>,
+}
+
+impl Iterator for DeviceListIter<'_> {
+    type Item = Device;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        for entry in self.entries.by_ref() {
+            let network = match entry_connection_type(entry) {
+                Some(connection_type) => connection_type == ConnectionType::Network,
+                None => continue,
+            };
+            if self.network.is_some() && self.network != Some(network) {
+                continue;
             }
-        };
 
-        let mut device_info: unsafe_bindings::idevice_t = unsafe { std::mem::zeroed() };
-        let device_info_ptr: *mut unsafe_bindings::idevice_t = &mut device_info;
-        let result = unsafe {
-            unsafe_bindings::idevice_new_with_options(
-                device_info_ptr,
-                (*(*i)).udid,
-                if network {
-                    unsafe_bindings::idevice_options_IDEVICE_LOOKUP_NETWORK
-                } else {
-                    unsafe_bindings::idevice_options_IDEVICE_LOOKUP_USBMUX
-                },
-            )
-        };
-        if result != 0 {
-            trace!("Failed to create device struct");
-            continue;
+            match device_from_entry(entry) {
+                Some(device) => return Some(device),
+                None => continue,
+            }
+        }
+        None
+    }
+}
+
+/// Reads the connection type of a usbmuxd device list entry, skipping unknown types
+fn entry_connection_type(entry: &idevice_info_t) -> Option<ConnectionType> {
+    if entry.is_null() {
+        return None;
+    }
+    let conn_type = unsafe { (*(*entry)).conn_type };
+    match ConnectionType::try_from(conn_type) {
+        Ok(connection_type) => Some(connection_type),
+        Err(_) => {
+            warn!("Skipping device with unknown connection type {}", conn_type);
+            None
         }
-        let to_push = device_info.into();
-        to_return.push(to_push);
     }
+}
 
-    // Drop the memory that the C library allocated
-    info!("Freeing device list");
-    let device_list_ptr = device_list as *mut *mut std::os::raw::c_char;
-    unsafe {
-        unsafe_bindings::idevice_device_list_free(device_list_ptr);
+/// Reads the UDID of a usbmuxd device list entry, skipping entries without one
+fn entry_udid(entry: &idevice_info_t) -> Option<String> {
+    if entry.is_null() || unsafe { (*(*entry)).udid }.is_null() {
+        warn!("Skipping device list entry without a udid");
+        return None;
+    }
+    Some(
+        unsafe { CStr::from_ptr((*(*entry)).udid) }
+            .to_string_lossy()
+            .into_owned(),
+    )
+}
+
+/// Creates a device from a usbmuxd device list entry
+fn device_from_entry(entry: &idevice_info_t) -> Option<Device> {
+    let network = entry_connection_type(entry)? == ConnectionType::Network;
+    entry_udid(entry)?;
+
+    let mut device_info: unsafe_bindings::idevice_t = unsafe { std::mem::zeroed() };
+    let result = unsafe {
+        unsafe_bindings::idevice_new_with_options(
+            &mut device_info,
+            (*(*entry)).udid,
+            if network {
+                unsafe_bindings::idevice_options_IDEVICE_LOOKUP_NETWORK
+            } else {
+                unsafe_bindings::idevice_options_IDEVICE_LOOKUP_USBMUX
+            },
+        )
+    };
+    if result != 0 {
+        trace!("Failed to create device struct");
+        return None;
+    }
+    Some(device_info.into())
+}
+
+/// How a device is connected to the host
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum ConnectionType {
+    Usb,
+    Network,
+}
+
+impl TryFrom<u32> for ConnectionType {
+    type Error = ();
+
+    fn try_from(value: u32) -> Result<Self, Self::Error> {
+        match value {
+            unsafe_bindings::idevice_connection_type_CONNECTION_USBMUXD => Ok(ConnectionType::Usb),
+            unsafe_bindings::idevice_connection_type_CONNECTION_NETWORK => {
+                Ok(ConnectionType::Network)
+            }
+            _ => Err(()),
+        }
+    }
+}
+
+impl From<ConnectionType> for u32 {
+    fn from(connection_type: ConnectionType) -> Self {
+        match connection_type {
+            ConnectionType::Usb => unsafe_bindings::idevice_connection_type_CONNECTION_USBMUXD,
+            ConnectionType::Network => unsafe_bindings::idevice_connection_type_CONNECTION_NETWORK,
+        }
     }
-    info!("Returning device structs");
-    Ok(to_return)
+}
+
+/// Which connection to use when looking up a device that may be reachable in more than one way
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum LookupPreference {
+    UsbOnly,
+    NetworkOnly,
+    UsbThenNetwork,
+    NetworkThenUsb,
 }
 
 /// Fetches a list of devices, but returns one with the given udid
@@ -129,18 +323,85 @@ pub fn get_devices() -> Result<Vec<Device>, IdeviceError> {
 ///
 /// ***Verified:*** False
 pub fn get_device(udid: String) -> Result<Device, IdeviceError> {
-    let devices = match get_devices() {
-        Ok(devices) => devices,
-        Err(e) => return Err(e),
+    get_device_with_options(&udid, LookupPreference::UsbThenNetwork)
+}
+
+/// Fetches a list of devices, and returns the one with the given udid over the preferred connection
+/// # Arguments
+/// * `udid` - The udid of the device to return
+/// * `prefer` - Which connections to accept, and in what order
+/// # Returns
+/// A device struct
+///
+/// ***Verified:*** False
+pub fn get_device_with_options(
+    udid: &str,
+    prefer: LookupPreference,
+) -> Result<Device, IdeviceError> {
+    let device_list = DeviceList::new()?;
+
+    let mut usb_entry = None;
+    let mut network_entry = None;
+    for entry in device_list.entries() {
+        if entry_udid(entry).as_deref() != Some(udid) {
+            continue;
+        }
+        match entry_connection_type(entry) {
+            Some(ConnectionType::Usb) => usb_entry = usb_entry.or(Some(entry)),
+            Some(ConnectionType::Network) => network_entry = network_entry.or(Some(entry)),
+            None => {}
+        }
+    }
+
+    let candidates = match prefer {
+        LookupPreference::UsbOnly => [usb_entry, None],
+        LookupPreference::NetworkOnly => [network_entry, None],
+        LookupPreference::UsbThenNetwork => [usb_entry, network_entry],
+        LookupPreference::NetworkThenUsb => [network_entry, usb_entry],
     };
-    for device in devices {
-        if device.get_udid() == udid {
+    for entry in candidates.iter().flatten() {
+        if let Some(device) = device_from_entry(entry) {
             return Ok(device);
         }
     }
     Err(error::IdeviceError::NoDevice)
 }
 
+/// Connects to a device over the network by its IP address, without discovering it through usbmuxd.
+/// The device must already be paired with the host.
+/// # Arguments
+/// * `udid` - The udid of the device, used to find its pairing record
+/// * `ip` - The IP address of the device
+/// # Returns
+/// A device that has answered a lockdownd query and is ready to start services
+///
+/// ***Verified:*** False
+pub fn connect_network(udid: &str, ip: IpAddr) -> Result<Device, IdeviceError> {
+    let device = match Device::new(udid.to_string(), true, Some(ip), 0) {
+        Ok(device) => device,
+        Err(_) => return Err(IdeviceError::InvalidArg),
+    };
+
+    info!("Checking that {} is reachable at {}", udid, ip);
+    let result = device
+        .new_lockdownd_client("rusty_libimobiledevice".to_string())
+        .and_then(|client| client.query_type());
+    match result {
+        Ok(query_type) => {
+            trace!("Device answered lockdownd query with {}", query_type);
+            Ok(device)
+        }
+        Err(e) => {
+            warn!("Lockdownd handshake with {} failed: {:?}", ip, e);
+            Err(match e {
+                LockdowndError::SslError | LockdowndError::PairingFailed => IdeviceError::SslError,
+                LockdowndError::RecieveTimeout => IdeviceError::Timeout,
+                _ => IdeviceError::ConnRefused,
+            })
+        }
+    }
+}
+
 /// Toggles usbmuxd's debug mode
 /// # Arguments
 /// * `debug` - Whether to turn on or off debug mode
@@ -149,16 +410,117 @@ pub fn get_device(udid: String) -> Result<Device, IdeviceError> {
 ///
 /// ***Verified:*** False
 pub fn set_debug(debug: bool) {
-    let debug = match debug {
+    set_debug_level(match debug {
         true => 1,
         false => 0,
-    };
-    trace!("Setting debug mode to {}", debug);
-    unsafe { unsafe_bindings::idevice_set_debug_level(debug) }
+    });
+}
+
+/// Sets the verbosity of libimobiledevice's debug output.
+/// The library has no hook for its debug output, so it is written to stderr rather than `log`.
+/// # Arguments
+/// * `level` - The debug level, 0 turns debug output off and higher values are more verbose
+/// # Returns
+/// ()
+///
+/// ***Verified:*** False
+pub fn set_debug_level(level: u32) {
+    let level = native_debug_level(level);
+    trace!("Setting debug level to {}", level);
+    unsafe { unsafe_bindings::idevice_set_debug_level(level) }
 }
 
-pub fn event_subscribe(_cb: IDeviceEventCallback) -> Result<(), IdeviceError> {
-    todo!()
+/// Clamps a debug level to what fits in the library's `int`
+fn native_debug_level(level: u32) -> c_int {
+    level.min(c_int::MAX as u32) as c_int
+}
+
+/// Gets the version of libimobiledevice this crate was built against
+/// # Arguments
+/// *none*
+/// # Returns
+/// The version string reported by pkg-config at build time, if it could be found
+///
+/// ***Verified:*** False
+pub fn library_version() -> Option<String> {
+    option_env!("LIBIMOBILEDEVICE_VERSION").map(|v| v.to_string())
+}
+
+/// Gets the version of libusbmuxd this crate was built against
+/// # Arguments
+/// *none*
+/// # Returns
+/// The version string reported by pkg-config at build time, if it could be found
+///
+/// ***Verified:*** False
+pub fn usbmuxd_version() -> Option<String> {
+    option_env!("LIBUSBMUXD_VERSION").map(|v| v.to_string())
+}
+
+/// Whether an `EventSubscription` is alive, as libimobiledevice only holds one callback
+static EVENT_SUBSCRIBED: AtomicBool = AtomicBool::new(false);
+
+/// Subscribes to device events, delivering them on a channel instead of a callback.
+/// Only one subscription can be active at a time.
+/// # Arguments
+/// *none*
+/// # Returns
+/// A subscription that unsubscribes when dropped, and a receiver of event types with the device's udid.
+/// Returns InvalidArg if another subscription is still active.
+///
+/// ***Verified:*** False
+pub fn event_channel() -> Result<(EventSubscription, Receiver<(EventType, String)>), IdeviceError> {
+    if EVENT_SUBSCRIBED.swap(true, Ordering::SeqCst) {
+        warn!("Already subscribed to device events");
+        return Err(IdeviceError::InvalidArg);
+    }
+    let (sender, receiver) = mpsc::channel();
+    let sender = Box::into_raw(Box::new(sender));
+
+    info!("Subscribing to device events");
+    let result: IdeviceError = unsafe {
+        unsafe_bindings::idevice_event_subscribe(
+            Some(event_channel_callback),
+            sender as *mut c_void,
+        )
+    }
+    .into();
+    if result != IdeviceError::Success {
+        drop(unsafe { Box::from_raw(sender) });
+        EVENT_SUBSCRIBED.store(false, Ordering::SeqCst);
+        return Err(result);
+    }
+
+    Ok((EventSubscription { sender }, receiver))
+}
+
+unsafe extern "C" fn event_channel_callback(
+    event: *const unsafe_bindings::idevice_event_t,
+    user_data: *mut c_void,
+) {
+    if event.is_null() || user_data.is_null() {
+        return;
+    }
+    let event = *event;
+    let event_type = match event.event {
+        unsafe_bindings::idevice_event_type_IDEVICE_DEVICE_ADD => EventType::Add,
+        unsafe_bindings::idevice_event_type_IDEVICE_DEVICE_REMOVE => EventType::Remove,
+        unsafe_bindings::idevice_event_type_IDEVICE_DEVICE_PAIRED => EventType::Pair,
+        _ => {
+            warn!("Unknown device event {}", event.event);
+            return;
+        }
+    };
+    let udid = if event.udid.is_null() {
+        String::new()
+    } else {
+        CStr::from_ptr(event.udid).to_string_lossy().into_owned()
+    };
+    trace!("Device event {:?} for {}", event_type, udid);
+
+    let sender = &*(user_data as *const Sender<(EventType, String)>);
+    // The receiver being dropped just means nobody is listening anymore
+    let _ = sender.send((event_type, udid));
 }
 
 // Structs
@@ -269,8 +631,8 @@ impl Device {
                 udid: udid_ptr as *mut c_char,
                 mux_id,
                 conn_type: match network {
-                    true => 2,
-                    false => 1,
+                    true => ConnectionType::Network.into(),
+                    false => ConnectionType::Usb.into(),
                 },
                 conn_data: ip_addr_ptr as *mut c_void,
                 version: 0,
@@ -313,15 +675,48 @@ impl Device {
     /// # Returns
     /// Whether the device is connected via network as a `bool`
     pub fn get_network(&self) -> bool {
-        unsafe {
-            if (*self.pointer).conn_type == 1 {
-                false
-            } else {
-                true
+        self.connection_type() == ConnectionType::Network
+    }
+
+    /// Get how the device is connected to the host
+    /// # Returns
+    /// The connection type. Unknown connection types are reported as `Network`,
+    /// since usbmuxd only reports USB devices as USB.
+    ///
+    /// ***Verified:*** False
+    pub fn connection_type(&self) -> ConnectionType {
+        let conn_type = unsafe { (*self.pointer).conn_type };
+        match ConnectionType::try_from(conn_type) {
+            Ok(connection_type) => connection_type,
+            Err(_) => {
+                warn!("Unknown connection type {}, assuming network", conn_type);
+                ConnectionType::Network
             }
         }
     }
 
+    /// Looks the device up in usbmuxd again and replaces the underlying handle,
+    /// such as after usbmuxd restarts or the cable is reconnected.
+    /// The device is looked up over the same kind of connection it used before.
+    /// Service clients created from the old handle can't be reused and must be recreated.
+    /// # Arguments
+    /// *none*
+    /// # Returns
+    /// *none*
+    ///
+    /// ***Verified:*** False
+    pub fn reconnect(&mut self) -> Result<(), IdeviceError> {
+        let prefer = match self.connection_type() {
+            ConnectionType::Usb => LookupPreference::UsbOnly,
+            ConnectionType::Network => LookupPreference::NetworkOnly,
+        };
+        let mut device = get_device_with_options(&self.get_udid(), prefer)?;
+        info!("Reconnected to {}", self.get_udid());
+        // The old handle is freed when `device` is dropped
+        std::mem::swap(&mut self.pointer, &mut device.pointer);
+        Ok(())
+    }
+
     /// Get the ip address of the device if connected over network
     /// # Returns
     /// The ip address of the device
@@ -393,6 +788,56 @@ impl Device {
         data.to_vec()
     }
 
+    /// Gets the name of the device, such as "Jackson's iPhone".
+    /// Opens a short-lived lockdownd client to fetch the value.
+    /// # Returns
+    /// The device name as a `String`
+    ///
+    /// ***Verified:*** False
+    pub fn get_name(&self) -> Result<String, LockdowndError> {
+        self.get_lockdownd_string("DeviceName")
+    }
+
+    /// Gets the iOS version of the device, such as "15.4.1".
+    /// Opens a short-lived lockdownd client to fetch the value.
+    /// # Returns
+    /// The product version as a `String`
+    ///
+    /// ***Verified:*** False
+    pub fn get_product_version(&self) -> Result<String, LockdowndError> {
+        self.get_lockdownd_string("ProductVersion")
+    }
+
+    /// Gets the model identifier of the device, such as "iPhone12,1".
+    /// Opens a short-lived lockdownd client to fetch the value.
+    /// # Returns
+    /// The product type as a `String`
+    ///
+    /// ***Verified:*** False
+    pub fn get_product_type(&self) -> Result<String, LockdowndError> {
+        self.get_lockdownd_string("ProductType")
+    }
+
+    /// Gets the unique device ID as reported by lockdownd.
+    /// Opens a short-lived lockdownd client to fetch the value.
+    /// # Returns
+    /// The unique device ID as a `String`
+    ///
+    /// ***Verified:*** False
+    pub fn get_unique_device_id(&self) -> Result<String, LockdowndError> {
+        self.get_lockdownd_string("UniqueDeviceID")
+    }
+
+    /// Fetches a string value from the global lockdownd domain, tearing the client down afterwards
+    fn get_lockdownd_string(&self, key: &str) -> Result<String, LockdowndError> {
+        let lockdownd_client = self.new_lockdownd_client("rusty_libimobiledevice".to_string())?;
+        let value = lockdownd_client.get_value(None, Some(key))?;
+        match value.get_string_val() {
+            Ok(value) => Ok(value),
+            Err(_) => Err(LockdowndError::InvalidResponse),
+        }
+    }
+
     /// Starts the lockdown service for the device
     /// This allows things like debuggers to be attached
     /// # Arguments
@@ -481,6 +926,69 @@ impl Device {
     }
 }
 
+/// Builds a device without going through usbmuxd's discovery.
+/// A safer alternative to `Device::new`, as a network device can't be built without its address.
+#[derive(Debug, Clone, Default)]
+pub struct DeviceBuilder {
+    udid: Option<String>,
+    connection: Option<BuilderConnection>,
+}
+
+#[derive(Debug, Clone)]
+enum BuilderConnection {
+    Usb(u32),
+    Network(IpAddr),
+}
+
+impl DeviceBuilder {
+    /// Creates a builder with no udid or connection set
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the udid of the device
+    pub fn udid(mut self, udid: impl Into<String>) -> Self {
+        self.udid = Some(udid.into());
+        self
+    }
+
+    /// Connects to the device over USB, using the ID given to it by the muxer
+    pub fn usb(mut self, mux_id: u32) -> Self {
+        self.connection = Some(BuilderConnection::Usb(mux_id));
+        self
+    }
+
+    /// Connects to the device over the network at the given address
+    pub fn network(mut self, ip_addr: IpAddr) -> Self {
+        self.connection = Some(BuilderConnection::Network(ip_addr));
+        self
+    }
+
+    /// Builds the device. The connection set last wins.
+    /// # Arguments
+    /// *none*
+    /// # Returns
+    /// A device struct
+    ///
+    /// ***Verified:*** False
+    pub fn build(self) -> Result<Device, DeviceBuildError> {
+        let udid = self.udid.ok_or(DeviceBuildError::MissingUdid)?;
+        if udid.is_empty() {
+            return Err(DeviceBuildError::MissingUdid);
+        }
+        if udid.contains('\0') {
+            return Err(DeviceBuildError::InvalidUdid);
+        }
+        let device = match self.connection {
+            Some(BuilderConnection::Usb(mux_id)) => Device::new(udid, false, None, mux_id),
+            Some(BuilderConnection::Network(ip_addr)) => Device::new(udid, true, Some(ip_addr), 0),
+            None => return Err(DeviceBuildError::MissingConnection),
+        };
+        // Device::new only fails for a network device without an address
+        device.map_err(|_| DeviceBuildError::MissingConnection)
+    }
+}
+
 pub struct IDeviceEvent {
     pub(crate) _pointer: unsafe_bindings::idevice_event_t,
 }
@@ -491,18 +999,94 @@ impl From<unsafe_bindings::idevice_event_t> for IDeviceEvent {
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum EventType {
     Add,
     Remove,
     Pair,
 }
 
+/// An active subscription to device events created by `event_channel`.
+/// Dropping it unsubscribes and closes the channel.
+pub struct EventSubscription {
+    sender: *mut Sender<(EventType, String)>,
+}
+
+impl Drop for EventSubscription {
+    fn drop(&mut self) {
+        info!("Unsubscribing from device events");
+        unsafe {
+            unsafe_bindings::idevice_event_unsubscribe();
+            drop(Box::from_raw(self.sender));
+        }
+        EVENT_SUBSCRIBED.store(false, Ordering::SeqCst);
+    }
+}
+
 impl From<unsafe_bindings::idevice_t> for Device {
     fn from(device: unsafe_bindings::idevice_t) -> Device {
         return Device { pointer: device };
     }
 }
 
+/// Clones are independent handles to the device.
+/// Each clone owns its own copy of the underlying device struct and frees it when dropped,
+/// so a clone can be handed to a service on another thread and outlive the original.
+impl Clone for Device {
+    fn clone(&self) -> Self {
+        let source = unsafe { *self.pointer };
+
+        info!("Cloning device {}", self.get_udid());
+        let udid_ptr = if source.udid.is_null() {
+            null_mut()
+        } else {
+            let udid_length = unsafe { libc::strlen(source.udid) } + 1;
+            check_alloc(unsafe { libc::strdup(source.udid) }, udid_length)
+        };
+
+        // Network connection data is a sockaddr that stores its length in the first byte
+        let conn_data_ptr = if source.conn_data.is_null() {
+            null_mut()
+        } else {
+            let data = self.get_conn_data();
+            let conn_data_ptr = check_alloc(
+                unsafe { libc::malloc(data.len().max(1)) as *mut u8 },
+                data.len(),
+            );
+            // SAFETY: conn_data_ptr has capacity for data.len() bytes
+            unsafe { std::ptr::copy_nonoverlapping(data.as_ptr(), conn_data_ptr, data.len()) };
+            conn_data_ptr as *mut c_void
+        };
+
+        let i_private_size = std::mem::size_of::<unsafe_bindings::idevice_private>();
+        let i_private_ptr = check_alloc(
+            unsafe { libc::malloc(i_private_size) as *mut unsafe_bindings::idevice_private },
+            i_private_size,
+        );
+
+        // SAFETY: i_private_ptr has enough capacity for an idevice_private struct
+        unsafe {
+            i_private_ptr.write(unsafe_bindings::idevice_private {
+                udid: udid_ptr,
+                conn_data: conn_data_ptr,
+                ..source
+            });
+        }
+
+        i_private_ptr.into()
+    }
+}
+
+/// Aborts the way a failed Rust allocation does if libc couldn't allocate `size` bytes
+fn check_alloc<T>(pointer: *mut T, size: usize) -> *mut T {
+    if pointer.is_null() {
+        std::alloc::handle_alloc_error(
+            std::alloc::Layout::from_size_align(size.max(1), 1).unwrap(),
+        );
+    }
+    pointer
+}
+
 impl Debug for Device {
     fn fmt(&self, f: &mut Formatter) -> fmt::Result {
         write!(
@@ -525,3 +1109,340 @@ impl Drop for Device {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn empty_device_list_has_no_entries() {
+        // What `DeviceList::new` builds when usbmuxd reports success without a list
+        let list = DeviceList {
+            pointer: null_mut(),
+            count: 0,
+        };
+        assert!(list.is_empty());
+        assert_eq!(list.len(), 0);
+        assert!(list.entries().is_empty());
+        assert_eq!(list.iter().count(), 0);
+        assert_eq!(list.usb_devices().count(), 0);
+        assert_eq!(list.network_devices().count(), 0);
+    }
+
+    /// Allocates a device struct the way libimobiledevice does, so `idevice_free` can free it
+    fn malloc_device(udid: &str, conn_data: &[u8]) -> Device {
+        unsafe {
+            let udid = std::ffi::CString::new(udid).unwrap();
+            let conn_data_ptr = match conn_data.is_empty() {
+                true => null_mut(),
+                false => {
+                    let pointer = libc::malloc(conn_data.len());
+                    std::ptr::copy_nonoverlapping(
+                        conn_data.as_ptr(),
+                        pointer as *mut u8,
+                        conn_data.len(),
+                    );
+                    pointer
+                }
+            };
+            let pointer = libc::malloc(std::mem::size_of::<unsafe_bindings::idevice_private>())
+                as *mut unsafe_bindings::idevice_private;
+            pointer.write(unsafe_bindings::idevice_private {
+                udid: libc::strdup(udid.as_ptr()),
+                mux_id: 1,
+                conn_type: match conn_data.is_empty() {
+                    true => unsafe_bindings::idevice_connection_type_CONNECTION_USBMUXD,
+                    false => unsafe_bindings::idevice_connection_type_CONNECTION_NETWORK,
+                },
+                conn_data: conn_data_ptr,
+                version: 0,
+                device_class: 0,
+            });
+            pointer.into()
+        }
+    }
+
+    #[test]
+    fn clones_drop_independently() {
+        let usb = malloc_device("00008030-001A", &[]);
+        let usb_clone = usb.clone();
+        assert_ne!(usb.pointer, usb_clone.pointer);
+        assert_eq!(usb_clone.get_udid(), "00008030-001A");
+        drop(usb);
+        assert_eq!(usb_clone.get_udid(), "00008030-001A");
+        drop(usb_clone);
+
+        // A sockaddr_in for 192.168.1.2, which starts with its own length
+        let sockaddr = [16, 2, 0, 0, 192, 168, 1, 2, 0, 0, 0, 0, 0, 0, 0, 0];
+        let network = malloc_device("00008030-001B", &sockaddr);
+        let network_clone = network.clone();
+        drop(network);
+        assert_eq!(network_clone.get_conn_data(), sockaddr.to_vec());
+        drop(network_clone);
+    }
+
+    #[test]
+    fn builder_rejects_incomplete_options() {
+        let ip: IpAddr = "192.168.1.2".parse().unwrap();
+        assert_eq!(
+            DeviceBuilder::new().usb(1).build().err(),
+            Some(DeviceBuildError::MissingUdid)
+        );
+        assert_eq!(
+            DeviceBuilder::new().udid("").network(ip).build().err(),
+            Some(DeviceBuildError::MissingUdid)
+        );
+        assert_eq!(
+            DeviceBuilder::new().udid("0000\08030").usb(1).build().err(),
+            Some(DeviceBuildError::InvalidUdid)
+        );
+        assert_eq!(
+            DeviceBuilder::new().udid("00008030-001A").build().err(),
+            Some(DeviceBuildError::MissingConnection)
+        );
+    }
+
+    #[test]
+    fn builder_uses_last_connection() {
+        let ip: IpAddr = "192.168.1.2".parse().unwrap();
+        let device = DeviceBuilder::new()
+            .udid("00008030-001A")
+            .network(ip)
+            .build()
+            .unwrap();
+        assert_eq!(device.get_udid(), "00008030-001A");
+        assert_eq!(device.connection_type(), ConnectionType::Network);
+        assert_eq!(device.get_ip_address(), Some("192.168.1.2".to_string()));
+
+        let device = DeviceBuilder::new()
+            .udid("00008030-001A")
+            .network(ip)
+            .usb(3)
+            .build()
+            .unwrap();
+        assert_eq!(device.connection_type(), ConnectionType::Usb);
+    }
+
+    #[test]
+    fn connection_types_convert() {
+        assert_eq!(
+            ConnectionType::try_from(unsafe_bindings::idevice_connection_type_CONNECTION_USBMUXD),
+            Ok(ConnectionType::Usb)
+        );
+        assert_eq!(
+            ConnectionType::try_from(unsafe_bindings::idevice_connection_type_CONNECTION_NETWORK),
+            Ok(ConnectionType::Network)
+        );
+        assert_eq!(ConnectionType::try_from(7), Err(()));
+        for connection_type in [ConnectionType::Usb, ConnectionType::Network] {
+            assert_eq!(
+                ConnectionType::try_from(u32::from(connection_type)),
+                Ok(connection_type)
+            );
+        }
+    }
+
+    #[test]
+    fn udid_list_skips_malformed_entries() {
+        let udid = std::ffi::CString::new("00008030-001A").unwrap();
+        let mut infos = [
+            unsafe_bindings::idevice_info {
+                udid: udid.as_ptr() as *mut c_char,
+                conn_type: unsafe_bindings::idevice_connection_type_CONNECTION_USBMUXD,
+                conn_data: null_mut(),
+            },
+            unsafe_bindings::idevice_info {
+                udid: null_mut(),
+                conn_type: unsafe_bindings::idevice_connection_type_CONNECTION_USBMUXD,
+                conn_data: null_mut(),
+            },
+        ];
+        let entries: Vec<idevice_info_t> = vec![&mut infos[0], null_mut(), &mut infos[1]];
+
+        assert_eq!(entry_udids(&entries), vec!["00008030-001A".to_string()]);
+        // Entries without a udid are never handed to libimobiledevice
+        assert!(device_from_entry(&entries[1]).is_none());
+        assert!(device_from_entry(&entries[2]).is_none());
+    }
+
+    #[test]
+    fn native_versions_look_like_semver() {
+        assert_eq!(
+            library_version().is_some(),
+            option_env!("LIBIMOBILEDEVICE_VERSION").is_some()
+        );
+        // pkg-config versions can carry a suffix such as `1.3.0-git`
+        for version in [library_version(), usbmuxd_version()].into_iter().flatten() {
+            let release = version.split('-').next().unwrap();
+            let parts: Vec<&str> = release.split('.').collect();
+            assert!(parts.len() >= 2, "{} is not a version", version);
+            assert!(
+                parts
+                    .iter()
+                    .all(|p| !p.is_empty() && p.chars().all(|c| c.is_ascii_digit())),
+                "{} is not a version",
+                version
+            );
+        }
+    }
+
+    /// Allocates a null terminated device list the way libimobiledevice does,
+    /// so `idevice_device_list_extended_free` can free it
+    fn malloc_list(udids: &[&str]) -> DeviceList {
+        unsafe {
+            let size = std::mem::size_of::<idevice_info_t>() * (udids.len() + 1);
+            let pointer = libc::malloc(size) as *mut idevice_info_t;
+            for (i, udid) in udids.iter().enumerate() {
+                let udid = std::ffi::CString::new(*udid).unwrap();
+                let entry = libc::malloc(std::mem::size_of::<unsafe_bindings::idevice_info>())
+                    as idevice_info_t;
+                entry.write(unsafe_bindings::idevice_info {
+                    udid: libc::strdup(udid.as_ptr()),
+                    conn_type: unsafe_bindings::idevice_connection_type_CONNECTION_USBMUXD,
+                    conn_data: null_mut(),
+                });
+                pointer.add(i).write(entry);
+            }
+            pointer.add(udids.len()).write(null_mut());
+            DeviceList {
+                pointer,
+                count: udids.len(),
+            }
+        }
+    }
+
+    #[test]
+    fn device_list_can_be_walked_twice() {
+        let list = malloc_list(&["00008030-001A", "00008101-002B"]);
+
+        let first: Vec<String> = list.iter().map(|d| d.get_udid()).collect();
+        let second: Vec<String> = list.iter().map(|d| d.get_udid()).collect();
+        assert_eq!(first, second);
+        // Walking the list doesn't use up its entries
+        assert_eq!(list.len(), 2);
+        assert_eq!(
+            entry_udids(list.entries()),
+            vec!["00008030-001A".to_string(), "00008101-002B".to_string()]
+        );
+        assert_eq!(entry_udids(list.entries()), entry_udids(list.entries()));
+    }
+
+    #[test]
+    #[cfg_attr(not(feature = "hardware"), ignore = "needs a connected device")]
+    fn connected_devices_are_listed_twice() {
+        let list = DeviceList::new().unwrap();
+        let first: Vec<String> = list.iter().map(|d| d.get_udid()).collect();
+        let second: Vec<String> = (&list).into_iter().map(|d| d.get_udid()).collect();
+        assert!(!first.is_empty());
+        assert_eq!(first, second);
+    }
+
+    #[test]
+    fn debug_levels_are_clamped() {
+        assert_eq!(native_debug_level(0), 0);
+        assert_eq!(native_debug_level(3), 3);
+        assert_eq!(native_debug_level(u32::MAX), c_int::MAX);
+    }
+
+    #[test]
+    fn debug_level_can_be_set_and_reset() {
+        set_debug_level(3);
+        set_debug_level(u32::MAX);
+        set_debug(true);
+        set_debug(false);
+        set_debug_level(0);
+    }
+
+    #[test]
+    #[cfg_attr(not(feature = "hardware"), ignore = "needs a connected device")]
+    fn lockdownd_strings_are_not_empty() {
+        let device = test_device();
+        assert!(!device.get_name().unwrap().is_empty());
+        assert!(!device.get_product_version().unwrap().is_empty());
+        assert!(!device.get_product_type().unwrap().is_empty());
+        assert_eq!(device.get_unique_device_id().unwrap(), device.get_udid());
+    }
+
+    /// The udid and connection of each device, sorted so lists can be compared
+    fn connections(devices: Vec<Device>) -> Vec<(String, bool)> {
+        let mut connections: Vec<(String, bool)> = devices
+            .iter()
+            .map(|d| (d.get_udid(), d.get_network()))
+            .collect();
+        connections.sort();
+        connections
+    }
+
+    #[test]
+    #[cfg_attr(not(feature = "hardware"), ignore = "needs a connected device")]
+    fn usb_and_network_devices_make_up_all_devices() {
+        let usb = get_usb_devices().unwrap();
+        let network = get_network_devices().unwrap();
+        assert!(usb
+            .iter()
+            .all(|d| d.connection_type() == ConnectionType::Usb));
+        assert!(network
+            .iter()
+            .all(|d| d.connection_type() == ConnectionType::Network));
+
+        let mut union = usb;
+        union.extend(network);
+        assert_eq!(connections(union), connections(get_devices().unwrap()));
+    }
+
+    #[test]
+    #[cfg_attr(not(feature = "hardware"), ignore = "needs a connected device")]
+    fn lookup_preference_picks_the_available_connection() {
+        let all = connections(get_devices().unwrap());
+        assert!(!all.is_empty());
+        for (udid, _) in &all {
+            let usb = all.contains(&(udid.clone(), false));
+            let network = all.contains(&(udid.clone(), true));
+            // What each preference should find, given which connections the device has
+            let expected = [
+                (LookupPreference::UsbOnly, usb.then_some(false)),
+                (LookupPreference::NetworkOnly, network.then_some(true)),
+                (
+                    LookupPreference::UsbThenNetwork,
+                    (usb || network).then_some(!usb),
+                ),
+                (
+                    LookupPreference::NetworkThenUsb,
+                    (usb || network).then_some(network),
+                ),
+            ];
+            for (prefer, network) in expected {
+                let device = get_device_with_options(udid, prefer);
+                match network {
+                    Some(network) => assert_eq!(device.unwrap().get_network(), network),
+                    None => assert_eq!(device.err(), Some(IdeviceError::NoDevice)),
+                }
+            }
+        }
+    }
+
+    #[test]
+    #[cfg_attr(
+        not(feature = "hardware"),
+        ignore = "needs a device reachable over the network"
+    )]
+    fn connects_by_ip_address() {
+        let discovered = get_network_devices()
+            .unwrap()
+            .into_iter()
+            .next()
+            .expect("No device reachable over the network");
+        let ip: IpAddr = discovered.get_ip_address().unwrap().parse().unwrap();
+
+        let device = connect_network(&discovered.get_udid(), ip).unwrap();
+        assert!(device.get_network());
+        assert_eq!(device.get_udid(), discovered.get_udid());
+        assert_eq!(
+            device.get_unique_device_id().unwrap(),
+            discovered.get_udid()
+        );
+
+        // Nothing answers lockdownd on this host, so the handshake fails quickly
+        assert!(connect_network(&discovered.get_udid(), "127.0.0.1".parse().unwrap()).is_err());
+    }
+}
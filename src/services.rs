@@ -1,5 +1,8 @@
 // jkcoxson
 
+use crate::bindings as unsafe_bindings;
+use log::{log_enabled, trace, Level};
+
 /// Transfers files between host and the iDevice
 pub mod afc;
 /// A proxy for interoping with devices paired with the iOS device
@@ -44,7 +47,26 @@ pub mod restored;
 pub mod screenshotr;
 /// Manages the device's OS base and homescreen.
 pub mod springboard_services;
+/// Relays the device's system log to the host
+pub mod syslog_relay;
 /// The iOS device's settings. Very fun to mess with.
 pub mod userpref;
 /// First used on MacOS, this service is used to inspect the JavaScript and HTML of a site running on the device
 pub mod web_inspector;
+
+/// Logs the XML form of a plist at trace level.
+/// The plist is only serialized when trace logging is enabled.
+pub(crate) fn trace_plist(context: &str, plist: unsafe_bindings::plist_t) {
+    if !log_enabled!(Level::Trace) || plist.is_null() {
+        return;
+    }
+    let mut xml = std::ptr::null_mut();
+    let mut length = 0;
+    unsafe { unsafe_bindings::plist_to_xml(plist, &mut xml, &mut length) };
+    if xml.is_null() {
+        return;
+    }
+    let bytes = unsafe { std::slice::from_raw_parts(xml as *const u8, length as usize) };
+    trace!("{}:\n{}", context, String::from_utf8_lossy(bytes));
+    unsafe { unsafe_bindings::plist_to_xml_free(xml) };
+}
@@ -1,47 +1,119 @@
 // jkcoxson
 
-use std::{convert::TryFrom, ffi::CStr, os::raw::c_char};
+use std::{
+    collections::HashMap,
+    convert::TryFrom,
+    ffi::{CStr, CString},
+    io::{self, Read, Write},
+    ops::Deref,
+    os::raw::c_char,
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
 
 use crate::{
     bindings as unsafe_bindings, error::AfcError, idevice::Device,
     services::house_arrest::HouseArrest, services::lockdownd::LockdowndService,
 };
 
+use log::warn;
+
 /// Transfers files between host and the iDevice
 pub struct AfcClient<'a> {
     pub(crate) pointer: unsafe_bindings::afc_client_t,
+    chunk_size: usize,
     phantom: std::marker::PhantomData<&'a Device>,
 }
 
+/// The default number of bytes moved per read or write by the whole-file helpers
+pub const DEFAULT_CHUNK_SIZE: usize = 64 * 1024;
+
+// libimobiledevice locks a mutex around every afc operation
+unsafe impl Send for AfcClient<'_> {}
+unsafe impl Sync for AfcClient<'_> {}
+
+/// An afc client that keeps its device alive, so it isn't bound to a borrow of the device.
+/// This allows the client to be moved into threads that outlive the current scope.
+/// Usable anywhere an `AfcClient` is through `Deref`.
+#[derive(Debug)]
+pub struct OwnedAfcClient {
+    // Dropped before the device
+    client: AfcClient<'static>,
+    _device: Arc<Device>,
+}
+
+impl OwnedAfcClient {
+    /// Creates a new afc client from a service started with lockdownd
+    /// # Arguments
+    /// * `device` - The device to create the service with, kept alive by the client
+    /// * `descriptor` - The lockdown service to connect on
+    /// # Returns
+    /// An afc service connection
+    ///
+    /// ***Verified:*** False
+    pub fn new(device: Arc<Device>, descriptor: &LockdowndService) -> Result<Self, AfcError> {
+        let client = AfcClient::new(&device, descriptor)?;
+        Ok(OwnedAfcClient {
+            client,
+            _device: device,
+        })
+    }
+
+    /// Starts an afc service connection to the device
+    /// # Arguments
+    /// * `device` - The device to create the service with, kept alive by the client
+    /// * `service_name` - The name of the service to start
+    /// # Returns
+    /// An afc service connection
+    ///
+    /// ***Verified:*** False
+    pub fn start_service(device: Arc<Device>, service_name: &str) -> Result<Self, AfcError> {
+        let client = AfcClient::start_service(&device, service_name)?;
+        Ok(OwnedAfcClient {
+            client,
+            _device: device,
+        })
+    }
+
+    /// Sets how many bytes the whole-file helpers move per read or write.
+    /// See `AfcClient::set_chunk_size`.
+    pub fn set_chunk_size(&mut self, chunk_size: usize) {
+        self.client.set_chunk_size(chunk_size);
+    }
+}
+
+// There is no DerefMut, so the client can't be swapped for one from another device
+impl Deref for OwnedAfcClient {
+    type Target = AfcClient<'static>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.client
+    }
+}
+
 impl AfcClient<'_> {
-    /// Creates a new afc service connection to the device
-    /// The use of this function is unknown
+    /// Creates a new afc client from a service started with lockdownd
     /// # Arguments
     /// * `device` - The device to create the service with
+    /// * `descriptor` - The lockdown service to connect on
     /// # Returns
-    /// The lockdownd service
+    /// An afc service connection
     ///
     /// ***Verified:*** False
-    pub fn new(device: &Device) -> Result<(Self, LockdowndService), String> {
-        let mut pointer = unsafe { std::mem::zeroed() };
-        let mut client_pointer = unsafe { std::mem::zeroed() };
+    pub fn new(device: &Device, descriptor: &LockdowndService) -> Result<Self, AfcError> {
+        let mut pointer = std::ptr::null_mut();
         let result = unsafe {
-            unsafe_bindings::afc_client_new(device.pointer, &mut pointer, &mut client_pointer)
-        };
-        if result != 0 {
-            return Err(format!("afc_client_new failed: {}", result));
+            unsafe_bindings::afc_client_new(device.pointer, descriptor.pointer, &mut pointer)
         }
-        Ok((
-            AfcClient {
-                pointer: client_pointer,
-                phantom: std::marker::PhantomData,
-            },
-            LockdowndService {
-                pointer: &mut pointer,
-                port: pointer.port as u32,
-                phantom: std::marker::PhantomData,
-            },
-        ))
+        .into();
+        if result != AfcError::Success {
+            return Err(result);
+        }
+        Ok(AfcClient {
+            pointer,
+            chunk_size: DEFAULT_CHUNK_SIZE,
+            phantom: std::marker::PhantomData,
+        })
     }
 
     /// Starts an afc service connection to the device
@@ -53,12 +125,13 @@ impl AfcClient<'_> {
     ///
     /// ***Verified:*** False
     pub fn start_service(device: &Device, service_name: &str) -> Result<Self, AfcError> {
+        let service_name = c_string(service_name)?;
         let mut pointer = unsafe { std::mem::zeroed() };
         let result = unsafe {
             unsafe_bindings::afc_client_start_service(
                 device.pointer,
                 &mut pointer,
-                service_name.as_ptr() as *const c_char,
+                service_name.as_ptr(),
             )
         }
         .into();
@@ -67,73 +140,288 @@ impl AfcClient<'_> {
         }
         Ok(AfcClient {
             pointer,
+            chunk_size: DEFAULT_CHUNK_SIZE,
             phantom: std::marker::PhantomData,
         })
     }
 
+    /// Sets how many bytes the whole-file helpers move per read or write.
+    /// Larger chunks are faster on fast connections, at the cost of memory per request.
+    /// # Arguments
+    /// * `chunk_size` - The number of bytes per request. Zero resets it to `DEFAULT_CHUNK_SIZE`
+    /// # Returns
+    /// *none*
+    ///
+    /// ***Verified:*** False
+    pub fn set_chunk_size(&mut self, chunk_size: usize) {
+        self.chunk_size = match chunk_size {
+            0 => DEFAULT_CHUNK_SIZE,
+            // A single afc request carries its length as a u32
+            c => c.min(u32::MAX as usize),
+        };
+    }
+
+    /// Gets how many bytes the whole-file helpers move per read or write
+    /// # Arguments
+    /// *none*
+    /// # Returns
+    /// The chunk size in bytes
+    ///
+    /// ***Verified:*** False
+    pub fn chunk_size(&self) -> usize {
+        self.chunk_size
+    }
+
+    /// Reads a whole file from the device, one chunk at a time
+    /// # Arguments
+    /// * `path` - The path to the file
+    /// # Returns
+    /// The contents of the file
+    ///
+    /// ***Verified:*** False
+    pub fn read_file(&self, path: &str) -> Result<Vec<u8>, AfcError> {
+        self.read_with_progress(path, |_, _| {})
+    }
+
+    /// Reads a whole file from the device, reporting progress after each chunk
+    /// # Arguments
+    /// * `path` - The path to the file
+    /// * `progress` - Called with the bytes read so far and the size of the file
+    /// # Returns
+    /// The contents of the file
+    ///
+    /// ***Verified:*** False
+    pub fn read_with_progress<F: FnMut(u64, u64)>(
+        &self,
+        path: &str,
+        progress: F,
+    ) -> Result<Vec<u8>, AfcError> {
+        let total = self.metadata(path)?.len();
+        let handle = self.file_open(path.to_string(), AfcFileMode::ReadOnly)?;
+        let result = read_chunks(
+            self.chunk_size,
+            total,
+            |length| self.file_read(handle, length),
+            progress,
+        );
+        self.file_close(handle)?;
+        result
+    }
+
+    /// Writes a whole file to the device, one chunk at a time.
+    /// The file is created if it doesn't exist, and replaced if it does.
+    /// # Arguments
+    /// * `path` - The path to the file
+    /// * `data` - The contents to write
+    /// # Returns
+    /// *none*
+    ///
+    /// ***Verified:*** False
+    pub fn write_file(&self, path: &str, data: &[u8]) -> Result<(), AfcError> {
+        self.write_with_progress(path, data, |_, _| {})
+    }
+
+    /// Writes a whole file to the device, reporting progress after each chunk.
+    /// The file is created if it doesn't exist, and replaced if it does.
+    /// # Arguments
+    /// * `path` - The path to the file
+    /// * `data` - The contents to write
+    /// * `progress` - Called with the bytes written so far and the length of `data`
+    /// # Returns
+    /// *none*
+    ///
+    /// ***Verified:*** False
+    pub fn write_with_progress<F: FnMut(u64, u64)>(
+        &self,
+        path: &str,
+        data: &[u8],
+        progress: F,
+    ) -> Result<(), AfcError> {
+        let handle = self.file_open(path.to_string(), AfcFileMode::WriteOnly)?;
+        let result = write_chunks(
+            self.chunk_size,
+            data,
+            |chunk| self.file_write(handle, chunk),
+            progress,
+        );
+        self.file_close(handle)?;
+        result
+    }
+
+    /// Copies a file to another path on the device.
+    /// AFC has no copy command, so the file is streamed through the connection one chunk
+    /// at a time. Only a single chunk is held on the host at once.
+    /// # Arguments
+    /// * `src` - The path of the file to copy
+    /// * `dst` - The path to copy to. It is created if it doesn't exist, and replaced if it does.
+    /// # Returns
+    /// *none*
+    ///
+    /// ***Verified:*** False
+    pub fn copy(&self, src: &str, dst: &str) -> Result<(), AfcError> {
+        let src_handle = self.file_open(src.to_string(), AfcFileMode::ReadOnly)?;
+        let dst_handle = match self.file_open(dst.to_string(), AfcFileMode::WriteOnly) {
+            Ok(handle) => handle,
+            Err(e) => {
+                self.file_close(src_handle)?;
+                return Err(e);
+            }
+        };
+        let result = copy_chunks(
+            self.chunk_size,
+            |length| self.file_read(src_handle, length),
+            |chunk| self.file_write(dst_handle, chunk),
+        );
+        let src_closed = self.file_close(src_handle);
+        self.file_close(dst_handle)?;
+        src_closed?;
+        result
+    }
+
     /// Get information about the device
     /// # Arguments
     /// *none*
     /// # Returns
-    /// A string containing the device information
+    /// A string containing the device information, one `key: value` pair per line
     ///
     /// ***Verified:*** False
     pub fn get_device_info(&self) -> Result<String, AfcError> {
-        let mut info = unsafe { std::mem::zeroed() };
-        let mut info_ptr: *mut *mut c_char = &mut info;
+        Ok(self
+            .get_device_info_map()?
+            .iter()
+            .map(|(key, value)| format!("{}: {}", key, value))
+            .collect::<Vec<String>>()
+            .join("\n"))
+    }
+
+    /// Get information about the device as a map
+    /// # Arguments
+    /// *none*
+    /// # Returns
+    /// A map of device information keys such as `Model` and `FSTotalBytes` to their values
+    ///
+    /// ***Verified:*** False
+    pub fn get_device_info_map(&self) -> Result<HashMap<String, String>, AfcError> {
+        let mut info = std::ptr::null_mut();
         let result =
-            unsafe { unsafe_bindings::afc_get_device_info(self.pointer, &mut info_ptr) }.into();
+            unsafe { unsafe_bindings::afc_get_device_info(self.pointer, &mut info) }.into();
         if result != AfcError::Success {
             return Err(result);
         }
-        Ok(unsafe { CStr::from_ptr(info) }
-            .to_string_lossy()
-            .into_owned())
+        Ok(key_value_map(string_list(info)))
+    }
+
+    /// Get the capacity and usage of the device's storage
+    /// # Arguments
+    /// *none*
+    /// # Returns
+    /// The storage totals reported by the device, along with the computed usage
+    ///
+    /// ***Verified:*** False
+    pub fn get_storage_info(&self) -> Result<AfcStorageInfo, AfcError> {
+        let info = self.get_device_info_map()?;
+        let value = |key: &str| -> u64 {
+            info.get(key)
+                .and_then(|value| value.parse().ok())
+                .unwrap_or(0)
+        };
+        Ok(AfcStorageInfo::new(
+            value("FSTotalBytes"),
+            value("FSFreeBytes"),
+            value("FSBlockSize"),
+        ))
     }
 
     /// Read a directory on the device
     /// # Arguments
     /// * `directory` - The directory to read
     /// # Returns
-    /// A vector of strings containing the directory contents
+    /// A vector of the names of the entries in the directory, without `.` and `..`
+    ///
+    /// ***Verified:*** False
+    pub fn read_directory(&self, directory: String) -> Result<Vec<String>, AfcError> {
+        Ok(without_dot_entries(self.read_directory_all(directory)?))
+    }
+
+    /// Read a directory on the device, exactly as the device lists it
+    /// # Arguments
+    /// * `directory` - The directory to read
+    /// # Returns
+    /// A vector of the names of the entries in the directory, including `.` and `..`
     ///
     /// ***Verified:*** False
-    pub fn read_directory(&self, directory: String) -> Result<String, AfcError> {
-        let directory_ptr: *const c_char = directory.as_ptr() as *const c_char;
-        let mut entries = unsafe { std::mem::zeroed() };
-        let mut entries_ptr: *mut *mut c_char = &mut entries;
+    pub fn read_directory_all(&self, directory: String) -> Result<Vec<String>, AfcError> {
+        let directory = c_string(&directory)?;
+        let mut entries = std::ptr::null_mut();
         let result = unsafe {
-            unsafe_bindings::afc_read_directory(self.pointer, directory_ptr, &mut entries_ptr)
+            unsafe_bindings::afc_read_directory(self.pointer, directory.as_ptr(), &mut entries)
         }
         .into();
         if result != AfcError::Success {
             return Err(result);
         }
-        Ok(unsafe { CStr::from_ptr(entries) }
-            .to_string_lossy()
-            .into_owned())
+        Ok(string_list(entries))
     }
 
     /// Get information about a file on the device
     /// # Arguments
     /// * `path` - The path to the file
     /// # Returns
-    /// A string containing the file information
+    /// A string containing the file information, one `key: value` pair per line
     ///
     /// ***Verified:*** False
     pub fn get_file_info(&self, path: String) -> Result<String, AfcError> {
-        let path_ptr: *const c_char = path.as_ptr() as *const c_char;
-        let mut info = unsafe { std::mem::zeroed() };
-        let mut info_ptr: *mut *mut c_char = &mut info;
+        Ok(self
+            .file_info_list(&path)?
+            .chunks(2)
+            .filter(|pair| pair.len() == 2)
+            .map(|pair| format!("{}: {}", pair[0], pair[1]))
+            .collect::<Vec<String>>()
+            .join("\n"))
+    }
+
+    /// Get information about a file on the device, similar to `std::fs::metadata`
+    /// # Arguments
+    /// * `path` - The path to the file. Symbolic links are not followed.
+    /// # Returns
+    /// The metadata of the file
+    ///
+    /// ***Verified:*** False
+    pub fn metadata(&self, path: &str) -> Result<AfcMetadata, AfcError> {
+        Ok(AfcMetadata {
+            values: key_value_map(self.file_info_list(path)?),
+        })
+    }
+
+    /// Reads the target of a symbolic link on the device
+    /// # Arguments
+    /// * `path` - The path to the link
+    /// # Returns
+    /// The path the link points to. Fails with `InvalidArg` if `path` is not a symbolic link.
+    ///
+    /// ***Verified:*** False
+    pub fn read_link(&self, path: &str) -> Result<String, AfcError> {
+        let metadata = self.metadata(path)?;
+        if !metadata.is_symlink() {
+            return Err(AfcError::InvalidArg);
+        }
+        metadata
+            .get("LinkTarget")
+            .map(|target| target.to_string())
+            .ok_or(AfcError::InvalidArg)
+    }
+
+    /// Fetches the raw key, value list describing a file
+    fn file_info_list(&self, path: &str) -> Result<Vec<String>, AfcError> {
+        let path = c_string(path)?;
+        let mut info = std::ptr::null_mut();
         let result =
-            unsafe { unsafe_bindings::afc_get_file_info(self.pointer, path_ptr, &mut info_ptr) }
+            unsafe { unsafe_bindings::afc_get_file_info(self.pointer, path.as_ptr(), &mut info) }
                 .into();
         if result != AfcError::Success {
             return Err(result);
         }
-        Ok(unsafe { CStr::from_ptr(info) }
-            .to_string_lossy()
-            .into_owned())
+        Ok(string_list(info))
     }
 
     /// Open a file on the device and return a handle to it
@@ -145,10 +433,10 @@ impl AfcClient<'_> {
     ///
     /// ***Verified:*** False
     pub fn file_open(&self, path: String, mode: AfcFileMode) -> Result<u64, AfcError> {
-        let file_name_ptr: *const c_char = path.as_ptr() as *const c_char;
+        let path = c_string(&path)?;
         let mut handle = unsafe { std::mem::zeroed() };
         let result = unsafe {
-            unsafe_bindings::afc_file_open(self.pointer, file_name_ptr, mode.into(), &mut handle)
+            unsafe_bindings::afc_file_open(self.pointer, path.as_ptr(), mode.into(), &mut handle)
         }
         .into();
         if result != AfcError::Success {
@@ -157,6 +445,68 @@ impl AfcClient<'_> {
         Ok(handle)
     }
 
+    /// Opens a file on the device, returning a handle that closes the file when dropped
+    /// # Arguments
+    /// * `path` - The path to the file
+    /// * `mode` - The mode to open the file in
+    /// # Returns
+    /// The open file
+    ///
+    /// ***Verified:*** False
+    pub fn open<'b>(&'b self, path: &str, mode: AfcFileMode) -> Result<AfcFile<'b>, AfcError> {
+        Ok(AfcFile {
+            client: self,
+            handle: self.file_open(path.to_string(), mode)?,
+            closed: false,
+        })
+    }
+
+    /// Opens several files on the device.
+    /// If any fails to open, the files already opened are closed before the error is returned.
+    /// # Arguments
+    /// * `paths` - The paths to open, each with the mode to open it in
+    /// # Returns
+    /// The open files, in the same order as `paths`
+    ///
+    /// ***Verified:*** False
+    pub fn open_many<'b>(
+        &'b self,
+        paths: &[(&str, AfcFileMode)],
+    ) -> Result<Vec<AfcFile<'b>>, AfcError> {
+        open_each(paths, |path, mode| self.open(path, mode))
+    }
+
+    /// Creates a file on the device and opens it for writing, failing if it already exists.
+    /// AFC has no exclusive create flag, so this checks for the file before opening it.
+    /// Another client could still create the file in between.
+    /// # Arguments
+    /// * `path` - The path to the file
+    /// # Returns
+    /// The file handle, or `ObjectExists` if something is already at `path`
+    ///
+    /// ***Verified:*** False
+    pub fn create_new(&self, path: &str) -> Result<u64, AfcError> {
+        open_new(
+            || self.exists(path),
+            || self.file_open(path.to_string(), AfcFileMode::WriteOnly),
+        )
+    }
+
+    /// Checks whether a file or directory exists on the device
+    /// # Arguments
+    /// * `path` - The path to check
+    /// # Returns
+    /// Whether anything exists at the path
+    ///
+    /// ***Verified:*** False
+    pub fn exists(&self, path: &str) -> Result<bool, AfcError> {
+        match self.file_info_list(path) {
+            Ok(_) => Ok(true),
+            Err(AfcError::ObjectNotFound) => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
     /// Closes a file on the device
     /// # Arguments
     /// * `handle` - The handle to the file
@@ -190,40 +540,35 @@ impl AfcClient<'_> {
         Ok(())
     }
 
-    /// Reads out a file from the device
+    /// Reads out a file from the device.
+    /// A single afc request is capped at the protocol's packet size, so this keeps reading
+    /// until `length` bytes have been read or the end of the file is reached.
     /// # Arguments
     /// * `handle` - The handle to the file
     /// * `length` - The length of the data to read
     /// # Returns
-    /// A vector of bytes containing the data read
+    /// A vector of bytes containing the data read.
+    /// If it is shorter than `length`, the end of the file was reached, and an empty vector means nothing was left to read.
     ///
     /// ***Verified:*** False
-    pub fn file_read(&self, handle: u64, length: u32) -> Result<Vec<i8>, AfcError> {
-        let mut buffer = unsafe { std::mem::zeroed() };
-        let mut bytes_written = unsafe { std::mem::zeroed() };
-        let result = unsafe {
-            unsafe_bindings::afc_file_read(
-                self.pointer,
-                handle,
-                &mut buffer,
-                length,
-                &mut bytes_written,
-            )
-        }
-        .into();
-        if result != AfcError::Success {
-            return Err(result);
-        }
-
-        let vec = unsafe {
-            Vec::from_raw_parts(
-                buffer as *mut i8,
-                bytes_written as usize,
-                bytes_written as usize,
-            )
-        };
-
-        Ok(vec)
+    pub fn file_read(&self, handle: u64, length: u32) -> Result<Vec<u8>, AfcError> {
+        read_until_full(length, |buffer| {
+            let mut bytes_read = 0;
+            let result = unsafe {
+                unsafe_bindings::afc_file_read(
+                    self.pointer,
+                    handle,
+                    buffer.as_mut_ptr() as *mut c_char,
+                    buffer.len() as u32,
+                    &mut bytes_read,
+                )
+            }
+            .into();
+            if result != AfcError::Success {
+                return Err(result);
+            }
+            Ok(bytes_read)
+        })
     }
 
     /// Writes data to a file on the device
@@ -234,14 +579,13 @@ impl AfcClient<'_> {
     /// *none*
     ///
     /// ***Verified:*** False
-    pub fn file_write(&self, handle: u64, data: String) -> Result<(), AfcError> {
-        let data_ptr: *const c_char = data.as_ptr() as *const c_char;
+    pub fn file_write(&self, handle: u64, data: &[u8]) -> Result<(), AfcError> {
         let mut bytes_written = unsafe { std::mem::zeroed() };
         let result = unsafe {
             unsafe_bindings::afc_file_write(
                 self.pointer,
                 handle,
-                data_ptr,
+                data.as_ptr() as *const c_char,
                 data.len() as u32,
                 &mut bytes_written,
             )
@@ -289,10 +633,13 @@ impl AfcClient<'_> {
         Ok(position)
     }
 
-    /// Truncates a file on the iOS device
+    /// Truncates or extends an open file to a length, by its handle.
+    /// Use `truncate` to resize a file by its path without opening it.
+    /// The file must be opened in a writable mode, and extending it pads the end with zeros.
+    /// The new length isn't verified, check it with `metadata` if needed.
     /// # Arguments
-    /// * `handle` - The handle to the file
-    /// * `length` - The length of which to truncate the file to
+    /// * `handle` - The handle to the open file
+    /// * `length` - The new length of the file in bytes
     /// # Returns
     /// *none*
     ///
@@ -306,22 +653,41 @@ impl AfcClient<'_> {
         Ok(())
     }
 
-    /// Removes a path on the iOS device
+    /// Removes a file or empty directory on the iOS device
     /// # Arguments
-    /// * `path` - The path to the folder that's being removed
+    /// * `path` - The path to the file or folder that's being removed
     /// # Returns
     /// *none*
     ///
+    /// The common errors are `ObjectNotFound` if the path doesn't exist,
+    /// `PermDenied` if the path is outside of what the service can modify,
+    /// and `DirNotEmpty` if the path is a directory that still has contents.
+    ///
     /// ***Verified:*** False
     pub fn remove_path(&self, path: String) -> Result<(), AfcError> {
-        let path_ptr: *const c_char = path.as_ptr() as *const c_char;
-        let result = unsafe { unsafe_bindings::afc_remove_path(self.pointer, path_ptr) }.into();
+        let path = c_string(&path)?;
+        let result =
+            unsafe { unsafe_bindings::afc_remove_path(self.pointer, path.as_ptr()) }.into();
         if result != AfcError::Success {
             return Err(result);
         }
         Ok(())
     }
 
+    /// Removes a file or empty directory, treating a path that doesn't exist as already removed
+    /// # Arguments
+    /// * `path` - The path to the file or folder that's being removed
+    /// # Returns
+    /// *none*
+    ///
+    /// ***Verified:*** False
+    pub fn remove_if_exists(&self, path: &str) -> Result<(), AfcError> {
+        match self.remove_path(path.to_string()) {
+            Ok(()) | Err(AfcError::ObjectNotFound) => Ok(()),
+            Err(e) => Err(e),
+        }
+    }
+
     /// Renames or moves a folder on the iOS device
     /// # Arguments
     /// * `old_path` - The path to the folder to rename
@@ -331,11 +697,12 @@ impl AfcClient<'_> {
     ///
     /// ***Verified:*** False
     pub fn rename_path(&self, old_path: String, new_path: String) -> Result<(), AfcError> {
-        let old_path_ptr: *const c_char = old_path.as_ptr() as *const c_char;
-        let new_path_ptr: *const c_char = new_path.as_ptr() as *const c_char;
-        let result =
-            unsafe { unsafe_bindings::afc_rename_path(self.pointer, old_path_ptr, new_path_ptr) }
-                .into();
+        let old_path = c_string(&old_path)?;
+        let new_path = c_string(&new_path)?;
+        let result = unsafe {
+            unsafe_bindings::afc_rename_path(self.pointer, old_path.as_ptr(), new_path.as_ptr())
+        }
+        .into();
         if result != AfcError::Success {
             return Err(result);
         }
@@ -350,26 +717,57 @@ impl AfcClient<'_> {
     ///
     /// ***Verified:*** False
     pub fn make_directory(&self, path: String) -> Result<(), AfcError> {
-        let path_ptr: *const c_char = path.as_ptr() as *const c_char;
-        let result = unsafe { unsafe_bindings::afc_make_directory(self.pointer, path_ptr) }.into();
+        let path = c_string(&path)?;
+        let result =
+            unsafe { unsafe_bindings::afc_make_directory(self.pointer, path.as_ptr()) }.into();
         if result != AfcError::Success {
             return Err(result);
         }
         Ok(())
     }
 
-    /// Usage unknown
+    /// Creates a directory and any missing parent directories, like `mkdir -p`.
+    /// Directories that already exist are not an error, unlike `make_directory`.
     /// # Arguments
-    /// * `handle` - The handle to the file
-    /// * `length` - Unknown
+    /// * `path` - The path to create
+    /// # Returns
+    /// *none*
+    ///
+    /// ***Verified:*** False
+    pub fn make_directory_all(&self, path: &str) -> Result<(), AfcError> {
+        let mut current = if path.starts_with('/') {
+            "/".to_string()
+        } else {
+            String::new()
+        };
+        for component in path.split('/').filter(|c| !c.is_empty()) {
+            if !current.is_empty() && !current.ends_with('/') {
+                current.push('/');
+            }
+            current.push_str(component);
+            match self.make_directory(current.clone()) {
+                Ok(()) | Err(AfcError::ObjectExists) => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(())
+    }
+
+    /// Truncates or extends a file to a length, by its path.
+    /// Use `file_truncate` to resize a file that is already open.
+    /// Extending the file pads the end with zeros.
+    /// The new length isn't verified, check it with `metadata` if needed.
+    /// # Arguments
+    /// * `path` - The path to the file
+    /// * `length` - The new length of the file in bytes
     /// # Returns
     /// *none*
     ///
     /// ***Verified:*** False
     pub fn truncate(&self, path: String, length: u64) -> Result<(), AfcError> {
-        let path_ptr: *const c_char = path.as_ptr() as *const c_char;
+        let path = c_string(&path)?;
         let result =
-            unsafe { unsafe_bindings::afc_truncate(self.pointer, path_ptr, length) }.into();
+            unsafe { unsafe_bindings::afc_truncate(self.pointer, path.as_ptr(), length) }.into();
         if result != AfcError::Success {
             return Err(result);
         }
@@ -391,14 +789,14 @@ impl AfcClient<'_> {
         link_type: LinkType,
         link_path: String,
     ) -> Result<(), AfcError> {
-        let target_ptr: *const c_char = target.as_ptr() as *const c_char;
-        let link_name_ptr: *const c_char = link_path.as_ptr() as *const c_char;
+        let target = c_string(&target)?;
+        let link_path = c_string(&link_path)?;
         let result = unsafe {
             unsafe_bindings::afc_make_link(
                 self.pointer,
                 link_type.into(),
-                target_ptr,
-                link_name_ptr,
+                target.as_ptr(),
+                link_path.as_ptr(),
             )
         }
         .into();
@@ -408,24 +806,38 @@ impl AfcClient<'_> {
         Ok(())
     }
 
-    /// Sets the time metadata of a file
+    /// Sets the modification time of a file
     /// # Arguments
     /// * `path` - The path to the file
-    /// * `mtime` - The unix epoch time in miliseconds
+    /// * `mtime` - The modification time in nanoseconds since the unix epoch
     /// # Returns
     /// *none*
     ///
     /// ***Verified:*** False
     pub fn set_file_time(&self, path: String, mtime: u64) -> Result<(), AfcError> {
-        let path_ptr: *const c_char = path.as_ptr() as *const c_char;
+        let path = c_string(&path)?;
         let result =
-            unsafe { unsafe_bindings::afc_set_file_time(self.pointer, path_ptr, mtime) }.into();
+            unsafe { unsafe_bindings::afc_set_file_time(self.pointer, path.as_ptr(), mtime) }
+                .into();
         if result != AfcError::Success {
             return Err(result);
         }
         Ok(())
     }
 
+    /// Sets the modification time of a file from a `SystemTime`.
+    /// The time is converted to the nanoseconds since the unix epoch that AFC expects.
+    /// # Arguments
+    /// * `path` - The path to the file
+    /// * `time` - The modification time. Times before the unix epoch are rejected with `InvalidArg`
+    /// # Returns
+    /// *none*
+    ///
+    /// ***Verified:*** False
+    pub fn set_file_time_system(&self, path: &str, time: SystemTime) -> Result<(), AfcError> {
+        self.set_file_time(path.to_string(), afc_time(time)?)
+    }
+
     /// Removes a path and the files inside it
     /// # Arguments
     /// * `path` - The path to the folder being destroyed
@@ -434,9 +846,10 @@ impl AfcClient<'_> {
     ///
     /// ***Verified:*** False
     pub fn remove_path_and_contents(&self, path: String) -> Result<(), AfcError> {
-        let path_ptr: *const c_char = path.as_ptr() as *const c_char;
+        let path = c_string(&path)?;
         let result =
-            unsafe { unsafe_bindings::afc_remove_path_and_contents(self.pointer, path_ptr) }.into();
+            unsafe { unsafe_bindings::afc_remove_path_and_contents(self.pointer, path.as_ptr()) }
+                .into();
         if result != AfcError::Success {
             return Err(result);
         }
@@ -451,19 +864,193 @@ impl AfcClient<'_> {
     ///
     /// ***Verified:*** False
     pub fn get_device_info_key(&self, key: String) -> Result<String, AfcError> {
-        let key_ptr: *const c_char = key.as_ptr() as *const c_char;
-        let mut value_ptr = unsafe { std::mem::zeroed() };
+        let key = c_string(&key)?;
+        let mut value_ptr: *mut c_char = std::ptr::null_mut();
         let result = unsafe {
-            unsafe_bindings::afc_get_device_info_key(self.pointer, key_ptr, &mut value_ptr)
+            unsafe_bindings::afc_get_device_info_key(self.pointer, key.as_ptr(), &mut value_ptr)
         }
         .into();
         if result != AfcError::Success {
             return Err(result);
         }
-        Ok(unsafe { CStr::from_ptr(value_ptr) }
+        if value_ptr.is_null() {
+            return Err(AfcError::ObjectNotFound);
+        }
+        let value = unsafe { CStr::from_ptr(value_ptr) }
             .to_string_lossy()
-            .into_owned())
+            .into_owned();
+        unsafe { libc::free(value_ptr as *mut libc::c_void) };
+        Ok(value)
+    }
+}
+
+/// Calls `open` only if `exists` finds nothing in the way
+fn open_new<E, O>(exists: E, open: O) -> Result<u64, AfcError>
+where
+    E: FnOnce() -> Result<bool, AfcError>,
+    O: FnOnce() -> Result<u64, AfcError>,
+{
+    if exists()? {
+        return Err(AfcError::ObjectExists);
     }
+    open()
+}
+
+/// Calls `read` until `length` bytes have been read or it reads nothing, which is the end of the file
+fn read_until_full<R>(length: u32, mut read: R) -> Result<Vec<u8>, AfcError>
+where
+    R: FnMut(&mut [u8]) -> Result<u32, AfcError>,
+{
+    let mut buffer = vec![0u8; length as usize];
+    let mut total = 0;
+    while total < buffer.len() {
+        let bytes_read = read(&mut buffer[total..])?;
+        if bytes_read == 0 {
+            break;
+        }
+        total += bytes_read as usize;
+    }
+
+    buffer.truncate(total);
+    Ok(buffer)
+}
+
+/// Reads `chunk_size` bytes at a time until a short read marks the end of the file
+fn read_chunks<R, F>(
+    chunk_size: usize,
+    total: u64,
+    mut read: R,
+    mut progress: F,
+) -> Result<Vec<u8>, AfcError>
+where
+    R: FnMut(u32) -> Result<Vec<u8>, AfcError>,
+    F: FnMut(u64, u64),
+{
+    let mut data = Vec::with_capacity(total as usize);
+    loop {
+        let chunk = read(chunk_size as u32)?;
+        data.extend_from_slice(&chunk);
+        // The file may have grown since its size was read
+        progress(data.len() as u64, total.max(data.len() as u64));
+        // A short read means the end of the file was reached
+        if chunk.len() < chunk_size {
+            return Ok(data);
+        }
+    }
+}
+
+/// Writes `data` in chunks of at most `chunk_size` bytes
+fn write_chunks<W, F>(
+    chunk_size: usize,
+    data: &[u8],
+    mut write: W,
+    mut progress: F,
+) -> Result<(), AfcError>
+where
+    W: FnMut(&[u8]) -> Result<(), AfcError>,
+    F: FnMut(u64, u64),
+{
+    let total = data.len() as u64;
+    // An empty file has no chunks, but still reports that it finished
+    if data.is_empty() {
+        progress(0, 0);
+        return Ok(());
+    }
+    let mut done = 0;
+    data.chunks(chunk_size).try_for_each(|chunk| {
+        write(chunk)?;
+        done += chunk.len() as u64;
+        progress(done, total);
+        Ok(())
+    })
+}
+
+/// Passes each chunk from `read` straight to `write` until a short read marks the end of the file
+fn copy_chunks<R, W>(chunk_size: usize, mut read: R, mut write: W) -> Result<(), AfcError>
+where
+    R: FnMut(u32) -> Result<Vec<u8>, AfcError>,
+    W: FnMut(&[u8]) -> Result<(), AfcError>,
+{
+    loop {
+        let chunk = read(chunk_size as u32)?;
+        write(&chunk)?;
+        if chunk.len() < chunk_size {
+            return Ok(());
+        }
+    }
+}
+
+/// Converts a time to the nanoseconds since the unix epoch that AFC uses
+fn afc_time(time: SystemTime) -> Result<u64, AfcError> {
+    let nanos = match time.duration_since(UNIX_EPOCH) {
+        Ok(duration) => duration.as_nanos(),
+        Err(_) => return Err(AfcError::InvalidArg),
+    };
+    if nanos > u64::MAX as u128 {
+        return Err(AfcError::InvalidArg);
+    }
+    Ok(nanos as u64)
+}
+
+fn c_string(value: &str) -> Result<CString, AfcError> {
+    match CString::new(value) {
+        Ok(s) => Ok(s),
+        Err(_) => Err(AfcError::InvalidArg),
+    }
+}
+
+/// Copies a NULL terminated list of strings returned by libimobiledevice and frees it
+fn string_list(list: *mut *mut c_char) -> Vec<String> {
+    let mut strings = Vec::new();
+    if list.is_null() {
+        return strings;
+    }
+    let mut index = 0;
+    loop {
+        let item = unsafe { *list.add(index) };
+        if item.is_null() {
+            break;
+        }
+        strings.push(
+            unsafe { CStr::from_ptr(item) }
+                .to_string_lossy()
+                .into_owned(),
+        );
+        index += 1;
+    }
+    unsafe {
+        unsafe_bindings::afc_dictionary_free(list);
+    }
+    strings
+}
+
+/// Pairs up a flat key, value, key, value list into a map
+/// Opens each path in order, stopping at the first failure.
+/// The files opened before the failure are dropped, which closes them.
+fn open_each<T>(
+    paths: &[(&str, AfcFileMode)],
+    mut open: impl FnMut(&str, AfcFileMode) -> Result<T, AfcError>,
+) -> Result<Vec<T>, AfcError> {
+    let mut files = Vec::with_capacity(paths.len());
+    for (path, mode) in paths {
+        files.push(open(path, *mode)?);
+    }
+    Ok(files)
+}
+
+/// Drops the `.` and `..` entries from a directory listing
+fn without_dot_entries(mut entries: Vec<String>) -> Vec<String> {
+    entries.retain(|entry| entry != "." && entry != "..");
+    entries
+}
+
+fn key_value_map(list: Vec<String>) -> HashMap<String, String> {
+    let mut map = HashMap::new();
+    let mut list = list.into_iter();
+    while let (Some(key), Some(value)) = (list.next(), list.next()) {
+        map.insert(key, value);
+    }
+    map
 }
 
 impl TryFrom<HouseArrest<'_>> for AfcClient<'_> {
@@ -483,17 +1070,182 @@ impl TryFrom<HouseArrest<'_>> for AfcClient<'_> {
         }
         Ok(Self {
             pointer: to_fill,
+            chunk_size: DEFAULT_CHUNK_SIZE,
             phantom: std::marker::PhantomData,
         })
     }
 }
 
+/// A file open on the device, closed when dropped
+#[derive(Debug)]
+pub struct AfcFile<'a> {
+    client: &'a AfcClient<'a>,
+    handle: u64,
+    closed: bool,
+}
+
+impl AfcFile<'_> {
+    /// The raw handle, for use with the `file_*` methods of `AfcClient`
+    pub fn handle(&self) -> u64 {
+        self.handle
+    }
+
+    /// Reads up to `length` bytes, returning fewer only at the end of the file.
+    /// The file also implements `std::io::Read`.
+    pub fn read_chunk(&self, length: u32) -> Result<Vec<u8>, AfcError> {
+        self.client.file_read(self.handle, length)
+    }
+
+    /// Writes data at the current position in the file.
+    /// The file also implements `std::io::Write`.
+    pub fn write_chunk(&self, data: &[u8]) -> Result<(), AfcError> {
+        self.client.file_write(self.handle, data)
+    }
+
+    /// Closes the file, returning any error instead of ignoring it like dropping does
+    pub fn close(mut self) -> Result<(), AfcError> {
+        self.closed = true;
+        self.client.file_close(self.handle)
+    }
+}
+
+impl Read for AfcFile<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let length = buf.len().min(self.client.chunk_size());
+        let chunk = self.client.file_read(self.handle, length as u32)?;
+        buf[..chunk.len()].copy_from_slice(&chunk);
+        Ok(chunk.len())
+    }
+}
+
+impl Write for AfcFile<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        // afc writes the whole request or fails
+        let length = buf.len().min(self.client.chunk_size());
+        self.client.file_write(self.handle, &buf[..length])?;
+        Ok(length)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Drop for AfcFile<'_> {
+    fn drop(&mut self) {
+        if self.closed {
+            return;
+        }
+        if let Err(e) = self.client.file_close(self.handle) {
+            warn!("Failed to close AFC file handle {}: {:?}", self.handle, e);
+        }
+    }
+}
+
+/// Metadata about a file on the device, mimicking `std::fs::Metadata`
+#[derive(Debug, Clone)]
+pub struct AfcMetadata {
+    values: HashMap<String, String>,
+}
+
+impl AfcMetadata {
+    /// Whether the path is a regular file
+    pub fn is_file(&self) -> bool {
+        self.file_type() == Some("S_IFREG")
+    }
+
+    /// Whether the path is a directory
+    pub fn is_dir(&self) -> bool {
+        self.file_type() == Some("S_IFDIR")
+    }
+
+    /// Whether the path is a symbolic link
+    pub fn is_symlink(&self) -> bool {
+        self.file_type() == Some("S_IFLNK")
+    }
+
+    /// The size of the file in bytes
+    pub fn len(&self) -> u64 {
+        self.get("st_size")
+            .and_then(|size| size.parse().ok())
+            .unwrap_or(0)
+    }
+
+    /// Whether the file has a size of zero bytes
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The last modification time, if the device reported it
+    pub fn modified(&self) -> Option<SystemTime> {
+        self.time("st_mtime")
+    }
+
+    /// The creation time, if the device reported it
+    pub fn created(&self) -> Option<SystemTime> {
+        self.time("st_birthtime")
+    }
+
+    /// Gets a raw value reported by the device, such as `st_nlink` or `LinkTarget`
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.values.get(key).map(|v| v.as_str())
+    }
+
+    fn file_type(&self) -> Option<&str> {
+        self.get("st_ifmt")
+    }
+
+    // AFC reports times in nanoseconds since the unix epoch
+    fn time(&self, key: &str) -> Option<SystemTime> {
+        let nanos: u64 = self.get(key)?.parse().ok()?;
+        Some(UNIX_EPOCH + Duration::from_nanos(nanos))
+    }
+}
+
+/// Storage capacity of the device as reported by AFC
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AfcStorageInfo {
+    pub total_bytes: u64,
+    pub free_bytes: u64,
+    pub block_size: u64,
+    pub used_bytes: u64,
+    /// Percentage of the storage in use, from 0 to 100. Zero if the device reported no capacity.
+    pub used_percent: f64,
+}
+
+impl AfcStorageInfo {
+    fn new(total_bytes: u64, free_bytes: u64, block_size: u64) -> Self {
+        let used_bytes = total_bytes.saturating_sub(free_bytes);
+        let used_percent = if total_bytes == 0 {
+            0.0
+        } else {
+            used_bytes as f64 / total_bytes as f64 * 100.0
+        };
+        AfcStorageInfo {
+            total_bytes,
+            free_bytes,
+            block_size,
+            used_bytes,
+            used_percent,
+        }
+    }
+}
+
+/// The modes a file can be opened in, matching the modes of C's `fopen`
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum AfcFileMode {
+    /// `r` - Read only. The file must exist.
     ReadOnly,
+    /// `r+` - Read and write, starting at the beginning. The file is created if it doesn't exist,
+    /// and existing contents are kept.
     ReadWrite,
+    /// `w` - Write only. The file is created if it doesn't exist, and truncated if it does.
     WriteOnly,
+    /// `w+` - Read and write. The file is created if it doesn't exist, and truncated if it does.
     WriteRead,
+    /// `a` - Write only, always at the end. The file is created if it doesn't exist.
     Append,
+    /// `a+` - Read anywhere and write at the end. The file is created if it doesn't exist.
     ReadAppend,
 }
 
@@ -524,6 +1276,7 @@ impl From<AfcFileMode> for u32 {
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum AfcLockOp {
     Sh,
     Ex,
@@ -540,6 +1293,20 @@ impl From<AfcLockOp> for u32 {
     }
 }
 
+impl TryFrom<u32> for AfcLockOp {
+    type Error = AfcError;
+
+    fn try_from(op: u32) -> Result<Self, Self::Error> {
+        match op {
+            5 => Ok(AfcLockOp::Sh),
+            6 => Ok(AfcLockOp::Ex),
+            12 => Ok(AfcLockOp::Un),
+            _ => Err(AfcError::InvalidArg),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum LinkType {
     HardLink,
     SymbolicLink,
@@ -554,6 +1321,28 @@ impl From<LinkType> for u32 {
     }
 }
 
+impl TryFrom<u32> for LinkType {
+    type Error = AfcError;
+
+    fn try_from(link_type: u32) -> Result<Self, Self::Error> {
+        match link_type {
+            1 => Ok(LinkType::HardLink),
+            2 => Ok(LinkType::SymbolicLink),
+            _ => Err(AfcError::InvalidArg),
+        }
+    }
+}
+
+// The client is opaque, so only whether it is connected is shown
+impl std::fmt::Debug for AfcClient<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("AfcClient")
+            .field("connected", &!self.pointer.is_null())
+            .field("chunk_size", &self.chunk_size)
+            .finish()
+    }
+}
+
 impl Drop for AfcClient<'_> {
     fn drop(&mut self) {
         unsafe {
@@ -561,3 +1350,503 @@ impl Drop for AfcClient<'_> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+
+    fn assert_send_static<T: Send + 'static>() {}
+
+    #[test]
+    fn owned_client_can_move_to_threads() {
+        assert_send_static::<OwnedAfcClient>();
+    }
+
+    #[test]
+    fn lock_ops_round_trip() {
+        for op in [AfcLockOp::Sh, AfcLockOp::Ex, AfcLockOp::Un] {
+            assert_eq!(AfcLockOp::try_from(u32::from(op)), Ok(op));
+        }
+        assert_eq!(AfcLockOp::try_from(4), Err(AfcError::InvalidArg));
+    }
+
+    #[test]
+    fn link_types_round_trip() {
+        for link_type in [LinkType::HardLink, LinkType::SymbolicLink] {
+            assert_eq!(LinkType::try_from(u32::from(link_type)), Ok(link_type));
+        }
+        assert_eq!(LinkType::try_from(0), Err(AfcError::InvalidArg));
+    }
+
+    fn metadata_from(list: &[&str]) -> AfcMetadata {
+        AfcMetadata {
+            values: key_value_map(list.iter().map(|s| s.to_string()).collect()),
+        }
+    }
+
+    #[test]
+    fn metadata_of_a_file() {
+        let metadata = metadata_from(&[
+            "st_size",
+            "1024",
+            "st_blocks",
+            "8",
+            "st_nlink",
+            "1",
+            "st_ifmt",
+            "S_IFREG",
+            "st_mtime",
+            "1650000000000000000",
+            "st_birthtime",
+            "1640000000000000000",
+        ]);
+        assert!(metadata.is_file());
+        assert!(!metadata.is_dir());
+        assert!(!metadata.is_symlink());
+        assert_eq!(metadata.len(), 1024);
+        assert!(!metadata.is_empty());
+        assert_eq!(metadata.get("st_nlink"), Some("1"));
+        assert_eq!(
+            metadata.modified(),
+            Some(UNIX_EPOCH + Duration::from_secs(1_650_000_000))
+        );
+        assert_eq!(
+            metadata.created(),
+            Some(UNIX_EPOCH + Duration::from_secs(1_640_000_000))
+        );
+    }
+
+    #[test]
+    fn metadata_file_types() {
+        assert!(metadata_from(&["st_ifmt", "S_IFDIR"]).is_dir());
+        let link = metadata_from(&["st_ifmt", "S_IFLNK", "LinkTarget", "/var/mobile"]);
+        assert!(link.is_symlink());
+        assert_eq!(link.get("LinkTarget"), Some("/var/mobile"));
+    }
+
+    #[test]
+    fn metadata_with_missing_keys() {
+        // A trailing key without a value is dropped
+        let metadata = metadata_from(&["st_nlink", "2", "st_size"]);
+        assert!(!metadata.is_file() && !metadata.is_dir() && !metadata.is_symlink());
+        assert_eq!(metadata.len(), 0);
+        assert!(metadata.is_empty());
+        assert_eq!(metadata.get("st_size"), None);
+        assert_eq!(metadata.modified(), None);
+        assert_eq!(metadata.created(), None);
+    }
+
+    #[test]
+    fn storage_usage() {
+        let info = AfcStorageInfo::new(64_000, 16_000, 4096);
+        assert_eq!(info.used_bytes, 48_000);
+        assert_eq!(info.used_percent, 75.0);
+        assert_eq!(info.block_size, 4096);
+    }
+
+    #[test]
+    fn storage_usage_without_capacity() {
+        let info = AfcStorageInfo::new(0, 0, 0);
+        assert_eq!(info.used_bytes, 0);
+        assert_eq!(info.used_percent, 0.0);
+
+        // More free space than capacity can't make the usage negative
+        let info = AfcStorageInfo::new(0, 100, 4096);
+        assert_eq!(info.used_bytes, 0);
+        assert_eq!(info.used_percent, 0.0);
+    }
+
+    #[test]
+    fn directory_listing_drops_dot_entries() {
+        let entries = ["..", "DCIM", ".", "..hidden", ".Trashes"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+        assert_eq!(
+            without_dot_entries(entries),
+            vec!["DCIM", "..hidden", ".Trashes"]
+        );
+        assert!(without_dot_entries(vec![".".to_string(), "..".to_string()]).is_empty());
+    }
+
+    /// Stands in for an open file, recording when it is closed
+    struct TrackedFile<'a> {
+        path: String,
+        closed: &'a RefCell<Vec<String>>,
+    }
+
+    impl Drop for TrackedFile<'_> {
+        fn drop(&mut self) {
+            self.closed.borrow_mut().push(self.path.clone());
+        }
+    }
+
+    #[test]
+    fn failed_open_closes_opened_files() {
+        let closed = RefCell::new(vec![]);
+        let mut attempted = vec![];
+        let paths = [
+            ("/a.txt", AfcFileMode::ReadOnly),
+            ("/missing.txt", AfcFileMode::ReadOnly),
+            ("/c.txt", AfcFileMode::ReadOnly),
+        ];
+        let result = open_each(&paths, |path, _| {
+            attempted.push(path.to_string());
+            match path {
+                "/missing.txt" => Err(AfcError::ObjectNotFound),
+                _ => Ok(TrackedFile {
+                    path: path.to_string(),
+                    closed: &closed,
+                }),
+            }
+        });
+
+        assert_eq!(result.err(), Some(AfcError::ObjectNotFound));
+        assert_eq!(attempted, vec!["/a.txt", "/missing.txt"]);
+        assert_eq!(*closed.borrow(), vec!["/a.txt"]);
+    }
+
+    #[test]
+    fn open_each_keeps_order() {
+        let closed = RefCell::new(vec![]);
+        let paths = [
+            ("/a.txt", AfcFileMode::ReadOnly),
+            ("/b.txt", AfcFileMode::WriteOnly),
+        ];
+        let files = open_each(&paths, |path, _| {
+            Ok(TrackedFile {
+                path: path.to_string(),
+                closed: &closed,
+            })
+        })
+        .unwrap();
+        let opened: Vec<&str> = files.iter().map(|f| f.path.as_str()).collect();
+        assert_eq!(opened, vec!["/a.txt", "/b.txt"]);
+        assert!(closed.borrow().is_empty());
+    }
+
+    #[test]
+    fn debug_shows_connection_without_dereferencing() {
+        let client = AfcClient {
+            pointer: std::ptr::null_mut(),
+            chunk_size: DEFAULT_CHUNK_SIZE,
+            phantom: std::marker::PhantomData,
+        };
+        assert_eq!(
+            format!("{:?}", client),
+            "AfcClient { connected: false, chunk_size: 65536 }"
+        );
+        std::mem::forget(client);
+
+        // A dangling pointer would crash if it were read
+        let client = AfcClient {
+            pointer: std::ptr::NonNull::dangling().as_ptr(),
+            chunk_size: 512,
+            phantom: std::marker::PhantomData,
+        };
+        assert_eq!(
+            format!("{:?}", client),
+            "AfcClient { connected: true, chunk_size: 512 }"
+        );
+        std::mem::forget(client);
+    }
+
+    #[test]
+    fn file_times_are_nanoseconds() {
+        let time = UNIX_EPOCH + Duration::new(1_600_000_000, 123_456_789);
+        assert_eq!(afc_time(time), Ok(1_600_000_000_123_456_789));
+        assert_eq!(afc_time(UNIX_EPOCH), Ok(0));
+        assert_eq!(
+            afc_time(UNIX_EPOCH - Duration::from_secs(1)),
+            Err(AfcError::InvalidArg)
+        );
+
+        // What the device reports back is read with the same unit
+        let metadata = metadata_from(&["st_mtime", &afc_time(time).unwrap().to_string()]);
+        assert_eq!(metadata.modified(), Some(time));
+    }
+
+    #[test]
+    #[cfg_attr(not(feature = "hardware"), ignore = "needs a connected device")]
+    fn set_file_time_reads_back() {
+        let device = crate::idevice::test_device();
+        let client = AfcClient::start_service(&device, "afc-test").unwrap();
+        let path = "/afc_file_time_test.txt";
+        client.write_file(path, b"time").unwrap();
+
+        let time = UNIX_EPOCH + Duration::from_secs(1_600_000_000);
+        client.set_file_time_system(path, time).unwrap();
+        let modified = client.metadata(path).unwrap().modified();
+        client.remove_path(path.to_string()).unwrap();
+        assert_eq!(modified, Some(time));
+    }
+
+    #[test]
+    fn reads_use_the_chunk_size() {
+        let file: Vec<u8> = (0..10u8).collect();
+        let mut requests = vec![];
+        let mut offset = 0;
+        let data = read_chunks(
+            4,
+            file.len() as u64,
+            |length| {
+                requests.push(length);
+                let end = (offset + length as usize).min(file.len());
+                let chunk = file[offset..end].to_vec();
+                offset = end;
+                Ok(chunk)
+            },
+            |_, _| {},
+        )
+        .unwrap();
+        assert_eq!(data, file);
+        assert_eq!(requests, vec![4, 4, 4]);
+    }
+
+    #[test]
+    fn writes_use_the_chunk_size() {
+        let data: Vec<u8> = (0..10u8).collect();
+        let mut writes = vec![];
+        let mut reports = vec![];
+        write_chunks(
+            4,
+            &data,
+            |chunk| {
+                writes.push(chunk.to_vec());
+                Ok(())
+            },
+            |done, total| reports.push((done, total)),
+        )
+        .unwrap();
+        assert_eq!(writes, vec![vec![0, 1, 2, 3], vec![4, 5, 6, 7], vec![8, 9]]);
+        assert_eq!(reports, vec![(4, 10), (8, 10), (10, 10)]);
+
+        // A failed write stops the transfer
+        let mut calls = 0;
+        assert_eq!(
+            write_chunks(
+                4,
+                &data,
+                |_| {
+                    calls += 1;
+                    Err(AfcError::IoError)
+                },
+                |_, _| {}
+            ),
+            Err(AfcError::IoError)
+        );
+        assert_eq!(calls, 1);
+    }
+
+    /// Reads from `file` like afc_file_read, returning at most `packet_size` bytes per call
+    fn packet_reader(
+        file: &[u8],
+        packet_size: usize,
+    ) -> impl FnMut(&mut [u8]) -> Result<u32, AfcError> + '_ {
+        let mut offset = 0;
+        move |buffer| {
+            let len = buffer.len().min(packet_size).min(file.len() - offset);
+            buffer[..len].copy_from_slice(&file[offset..offset + len]);
+            offset += len;
+            Ok(len as u32)
+        }
+    }
+
+    #[test]
+    fn read_longer_than_a_packet_is_complete() {
+        let file: Vec<u8> = (0..10_000).map(|i| (i % 251) as u8).collect();
+        assert_eq!(
+            read_until_full(8_000, packet_reader(&file, 4096)).unwrap(),
+            file[..8_000]
+        );
+        // Asking for more than is left stops at the end of the file
+        assert_eq!(
+            read_until_full(20_000, packet_reader(&file, 4096)).unwrap(),
+            file
+        );
+    }
+
+    #[test]
+    fn failed_read_is_an_error() {
+        assert_eq!(
+            read_until_full(10, |_| Err(AfcError::IoError)),
+            Err(AfcError::IoError)
+        );
+    }
+
+    /// Reads from `file` like file_read, returning up to `length` bytes per call
+    fn chunk_reader(file: &[u8]) -> impl FnMut(u32) -> Result<Vec<u8>, AfcError> + '_ {
+        let mut offset = 0;
+        move |length| {
+            let end = (offset + length as usize).min(file.len());
+            let chunk = file[offset..end].to_vec();
+            offset = end;
+            Ok(chunk)
+        }
+    }
+
+    #[test]
+    fn read_progress_ends_at_the_total() {
+        for len in [0, 3, 8, 10] {
+            let file: Vec<u8> = (0..len).collect();
+            let mut reports = vec![];
+            read_chunks(4, file.len() as u64, chunk_reader(&file), |done, total| {
+                reports.push((done, total))
+            })
+            .unwrap();
+            assert_eq!(reports.last(), Some(&(len as u64, len as u64)));
+        }
+    }
+
+    #[test]
+    fn write_progress_ends_at_the_total() {
+        for len in [0, 3, 8, 10] {
+            let data: Vec<u8> = (0..len).collect();
+            let mut reports = vec![];
+            write_chunks(
+                4,
+                &data,
+                |_| Ok(()),
+                |done, total| reports.push((done, total)),
+            )
+            .unwrap();
+            assert_eq!(reports.last(), Some(&(len as u64, len as u64)));
+        }
+    }
+
+    #[test]
+    fn copy_moves_every_chunk() {
+        for len in [0, 3, 8, 10] {
+            let file: Vec<u8> = (0..len).collect();
+            let mut copied = vec![];
+            let mut largest = 0;
+            copy_chunks(4, chunk_reader(&file), |chunk| {
+                largest = largest.max(chunk.len());
+                copied.extend_from_slice(chunk);
+                Ok(())
+            })
+            .unwrap();
+            assert_eq!(copied, file);
+            // Only one chunk is held at a time
+            assert!(largest <= 4);
+        }
+    }
+
+    #[test]
+    fn copy_stops_on_errors() {
+        let file: Vec<u8> = (0..10).collect();
+        let mut writes = 0;
+        let result = copy_chunks(4, chunk_reader(&file), |_| {
+            writes += 1;
+            Err(AfcError::NoSpaceLeft)
+        });
+        assert_eq!(result, Err(AfcError::NoSpaceLeft));
+        assert_eq!(writes, 1);
+        assert_eq!(
+            copy_chunks(4, |_| Err(AfcError::IoError), |_| Ok(())),
+            Err(AfcError::IoError)
+        );
+    }
+
+    #[test]
+    #[cfg_attr(not(feature = "hardware"), ignore = "needs a connected device")]
+    fn copied_file_has_the_same_size() {
+        let device = crate::idevice::test_device();
+        let mut client = AfcClient::start_service(&device, "afc-test").unwrap();
+        client.set_chunk_size(1024);
+        let (src, dst) = ("/afc_copy_src.bin", "/afc_copy_dst.bin");
+        let data: Vec<u8> = (0..5000).map(|i| (i % 251) as u8).collect();
+        client.write_file(src, &data).unwrap();
+
+        client.copy(src, dst).unwrap();
+        let sizes = (
+            client.metadata(src).unwrap().len(),
+            client.metadata(dst).unwrap().len(),
+        );
+        let copied = client.read_file(dst).unwrap();
+        client.remove_path(src.to_string()).unwrap();
+        client.remove_path(dst.to_string()).unwrap();
+        assert_eq!(sizes, (5000, 5000));
+        assert_eq!(copied, data);
+    }
+
+    #[test]
+    fn modes_match_the_afc_open_flags() {
+        let modes = [
+            (
+                AfcFileMode::ReadOnly,
+                unsafe_bindings::afc_file_mode_t_AFC_FOPEN_RDONLY,
+            ),
+            (
+                AfcFileMode::ReadWrite,
+                unsafe_bindings::afc_file_mode_t_AFC_FOPEN_RW,
+            ),
+            (
+                AfcFileMode::WriteOnly,
+                unsafe_bindings::afc_file_mode_t_AFC_FOPEN_WRONLY,
+            ),
+            (
+                AfcFileMode::WriteRead,
+                unsafe_bindings::afc_file_mode_t_AFC_FOPEN_WR,
+            ),
+            (
+                AfcFileMode::Append,
+                unsafe_bindings::afc_file_mode_t_AFC_FOPEN_APPEND,
+            ),
+            (
+                AfcFileMode::ReadAppend,
+                unsafe_bindings::afc_file_mode_t_AFC_FOPEN_RDAPPEND,
+            ),
+        ];
+        for (mode, flag) in modes {
+            assert_eq!(u32::from(mode), flag);
+            assert_eq!(AfcFileMode::from(flag as i8), mode);
+        }
+    }
+
+    #[test]
+    fn create_new_refuses_to_clobber() {
+        let mut opened = false;
+        assert_eq!(
+            open_new(
+                || Ok(true),
+                || {
+                    opened = true;
+                    Ok(1)
+                }
+            ),
+            Err(AfcError::ObjectExists)
+        );
+        assert!(!opened);
+
+        assert_eq!(open_new(|| Ok(false), || Ok(7)), Ok(7));
+        assert_eq!(
+            open_new(|| Err(AfcError::PermDenied), || Ok(7)),
+            Err(AfcError::PermDenied)
+        );
+    }
+
+    #[test]
+    #[cfg_attr(not(feature = "hardware"), ignore = "needs a connected device")]
+    fn write_only_truncates_and_create_new_does_not_clobber() {
+        let device = crate::idevice::test_device();
+        let client = AfcClient::start_service(&device, "afc-test").unwrap();
+        let path = "/afc_open_mode_test.txt";
+        client.write_file(path, b"a longer first version").unwrap();
+
+        let handle = client
+            .file_open(path.to_string(), AfcFileMode::WriteOnly)
+            .unwrap();
+        client.file_write(handle, b"short").unwrap();
+        client.file_close(handle).unwrap();
+        let rewritten = client.read_file(path).unwrap();
+
+        let created = client.create_new(path);
+        let kept = client.read_file(path).unwrap();
+        client.remove_path(path.to_string()).unwrap();
+        assert_eq!(rewritten, b"short");
+        assert_eq!(created, Err(AfcError::ObjectExists));
+        assert_eq!(kept, b"short");
+    }
+}
@@ -1,21 +1,169 @@
 // jkcoxson
 
+use std::cell::RefCell;
 use std::os::raw::c_char;
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::Duration;
 
 use crate::{
     bindings as unsafe_bindings, error::CompanionProxyError, idevice::Device,
-    services::lockdownd::LockdowndService,
+    services::lockdownd::LockdowndService, services::trace_plist,
 };
 
+use log::warn;
 use plist_plus::Plist;
 
 /// A proxy for interoping with devices paired with the iOS device
 /// This includes the Apple Watch
 pub struct CompanionProxy<'a> {
     pub(crate) pointer: unsafe_bindings::companion_proxy_client_t,
+    /// A receive started by `receive_timeout` that hasn't delivered its message yet
+    pending: RefCell<Option<PendingReceive>>,
     phantom: std::marker::PhantomData<&'a Device>,
 }
 
+/// A blocking receive running on a worker thread
+struct PendingReceive {
+    receiver: mpsc::Receiver<Result<ReceivedPlist, CompanionProxyError>>,
+    handle: JoinHandle<()>,
+    /// Set when the client is dropped before the message arrives.
+    /// The worker then frees the client once its receive returns.
+    abandoned: Arc<Mutex<bool>>,
+}
+
+/// The client pointer handed to a receive worker.
+/// The client is only freed once the worker is done with it, either by the client or the worker.
+struct ClientPointer(unsafe_bindings::companion_proxy_client_t);
+
+/// A plist received by a worker, owned by whoever takes it off the channel
+struct ReceivedPlist(unsafe_bindings::plist_t);
+
+unsafe impl Send for ClientPointer {}
+unsafe impl Send for ReceivedPlist {}
+
+type ReceiveFn = unsafe extern "C" fn(
+    unsafe_bindings::companion_proxy_client_t,
+    *mut unsafe_bindings::plist_t,
+) -> unsafe_bindings::companion_proxy_error_t;
+type FreeFn = unsafe extern "C" fn(
+    unsafe_bindings::companion_proxy_client_t,
+) -> unsafe_bindings::companion_proxy_error_t;
+type GetValueFn = unsafe extern "C" fn(
+    unsafe_bindings::companion_proxy_client_t,
+    *const c_char,
+    *const c_char,
+    *mut unsafe_bindings::plist_t,
+) -> unsafe_bindings::companion_proxy_error_t;
+
+impl ClientPointer {
+    fn get(&self) -> unsafe_bindings::companion_proxy_client_t {
+        self.0
+    }
+}
+
+impl PendingReceive {
+    fn spawn(pointer: unsafe_bindings::companion_proxy_client_t) -> Self {
+        Self::spawn_with(
+            pointer,
+            unsafe_bindings::companion_proxy_receive,
+            unsafe_bindings::companion_proxy_client_free,
+        )
+    }
+
+    fn spawn_with(
+        pointer: unsafe_bindings::companion_proxy_client_t,
+        receive: ReceiveFn,
+        free: FreeFn,
+    ) -> Self {
+        let pointer = ClientPointer(pointer);
+        let abandoned = Arc::new(Mutex::new(false));
+        let worker_abandoned = abandoned.clone();
+        let (sender, receiver) = mpsc::channel();
+        let handle = std::thread::spawn(move || {
+            let mut plist = std::ptr::null_mut();
+            let result: CompanionProxyError = unsafe { receive(pointer.get(), &mut plist) }.into();
+            // Holding the lock while sending keeps the client from being abandoned in between
+            let abandoned = worker_abandoned
+                .lock()
+                .unwrap_or_else(|poisoned| poisoned.into_inner());
+            if *abandoned {
+                if result == CompanionProxyError::Success {
+                    unsafe { unsafe_bindings::plist_free(plist) };
+                }
+                unsafe { free(pointer.get()) };
+                return;
+            }
+            let _ = sender.send(match result {
+                CompanionProxyError::Success => Ok(ReceivedPlist(plist)),
+                e => Err(e),
+            });
+        });
+        PendingReceive {
+            receiver,
+            handle,
+            abandoned,
+        }
+    }
+
+    /// Blocks until the worker delivers its message
+    fn wait(self) -> Result<Plist, CompanionProxyError> {
+        let received = self.receiver.recv();
+        let _ = self.handle.join();
+        Self::finish(received.unwrap_or(Err(CompanionProxyError::UnknownError)))
+    }
+
+    /// Waits up to `timeout` for the worker's message.
+    /// If nothing arrived the receive is handed back, so a later call can collect it.
+    fn wait_timeout(self, timeout: Duration) -> Result<Result<Plist, CompanionProxyError>, Self> {
+        match self.receiver.recv_timeout(timeout) {
+            Ok(received) => {
+                let _ = self.handle.join();
+                Ok(Self::finish(received))
+            }
+            Err(mpsc::RecvTimeoutError::Timeout) => Err(self),
+            Err(mpsc::RecvTimeoutError::Disconnected) => {
+                let _ = self.handle.join();
+                Ok(Err(CompanionProxyError::UnknownError))
+            }
+        }
+    }
+
+    /// Gives up on the message without blocking.
+    /// Returns true if the worker is still receiving and will free the client itself.
+    fn abandon(self) -> bool {
+        let mut abandoned = self
+            .abandoned
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        match self.receiver.try_recv() {
+            Err(mpsc::TryRecvError::Empty) => {
+                *abandoned = true;
+                true
+            }
+            received => {
+                drop(abandoned);
+                let _ = self.handle.join();
+                if let Ok(Ok(plist)) = received {
+                    unsafe { unsafe_bindings::plist_free(plist.0) };
+                }
+                false
+            }
+        }
+    }
+
+    fn finish(
+        received: Result<ReceivedPlist, CompanionProxyError>,
+    ) -> Result<Plist, CompanionProxyError> {
+        let plist = received?.0;
+        trace_plist("Companion proxy receive", plist);
+        Ok(plist.into())
+    }
+}
+
+// The client can move between threads, but libimobiledevice doesn't lock its requests so it isn't Sync
+unsafe impl Send for CompanionProxy<'_> {}
+
 impl CompanionProxy<'_> {
     /// Creates a new companion proxy from a lockdown connection
     /// # Arguments
@@ -25,7 +173,10 @@ impl CompanionProxy<'_> {
     /// A companion proxy struct
     ///
     /// ***Verified:*** False
-    pub fn new(device: &Device, descriptor: LockdowndService) -> Result<Self, CompanionProxyError> {
+    pub fn new(
+        device: &Device,
+        descriptor: &LockdowndService,
+    ) -> Result<Self, CompanionProxyError> {
         let mut pointer = unsafe { std::mem::zeroed() };
         let result = unsafe {
             unsafe_bindings::companion_proxy_client_new(
@@ -41,6 +192,7 @@ impl CompanionProxy<'_> {
 
         Ok(CompanionProxy {
             pointer,
+            pending: RefCell::new(None),
             phantom: std::marker::PhantomData,
         })
     }
@@ -69,6 +221,7 @@ impl CompanionProxy<'_> {
 
         Ok(CompanionProxy {
             pointer,
+            pending: RefCell::new(None),
             phantom: std::marker::PhantomData,
         })
     }
@@ -81,6 +234,7 @@ impl CompanionProxy<'_> {
     ///
     /// ***Verified:*** False
     pub fn send(&self, message: Plist) -> Result<(), CompanionProxyError> {
+        trace_plist("Companion proxy send", message.get_pointer());
         let result =
             unsafe { unsafe_bindings::companion_proxy_send(self.pointer, message.get_pointer()) }
                 .into();
@@ -92,7 +246,8 @@ impl CompanionProxy<'_> {
     }
 
     /// Receives a message from the companion proxy service.
-    /// Blocks until a full plist is received
+    /// Blocks until a full plist is received.
+    /// If a `receive_timeout` gave up waiting, this collects the message it was waiting for.
     /// # Arguments
     /// *none*
     /// # Returns
@@ -100,16 +255,65 @@ impl CompanionProxy<'_> {
     ///
     /// ***Verified:*** False
     pub fn receive(&self) -> Result<Plist, CompanionProxyError> {
+        if let Some(pending) = self.pending.borrow_mut().take() {
+            return pending.wait();
+        }
         let mut plist = unsafe { std::mem::zeroed() };
         let result =
             unsafe { unsafe_bindings::companion_proxy_receive(self.pointer, &mut plist) }.into();
         if result != CompanionProxyError::Success {
             return Err(result);
         }
+        trace_plist("Companion proxy receive", plist);
 
         Ok(plist.into())
     }
 
+    /// Receives a message from the companion proxy service, giving up after a timeout.
+    /// libimobiledevice has no receive with a timeout for this service, so the receive runs
+    /// on a worker thread. When the timeout expires the receive keeps waiting in the background,
+    /// and the next call to `receive` or `receive_timeout` picks up its message.
+    /// Collect it before making other requests, as it would take their reply.
+    /// Dropping the client while a receive is pending doesn't block. The message is discarded,
+    /// and the worker frees the client once the device replies or the connection closes.
+    /// # Arguments
+    /// * `timeout_ms` - How long to wait for a message in milliseconds
+    /// # Returns
+    /// A plist containing the message, or a Timeout error if nothing arrived
+    ///
+    /// ***Verified:*** False
+    pub fn receive_timeout(&self, timeout_ms: u32) -> Result<Plist, CompanionProxyError> {
+        if self.pointer.is_null() {
+            return Err(CompanionProxyError::InvalidArg);
+        }
+        let pending = self
+            .pending
+            .borrow_mut()
+            .take()
+            .unwrap_or_else(|| PendingReceive::spawn(self.pointer));
+        match pending.wait_timeout(Duration::from_millis(timeout_ms as u64)) {
+            Ok(received) => received,
+            Err(pending) => {
+                *self.pending.borrow_mut() = Some(pending);
+                Err(CompanionProxyError::Timeout)
+            }
+        }
+    }
+
+    /// Returns an iterator that receives messages from the companion proxy service.
+    /// Each call to `next` blocks until a full plist is received, just like `receive`.
+    /// The iterator ends when the connection is closed or the receive times out.
+    /// Any other error is yielded once, after which the iterator ends.
+    /// # Arguments
+    /// *none*
+    /// # Returns
+    /// An iterator of received messages
+    ///
+    /// ***Verified:*** False
+    pub fn messages(&self) -> impl Iterator<Item = Result<Plist, CompanionProxyError>> + '_ {
+        messages_from(move || self.receive())
+    }
+
     /// Fetches the registry from the iOS device.
     /// Closes the connection after a reply, so this consumes the companion proxy.
     /// # Arguments
@@ -119,16 +323,26 @@ impl CompanionProxy<'_> {
     ///
     /// ***Verified:*** False
     pub fn get_device_registry(self) -> Result<Plist, CompanionProxyError> {
-        let mut plist = unsafe { std::mem::zeroed() };
-        let result = unsafe {
-            unsafe_bindings::companion_proxy_get_device_registry(self.pointer, &mut plist)
-        }
-        .into();
-        if result != CompanionProxyError::Success {
-            return Err(result);
-        }
+        self.device_registry_ref()
+    }
 
-        Ok(plist.into())
+    /// Fetches the registry from the iOS device without consuming the companion proxy.
+    /// Use this to make several queries over the same connection.
+    /// # Arguments
+    /// *none*
+    /// # Returns
+    /// A plist containing the paired devices, or a NoCompanion error if none is paired
+    ///
+    /// ***Verified:*** False
+    pub fn device_registry_ref(&self) -> Result<Plist, CompanionProxyError> {
+        // libimobiledevice collapses most error replies into UnknownError, so the request
+        // is made by hand to be able to read the reply's Error key
+        let mut request = Plist::new_dict();
+        request
+            .dict_set_item("Command", Plist::new_string("GetDeviceRegistry"))
+            .map_err(|_| CompanionProxyError::PlistError)?;
+        self.send(request)?;
+        registry_from_reply(&self.receive()?)
     }
 
     /// Gets a value from the device's registry.
@@ -141,12 +355,46 @@ impl CompanionProxy<'_> {
         udid: String,
         key: String,
     ) -> Result<Plist, CompanionProxyError> {
+        self.value_from_registry_ref(&udid, &key)
+    }
+
+    /// Gets a value from the device's registry without consuming the companion proxy.
+    /// Use this to fetch several keys over the same connection.
+    /// # Arguments
+    /// * `udid` - The UDID of the paired device
+    /// * `key` - The value to fetch from the registry
+    /// # Returns
+    /// A plist containing the value
+    ///
+    /// ***Verified:*** False
+    pub fn value_from_registry_ref(
+        &self,
+        udid: &str,
+        key: &str,
+    ) -> Result<Plist, CompanionProxyError> {
+        self.value_from_registry_with(
+            udid,
+            key,
+            unsafe_bindings::companion_proxy_get_value_from_registry,
+        )
+    }
+
+    /// Gets a value from the registry with the given lookup, so queries can be tested without a device
+    fn value_from_registry_with(
+        &self,
+        udid: &str,
+        key: &str,
+        get_value: GetValueFn,
+    ) -> Result<Plist, CompanionProxyError> {
+        let udid_c_str =
+            std::ffi::CString::new(udid).map_err(|_| CompanionProxyError::InvalidArg)?;
+        let key_c_str = std::ffi::CString::new(key).map_err(|_| CompanionProxyError::InvalidArg)?;
         let mut plist = unsafe { std::mem::zeroed() };
         let result = unsafe {
-            unsafe_bindings::companion_proxy_get_value_from_registry(
+            get_value(
                 self.pointer,
-                udid.as_ptr() as *const c_char,
-                key.as_ptr() as *const c_char,
+                udid_c_str.as_ptr(),
+                key_c_str.as_ptr(),
                 &mut plist,
             )
         }
@@ -162,7 +410,7 @@ impl CompanionProxy<'_> {
     /// # Arguments
     /// * `port` - The internal port to open to
     /// * `service_name` - The name of the service
-    /// * `options` - Options for the port forward
+    /// * `options` - Options for the port forward, or `None` for the defaults
     /// # Returns
     /// The external port that was opened
     ///
@@ -171,16 +419,21 @@ impl CompanionProxy<'_> {
         &self,
         port: u16,
         service_name: String,
-        options: Plist,
+        options: Option<Plist>,
     ) -> Result<u16, CompanionProxyError> {
+        let service_name_c_str =
+            std::ffi::CString::new(service_name).map_err(|_| CompanionProxyError::InvalidArg)?;
         let mut result_port = 0;
         let result = unsafe {
             unsafe_bindings::companion_proxy_start_forwarding_service_port(
                 self.pointer,
                 port,
-                service_name.as_ptr() as *const c_char,
+                service_name_c_str.as_ptr(),
                 &mut result_port,
-                options.get_pointer(),
+                options
+                    .as_ref()
+                    .map(|o| o.get_pointer())
+                    .unwrap_or(std::ptr::null_mut()),
             )
         }
         .into();
@@ -211,10 +464,313 @@ impl CompanionProxy<'_> {
     }
 }
 
+impl std::fmt::Debug for CompanionProxy<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("CompanionProxy")
+            .field("connected", &!self.pointer.is_null())
+            .finish()
+    }
+}
+
 impl Drop for CompanionProxy<'_> {
     fn drop(&mut self) {
+        // A receive stuck on the device still uses the client, so its worker frees it instead
+        if let Some(pending) = self.pending.get_mut().take() {
+            if pending.abandon() {
+                return;
+            }
+        }
         unsafe {
             unsafe_bindings::companion_proxy_client_free(self.pointer);
         }
     }
 }
+
+/// Yields messages from `receive` until the connection closes or an error is yielded
+fn messages_from<F>(mut receive: F) -> impl Iterator<Item = Result<Plist, CompanionProxyError>>
+where
+    F: FnMut() -> Result<Plist, CompanionProxyError>,
+{
+    let mut finished = false;
+    std::iter::from_fn(move || {
+        if finished {
+            return None;
+        }
+        match receive() {
+            Ok(plist) => Some(Ok(plist)),
+            Err(CompanionProxyError::MuxError) | Err(CompanionProxyError::Timeout) => {
+                finished = true;
+                None
+            }
+            Err(e) => {
+                finished = true;
+                Some(Err(e))
+            }
+        }
+    })
+}
+
+/// Pulls the paired device list out of a GetDeviceRegistry reply.
+/// A reply saying no companion is paired or connected is mapped to `NoCompanion`.
+fn registry_from_reply(reply: &Plist) -> Result<Plist, CompanionProxyError> {
+    if let Ok(devices) = reply.dict_get_item("PairedDevicesArray") {
+        return Ok(devices.clone());
+    }
+    match reply.dict_get_item("Error") {
+        Ok(error) => {
+            let error = error.get_string_val().unwrap_or_default();
+            warn!("Companion proxy registry request failed: {}", error);
+            Err(registry_error(&error))
+        }
+        Err(_) => Err(CompanionProxyError::PlistError),
+    }
+}
+
+/// Maps the Error string of a registry reply to an error
+fn registry_error(error: &str) -> CompanionProxyError {
+    match error {
+        "NoDevices" | "NoPairedWatches" | "NotConnected" => CompanionProxyError::NoCompanion,
+        _ => CompanionProxyError::UnknownError,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    static STUCK_CLIENT_FREED: AtomicBool = AtomicBool::new(false);
+
+    unsafe extern "C" fn stuck_receive(
+        _client: unsafe_bindings::companion_proxy_client_t,
+        _plist: *mut unsafe_bindings::plist_t,
+    ) -> unsafe_bindings::companion_proxy_error_t {
+        std::thread::sleep(Duration::from_millis(200));
+        unsafe_bindings::companion_proxy_error_t_COMPANION_PROXY_E_MUX_ERROR
+    }
+
+    unsafe extern "C" fn failed_receive(
+        _client: unsafe_bindings::companion_proxy_client_t,
+        _plist: *mut unsafe_bindings::plist_t,
+    ) -> unsafe_bindings::companion_proxy_error_t {
+        unsafe_bindings::companion_proxy_error_t_COMPANION_PROXY_E_MUX_ERROR
+    }
+
+    unsafe extern "C" fn free_stuck_client(
+        _client: unsafe_bindings::companion_proxy_client_t,
+    ) -> unsafe_bindings::companion_proxy_error_t {
+        STUCK_CLIENT_FREED.store(true, Ordering::SeqCst);
+        unsafe_bindings::companion_proxy_error_t_COMPANION_PROXY_E_SUCCESS
+    }
+
+    unsafe extern "C" fn unused_free(
+        _client: unsafe_bindings::companion_proxy_client_t,
+    ) -> unsafe_bindings::companion_proxy_error_t {
+        panic!("the client should not be freed by the worker");
+    }
+
+    #[test]
+    fn receive_timeout_gives_up_and_drop_does_not_block() {
+        let pointer = std::ptr::NonNull::dangling().as_ptr();
+        let proxy = CompanionProxy {
+            pointer,
+            pending: RefCell::new(Some(PendingReceive::spawn_with(
+                pointer,
+                stuck_receive,
+                free_stuck_client,
+            ))),
+            phantom: std::marker::PhantomData,
+        };
+        assert_eq!(
+            proxy.receive_timeout(10).err(),
+            Some(CompanionProxyError::Timeout)
+        );
+
+        // The worker is still stuck, so dropping hands the client over to it
+        let started = std::time::Instant::now();
+        drop(proxy);
+        assert!(started.elapsed() < Duration::from_millis(100));
+        assert!(!STUCK_CLIENT_FREED.load(Ordering::SeqCst));
+        while !STUCK_CLIENT_FREED.load(Ordering::SeqCst) {
+            assert!(started.elapsed() < Duration::from_secs(5));
+            std::thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    #[test]
+    fn receive_in_time_is_returned() {
+        let pending = PendingReceive::spawn_with(
+            std::ptr::NonNull::dangling().as_ptr(),
+            failed_receive,
+            unused_free,
+        );
+        match pending.wait_timeout(Duration::from_secs(5)) {
+            Ok(received) => assert_eq!(received.err(), Some(CompanionProxyError::MuxError)),
+            Err(_) => panic!("the receive should have finished"),
+        }
+    }
+
+    #[test]
+    fn no_companion_reply_maps_to_no_companion() {
+        let mut reply = Plist::new_dict();
+        reply
+            .dict_set_item("Error", Plist::new_string("NoPairedWatches"))
+            .unwrap();
+        assert_eq!(
+            registry_from_reply(&reply).err(),
+            Some(CompanionProxyError::NoCompanion)
+        );
+    }
+
+    #[test]
+    fn registry_errors() {
+        assert_eq!(
+            registry_error("NoDevices"),
+            CompanionProxyError::NoCompanion
+        );
+        assert_eq!(
+            registry_error("NotConnected"),
+            CompanionProxyError::NoCompanion
+        );
+        assert_eq!(
+            registry_error("InternalError"),
+            CompanionProxyError::UnknownError
+        );
+    }
+
+    #[test]
+    fn service_name_with_nul_is_rejected() {
+        let proxy = CompanionProxy {
+            pointer: std::ptr::NonNull::dangling().as_ptr(),
+            pending: RefCell::new(None),
+            phantom: std::marker::PhantomData,
+        };
+        // Rejected before the name reaches libimobiledevice
+        assert_eq!(
+            proxy.start_forwarding_service_port(80, "web\0server".to_string(), None),
+            Err(CompanionProxyError::InvalidArg)
+        );
+        std::mem::forget(proxy);
+    }
+
+    #[test]
+    fn debug_shows_connection_without_dereferencing() {
+        let proxy = CompanionProxy {
+            pointer: std::ptr::null_mut(),
+            pending: RefCell::new(None),
+            phantom: std::marker::PhantomData,
+        };
+        assert_eq!(
+            format!("{:?}", proxy),
+            "CompanionProxy { connected: false }"
+        );
+        std::mem::forget(proxy);
+
+        let proxy = CompanionProxy {
+            pointer: std::ptr::NonNull::dangling().as_ptr(),
+            pending: RefCell::new(None),
+            phantom: std::marker::PhantomData,
+        };
+        assert_eq!(format!("{:?}", proxy), "CompanionProxy { connected: true }");
+        std::mem::forget(proxy);
+    }
+
+    /// A receive that plays back `script`, then reports the connection as closed
+    fn scripted(
+        script: Vec<Result<&'static str, CompanionProxyError>>,
+    ) -> impl FnMut() -> Result<Plist, CompanionProxyError> {
+        let mut script = script.into_iter();
+        move || match script.next() {
+            Some(Ok(command)) => {
+                let mut message = Plist::new_dict();
+                message
+                    .dict_set_item("Command", Plist::new_string(command))
+                    .unwrap();
+                Ok(message)
+            }
+            Some(Err(e)) => Err(e),
+            None => Err(CompanionProxyError::MuxError),
+        }
+    }
+
+    fn command(message: Result<Plist, CompanionProxyError>) -> String {
+        message
+            .unwrap()
+            .dict_get_item("Command")
+            .unwrap()
+            .get_string_val()
+            .unwrap()
+    }
+
+    #[test]
+    fn messages_end_when_the_connection_closes() {
+        let commands: Vec<String> = messages_from(scripted(vec![
+            Ok("DeviceAdded"),
+            Ok("DeviceRemoved"),
+            Ok("DeviceAdded"),
+        ]))
+        .map(command)
+        .collect();
+        assert_eq!(
+            commands,
+            vec!["DeviceAdded", "DeviceRemoved", "DeviceAdded"]
+        );
+
+        let mut messages = messages_from(scripted(vec![
+            Ok("DeviceAdded"),
+            Err(CompanionProxyError::Timeout),
+            Ok("DeviceRemoved"),
+        ]));
+        assert_eq!(command(messages.next().unwrap()), "DeviceAdded");
+        assert!(messages.next().is_none());
+    }
+
+    #[test]
+    fn messages_yield_other_errors_once() {
+        let mut messages = messages_from(scripted(vec![
+            Ok("DeviceAdded"),
+            Err(CompanionProxyError::PlistError),
+            Ok("DeviceRemoved"),
+        ]));
+        assert_eq!(command(messages.next().unwrap()), "DeviceAdded");
+        assert_eq!(
+            messages.next().unwrap().err(),
+            Some(CompanionProxyError::PlistError)
+        );
+        assert!(messages.next().is_none());
+    }
+
+    /// Answers every registry query with `<udid>/<key>`
+    unsafe extern "C" fn echo_registry(
+        client: unsafe_bindings::companion_proxy_client_t,
+        udid: *const c_char,
+        key: *const c_char,
+        value: *mut unsafe_bindings::plist_t,
+    ) -> unsafe_bindings::companion_proxy_error_t {
+        assert!(!client.is_null());
+        let udid = std::ffi::CStr::from_ptr(udid).to_string_lossy();
+        let key = std::ffi::CStr::from_ptr(key).to_string_lossy();
+        let plist = Plist::new_string(&format!("{}/{}", udid, key));
+        *value = plist.get_pointer();
+        plist.false_drop();
+        unsafe_bindings::companion_proxy_error_t_COMPANION_PROXY_E_SUCCESS
+    }
+
+    #[test]
+    fn fetches_two_keys_from_one_proxy() {
+        let proxy = CompanionProxy {
+            pointer: std::ptr::NonNull::dangling().as_ptr(),
+            pending: RefCell::new(None),
+            phantom: std::marker::PhantomData,
+        };
+        let name = proxy
+            .value_from_registry_with("watch", "DeviceName", echo_registry)
+            .unwrap();
+        let version = proxy
+            .value_from_registry_with("watch", "ProductVersion", echo_registry)
+            .unwrap();
+        assert_eq!(name.get_string_val().unwrap(), "watch/DeviceName");
+        assert_eq!(version.get_string_val().unwrap(), "watch/ProductVersion");
+        std::mem::forget(proxy);
+    }
+}
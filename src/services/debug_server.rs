@@ -1,9 +1,9 @@
 // jkcoxson
 
-use std::{convert::TryInto, os::raw::c_char};
+use std::{collections::BTreeMap, convert::TryInto, os::raw::c_char};
 
 use libc::c_int;
-use log::info;
+use log::{info, trace, warn};
 
 use crate::{bindings as unsafe_bindings, error::DebugServerError, idevice::Device};
 
@@ -201,22 +201,24 @@ impl DebugServer<'_> {
     /// # Arguments
     /// * `command` - The command to send as a debug server command
     /// # Returns
-    /// The response from the command, usually 'OK'
+    /// The payload of the reply, with the `$...#xx` framing removed.
+    /// Fails with `ResponseError` if the reply's checksum is wrong or it is an `Exx` error reply.
     ///
     /// ***Verified:*** False
-    pub fn send_command(&self, command: DebugServerCommand) -> Result<String, DebugServerError> {
-        let mut response: std::os::raw::c_char = unsafe { std::mem::zeroed() };
-        let mut response_ptr: *mut std::os::raw::c_char = &mut response;
-        let response_ptr_ptr: *mut *mut std::os::raw::c_char = &mut response_ptr;
+    pub fn send_command(
+        &self,
+        command: DebugServerCommand,
+    ) -> Result<DebugResponse, DebugServerError> {
+        let mut response: *mut c_char = std::ptr::null_mut();
+        let mut response_size: unsafe_bindings::size_t = 0;
 
-        let response_size = std::ptr::null_mut();
         info!("Sending command to debug server");
         let result = unsafe {
             unsafe_bindings::debugserver_client_send_command(
                 self.pointer,
                 command.command,
-                response_ptr_ptr,
-                response_size,
+                &mut response,
+                &mut response_size,
             )
         }
         .into();
@@ -224,17 +226,9 @@ impl DebugServer<'_> {
             return Err(result);
         }
 
-        if response == 0 {
-            return Ok("".to_string());
-        }
-        // Convert response to String
-        let response_str = unsafe {
-            std::ffi::CStr::from_ptr(response_ptr)
-                .to_string_lossy()
-                .to_string()
-        };
-
-        Ok(response_str)
+        Ok(DebugResponse {
+            payload: take_response_bytes(response, response_size, "command")?,
+        })
     }
 
     /// Sets the argument value for a command
@@ -245,26 +239,28 @@ impl DebugServer<'_> {
     ///
     /// ***Verified:*** False
     pub fn set_argv(&self, args: Vec<String>) -> Result<String, DebugServerError> {
-        let mut argv: Vec<*mut std::os::raw::c_char> = Vec::new();
         let mut c_strings = vec![];
         for arg in args {
-            let arg_c_str = std::ffi::CString::new(arg).unwrap().into_raw();
-            argv.push(arg_c_str);
-            c_strings.push(arg_c_str);
+            match std::ffi::CString::new(arg) {
+                Ok(arg) => c_strings.push(arg),
+                Err(_) => return Err(DebugServerError::InvalidArg),
+            }
         }
+        let mut argv: Vec<*mut c_char> = c_strings
+            .iter()
+            .map(|s| s.as_ptr() as *mut c_char)
+            .collect();
         argv.push(std::ptr::null_mut());
 
-        let mut response: std::os::raw::c_char = unsafe { std::mem::zeroed() };
-        let mut response_ptr: *mut std::os::raw::c_char = &mut response;
-        let response_ptr_ptr: *mut *mut std::os::raw::c_char = &mut response_ptr;
+        let mut response: *mut c_char = std::ptr::null_mut();
 
         info!("Setting argv for debug server");
         let result = unsafe {
             unsafe_bindings::debugserver_client_set_argv(
                 self.pointer,
-                argv.len() as i32,
+                c_strings.len() as i32,
                 argv.as_mut_ptr(),
-                response_ptr_ptr,
+                &mut response,
             )
         }
         .into();
@@ -272,16 +268,195 @@ impl DebugServer<'_> {
             return Err(result);
         }
 
+        if response.is_null() {
+            return Ok("".to_string());
+        }
         // Convert response to String
         let response_str = unsafe {
-            std::ffi::CStr::from_ptr(response_ptr)
+            let response_str = std::ffi::CStr::from_ptr(response)
                 .to_string_lossy()
-                .to_string()
+                .to_string();
+            libc::free(response as *mut libc::c_void);
+            response_str
         };
 
         Ok(response_str)
     }
 
+    /// Launches a process by setting its arguments and checking that it started
+    /// # Arguments
+    /// * `args` - The arguments, starting with the path to the executable on the device
+    /// # Returns
+    /// *none*
+    ///
+    /// ***Verified:*** False
+    pub fn launch(&self, args: &[&str]) -> Result<(), DebugServerError> {
+        launch_over(self, args)
+    }
+
+    /// Attaches to a running process
+    /// # Arguments
+    /// * `pid` - The process ID to attach to
+    /// # Returns
+    /// The stop reply sent once the process is halted
+    ///
+    /// ***Verified:*** False
+    pub fn attach(&self, pid: u32) -> Result<StopReply, DebugServerError> {
+        attach_over(self, pid)
+    }
+
+    /// Reads memory from the debugged process
+    /// # Arguments
+    /// * `addr` - The address to start reading from
+    /// * `len` - The number of bytes to read
+    /// # Returns
+    /// The bytes read. This may be shorter than `len` if part of the range is unreadable.
+    ///
+    /// ***Verified:*** False
+    pub fn read_memory(&self, addr: u64, len: usize) -> Result<Vec<u8>, DebugServerError> {
+        read_memory_over(self, addr, len)
+    }
+
+    /// Writes memory in the debugged process
+    /// # Arguments
+    /// * `addr` - The address to start writing at
+    /// * `data` - The bytes to write
+    /// # Returns
+    /// *none*
+    ///
+    /// ***Verified:*** False
+    pub fn write_memory(&self, addr: u64, data: &[u8]) -> Result<(), DebugServerError> {
+        write_memory_over(self, addr, data)
+    }
+
+    /// Sets the largest packet the debug server may send
+    /// # Arguments
+    /// * `size` - The maximum packet size in bytes
+    /// # Returns
+    /// *none*
+    ///
+    /// ***Verified:*** False
+    pub fn set_max_packet_size(&self, size: u32) -> Result<(), DebugServerError> {
+        set_max_packet_size_over(self, size)
+    }
+
+    /// Sets the working directory of the process that will be launched
+    /// # Arguments
+    /// * `path` - The path of the working directory on the device
+    /// # Returns
+    /// *none*
+    ///
+    /// ***Verified:*** False
+    pub fn set_working_directory(&self, path: &str) -> Result<(), DebugServerError> {
+        set_working_directory_over(self, path)
+    }
+
+    /// Sets environment variables for the process that will be launched.
+    /// Each variable is sent in its own `QEnvironmentHexEncoded` packet.
+    /// # Arguments
+    /// * `variables` - The variables as key value pairs
+    /// # Returns
+    /// *none*
+    ///
+    /// ***Verified:*** False
+    pub fn set_environment(&self, variables: &[(&str, &str)]) -> Result<(), DebugServerError> {
+        set_environment_over(self, variables)
+    }
+
+    /// Continues the process and blocks until it stops again
+    /// # Arguments
+    /// *none*
+    /// # Returns
+    /// Why the process stopped
+    ///
+    /// ***Verified:*** False
+    pub fn cont(&self) -> Result<StopReply, DebugServerError> {
+        let response = self.send_packet("c")?;
+        wait_for_stop(self, response)
+    }
+
+    /// Steps the current thread by a single instruction
+    /// # Arguments
+    /// *none*
+    /// # Returns
+    /// Why the process stopped, usually a trap after the step
+    ///
+    /// ***Verified:*** False
+    pub fn step(&self) -> Result<StopReply, DebugServerError> {
+        let response = self.send_packet("s")?;
+        wait_for_stop(self, response)
+    }
+
+    /// Reads the general purpose registers of the current thread
+    /// # Arguments
+    /// *none*
+    /// # Returns
+    /// The registers, tagged with the architecture of the process
+    ///
+    /// ***Verified:*** False
+    pub fn read_registers(&self) -> Result<RegisterSet, DebugServerError> {
+        let response = self.send_packet("g")?;
+        let data = hex_decode(&response)?;
+        Ok(RegisterSet::Arm64(Arm64Registers::from_bytes(&data)?))
+    }
+
+    /// Reads a single register of the current thread
+    /// # Arguments
+    /// * `index` - The register number, see `Arm64Registers::index_of`
+    /// # Returns
+    /// The value of the register
+    ///
+    /// ***Verified:*** False
+    pub fn read_register(&self, index: u32) -> Result<u64, DebugServerError> {
+        let response = self.send_packet(&format!("p{:x}", index))?;
+        let data = hex_decode(&response)?;
+        if data.is_empty() || data.len() > 8 {
+            return Err(DebugServerError::ResponseError);
+        }
+        Ok(read_le(&data))
+    }
+
+    /// Writes a single register of the current thread
+    /// # Arguments
+    /// * `index` - The register number, see `Arm64Registers::index_of`
+    /// * `value` - The value to write
+    /// # Returns
+    /// *none*
+    ///
+    /// ***Verified:*** False
+    pub fn write_register(&self, index: u32, value: u64) -> Result<(), DebugServerError> {
+        // cpsr is the only 32 bit general purpose register
+        let size = if index == Arm64Registers::CPSR_INDEX {
+            4
+        } else {
+            8
+        };
+        let response = self.send_packet(&format!(
+            "P{:x}={}",
+            index,
+            hex_encode(&value.to_le_bytes()[..size])
+        ))?;
+        expect_ok(&response)
+    }
+
+    /// Writes every general purpose register of the current thread, one register at a time
+    /// # Arguments
+    /// * `registers` - The values to write
+    /// # Returns
+    /// *none*
+    ///
+    /// ***Verified:*** False
+    pub fn write_registers(&self, registers: &RegisterSet) -> Result<(), DebugServerError> {
+        match registers {
+            RegisterSet::Arm64(registers) => {
+                for (index, value) in registers.values().iter().enumerate() {
+                    self.write_register(index as u32, *value)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
     /// Encodes a string into hex notation
     /// # Arguments
     /// * `buffer` - The string to encode
@@ -330,6 +505,175 @@ impl DebugServer<'_> {
     }
 }
 
+/// The packet level operations the typed helpers are built on,
+/// so the packets they send can be checked without a device
+trait PacketChannel {
+    /// Sends a raw GDB remote packet and returns the payload of the reply.
+    /// libimobiledevice adds the `$...#xx` framing, strips it from the reply and validates its checksum.
+    /// Replies of the form `Exx` are returned as errors.
+    fn send_packet(&self, payload: &str) -> Result<String, DebugServerError>;
+
+    /// Receives the next GDB remote packet without sending anything, such as a stop reply
+    fn receive_packet(&self) -> Result<String, DebugServerError>;
+
+    /// Sends the `A` packet with the arguments of the process to launch
+    fn set_arguments(&self, args: &[&str]) -> Result<String, DebugServerError>;
+}
+
+impl PacketChannel for DebugServer<'_> {
+    fn send_packet(&self, payload: &str) -> Result<String, DebugServerError> {
+        let command = match DebugServerCommand::new(payload.to_string(), vec![]) {
+            Ok(c) => c,
+            Err(_) => return Err(DebugServerError::InvalidArg),
+        };
+        let mut response: *mut c_char = std::ptr::null_mut();
+        let mut response_size: unsafe_bindings::size_t = 0;
+
+        trace!("Sending packet to debug server: {}", payload);
+        let result = unsafe {
+            unsafe_bindings::debugserver_client_send_command(
+                self.pointer,
+                command.command,
+                &mut response,
+                &mut response_size,
+            )
+        }
+        .into();
+        if result != DebugServerError::Success {
+            return Err(result);
+        }
+
+        take_response(response, response_size, payload)
+    }
+
+    fn receive_packet(&self) -> Result<String, DebugServerError> {
+        let mut response: *mut c_char = std::ptr::null_mut();
+        let mut response_size: unsafe_bindings::size_t = 0;
+        let result = unsafe {
+            unsafe_bindings::debugserver_client_receive_response(
+                self.pointer,
+                &mut response,
+                &mut response_size,
+            )
+        }
+        .into();
+        if result != DebugServerError::Success {
+            return Err(result);
+        }
+
+        take_response(response, response_size, "receive")
+    }
+
+    fn set_arguments(&self, args: &[&str]) -> Result<String, DebugServerError> {
+        self.set_argv(args.iter().map(|a| a.to_string()).collect())
+    }
+}
+
+/// Waits for the process to stop, skipping any console output packets
+fn wait_for_stop<C: PacketChannel>(
+    channel: &C,
+    mut response: String,
+) -> Result<StopReply, DebugServerError> {
+    while response.starts_with('O') && response != "OK" {
+        let output = hex_decode(&response[1..]).unwrap_or_default();
+        info!("Process output: {}", String::from_utf8_lossy(&output));
+        response = channel.receive_packet()?;
+    }
+    StopReply::parse(&response)
+}
+
+/// Sets the arguments of the process, then checks that it launched
+fn launch_over<C: PacketChannel>(channel: &C, args: &[&str]) -> Result<(), DebugServerError> {
+    let response = channel.set_arguments(args)?;
+    expect_ok(&response)?;
+
+    let response = channel.send_packet("qLaunchSuccess")?;
+    expect_ok(&response)
+}
+
+/// Attaches to a process and waits for it to halt
+fn attach_over<C: PacketChannel>(channel: &C, pid: u32) -> Result<StopReply, DebugServerError> {
+    let response = channel.send_packet(&format!("vAttach;{:x}", pid))?;
+    wait_for_stop(channel, response)
+}
+
+/// Reads memory in chunks, stopping early if the process can't read the whole range
+fn read_memory_over<C: PacketChannel>(
+    channel: &C,
+    addr: u64,
+    len: usize,
+) -> Result<Vec<u8>, DebugServerError> {
+    let mut data = Vec::with_capacity(len);
+    while data.len() < len {
+        let chunk_len = std::cmp::min(len - data.len(), MEMORY_CHUNK_SIZE);
+        let chunk_addr = addr + data.len() as u64;
+        let response = channel.send_packet(&format!("m{:x},{:x}", chunk_addr, chunk_len))?;
+        let chunk = hex_decode(&response)?;
+        let read = chunk.len();
+        data.extend(chunk);
+        if read < chunk_len {
+            break;
+        }
+    }
+
+    Ok(data)
+}
+
+/// Writes memory in chunks, checking each one was written
+fn write_memory_over<C: PacketChannel>(
+    channel: &C,
+    addr: u64,
+    data: &[u8],
+) -> Result<(), DebugServerError> {
+    for (i, chunk) in data.chunks(MEMORY_CHUNK_SIZE).enumerate() {
+        let chunk_addr = addr + (i * MEMORY_CHUNK_SIZE) as u64;
+        let response = channel.send_packet(&format!(
+            "M{:x},{:x}:{}",
+            chunk_addr,
+            chunk.len(),
+            hex_encode(chunk)
+        ))?;
+        expect_ok(&response)?;
+    }
+
+    Ok(())
+}
+
+/// Sets the largest packet the debug server may send
+fn set_max_packet_size_over<C: PacketChannel>(
+    channel: &C,
+    size: u32,
+) -> Result<(), DebugServerError> {
+    let response = channel.send_packet(&format!("QSetMaxPacketSize:{:x}", size))?;
+    expect_ok(&response)
+}
+
+/// Sets the working directory, hex encoded so any path can be sent
+fn set_working_directory_over<C: PacketChannel>(
+    channel: &C,
+    path: &str,
+) -> Result<(), DebugServerError> {
+    let response =
+        channel.send_packet(&format!("QSetWorkingDir:{}", hex_encode(path.as_bytes())))?;
+    expect_ok(&response)
+}
+
+/// Sets environment variables with one packet per variable
+fn set_environment_over<C: PacketChannel>(
+    channel: &C,
+    variables: &[(&str, &str)],
+) -> Result<(), DebugServerError> {
+    for (key, value) in variables {
+        let variable = format!("{}={}", key, value);
+        let response = channel.send_packet(&format!(
+            "QEnvironmentHexEncoded:{}",
+            hex_encode(variable.as_bytes())
+        ))?;
+        expect_ok(&response)?;
+    }
+    Ok(())
+}
+
 impl DebugServerCommand {
     /// Assembles a new debug server command
     /// # Arguments
@@ -378,6 +722,305 @@ impl DebugServerCommand {
     }
 }
 
+impl Drop for DebugServerCommand {
+    fn drop(&mut self) {
+        unsafe {
+            unsafe_bindings::debugserver_command_free(self.command);
+        }
+    }
+}
+
+/// Converts a reply allocated by libimobiledevice into a string and frees it.
+/// `Exx` error replies are returned as errors.
+fn take_response(
+    response: *mut c_char,
+    response_size: unsafe_bindings::size_t,
+    context: &str,
+) -> Result<String, DebugServerError> {
+    let bytes = take_response_bytes(response, response_size, context)?;
+    Ok(String::from_utf8_lossy(&bytes).into_owned())
+}
+
+/// Copies a reply allocated by libimobiledevice and frees it.
+/// `Exx` error replies are returned as errors.
+fn take_response_bytes(
+    response: *mut c_char,
+    response_size: unsafe_bindings::size_t,
+    context: &str,
+) -> Result<Vec<u8>, DebugServerError> {
+    // A bad checksum is reported through the result, but a missing reply is just as unusable
+    if response.is_null() {
+        warn!("Debug server gave no reply to {}", context);
+        return Err(DebugServerError::ResponseError);
+    }
+    let bytes = unsafe {
+        let bytes =
+            std::slice::from_raw_parts(response as *const u8, response_size as usize).to_vec();
+        libc::free(response as *mut libc::c_void);
+        bytes
+    };
+    trace!("Debug server replied: {}", String::from_utf8_lossy(&bytes));
+
+    if is_error_reply(&bytes) {
+        warn!(
+            "Debug server replied to {} with error {}",
+            context,
+            String::from_utf8_lossy(&bytes)
+        );
+        return Err(DebugServerError::ResponseError);
+    }
+
+    Ok(bytes)
+}
+
+/// The payload of a reply from the debug server, with its framing removed and checksum verified
+#[derive(Debug, Clone, PartialEq)]
+pub struct DebugResponse {
+    payload: Vec<u8>,
+}
+
+impl DebugResponse {
+    /// The raw bytes of the payload
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.payload
+    }
+
+    /// The payload as a string, replacing invalid UTF-8
+    pub fn to_string_lossy(&self) -> String {
+        String::from_utf8_lossy(&self.payload).into_owned()
+    }
+
+    /// Whether the reply is `OK`
+    pub fn is_ok(&self) -> bool {
+        self.payload == b"OK"
+    }
+
+    /// Whether the reply is empty, which the stub sends for unsupported commands
+    pub fn is_empty(&self) -> bool {
+        self.payload.is_empty()
+    }
+}
+
+/// Why a process stopped, parsed from a GDB remote stop reply packet
+#[derive(Debug, Clone, PartialEq)]
+pub enum StopReply {
+    /// `Sxx` - The process received a signal
+    Signal(u8),
+    /// `Txx` - The process received a signal, with extra information from the stub
+    Stopped {
+        signal: u8,
+        /// The thread that caused the stop
+        thread: Option<u64>,
+        /// Register values by register number, as reported with the stop
+        registers: BTreeMap<u32, u64>,
+        /// Any other key value pairs, such as `reason`
+        info: BTreeMap<String, String>,
+    },
+    /// `Wxx` - The process exited with a status
+    Exited(u8),
+    /// `Xxx` - The process was terminated by a signal
+    Terminated(u8),
+}
+
+impl StopReply {
+    /// Parses a stop reply packet
+    /// # Arguments
+    /// * `packet` - The payload of the packet, without framing
+    /// # Returns
+    /// The parsed stop reply
+    ///
+    /// ***Verified:*** False
+    pub fn parse(packet: &str) -> Result<Self, DebugServerError> {
+        let code = packet
+            .get(1..3)
+            .and_then(|c| u8::from_str_radix(c, 16).ok())
+            .ok_or(DebugServerError::ResponseError)?;
+        match packet.chars().next() {
+            Some('S') => Ok(StopReply::Signal(code)),
+            Some('W') => Ok(StopReply::Exited(code)),
+            Some('X') => Ok(StopReply::Terminated(code)),
+            Some('T') => {
+                let mut thread = None;
+                let mut registers = BTreeMap::new();
+                let mut info = BTreeMap::new();
+                for pair in packet[3..].split(';').filter(|p| !p.is_empty()) {
+                    let (key, value) = match pair.split_once(':') {
+                        Some(kv) => kv,
+                        None => continue,
+                    };
+                    if key == "thread" {
+                        thread = u64::from_str_radix(value, 16).ok();
+                    } else if let Ok(register) = u32::from_str_radix(key, 16) {
+                        let bytes = hex_decode(value)?;
+                        registers.insert(register, read_le(&bytes[..bytes.len().min(8)]));
+                    } else {
+                        info.insert(key.to_string(), value.to_string());
+                    }
+                }
+                Ok(StopReply::Stopped {
+                    signal: code,
+                    thread,
+                    registers,
+                    info,
+                })
+            }
+            _ => {
+                warn!("Unknown stop reply: {}", packet);
+                Err(DebugServerError::ResponseError)
+            }
+        }
+    }
+}
+
+/// The general purpose registers of a thread, tagged with the architecture
+#[derive(Debug, Clone, PartialEq)]
+pub enum RegisterSet {
+    Arm64(Arm64Registers),
+}
+
+impl RegisterSet {
+    /// Gets a register by name
+    /// # Arguments
+    /// * `name` - The name of the register, such as `x0`, `sp` or `pc`
+    /// # Returns
+    /// The value of the register, or None if the architecture has no such register
+    ///
+    /// ***Verified:*** False
+    pub fn get(&self, name: &str) -> Option<u64> {
+        match self {
+            RegisterSet::Arm64(registers) => registers.get(name),
+        }
+    }
+}
+
+/// The general purpose registers of an arm64 thread, in debugserver's numbering
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Arm64Registers {
+    pub x: [u64; 29],
+    pub fp: u64,
+    pub lr: u64,
+    pub sp: u64,
+    pub pc: u64,
+    pub cpsr: u32,
+}
+
+impl Arm64Registers {
+    /// The register number of cpsr, the last general purpose register
+    pub const CPSR_INDEX: u32 = 33;
+
+    /// Parses the start of a `g` reply, where each register is in target (little endian) byte order
+    fn from_bytes(data: &[u8]) -> Result<Self, DebugServerError> {
+        if data.len() < 33 * 8 + 4 {
+            warn!("Register data is too short for arm64: {} bytes", data.len());
+            return Err(DebugServerError::ResponseError);
+        }
+        let mut values = [0u64; 33];
+        for (i, value) in values.iter_mut().enumerate() {
+            *value = read_le(&data[i * 8..i * 8 + 8]);
+        }
+
+        let mut x = [0u64; 29];
+        x.copy_from_slice(&values[..29]);
+        Ok(Arm64Registers {
+            x,
+            fp: values[29],
+            lr: values[30],
+            sp: values[31],
+            pc: values[32],
+            cpsr: read_le(&data[33 * 8..33 * 8 + 4]) as u32,
+        })
+    }
+
+    /// Gets the register number of a named register, for use with `read_register` and `write_register`
+    /// # Arguments
+    /// * `name` - The name of the register, such as `x0`, `sp` or `pc`
+    /// # Returns
+    /// The register number
+    ///
+    /// ***Verified:*** False
+    pub fn index_of(name: &str) -> Option<u32> {
+        match name {
+            "fp" | "x29" => Some(29),
+            "lr" | "x30" => Some(30),
+            "sp" => Some(31),
+            "pc" => Some(32),
+            "cpsr" => Some(Self::CPSR_INDEX),
+            _ => match name.strip_prefix('x')?.parse::<u32>() {
+                Ok(i) if i < 29 => Some(i),
+                _ => None,
+            },
+        }
+    }
+
+    /// Gets a register by name
+    /// # Arguments
+    /// * `name` - The name of the register, such as `x0`, `sp` or `pc`
+    /// # Returns
+    /// The value of the register
+    ///
+    /// ***Verified:*** False
+    pub fn get(&self, name: &str) -> Option<u64> {
+        Self::index_of(name).map(|i| self.values()[i as usize])
+    }
+
+    /// The registers in debugserver's numbering
+    fn values(&self) -> [u64; 34] {
+        let mut values = [0u64; 34];
+        values[..29].copy_from_slice(&self.x);
+        values[29] = self.fp;
+        values[30] = self.lr;
+        values[31] = self.sp;
+        values[32] = self.pc;
+        values[33] = self.cpsr as u64;
+        values
+    }
+}
+
+/// Reads up to 8 little endian bytes into an integer
+fn read_le(data: &[u8]) -> u64 {
+    data.iter()
+        .rev()
+        .fold(0u64, |acc, b| (acc << 8) | *b as u64)
+}
+
+/// The largest number of bytes to read or write with a single memory packet
+const MEMORY_CHUNK_SIZE: usize = 0x400;
+
+/// Encodes bytes as hex, as used in GDB remote packets
+fn hex_encode(data: &[u8]) -> String {
+    data.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Decodes hex from a GDB remote packet into bytes
+fn hex_decode(data: &str) -> Result<Vec<u8>, DebugServerError> {
+    if !data.len().is_multiple_of(2) {
+        return Err(DebugServerError::ResponseError);
+    }
+    (0..data.len())
+        .step_by(2)
+        .map(|i| {
+            data.get(i..i + 2)
+                .and_then(|b| u8::from_str_radix(b, 16).ok())
+                .ok_or(DebugServerError::ResponseError)
+        })
+        .collect()
+}
+
+/// Checks whether a reply is an `Exx` error reply
+fn is_error_reply(response: &[u8]) -> bool {
+    response.len() == 3 && response[0] == b'E' && response[1..].iter().all(u8::is_ascii_hexdigit)
+}
+
+/// Checks that a reply is `OK`
+fn expect_ok(response: &str) -> Result<(), DebugServerError> {
+    if response == "OK" {
+        Ok(())
+    } else {
+        warn!("Expected OK from debug server, got {}", response);
+        Err(DebugServerError::ResponseError)
+    }
+}
+
 impl Into<DebugServerCommand> for String {
     fn into(self) -> DebugServerCommand {
         // Split string into command and arguments
@@ -392,3 +1035,316 @@ impl Into<DebugServerCommand> for &str {
         self.to_string().into()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::RefCell, collections::VecDeque};
+
+    use super::*;
+
+    /// Records every packet sent and answers with canned replies, in order
+    struct MockChannel {
+        sent: RefCell<Vec<Vec<u8>>>,
+        replies: RefCell<VecDeque<String>>,
+    }
+
+    impl MockChannel {
+        fn new<S: ToString>(replies: &[S]) -> Self {
+            MockChannel {
+                sent: RefCell::new(vec![]),
+                replies: RefCell::new(replies.iter().map(|r| r.to_string()).collect()),
+            }
+        }
+
+        fn reply(&self) -> Result<String, DebugServerError> {
+            let reply = self
+                .replies
+                .borrow_mut()
+                .pop_front()
+                .expect("no reply left");
+            if is_error_reply(reply.as_bytes()) {
+                return Err(DebugServerError::ResponseError);
+            }
+            Ok(reply)
+        }
+
+        fn sent(&self) -> Vec<Vec<u8>> {
+            self.sent.borrow().clone()
+        }
+    }
+
+    impl PacketChannel for MockChannel {
+        fn send_packet(&self, payload: &str) -> Result<String, DebugServerError> {
+            self.sent.borrow_mut().push(payload.as_bytes().to_vec());
+            self.reply()
+        }
+
+        fn receive_packet(&self) -> Result<String, DebugServerError> {
+            self.reply()
+        }
+
+        /// Records the `A` packet debugserver_client_set_argv would build
+        fn set_arguments(&self, args: &[&str]) -> Result<String, DebugServerError> {
+            let args: Vec<String> = args
+                .iter()
+                .enumerate()
+                .map(|(i, arg)| {
+                    let arg = hex_encode(arg.as_bytes());
+                    format!("{},{},{}", arg.len(), i, arg)
+                })
+                .collect();
+            self.send_packet(&format!("A{}", args.join(",")))
+        }
+    }
+
+    /// A `g` reply with x0..x28 = 0..28, fp = 0x1000, lr = 0x2000, sp = 0x3000,
+    /// pc = 0x100004000 and cpsr = 0x60000000
+    fn arm64_g_reply() -> String {
+        let mut values: Vec<u64> = (0..29).collect();
+        values.extend([0x1000, 0x2000, 0x3000, 0x1_0000_4000]);
+        let mut bytes: Vec<u8> = values.iter().flat_map(|v| v.to_le_bytes()).collect();
+        bytes.extend(0x6000_0000u32.to_le_bytes());
+        // debugserver also sends the floating point registers, which are ignored
+        bytes.extend([0u8; 16]);
+        hex_encode(&bytes)
+    }
+
+    #[test]
+    fn decodes_arm64_registers() {
+        let registers = Arm64Registers::from_bytes(&hex_decode(&arm64_g_reply()).unwrap()).unwrap();
+        assert_eq!(registers.x[0], 0);
+        assert_eq!(registers.x[28], 28);
+        assert_eq!(registers.fp, 0x1000);
+        assert_eq!(registers.lr, 0x2000);
+        assert_eq!(registers.sp, 0x3000);
+        assert_eq!(registers.pc, 0x1_0000_4000);
+        assert_eq!(registers.cpsr, 0x6000_0000);
+
+        let set = RegisterSet::Arm64(registers);
+        assert_eq!(set.get("x7"), Some(7));
+        assert_eq!(set.get("x29"), Some(0x1000));
+        assert_eq!(set.get("pc"), Some(0x1_0000_4000));
+        assert_eq!(set.get("cpsr"), Some(0x6000_0000));
+        assert_eq!(set.get("x29x"), None);
+        assert_eq!(set.get("x31"), None);
+    }
+
+    #[test]
+    fn rejects_short_register_data() {
+        assert_eq!(
+            Arm64Registers::from_bytes(&[0u8; 33 * 8]),
+            Err(DebugServerError::ResponseError)
+        );
+    }
+
+    #[test]
+    fn parses_signal_exit_and_terminate_replies() {
+        assert_eq!(StopReply::parse("S05"), Ok(StopReply::Signal(5)));
+        assert_eq!(StopReply::parse("W00"), Ok(StopReply::Exited(0)));
+        assert_eq!(StopReply::parse("X09"), Ok(StopReply::Terminated(9)));
+    }
+
+    #[test]
+    fn parses_stop_with_registers() {
+        let reply = StopReply::parse(
+            "T05thread:1a2b;20:0040000001000000;1f:0030000000000000;reason:breakpoint;",
+        )
+        .unwrap();
+        let mut registers = BTreeMap::new();
+        registers.insert(0x20, 0x1_0000_4000);
+        registers.insert(0x1f, 0x3000);
+        let mut info = BTreeMap::new();
+        info.insert("reason".to_string(), "breakpoint".to_string());
+        assert_eq!(
+            reply,
+            StopReply::Stopped {
+                signal: 5,
+                thread: Some(0x1a2b),
+                registers,
+                info,
+            }
+        );
+    }
+
+    #[test]
+    fn rejects_malformed_stop_replies() {
+        assert_eq!(StopReply::parse(""), Err(DebugServerError::ResponseError));
+        assert_eq!(StopReply::parse("S"), Err(DebugServerError::ResponseError));
+        assert_eq!(
+            StopReply::parse("Q05"),
+            Err(DebugServerError::ResponseError)
+        );
+        assert_eq!(
+            StopReply::parse("T0520:abc;"),
+            Err(DebugServerError::ResponseError)
+        );
+    }
+
+    /// Copies a reply into memory allocated like libimobiledevice's
+    fn malloc_reply(reply: &[u8]) -> (*mut c_char, unsafe_bindings::size_t) {
+        unsafe {
+            let response = libc::malloc(reply.len().max(1)) as *mut c_char;
+            std::ptr::copy_nonoverlapping(reply.as_ptr(), response as *mut u8, reply.len());
+            (response, reply.len() as unsafe_bindings::size_t)
+        }
+    }
+
+    #[test]
+    fn takes_good_response() {
+        let (response, size) = malloc_reply(b"OK");
+        let payload = take_response_bytes(response, size, "test").unwrap();
+        let response = DebugResponse { payload };
+        assert!(response.is_ok());
+        assert_eq!(response.as_bytes(), b"OK");
+        assert_eq!(response.to_string_lossy(), "OK");
+    }
+
+    #[test]
+    fn rejects_error_response() {
+        let (response, size) = malloc_reply(b"E08");
+        assert_eq!(
+            take_response_bytes(response, size, "test"),
+            Err(DebugServerError::ResponseError)
+        );
+        assert!(is_error_reply(b"E3f"));
+        assert!(!is_error_reply(b"E3"));
+        assert!(!is_error_reply(b"Exy"));
+        assert!(!is_error_reply(b"OK"));
+    }
+
+    #[test]
+    fn bad_checksum_is_response_error() {
+        // libimobiledevice reports a reply whose checksum doesn't match with this code
+        assert_eq!(
+            DebugServerError::from(
+                unsafe_bindings::debugserver_error_t_DEBUGSERVER_E_RESPONSE_ERROR
+            ),
+            DebugServerError::ResponseError
+        );
+        assert_eq!(
+            DebugServerError::from(unsafe_bindings::debugserver_error_t_DEBUGSERVER_E_TIMEOUT),
+            DebugServerError::Timeout
+        );
+        assert_eq!(
+            DebugServerError::from(
+                unsafe_bindings::debugserver_error_t_DEBUGSERVER_E_UNKNOWN_ERROR
+            ),
+            DebugServerError::UnknownError
+        );
+    }
+
+    #[test]
+    fn rejects_missing_reply() {
+        assert_eq!(
+            take_response_bytes(std::ptr::null_mut(), 0, "test"),
+            Err(DebugServerError::ResponseError)
+        );
+    }
+
+    #[test]
+    fn decodes_hex() {
+        assert_eq!(hex_decode("00ff7a"), Ok(vec![0x00, 0xff, 0x7a]));
+        assert_eq!(hex_decode(&hex_encode(b"memory")), Ok(b"memory".to_vec()));
+        assert_eq!(hex_decode("abc"), Err(DebugServerError::ResponseError));
+        assert_eq!(hex_decode("zz"), Err(DebugServerError::ResponseError));
+    }
+
+    #[test]
+    fn read_memory_packets() {
+        let channel = MockChannel::new(&["deadbeef"]);
+        assert_eq!(
+            read_memory_over(&channel, 0x1_0000_4000, 4).unwrap(),
+            vec![0xde, 0xad, 0xbe, 0xef]
+        );
+        assert_eq!(channel.sent(), vec![b"m100004000,4".to_vec()]);
+    }
+
+    #[test]
+    fn read_memory_is_split_into_chunks() {
+        let channel = MockChannel::new(&["00".repeat(MEMORY_CHUNK_SIZE), "0102".to_string()]);
+
+        let data = read_memory_over(&channel, 0x2000, MEMORY_CHUNK_SIZE + 4).unwrap();
+        // The second chunk came back short, so only the readable part is returned
+        assert_eq!(data.len(), MEMORY_CHUNK_SIZE + 2);
+        assert_eq!(
+            channel.sent(),
+            vec![b"m2000,400".to_vec(), b"m2400,4".to_vec()]
+        );
+    }
+
+    #[test]
+    fn write_memory_packets() {
+        let channel = MockChannel::new(&["OK"]);
+        write_memory_over(&channel, 0x1000, &[0x01, 0x02, 0xff]).unwrap();
+        assert_eq!(channel.sent(), vec![b"M1000,3:0102ff".to_vec()]);
+
+        let channel = MockChannel::new(&["E01"]);
+        assert_eq!(
+            write_memory_over(&channel, 0x1000, &[0x00]),
+            Err(DebugServerError::ResponseError)
+        );
+    }
+
+    #[test]
+    fn max_packet_size_packet() {
+        let channel = MockChannel::new(&["OK"]);
+        set_max_packet_size_over(&channel, 0x20000).unwrap();
+        assert_eq!(channel.sent(), vec![b"QSetMaxPacketSize:20000".to_vec()]);
+    }
+
+    #[test]
+    fn working_directory_packet() {
+        let channel = MockChannel::new(&["OK"]);
+        set_working_directory_over(&channel, "/tmp").unwrap();
+        assert_eq!(channel.sent(), vec![b"QSetWorkingDir:2f746d70".to_vec()]);
+    }
+
+    #[test]
+    fn environment_packets() {
+        let channel = MockChannel::new(&["OK", "OK"]);
+        set_environment_over(&channel, &[("A", "1"), ("HOME", "/")]).unwrap();
+        assert_eq!(
+            channel.sent(),
+            vec![
+                b"QEnvironmentHexEncoded:413d31".to_vec(),
+                b"QEnvironmentHexEncoded:484f4d453d2f".to_vec(),
+            ]
+        );
+
+        // Stops at the first variable that isn't accepted
+        let channel = MockChannel::new(&["E08", "OK"]);
+        assert_eq!(
+            set_environment_over(&channel, &[("A", "1"), ("B", "2")]),
+            Err(DebugServerError::ResponseError)
+        );
+        assert_eq!(channel.sent().len(), 1);
+    }
+
+    #[test]
+    fn launch_sets_arguments_before_checking_launch() {
+        let channel = MockChannel::new(&["OK", "OK"]);
+        launch_over(&channel, &["/bin/ls", "-l"]).unwrap();
+        assert_eq!(
+            channel.sent(),
+            vec![
+                b"A14,0,2f62696e2f6c73,4,1,2d6c".to_vec(),
+                b"qLaunchSuccess".to_vec(),
+            ]
+        );
+
+        // The launch is not checked if the arguments were rejected
+        let channel = MockChannel::new(&["E01"]);
+        assert_eq!(
+            launch_over(&channel, &["/bin/ls"]),
+            Err(DebugServerError::ResponseError)
+        );
+        assert_eq!(channel.sent().len(), 1);
+    }
+
+    #[test]
+    fn attach_waits_for_stop() {
+        let channel = MockChannel::new(&["O6869", "S11"]);
+        assert_eq!(attach_over(&channel, 0x1f4), Ok(StopReply::Signal(0x11)));
+        assert_eq!(channel.sent(), vec![b"vAttach;1f4".to_vec()]);
+    }
+}
@@ -1,6 +1,9 @@
 // jkcoxson
 
+use std::{ffi::CString, os::raw::c_char};
+
 use libc::c_uint;
+use log::info;
 
 use crate::{
     bindings as unsafe_bindings, error::DiagnosticsRelayError, idevice::Device,
@@ -10,12 +13,14 @@ use crate::{
 use plist_plus::Plist;
 
 /// Relays diagnostic logs from the iOS device to the host
-pub struct DiagnosticsRelay<'a> {
+pub struct DiagnosticsRelayClient<'a> {
     pub(crate) pointer: unsafe_bindings::diagnostics_relay_client_t,
     phantom: std::marker::PhantomData<&'a Device>,
 }
 
-impl DiagnosticsRelay<'_> {
+unsafe impl Send for DiagnosticsRelayClient<'_> {}
+
+impl DiagnosticsRelayClient<'_> {
     /// Creates a new diagnostics relay service from a lockdown service
     /// # Arguments
     /// * `device` - The device to create the sevice with
@@ -39,8 +44,8 @@ impl DiagnosticsRelay<'_> {
             return Err(result);
         }
 
-        Ok(DiagnosticsRelay {
-            pointer: pointer,
+        Ok(DiagnosticsRelayClient {
+            pointer,
             phantom: std::marker::PhantomData,
         })
     }
@@ -53,13 +58,14 @@ impl DiagnosticsRelay<'_> {
     /// A struct containing the handle to the service
     ///
     /// ***Verified:*** False
-    pub fn start_service(device: &Device, label: String) -> Result<Self, DiagnosticsRelayError> {
+    pub fn start_service(device: &Device, label: &str) -> Result<Self, DiagnosticsRelayError> {
+        let label_c_str = c_string(label)?;
         let mut pointer = std::ptr::null_mut();
         let result = unsafe {
             unsafe_bindings::diagnostics_relay_client_start_service(
                 device.pointer,
                 &mut pointer,
-                label.as_ptr() as *const std::os::raw::c_char,
+                label_c_str.as_ptr(),
             )
         }
         .into();
@@ -68,8 +74,8 @@ impl DiagnosticsRelay<'_> {
             return Err(result);
         }
 
-        Ok(DiagnosticsRelay {
-            pointer: pointer,
+        Ok(DiagnosticsRelayClient {
+            pointer,
             phantom: std::marker::PhantomData,
         })
     }
@@ -100,6 +106,7 @@ impl DiagnosticsRelay<'_> {
     ///
     /// ***Verified:*** False
     pub fn sleep(self) -> Result<(), DiagnosticsRelayError> {
+        info!("Putting the device to sleep");
         let result = unsafe { unsafe_bindings::diagnostics_relay_sleep(self.pointer) }.into();
 
         if result != DiagnosticsRelayError::Success {
@@ -111,14 +118,17 @@ impl DiagnosticsRelay<'_> {
 
     /// Restarts the connected device, breaking the connection.
     /// # Arguments
-    /// * `flag` - A flag to determine actions for the restart
+    /// * `actions` - Actions to take during the restart. Pass an empty slice for none.
     /// # Returns
     /// *none*
     ///
     /// ***Verified:*** False
-    pub fn restart(self, flag: c_uint) -> Result<(), DiagnosticsRelayError> {
-        let result =
-            unsafe { unsafe_bindings::diagnostics_relay_restart(self.pointer, flag) }.into();
+    pub fn restart(self, actions: &[DiagnosticsRelayAction]) -> Result<(), DiagnosticsRelayError> {
+        info!("Restarting the device");
+        let result = unsafe {
+            unsafe_bindings::diagnostics_relay_restart(self.pointer, action_flags(actions))
+        }
+        .into();
 
         if result != DiagnosticsRelayError::Success {
             return Err(result);
@@ -129,14 +139,17 @@ impl DiagnosticsRelay<'_> {
 
     /// Shuts the device off, breaking the connection.
     /// # Arguments
-    /// * `flag` - A flag to determine actions for the restart
+    /// * `actions` - Actions to take during the shutdown. Pass an empty slice for none.
     /// # Returns
     /// *none*
     ///
     /// ***Verified:*** False
-    pub fn shutdown(self, flag: c_uint) -> Result<(), DiagnosticsRelayError> {
-        let result =
-            unsafe { unsafe_bindings::diagnostics_relay_shutdown(self.pointer, flag) }.into();
+    pub fn shutdown(self, actions: &[DiagnosticsRelayAction]) -> Result<(), DiagnosticsRelayError> {
+        info!("Shutting down the device");
+        let result = unsafe {
+            unsafe_bindings::diagnostics_relay_shutdown(self.pointer, action_flags(actions))
+        }
+        .into();
 
         if result != DiagnosticsRelayError::Success {
             return Err(result);
@@ -147,17 +160,18 @@ impl DiagnosticsRelay<'_> {
 
     /// Requests diagnostics from the device
     /// # Arguments
-    /// * `type_` - The type of diagnostics to request
+    /// * `type_` - The type of diagnostics to request, such as "All", "WiFi", "GasGauge" or "NAND"
     /// # Returns
     /// A plist containing the diagnostics data
     ///
     /// ***Verified:*** False
-    pub fn request_diagnostics(&self, type_: String) -> Result<Plist, DiagnosticsRelayError> {
+    pub fn request_diagnostics(&self, type_: &str) -> Result<Plist, DiagnosticsRelayError> {
+        let type_c_str = c_string(type_)?;
         let mut plist = std::ptr::null_mut();
         let result = unsafe {
             unsafe_bindings::diagnostics_relay_request_diagnostics(
                 self.pointer,
-                type_.as_ptr() as *const std::os::raw::c_char,
+                type_c_str.as_ptr(),
                 &mut plist,
             )
         }
@@ -170,11 +184,11 @@ impl DiagnosticsRelay<'_> {
         Ok(plist.into())
     }
 
-    /// Usage unknown
+    /// Queries values from the device's MobileGestalt
     /// # Arguments
-    /// `keys` - Unknown
+    /// `keys` - A plist array of the MobileGestalt keys to query, such as "UniqueChipID"
     /// # Returns
-    /// A plist with unknown usage
+    /// A plist containing the values for the keys
     ///
     /// ***Verified:*** False
     pub fn query_mobilegestalt(&self, keys: Plist) -> Result<Plist, DiagnosticsRelayError> {
@@ -195,25 +209,32 @@ impl DiagnosticsRelay<'_> {
         Ok(plist.into())
     }
 
-    /// Requests data from the device's IO registry
+    /// Requests data from the device's IO registry.
+    /// At least one of the name or class must be given.
     /// # Arguments
-    /// * `entry_name` - The name to request
-    /// * `entry_class` - The class to request
+    /// * `entry_name` - The name of the entry to request
+    /// * `entry_class` - The class of the entry to request, such as "IOPMPowerSource" for battery info
     /// # Returns
-    /// A plist containing the entry
+    /// A plist containing the entry's properties
     ///
     /// ***Verified:*** False
     pub fn query_ioregistry_entry(
         &self,
-        entry_name: String,
-        entry_class: String,
+        entry_name: Option<&str>,
+        entry_class: Option<&str>,
     ) -> Result<Plist, DiagnosticsRelayError> {
+        if entry_name.is_none() && entry_class.is_none() {
+            return Err(DiagnosticsRelayError::InvalidArg);
+        }
+        let entry_name_c_str = entry_name.map(c_string).transpose()?;
+        let entry_class_c_str = entry_class.map(c_string).transpose()?;
+
         let mut plist = std::ptr::null_mut();
         let result = unsafe {
             unsafe_bindings::diagnostics_relay_query_ioregistry_entry(
                 self.pointer,
-                entry_name.as_ptr() as *const std::os::raw::c_char,
-                entry_class.as_ptr() as *const std::os::raw::c_char,
+                optional_c_string_ptr(&entry_name_c_str),
+                optional_c_string_ptr(&entry_class_c_str),
                 &mut plist,
             )
         }
@@ -223,22 +244,26 @@ impl DiagnosticsRelay<'_> {
             return Err(result);
         }
 
-        Ok(plist.into())
+        ioregistry_entry(plist.into())
     }
 
-    /// Usage unknown
+    /// Requests an entire plane of the device's IO registry
     /// # Arguments
-    /// * `plane` - Unknown
+    /// * `plane` - The plane to request, such as "IODeviceTree". Pass None for the default plane.
     /// # Returns
     /// A plist containing the requested data
     ///
     /// ***Verified:*** False
-    pub fn query_ioregistry_plane(&self, plane: String) -> Result<Plist, DiagnosticsRelayError> {
+    pub fn query_ioregistry_plane(
+        &self,
+        plane: Option<&str>,
+    ) -> Result<Plist, DiagnosticsRelayError> {
+        let plane_c_str = plane.map(c_string).transpose()?;
         let mut plist = std::ptr::null_mut();
         let result = unsafe {
             unsafe_bindings::diagnostics_relay_query_ioregistry_plane(
                 self.pointer,
-                plane.as_ptr() as *const std::os::raw::c_char,
+                optional_c_string_ptr(&plane_c_str),
                 &mut plist,
             )
         }
@@ -252,9 +277,14 @@ impl DiagnosticsRelay<'_> {
     }
 }
 
+/// Actions the device can take when restarting or shutting down
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum DiagnosticsRelayAction {
+    /// Wait until the host disconnects before acting
     WaitForDisconnect,
+    /// Show an "OK" dialog
     DisplayPass,
+    /// Show a "FAIL" dialog
     DisplayFail,
 }
 
@@ -268,10 +298,96 @@ impl From<DiagnosticsRelayAction> for c_uint {
     }
 }
 
-impl Drop for DiagnosticsRelay<'_> {
+fn action_flags(actions: &[DiagnosticsRelayAction]) -> c_uint {
+    actions
+        .iter()
+        .fold(0, |flags, action| flags | c_uint::from(*action))
+}
+
+/// Pulls the entry out of the diagnostics returned for an IO registry query
+fn ioregistry_entry(diagnostics: Plist) -> Result<Plist, DiagnosticsRelayError> {
+    match diagnostics.dict_get_item("IORegistry") {
+        Ok(entry) => Ok(entry.clone()),
+        Err(_) => Err(DiagnosticsRelayError::PlistError),
+    }
+}
+
+fn c_string(value: &str) -> Result<CString, DiagnosticsRelayError> {
+    match CString::new(value) {
+        Ok(s) => Ok(s),
+        Err(_) => Err(DiagnosticsRelayError::InvalidArg),
+    }
+}
+
+fn optional_c_string_ptr(value: &Option<CString>) -> *const c_char {
+    match value {
+        Some(s) => s.as_ptr(),
+        None => std::ptr::null(),
+    }
+}
+
+impl Drop for DiagnosticsRelayClient<'_> {
     fn drop(&mut self) {
+        info!("Dropping diagnostics relay client");
         unsafe {
             unsafe_bindings::diagnostics_relay_client_free(self.pointer);
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_ioregistry_entry() {
+        let mut battery = Plist::new_dict();
+        battery
+            .dict_set_item("CurrentCapacity", Plist::new_uint(87))
+            .unwrap();
+        battery
+            .dict_set_item("IsCharging", Plist::new_bool(true))
+            .unwrap();
+        let mut diagnostics = Plist::new_dict();
+        diagnostics.dict_set_item("IORegistry", battery).unwrap();
+
+        let entry = ioregistry_entry(diagnostics).unwrap();
+        assert_eq!(
+            entry
+                .dict_get_item("CurrentCapacity")
+                .unwrap()
+                .get_uint_val()
+                .unwrap(),
+            87
+        );
+        assert!(entry
+            .dict_get_item("IsCharging")
+            .unwrap()
+            .get_bool_val()
+            .unwrap());
+    }
+
+    #[test]
+    fn ioregistry_reply_without_entry_is_an_error() {
+        let mut diagnostics = Plist::new_dict();
+        diagnostics
+            .dict_set_item("GasGauge", Plist::new_dict())
+            .unwrap();
+        assert_eq!(
+            ioregistry_entry(diagnostics).err(),
+            Some(DiagnosticsRelayError::PlistError)
+        );
+    }
+
+    #[test]
+    fn action_flags_combine() {
+        assert_eq!(action_flags(&[]), 0);
+        assert_eq!(
+            action_flags(&[
+                DiagnosticsRelayAction::WaitForDisconnect,
+                DiagnosticsRelayAction::DisplayFail
+            ]),
+            10
+        );
+    }
+}
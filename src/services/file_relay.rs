@@ -1,10 +1,16 @@
 // jkcoxson
 
-use std::{ffi::CString, os::raw::c_char};
+use std::{ffi::CString, io::Read, os::raw::c_char, str::FromStr};
+
+use flate2::read::GzDecoder;
+use log::{info, warn};
 
 use crate::{
-    bindings as unsafe_bindings, connection::DeviceConnection, error::FileRelayError,
-    idevice::Device, services::lockdownd::LockdowndService,
+    bindings as unsafe_bindings,
+    connection::DeviceConnection,
+    error::{FileRelayError, IdeviceError},
+    idevice::Device,
+    services::lockdownd::LockdowndService,
 };
 
 /// Relays files from the iOS device to the host
@@ -13,6 +19,9 @@ pub struct FileRelay<'a> {
     phantom: std::marker::PhantomData<&'a Device>,
 }
 
+// Only Send, file relay requests have no locking of their own
+unsafe impl Send for FileRelay<'_> {}
+
 impl FileRelay<'_> {
     /// Creates a new file relay service from a lockdown service
     /// # Arguments
@@ -22,7 +31,7 @@ impl FileRelay<'_> {
     /// A struct containing the handle to the service
     ///
     /// ***Verified:*** False
-    pub fn new(device: &Device, service: LockdowndService) -> Result<Self, FileRelayError> {
+    pub fn new(device: &Device, service: &LockdowndService) -> Result<Self, FileRelayError> {
         let mut pointer = std::ptr::null_mut();
         let result = unsafe {
             unsafe_bindings::file_relay_client_new(device.pointer, service.pointer, &mut pointer)
@@ -72,30 +81,29 @@ impl FileRelay<'_> {
     /// Data will be placed in /tmp/mobile_file_relay.XXXX and must be removed manually.
     /// # Arguments
     /// * `sources` - A list of sources to request data for
-    /// * `connection` - A connection to the device
     /// * `timeout` - How long to wait for a response. If 0, this will block indefinitely.
     /// # Returns
-    /// *none*
-    pub fn request_sources(
-        &self,
-        sources: Vec<FileRelaySources>,
-        mut connection: DeviceConnection,
+    /// The file relay's connection, which the data is sent over. It is owned by this client,
+    /// so it can't outlive it. Read the data with `read_archive`.
+    ///
+    /// ***Verified:*** False
+    pub fn request_sources<'b>(
+        &'b self,
+        sources: &[FileRelaySources],
         timeout: u32,
-    ) -> Result<(), FileRelayError> {
-        let sources: Vec<FileRelaySources> = sources.into();
-        let mut source_ptrs = vec![];
-        for source in sources {
-            let source: CString = source.into();
-            source_ptrs.push(source.into_raw() as *const c_char);
-        }
+    ) -> Result<DeviceConnection<'b>, FileRelayError> {
+        // The CStrings are owned here so they are freed on every return path
+        let source_c_strings = source_names(sources);
+        let mut source_ptrs = null_terminated(&source_c_strings);
         let ptrs_ptr = source_ptrs.as_mut_ptr();
 
+        let mut relay_connection = std::ptr::null_mut();
         if timeout == 0 {
             let result = unsafe {
                 unsafe_bindings::file_relay_request_sources(
                     self.pointer,
                     ptrs_ptr,
-                    &mut connection.pointer,
+                    &mut relay_connection,
                 )
             }
             .into();
@@ -108,7 +116,7 @@ impl FileRelay<'_> {
                 unsafe_bindings::file_relay_request_sources_timeout(
                     self.pointer,
                     ptrs_ptr,
-                    &mut connection.pointer,
+                    &mut relay_connection,
                     timeout,
                 )
             }
@@ -119,11 +127,143 @@ impl FileRelay<'_> {
             }
         }
 
-        Ok(())
+        Ok(DeviceConnection::borrowed(relay_connection))
+    }
+
+    /// Reads the data sent after a successful `request_sources`.
+    /// The device sends a gzip compressed cpio archive and closes the connection when done.
+    /// # Arguments
+    /// * `connection` - The connection returned by `request_sources`
+    /// # Returns
+    /// The path and contents of each file in the archive
+    ///
+    /// ***Verified:*** False
+    pub fn read_archive(
+        &self,
+        connection: &mut DeviceConnection<'_>,
+    ) -> Result<Vec<(String, Vec<u8>)>, FileRelayError> {
+        let mut compressed = vec![];
+        let mut buffer = vec![0u8; 4096];
+        loop {
+            let mut received = 0;
+            let result: IdeviceError = unsafe {
+                unsafe_bindings::idevice_connection_receive(
+                    connection.pointer,
+                    buffer.as_mut_ptr() as *mut c_char,
+                    buffer.len() as u32,
+                    &mut received,
+                )
+            }
+            .into();
+            if received > 0 {
+                compressed.extend_from_slice(&buffer[..received as usize]);
+            }
+            if result != IdeviceError::Success || received == 0 {
+                // The device closes the connection once everything has been sent
+                break;
+            }
+        }
+        info!("Received {} bytes from file relay", compressed.len());
+
+        decode_archive(&compressed)
     }
 }
 
+/// Converts sources to the names the device expects
+fn source_names(sources: &[FileRelaySources]) -> Vec<CString> {
+    sources.iter().map(|&s| s.into()).collect()
+}
+
+/// Builds the NULL terminated array of string pointers libimobiledevice expects.
+/// The pointers borrow from `strings`, which must outlive the array.
+fn null_terminated(strings: &[CString]) -> Vec<*const c_char> {
+    let mut pointers: Vec<*const c_char> = strings.iter().map(|s| s.as_ptr()).collect();
+    pointers.push(std::ptr::null());
+    pointers
+}
+
+/// Decompresses and unpacks the gzip compressed cpio archive sent by the device
+fn decode_archive(compressed: &[u8]) -> Result<Vec<(String, Vec<u8>)>, FileRelayError> {
+    if compressed.is_empty() {
+        return Err(FileRelayError::StagingEmpty);
+    }
+
+    let mut archive = vec![];
+    if let Err(e) = GzDecoder::new(compressed).read_to_end(&mut archive) {
+        warn!("Unable to decompress file relay archive: {}", e);
+        return Err(FileRelayError::UnknownError);
+    }
+
+    parse_cpio(&archive)
+}
+
+/// Parses a cpio archive in either the odc (070707) or newc (070701) format.
+/// Only regular files are returned.
+fn parse_cpio(archive: &[u8]) -> Result<Vec<(String, Vec<u8>)>, FileRelayError> {
+    fn field(archive: &[u8], start: usize, len: usize, radix: u32) -> Option<usize> {
+        let bytes = archive.get(start..start + len)?;
+        usize::from_str_radix(std::str::from_utf8(bytes).ok()?, radix).ok()
+    }
+    fn align(value: usize, to: usize) -> usize {
+        value.next_multiple_of(to)
+    }
+
+    let mut files = vec![];
+    let mut offset = 0;
+    while offset < archive.len() {
+        let magic = archive.get(offset..offset + 6);
+        let (mode, name_size, file_size, name_start, alignment) = match magic {
+            Some(b"070707") => (
+                field(archive, offset + 18, 6, 8),
+                field(archive, offset + 59, 6, 8),
+                field(archive, offset + 65, 11, 8),
+                offset + 76,
+                1,
+            ),
+            Some(b"070701") | Some(b"070702") => (
+                field(archive, offset + 14, 8, 16),
+                field(archive, offset + 94, 8, 16),
+                field(archive, offset + 54, 8, 16),
+                offset + 110,
+                4,
+            ),
+            _ => {
+                warn!("Unknown cpio header at offset {}", offset);
+                return Err(FileRelayError::UnknownError);
+            }
+        };
+        let (mode, name_size, file_size) = match (mode, name_size, file_size) {
+            (Some(m), Some(n), Some(f)) => (m, n, f),
+            _ => return Err(FileRelayError::UnknownError),
+        };
+
+        let name = match archive.get(name_start..name_start + name_size) {
+            Some(name) => String::from_utf8_lossy(name)
+                .trim_end_matches('\0')
+                .to_string(),
+            None => return Err(FileRelayError::UnknownError),
+        };
+        if name == "TRAILER!!!" {
+            break;
+        }
+
+        let data_start = align(name_start + name_size, alignment);
+        let data = match archive.get(data_start..data_start + file_size) {
+            Some(data) => data,
+            None => return Err(FileRelayError::UnknownError),
+        };
+        // Only keep regular files
+        if mode & 0o170000 == 0o100000 {
+            files.push((name, data.to_vec()));
+        }
+        offset = align(data_start + file_size, alignment);
+    }
+
+    Ok(files)
+}
+
 /// The different types of interface sources that can have data requested for
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum FileRelaySources {
     AppleSupport,
     Network,
@@ -135,18 +275,65 @@ pub enum FileRelaySources {
     SystemConfiguration,
 }
 
+impl FileRelaySources {
+    /// Gets the name of the source as understood by the device
+    /// # Arguments
+    /// *none*
+    /// # Returns
+    /// The name of the source
+    ///
+    /// ***Verified:*** False
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            FileRelaySources::AppleSupport => "AppleSupport",
+            FileRelaySources::Network => "Network",
+            FileRelaySources::VPN => "VPN",
+            FileRelaySources::WiFi => "WiFi",
+            FileRelaySources::UserDatabases => "UserDatabases",
+            FileRelaySources::CrashReporter => "CrashReporter",
+            FileRelaySources::Tmp => "Tmp",
+            FileRelaySources::SystemConfiguration => "SystemConfiguration",
+        }
+    }
+}
+
+impl FromStr for FileRelaySources {
+    type Err = FileRelayError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "AppleSupport" => Ok(FileRelaySources::AppleSupport),
+            "Network" => Ok(FileRelaySources::Network),
+            "VPN" => Ok(FileRelaySources::VPN),
+            "WiFi" => Ok(FileRelaySources::WiFi),
+            "UserDatabases" => Ok(FileRelaySources::UserDatabases),
+            "CrashReporter" => Ok(FileRelaySources::CrashReporter),
+            "Tmp" => Ok(FileRelaySources::Tmp),
+            "SystemConfiguration" => Ok(FileRelaySources::SystemConfiguration),
+            _ => Err(FileRelayError::InvalidSource),
+        }
+    }
+}
+
+impl TryFrom<&str> for FileRelaySources {
+    type Error = FileRelayError;
+
+    fn try_from(s: &str) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
 impl From<FileRelaySources> for CString {
     fn from(source: FileRelaySources) -> Self {
-        match source {
-            FileRelaySources::AppleSupport => CString::new("AppleSupport").unwrap(),
-            FileRelaySources::Network => CString::new("Network").unwrap(),
-            FileRelaySources::VPN => CString::new("VPN").unwrap(),
-            FileRelaySources::WiFi => CString::new("WiFi").unwrap(),
-            FileRelaySources::UserDatabases => CString::new("UserDatabases").unwrap(),
-            FileRelaySources::CrashReporter => CString::new("CrashReporter").unwrap(),
-            FileRelaySources::Tmp => CString::new("Tmp").unwrap(),
-            FileRelaySources::SystemConfiguration => CString::new("SystemConfiguration").unwrap(),
-        }
+        CString::new(source.as_str()).unwrap()
+    }
+}
+
+impl std::fmt::Debug for FileRelay<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("FileRelay")
+            .field("connected", &!self.pointer.is_null())
+            .finish()
     }
 }
 
@@ -157,3 +344,244 @@ impl Drop for FileRelay<'_> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use flate2::{write::GzEncoder, Compression};
+
+    use super::*;
+
+    fn newc_entry(name: &str, mode: u32, data: &[u8]) -> Vec<u8> {
+        let name_size = name.len() + 1;
+        let mut entry = format!(
+            "070701{:08x}{:08x}{:08x}{:08x}{:08x}{:08x}{:08x}{:08x}{:08x}{:08x}{:08x}{:08x}{:08x}",
+            0,
+            mode,
+            0,
+            0,
+            1,
+            0,
+            data.len(),
+            0,
+            0,
+            0,
+            0,
+            name_size,
+            0
+        )
+        .into_bytes();
+        entry.extend_from_slice(name.as_bytes());
+        entry.push(0);
+        entry.resize(entry.len().next_multiple_of(4), 0);
+        entry.extend_from_slice(data);
+        entry.resize(entry.len().next_multiple_of(4), 0);
+        entry
+    }
+
+    fn odc_entry(name: &str, mode: u32, data: &[u8]) -> Vec<u8> {
+        let mut entry = format!(
+            "070707{:06o}{:06o}{:06o}{:06o}{:06o}{:06o}{:06o}{:011o}{:06o}{:011o}",
+            0,
+            0,
+            mode,
+            0,
+            0,
+            1,
+            0,
+            0,
+            name.len() + 1,
+            data.len()
+        )
+        .into_bytes();
+        entry.extend_from_slice(name.as_bytes());
+        entry.push(0);
+        entry.extend_from_slice(data);
+        entry
+    }
+
+    fn fixture() -> Vec<u8> {
+        let mut archive = vec![];
+        archive.extend(newc_entry("./var", 0o040755, b""));
+        archive.extend(newc_entry("./var/log/a.log", 0o100644, b"hello"));
+        archive.extend(newc_entry("./var/log/b.log", 0o100644, b"device log"));
+        archive.extend(newc_entry("TRAILER!!!", 0, b""));
+        archive
+    }
+
+    #[test]
+    fn parses_newc_regular_files() {
+        let files = parse_cpio(&fixture()).unwrap();
+        assert_eq!(
+            files,
+            vec![
+                ("./var/log/a.log".to_string(), b"hello".to_vec()),
+                ("./var/log/b.log".to_string(), b"device log".to_vec()),
+            ]
+        );
+    }
+
+    #[test]
+    fn parses_odc_archive() {
+        let mut archive = odc_entry("crash.ips", 0o100600, b"report");
+        archive.extend(odc_entry("TRAILER!!!", 0, b""));
+        let files = parse_cpio(&archive).unwrap();
+        assert_eq!(files, vec![("crash.ips".to_string(), b"report".to_vec())]);
+    }
+
+    #[test]
+    fn rejects_truncated_archive() {
+        let archive = fixture();
+        assert_eq!(
+            parse_cpio(&archive[..130]),
+            Err(FileRelayError::UnknownError)
+        );
+        assert_eq!(parse_cpio(b"garbage"), Err(FileRelayError::UnknownError));
+    }
+
+    #[test]
+    fn decodes_gzip_archive() {
+        let mut encoder = GzEncoder::new(vec![], Compression::default());
+        encoder.write_all(&fixture()).unwrap();
+        let compressed = encoder.finish().unwrap();
+
+        let files = decode_archive(&compressed).unwrap();
+        assert_eq!(files.len(), 2);
+        assert_eq!(files[1].1, b"device log");
+        assert_eq!(decode_archive(&[]), Err(FileRelayError::StagingEmpty));
+        assert_eq!(
+            decode_archive(b"not gzip"),
+            Err(FileRelayError::UnknownError)
+        );
+    }
+
+    #[test]
+    fn sources_round_trip_through_str() {
+        let sources = [
+            FileRelaySources::AppleSupport,
+            FileRelaySources::Network,
+            FileRelaySources::VPN,
+            FileRelaySources::WiFi,
+            FileRelaySources::UserDatabases,
+            FileRelaySources::CrashReporter,
+            FileRelaySources::Tmp,
+            FileRelaySources::SystemConfiguration,
+        ];
+        for source in sources {
+            assert_eq!(source.as_str().parse::<FileRelaySources>(), Ok(source));
+            assert_eq!(FileRelaySources::try_from(source.as_str()), Ok(source));
+            assert_eq!(CString::from(source).to_str().unwrap(), source.as_str());
+        }
+        assert_eq!(
+            "crashreporter".parse::<FileRelaySources>(),
+            Err(FileRelayError::InvalidSource)
+        );
+    }
+
+    #[test]
+    fn debug_shows_connection_without_dereferencing() {
+        let relay = FileRelay {
+            pointer: std::ptr::null_mut(),
+            phantom: std::marker::PhantomData,
+        };
+        assert_eq!(format!("{:?}", relay), "FileRelay { connected: false }");
+        std::mem::forget(relay);
+
+        let relay = FileRelay {
+            pointer: std::ptr::NonNull::dangling().as_ptr(),
+            phantom: std::marker::PhantomData,
+        };
+        assert_eq!(format!("{:?}", relay), "FileRelay { connected: true }");
+        std::mem::forget(relay);
+    }
+
+    thread_local! {
+        // Per thread, so tests running in parallel don't disturb each other
+        static LIVE_BYTES: std::cell::Cell<isize> = const { std::cell::Cell::new(0) };
+    }
+
+    /// Counts the bytes allocated and not yet freed by the current thread
+    struct CountingAllocator;
+
+    unsafe impl std::alloc::GlobalAlloc for CountingAllocator {
+        unsafe fn alloc(&self, layout: std::alloc::Layout) -> *mut u8 {
+            let _ = LIVE_BYTES.try_with(|live| live.set(live.get() + layout.size() as isize));
+            std::alloc::System.alloc(layout)
+        }
+
+        unsafe fn dealloc(&self, ptr: *mut u8, layout: std::alloc::Layout) {
+            let _ = LIVE_BYTES.try_with(|live| live.set(live.get() - layout.size() as isize));
+            std::alloc::System.dealloc(ptr, layout)
+        }
+    }
+
+    #[global_allocator]
+    static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+    #[test]
+    fn failed_requests_do_not_leak_sources() {
+        // libimobiledevice rejects a missing client, so every request takes the early error return
+        let relay = FileRelay {
+            pointer: std::ptr::null_mut(),
+            phantom: std::marker::PhantomData,
+        };
+        let sources = [
+            FileRelaySources::AppleSupport,
+            FileRelaySources::Network,
+            FileRelaySources::CrashReporter,
+        ];
+        assert!(relay.request_sources(&sources, 0).is_err());
+
+        let before = LIVE_BYTES.with(|live| live.get());
+        for i in 0..1000 {
+            assert!(relay.request_sources(&sources, i % 2).is_err());
+        }
+        assert_eq!(LIVE_BYTES.with(|live| live.get()), before);
+    }
+
+    #[test]
+    fn single_source_list_is_null_terminated() {
+        let sources: Vec<CString> = [FileRelaySources::AppleSupport]
+            .iter()
+            .map(|&s| s.into())
+            .collect();
+        let pointers = null_terminated(&sources);
+        assert_eq!(pointers.len(), 2);
+
+        // Walk the array the way libimobiledevice does, stopping at the first NULL
+        let mut walked = vec![];
+        let mut cursor = pointers.as_ptr();
+        unsafe {
+            while !(*cursor).is_null() {
+                walked.push(std::ffi::CStr::from_ptr(*cursor).to_str().unwrap());
+                cursor = cursor.add(1);
+            }
+            assert_eq!(cursor, pointers.as_ptr().add(pointers.len() - 1));
+        }
+        assert_eq!(walked, vec!["AppleSupport"]);
+    }
+
+    #[test]
+    fn sources_are_borrowed_from_a_slice() {
+        let names = source_names(&[FileRelaySources::CrashReporter, FileRelaySources::Tmp]);
+        let names: Vec<&str> = names.iter().map(|s| s.to_str().unwrap()).collect();
+        assert_eq!(names, vec!["CrashReporter", "Tmp"]);
+
+        // The caller keeps its sources after a request
+        let relay = FileRelay {
+            pointer: std::ptr::null_mut(),
+            phantom: std::marker::PhantomData,
+        };
+        let sources = vec![FileRelaySources::WiFi];
+        assert_eq!(
+            relay.request_sources(&sources, 0).err(),
+            Some(FileRelayError::InvalidArg)
+        );
+        assert_eq!(
+            relay.request_sources(&[FileRelaySources::Network], 0).err(),
+            Some(FileRelayError::InvalidArg)
+        );
+        assert_eq!(sources, vec![FileRelaySources::WiFi]);
+    }
+}
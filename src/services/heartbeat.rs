@@ -1,10 +1,17 @@
 // jkcoxson
 
-use std::ffi::CString;
+use std::{
+    ffi::CString,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread::JoinHandle,
+};
 
 use crate::{bindings as unsafe_bindings, error::HeartbeatError, idevice::Device};
 
-use log::info;
+use log::{info, warn};
 use plist_plus::Plist;
 
 /// A required service for most other services.
@@ -47,7 +54,7 @@ impl HeartbeatClient {
             return Err(result);
         }
         Ok(Self {
-            pointer: pointer,
+            pointer,
             // phantom: std::marker::PhantomData,
         })
     }
@@ -68,6 +75,17 @@ impl HeartbeatClient {
         Ok(())
     }
 
+    /// Replies to a "Marco" message from the device with a "Polo" message
+    /// # Arguments
+    /// *none*
+    /// # Returns
+    /// *none*
+    ///
+    /// ***Verified:*** False
+    pub fn send_polo(&self) -> Result<(), HeartbeatError> {
+        self.send(polo()?)
+    }
+
     /// Receive data from the heartbeat service.
     /// If the error is a MuxError, this usually means that the device has disconnected.
     /// # Arguments
@@ -77,26 +95,42 @@ impl HeartbeatClient {
     ///
     /// ***Verified:*** False
     pub fn receive(&self, timeout: u32) -> Result<Plist, HeartbeatError> {
+        if timeout != 0 {
+            return self.receive_with_timeout(timeout);
+        }
+
         let mut plist_ptr = unsafe { std::mem::zeroed() };
+        let result =
+            unsafe { unsafe_bindings::heartbeat_receive(self.pointer, &mut plist_ptr) }.into();
+        if result != HeartbeatError::Success {
+            return Err(result);
+        }
 
-        if timeout == 0 {
-            let result =
-                unsafe { unsafe_bindings::heartbeat_receive(self.pointer, &mut plist_ptr) }.into();
-            if result != HeartbeatError::Success {
-                return Err(result);
-            }
-        } else {
-            let result = unsafe {
-                unsafe_bindings::heartbeat_receive_with_timeout(
-                    self.pointer,
-                    &mut plist_ptr,
-                    timeout,
-                )
-            }
-            .into();
-            if result != HeartbeatError::Success {
-                return Err(result);
-            }
+        Ok(plist_ptr.into())
+    }
+
+    /// Receive data from the heartbeat service, giving up after a timeout.
+    /// The device sends a "Marco" message at the interval given in its previous message,
+    /// so the timeout should be a little longer than that interval.
+    /// # Arguments
+    /// * `timeout_ms` - How long to wait for a message in milliseconds
+    /// # Returns
+    /// The message as a plist, or a Timeout error if nothing arrived
+    ///
+    /// ***Verified:*** False
+    pub fn receive_with_timeout(&self, timeout_ms: u32) -> Result<Plist, HeartbeatError> {
+        let mut plist_ptr = unsafe { std::mem::zeroed() };
+
+        let result = unsafe {
+            unsafe_bindings::heartbeat_receive_with_timeout(
+                self.pointer,
+                &mut plist_ptr,
+                timeout_ms,
+            )
+        }
+        .into();
+        if result != HeartbeatError::Success {
+            return Err(result);
         }
 
         Ok(plist_ptr.into())
@@ -111,3 +145,275 @@ impl Drop for HeartbeatClient {
         }
     }
 }
+
+/// How long the background loop waits for a message before checking if it should stop
+const GUARD_POLL_MS: u32 = 500;
+
+/// Keeps a heartbeat connection alive on a background thread.
+/// The thread answers every "Marco" from the device with a "Polo" until the guard is dropped.
+pub struct HeartbeatGuard {
+    stop: Arc<AtomicBool>,
+    running: Arc<AtomicBool>,
+    handle: Option<JoinHandle<Result<(), HeartbeatError>>>,
+}
+
+impl HeartbeatGuard {
+    /// Starts a heartbeat client and answers it on a background thread
+    /// # Arguments
+    /// * `device` - The device to keep the heartbeat with
+    /// * `label` - The label to give the connection
+    /// # Returns
+    /// A guard that stops the heartbeat when dropped
+    ///
+    /// ***Verified:*** False
+    pub fn spawn(device: &Device, label: &str) -> Result<Self, HeartbeatError> {
+        let client = HeartbeatClient::new(device, label.to_string())?;
+        Ok(Self::spawn_with(client))
+    }
+
+    /// Answers `client` on a background thread, so the guard can be tested without a device
+    fn spawn_with<C: HeartbeatConnection + Send + 'static>(client: C) -> Self {
+        let stop = Arc::new(AtomicBool::new(false));
+        let running = Arc::new(AtomicBool::new(true));
+
+        let thread_stop = stop.clone();
+        let thread_running = running.clone();
+        info!("Spawning heartbeat thread");
+        let handle = std::thread::spawn(move || {
+            let result = heartbeat_loop(&client, &thread_stop);
+            if let Err(e) = &result {
+                warn!("Heartbeat thread stopped: {:?}", e);
+            }
+            thread_running.store(false, Ordering::SeqCst);
+            result
+        });
+
+        HeartbeatGuard {
+            stop,
+            running,
+            handle: Some(handle),
+        }
+    }
+
+    /// Checks whether the background loop is still answering the device
+    /// # Arguments
+    /// *none*
+    /// # Returns
+    /// False if the loop has died
+    ///
+    /// ***Verified:*** False
+    pub fn is_running(&self) -> bool {
+        self.running.load(Ordering::SeqCst)
+    }
+
+    /// Stops the background loop and waits for the thread to finish
+    /// # Arguments
+    /// *none*
+    /// # Returns
+    /// The error that ended the loop, if it died before being stopped
+    ///
+    /// ***Verified:*** False
+    pub fn stop(mut self) -> Result<(), HeartbeatError> {
+        self.join()
+    }
+
+    fn join(&mut self) -> Result<(), HeartbeatError> {
+        self.stop.store(true, Ordering::SeqCst);
+        match self.handle.take() {
+            Some(handle) => match handle.join() {
+                Ok(result) => result,
+                Err(_) => Err(HeartbeatError::UnknownError),
+            },
+            None => Ok(()),
+        }
+    }
+}
+
+impl Drop for HeartbeatGuard {
+    fn drop(&mut self) {
+        info!("Dropping heartbeat guard");
+        let _ = self.join();
+    }
+}
+
+/// The calls the background loop makes, so it can be tested without a device
+trait HeartbeatConnection {
+    fn receive_with_timeout(&self, timeout_ms: u32) -> Result<Plist, HeartbeatError>;
+    fn send(&self, message: Plist) -> Result<(), HeartbeatError>;
+}
+
+impl HeartbeatConnection for HeartbeatClient {
+    fn receive_with_timeout(&self, timeout_ms: u32) -> Result<Plist, HeartbeatError> {
+        HeartbeatClient::receive_with_timeout(self, timeout_ms)
+    }
+
+    fn send(&self, message: Plist) -> Result<(), HeartbeatError> {
+        HeartbeatClient::send(self, message)
+    }
+}
+
+/// Builds the "Polo" reply to a "Marco" message
+fn polo() -> Result<Plist, HeartbeatError> {
+    let mut message = Plist::new_dict();
+    if message
+        .dict_set_item("Command", Plist::new_string("Polo"))
+        .is_err()
+    {
+        return Err(HeartbeatError::PlistError);
+    }
+    Ok(message)
+}
+
+/// Answers the device until asked to stop or the connection fails
+fn heartbeat_loop<C: HeartbeatConnection>(
+    client: &C,
+    stop: &AtomicBool,
+) -> Result<(), HeartbeatError> {
+    while !stop.load(Ordering::SeqCst) {
+        let message = match client.receive_with_timeout(GUARD_POLL_MS) {
+            Ok(message) => message,
+            Err(HeartbeatError::Timeout) => continue,
+            Err(e) => return Err(e),
+        };
+        let command = match message.dict_get_item("Command") {
+            Ok(command) => command.get_string_val().unwrap_or_default(),
+            Err(_) => continue,
+        };
+        if command == "Marco" {
+            client.send(polo()?)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+    use std::collections::VecDeque;
+
+    /// Plays back messages from the device, then asks the loop to stop
+    struct MockHeartbeat<'a> {
+        incoming: RefCell<VecDeque<Result<&'static str, HeartbeatError>>>,
+        sent: RefCell<Vec<String>>,
+        stop: &'a AtomicBool,
+    }
+
+    impl HeartbeatConnection for MockHeartbeat<'_> {
+        fn receive_with_timeout(&self, _timeout_ms: u32) -> Result<Plist, HeartbeatError> {
+            match self.incoming.borrow_mut().pop_front() {
+                Some(Ok(command)) => {
+                    let mut message = Plist::new_dict();
+                    message
+                        .dict_set_item("Command", Plist::new_string(command))
+                        .unwrap();
+                    Ok(message)
+                }
+                Some(Err(e)) => Err(e),
+                None => {
+                    self.stop.store(true, Ordering::SeqCst);
+                    Err(HeartbeatError::Timeout)
+                }
+            }
+        }
+
+        fn send(&self, message: Plist) -> Result<(), HeartbeatError> {
+            let command = message.dict_get_item("Command").unwrap();
+            self.sent
+                .borrow_mut()
+                .push(command.get_string_val().unwrap());
+            Ok(())
+        }
+    }
+
+    fn mock<'a>(
+        incoming: Vec<Result<&'static str, HeartbeatError>>,
+        stop: &'a AtomicBool,
+    ) -> MockHeartbeat<'a> {
+        MockHeartbeat {
+            incoming: RefCell::new(incoming.into()),
+            sent: RefCell::new(vec![]),
+            stop,
+        }
+    }
+
+    #[test]
+    fn marco_is_answered_with_polo() {
+        let stop = AtomicBool::new(false);
+        let connection = mock(vec![Ok("Marco")], &stop);
+        assert_eq!(heartbeat_loop(&connection, &stop), Ok(()));
+        assert_eq!(connection.sent.into_inner(), vec!["Polo"]);
+    }
+
+    #[test]
+    fn loop_skips_timeouts_and_stops_on_errors() {
+        let stop = AtomicBool::new(false);
+        let connection = mock(
+            vec![
+                Err(HeartbeatError::Timeout),
+                Ok("Marco"),
+                Ok("SomethingElse"),
+                Err(HeartbeatError::MuxError),
+                Ok("Marco"),
+            ],
+            &stop,
+        );
+        assert_eq!(
+            heartbeat_loop(&connection, &stop),
+            Err(HeartbeatError::MuxError)
+        );
+        assert_eq!(connection.sent.into_inner(), vec!["Polo"]);
+    }
+
+    /// A device that never sends anything, or fails after its first receive
+    struct QuietHeartbeat {
+        fails: bool,
+    }
+
+    impl HeartbeatConnection for QuietHeartbeat {
+        fn receive_with_timeout(&self, _timeout_ms: u32) -> Result<Plist, HeartbeatError> {
+            std::thread::sleep(std::time::Duration::from_millis(10));
+            match self.fails {
+                true => Err(HeartbeatError::MuxError),
+                false => Err(HeartbeatError::Timeout),
+            }
+        }
+
+        fn send(&self, _message: Plist) -> Result<(), HeartbeatError> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn dropping_the_guard_stops_the_thread() {
+        let guard = HeartbeatGuard::spawn_with(QuietHeartbeat { fails: false });
+        assert!(guard.is_running());
+
+        let running = guard.running.clone();
+        drop(guard);
+        // Drop joins the thread, so it has finished by now
+        assert!(!running.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn guard_reports_the_error_that_ended_the_loop() {
+        let guard = HeartbeatGuard::spawn_with(QuietHeartbeat { fails: true });
+        let started = std::time::Instant::now();
+        while guard.is_running() {
+            assert!(started.elapsed() < std::time::Duration::from_secs(5));
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        }
+        assert_eq!(guard.stop(), Err(HeartbeatError::MuxError));
+    }
+
+    #[test]
+    #[cfg_attr(not(feature = "hardware"), ignore = "needs a connected device")]
+    fn device_sends_marco() {
+        let device = crate::idevice::test_device();
+        let client = HeartbeatClient::new(&device, "heartbeat-test".to_string()).unwrap();
+        let message = client.receive_with_timeout(15_000).unwrap();
+        let command = message.dict_get_item("Command").unwrap();
+        assert_eq!(command.get_string_val().unwrap(), "Marco");
+        client.send_polo().unwrap();
+    }
+}
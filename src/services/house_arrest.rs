@@ -1,10 +1,15 @@
 // jkcoxson
 
+use std::{convert::TryFrom, ffi::CString};
+
 use crate::{
-    bindings as unsafe_bindings, error::HouseArrestError, idevice::Device,
-    services::lockdownd::LockdowndService,
+    bindings as unsafe_bindings,
+    error::{AfcError, HouseArrestError},
+    idevice::Device,
+    services::{afc::AfcClient, lockdownd::LockdowndService},
 };
 
+use log::{info, warn};
 use plist_plus::Plist;
 
 /// iTunes file transfer service.
@@ -14,6 +19,9 @@ pub struct HouseArrest<'a> {
     phantom: std::marker::PhantomData<&'a Device>,
 }
 
+// Safe to move to another thread, but concurrent commands would interleave on the connection
+unsafe impl Send for HouseArrest<'_> {}
+
 impl HouseArrest<'_> {
     /// Creates a new house arrest service from a lockdown service
     /// # Arguments
@@ -49,12 +57,13 @@ impl HouseArrest<'_> {
     ///
     /// ***Verified:*** False
     pub fn start_service(device: &Device, label: String) -> Result<Self, HouseArrestError> {
+        let label_c_str = c_string(&label)?;
         let mut pointer = std::ptr::null_mut();
         let result = unsafe {
             unsafe_bindings::house_arrest_client_start_service(
                 device.pointer,
                 &mut pointer,
-                label.as_ptr() as *const std::os::raw::c_char,
+                label_c_str.as_ptr(),
             )
         }
         .into();
@@ -107,11 +116,13 @@ impl HouseArrest<'_> {
     ///
     /// ***Verified:*** False
     pub fn send_command(&self, command: String, app_id: String) -> Result<Plist, HouseArrestError> {
+        let command_c_str = c_string(&command)?;
+        let app_id_c_str = c_string(&app_id)?;
         let result = unsafe {
             unsafe_bindings::house_arrest_send_command(
                 self.pointer,
-                command.as_ptr() as *const std::os::raw::c_char,
-                app_id.as_ptr() as *const std::os::raw::c_char,
+                command_c_str.as_ptr(),
+                app_id_c_str.as_ptr(),
             )
         }
         .into();
@@ -133,6 +144,73 @@ impl HouseArrest<'_> {
     }
 }
 
+/// Opens an AFC client rooted at an app's container.
+/// The app must be installed on the device, and only apps with file sharing enabled
+/// (or development builds) are allowed to be vended.
+/// # Arguments
+/// * `device` - The device the app is installed on
+/// * `bundle_id` - The bundle identifier of the app
+/// # Returns
+/// An AFC client scoped to the app's container
+///
+/// ***Verified:*** False
+pub fn vend_container<'a>(device: &'a Device, bundle_id: &str) -> Result<AfcClient<'a>, AfcError> {
+    vend(device, "VendContainer", bundle_id)
+}
+
+/// Opens an AFC client rooted at an app's Documents folder.
+/// This is the folder shown in the Files app and iTunes file sharing.
+/// # Arguments
+/// * `device` - The device the app is installed on
+/// * `bundle_id` - The bundle identifier of the app
+/// # Returns
+/// An AFC client scoped to the app's documents
+///
+/// ***Verified:*** False
+pub fn vend_documents<'a>(device: &'a Device, bundle_id: &str) -> Result<AfcClient<'a>, AfcError> {
+    vend(device, "VendDocuments", bundle_id)
+}
+
+fn vend<'a>(device: &'a Device, command: &str, bundle_id: &str) -> Result<AfcClient<'a>, AfcError> {
+    let house_arrest =
+        match HouseArrest::start_service(device, "rusty_libimobiledevice".to_string()) {
+            Ok(house_arrest) => house_arrest,
+            Err(e) => {
+                warn!("Unable to start house arrest: {:?}", e);
+                return Err(AfcError::ServiceNotConnected);
+            }
+        };
+
+    info!("Sending {} for {}", command, bundle_id);
+    let response = match house_arrest.send_command(command.to_string(), bundle_id.to_string()) {
+        Ok(response) => response,
+        Err(e) => {
+            warn!("House arrest {} failed: {:?}", command, e);
+            return Err(AfcError::InvalidArg);
+        }
+    };
+    if let Ok(error) = response.dict_get_item("Error") {
+        let error = error.get_string_val().unwrap_or_default();
+        warn!(
+            "House arrest refused {} for {}: {}",
+            command, bundle_id, error
+        );
+        return Err(match error.as_str() {
+            "ApplicationLookupFailed" | "InstallationLookupFailed" => AfcError::ObjectNotFound,
+            _ => AfcError::PermDenied,
+        });
+    }
+
+    AfcClient::try_from(house_arrest)
+}
+
+fn c_string(value: &str) -> Result<CString, HouseArrestError> {
+    match CString::new(value) {
+        Ok(s) => Ok(s),
+        Err(_) => Err(HouseArrestError::InvalidArg),
+    }
+}
+
 impl Drop for HouseArrest<'_> {
     fn drop(&mut self) {
         unsafe {
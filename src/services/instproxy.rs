@@ -1,8 +1,16 @@
 // jkcoxson
 
-use std::ffi::CString;
+use std::{
+    collections::HashMap,
+    ffi::{CStr, CString},
+    os::raw::{c_char, c_int, c_void},
+    sync::mpsc,
+    time::Duration,
+};
 
-use crate::{bindings as unsafe_bindings, error::InstProxyError, idevice::Device};
+use crate::{
+    bindings as unsafe_bindings, error::InstProxyError, idevice::Device, services::trace_plist,
+};
 
 use log::info;
 use plist_plus::Plist;
@@ -58,27 +66,40 @@ impl InstProxyClient<'_> {
     ///
     /// ***Verified:*** False
     pub fn browse(&self, option: BrowseOption) -> Result<Plist, InstProxyError> {
-        let mut plist = std::ptr::null_mut();
-
-        let result = if option == BrowseOption::None {
-            unsafe {
-                unsafe_bindings::instproxy_browse(self.pointer, std::ptr::null_mut(), &mut plist)
-            }
+        if option == BrowseOption::None {
+            self.browse_with_options(None)
         } else {
-            let option_plist: Plist = option.into();
-            unsafe {
-                unsafe_bindings::instproxy_browse(
-                    self.pointer,
-                    option_plist.get_pointer(),
-                    &mut plist,
-                )
-            }
+            self.browse_with_options(Some(option.into()))
         }
-        .into();
+    }
+
+    /// Lists installed applications on the device with arbitrary client options
+    /// # Arguments
+    /// * `client_options` - A plist dictionary of options, such as `ApplicationType` or `ReturnAttributes`
+    /// # Returns
+    /// A plist array with a dictionary for each application
+    ///
+    /// ***Verified:*** False
+    pub fn browse_with_options(
+        &self,
+        client_options: Option<Plist>,
+    ) -> Result<Plist, InstProxyError> {
+        let mut plist = std::ptr::null_mut();
+        let options_ptr = client_options
+            .as_ref()
+            .map(|o| o.get_pointer())
+            .unwrap_or(std::ptr::null_mut());
+
+        info!("Instproxy browse");
+        trace_plist("Instproxy browse options", options_ptr);
+        let result =
+            unsafe { unsafe_bindings::instproxy_browse(self.pointer, options_ptr, &mut plist) }
+                .into();
 
         if result != InstProxyError::Success {
             return Err(result);
         }
+        trace_plist("Instproxy browse result", plist);
 
         Ok(plist.into())
     }
@@ -147,6 +168,7 @@ impl InstProxyClient<'_> {
 
         let mut res_plist: unsafe_bindings::plist_t = unsafe { std::mem::zeroed() };
         info!("Instproxy lookup");
+        trace_plist("Instproxy lookup options", opt_ptr);
         let result = unsafe {
             unsafe_bindings::instproxy_lookup(
                 self.pointer,
@@ -163,124 +185,208 @@ impl InstProxyClient<'_> {
         unsafe { unsafe_bindings::instproxy_client_options_free(opt_ptr) };
 
         info!("Instproxy lookup done");
+        trace_plist("Instproxy lookup result", res_plist);
         Ok(res_plist.into())
     }
 
-    /// Installs a package on the device
+    /// Looks up several apps on the device in a single request
+    /// # Arguments
+    /// * `bundle_ids` - The bundle ID's of the apps to lookup
+    /// * `options` - A plist containing options for the lookup. Create with `create_return_attributes`
+    /// # Returns
+    /// A map of bundle ID's to the plist dictionary describing each app.
+    /// Apps that aren't installed are missing from the map.
+    ///
+    /// ***Verified:*** False
+    pub fn lookup_many(
+        &self,
+        bundle_ids: &[&str],
+        options: Option<Plist>,
+    ) -> Result<HashMap<String, Plist>, InstProxyError> {
+        if bundle_ids.is_empty() {
+            return Ok(HashMap::new());
+        }
+        let results = self.lookup(bundle_ids.iter().map(|b| b.to_string()).collect(), options)?;
+
+        let mut apps = HashMap::new();
+        for app in results {
+            if let Some(bundle_id) = app.key {
+                apps.insert(bundle_id, app.plist.clone());
+            }
+        }
+        Ok(apps)
+    }
+
+    /// Looks up information about apps on the device, returning the common fields typed
+    /// # Arguments
+    /// * `bundle_ids` - The bundle ID's of apps to lookup. Pass an empty slice to lookup all apps.
+    /// * `attributes` - Extra attributes to fetch, accessible with `AppInfo::get`
+    /// # Returns
+    /// A map of bundle ID's to app information
+    ///
+    /// ***Verified:*** False
+    pub fn lookup_typed(
+        &self,
+        bundle_ids: &[&str],
+        attributes: &[&str],
+    ) -> Result<HashMap<String, AppInfo>, InstProxyError> {
+        let mut return_attributes: Vec<String> = AppInfo::COMMON_ATTRIBUTES
+            .iter()
+            .map(|a| a.to_string())
+            .collect();
+        for attribute in attributes {
+            if !return_attributes.iter().any(|a| a == attribute) {
+                return_attributes.push(attribute.to_string());
+            }
+        }
+
+        let client_options = Self::create_return_attributes(
+            vec![("ApplicationType".to_string(), Plist::new_string("Any"))],
+            return_attributes.clone(),
+        );
+        let results = self.lookup(
+            bundle_ids.iter().map(|b| b.to_string()).collect(),
+            Some(client_options),
+        )?;
+
+        let mut apps = HashMap::new();
+        for app in results {
+            let mut values = HashMap::new();
+            for attribute in &return_attributes {
+                if let Ok(value) = app.plist.dict_get_item(attribute) {
+                    if let Ok(value) = value.get_string_val() {
+                        values.insert(attribute.clone(), value);
+                    }
+                }
+            }
+            let bundle_identifier = match values.get("CFBundleIdentifier") {
+                Some(b) => b.clone(),
+                None => continue,
+            };
+            apps.insert(
+                bundle_identifier.clone(),
+                AppInfo {
+                    bundle_identifier,
+                    values,
+                },
+            );
+        }
+
+        Ok(apps)
+    }
+
+    /// Installs a package on the device, blocking until the installation finishes.
+    /// The package must already be on the device, usually uploaded with AFC to the
+    /// `PublicStaging` directory, and `pkg_path` is relative to the AFC root (e.g. `PublicStaging/app.ipa`).
     /// # Arguments
-    /// * `pkg_path` - The path to the .ipa or other package bundle
+    /// * `pkg_path` - The path to the .ipa or other package bundle on the device
     /// * `client_options` - The options in a plist dictionary for install
+    /// * `progress` - Called with the percent complete and the status name for each status update
     /// # Returns
     /// *none*
     ///
     /// ***Verified:*** False
-    pub fn install(
+    pub fn install<F: FnMut(u32, Option<String>)>(
         &self,
-        pkg_path: String,
+        pkg_path: &str,
         client_options: Option<Plist>,
+        progress: F,
     ) -> Result<(), InstProxyError> {
         info!("Instproxy install");
-        let pkg_path_c_str = std::ffi::CString::new(pkg_path).unwrap();
-
-        let ptr = if client_options.is_some() {
-            client_options.unwrap().get_pointer()
-        } else {
-            std::ptr::null_mut()
+        let pkg_path_c_str = match CString::new(pkg_path) {
+            Ok(s) => s,
+            Err(_) => return Err(InstProxyError::InvalidArg),
         };
+        let options_ptr = client_options
+            .as_ref()
+            .map(|o| o.get_pointer())
+            .unwrap_or(std::ptr::null_mut());
 
-        let result = unsafe {
+        trace_plist("Instproxy install options", options_ptr);
+        run_with_progress(progress, |callback, user_data| unsafe {
             unsafe_bindings::instproxy_install(
                 self.pointer,
                 pkg_path_c_str.as_ptr(),
-                ptr,
-                None, // I feel like this will segfault. The bindings are probably wrong.
-                std::ptr::null_mut(),
+                options_ptr,
+                callback,
+                user_data,
             )
-        }
-        .into();
-        if result != InstProxyError::Success {
-            return Err(result);
-        }
-
-        Ok(())
+        })
     }
 
-    /// Updates a package on the device
+    /// Updates a package on the device, blocking until the upgrade finishes.
+    /// Like `install`, the package must already be uploaded to the device.
     /// # Arguments
-    /// * `pkg_path` - The path to the new package
+    /// * `pkg_path` - The path to the new package on the device
     /// * `client_options` - The options in a plist dictionary for install
+    /// * `progress` - Called with the percent complete and the status name for each status update
     /// # Returns
     /// *none*
     ///
     /// ***Verified:*** False
-    pub fn upgrade(
+    pub fn upgrade<F: FnMut(u32, Option<String>)>(
         &self,
-        pkg_path: String,
+        pkg_path: &str,
         client_options: Option<Plist>,
+        progress: F,
     ) -> Result<(), InstProxyError> {
         info!("Instproxy upgrade");
-        let pkg_path_c_str = std::ffi::CString::new(pkg_path).unwrap();
-
-        let ptr = if client_options.is_some() {
-            client_options.unwrap().get_pointer()
-        } else {
-            std::ptr::null_mut()
+        let pkg_path_c_str = match CString::new(pkg_path) {
+            Ok(s) => s,
+            Err(_) => return Err(InstProxyError::InvalidArg),
         };
+        let options_ptr = client_options
+            .as_ref()
+            .map(|o| o.get_pointer())
+            .unwrap_or(std::ptr::null_mut());
 
-        let result = unsafe {
+        trace_plist("Instproxy upgrade options", options_ptr);
+        run_with_progress(progress, |callback, user_data| unsafe {
             unsafe_bindings::instproxy_upgrade(
                 self.pointer,
                 pkg_path_c_str.as_ptr(),
-                ptr,
-                None, // I feel like this will segfault. The bindings are probably wrong.
-                std::ptr::null_mut(),
+                options_ptr,
+                callback,
+                user_data,
             )
-        }
-        .into();
-        if result != InstProxyError::Success {
-            return Err(result);
-        }
-
-        Ok(())
+        })
     }
 
-    /// Uninstalls an app on the device
+    /// Uninstalls an app on the device, blocking until the removal finishes
     /// # Arguments
     /// * `app_id` - The bundle ID of the app to uninstall
     /// * `client_options` - The options in a plist dictionary for uninstall
+    /// * `progress` - Called with the percent complete and the status name for each status update
     /// # Returns
     /// *none*
     ///
     /// ***Verified:*** False
-    pub fn uninstall(
+    pub fn uninstall<F: FnMut(u32, Option<String>)>(
         &self,
-        app_id: String,
+        app_id: &str,
         client_options: Option<Plist>,
+        progress: F,
     ) -> Result<(), InstProxyError> {
         info!("Instproxy uninstall");
-        let app_id_c_str = std::ffi::CString::new(app_id).unwrap();
-
-        let ptr = if client_options.is_some() {
-            client_options.unwrap().get_pointer()
-        } else {
-            std::ptr::null_mut()
+        let app_id_c_str = match CString::new(app_id) {
+            Ok(s) => s,
+            Err(_) => return Err(InstProxyError::InvalidArg),
         };
+        let options_ptr = client_options
+            .as_ref()
+            .map(|o| o.get_pointer())
+            .unwrap_or(std::ptr::null_mut());
 
-        let result = unsafe {
+        trace_plist("Instproxy uninstall options", options_ptr);
+        run_with_progress(progress, |callback, user_data| unsafe {
             unsafe_bindings::instproxy_uninstall(
                 self.pointer,
                 app_id_c_str.as_ptr(),
-                ptr,
-                None, // I feel like this will segfault. The bindings are probably wrong.
-                std::ptr::null_mut(),
+                options_ptr,
+                callback,
+                user_data,
             )
-        }
-        .into();
-        if result != InstProxyError::Success {
-            return Err(result);
-        }
-
-        Ok(())
+        })
     }
 
     /// Gets a list of all the archives on the device
@@ -299,6 +405,7 @@ impl InstProxyClient<'_> {
         } else {
             std::ptr::null_mut()
         };
+        trace_plist("Instproxy lookup archives options", ptr);
         let result = unsafe {
             unsafe_bindings::instproxy_lookup_archives(self.pointer, ptr, &mut res_plist)
         }
@@ -309,119 +416,118 @@ impl InstProxyClient<'_> {
         Ok(res_plist.into())
     }
 
-    /// Creates an archive of the app
+    /// Creates an archive of the app, blocking until it is written.
+    /// Archives are stored on the device in the `ApplicationArchives` directory as `<app_id>.zip`,
+    /// which can be downloaded with an AFC client from `/ApplicationArchives/<app_id>.zip`.
     /// # Arguments
     /// * `app_id` - The bundle ID of the app to archive
     /// * `client_options` - The options for archive.
     ///     Current known options for plist dictionaries are `SkipUninstall: bool` and `ArchiveType: "ApplicationOnly"`
+    /// * `progress` - Called with the percent complete and the status name for each status update
     /// # Returns
     /// *none*
     ///
     /// ***Verified:*** False
-    pub fn archive(
+    pub fn archive<F: FnMut(u32, Option<String>)>(
         &self,
-        app_id: String,
+        app_id: &str,
         client_options: Option<Plist>,
+        progress: F,
     ) -> Result<(), InstProxyError> {
         info!("Instproxy archive");
-        let app_id_c_str = std::ffi::CString::new(app_id).unwrap();
-
-        let ptr = if client_options.is_some() {
-            client_options.unwrap().get_pointer()
-        } else {
-            std::ptr::null_mut()
+        let app_id_c_str = match CString::new(app_id) {
+            Ok(s) => s,
+            Err(_) => return Err(InstProxyError::InvalidArg),
         };
+        let options_ptr = client_options
+            .as_ref()
+            .map(|o| o.get_pointer())
+            .unwrap_or(std::ptr::null_mut());
 
-        let result = unsafe {
+        trace_plist("Instproxy archive options", options_ptr);
+        run_with_progress(progress, |callback, user_data| unsafe {
             unsafe_bindings::instproxy_archive(
                 self.pointer,
                 app_id_c_str.as_ptr(),
-                ptr,
-                None, // I feel like this will segfault. The bindings are probably wrong.
-                std::ptr::null_mut(),
+                options_ptr,
+                callback,
+                user_data,
             )
-        }
-        .into();
-        if result != InstProxyError::Success {
-            return Err(result);
-        }
-        Ok(())
+        })
     }
 
-    /// Restore an archived application back to the device
+    /// Restore an archived application back to the device, blocking until it finishes
     /// # Arguments
     /// * `app_id` - The bundle ID of the app to restore
     /// * `client_options` - The options for restoring the app
+    /// * `progress` - Called with the percent complete and the status name for each status update
     /// # Returns
     /// *none*
     ///
     /// ***Verified:*** False
-    pub fn restore(
+    pub fn restore<F: FnMut(u32, Option<String>)>(
         &self,
-        app_id: String,
+        app_id: &str,
         client_options: Option<Plist>,
+        progress: F,
     ) -> Result<(), InstProxyError> {
         info!("Instproxy restore");
-        let app_id_c_str = std::ffi::CString::new(app_id).unwrap();
-
-        let ptr = if client_options.is_some() {
-            client_options.unwrap().get_pointer()
-        } else {
-            std::ptr::null_mut()
+        let app_id_c_str = match CString::new(app_id) {
+            Ok(s) => s,
+            Err(_) => return Err(InstProxyError::InvalidArg),
         };
+        let options_ptr = client_options
+            .as_ref()
+            .map(|o| o.get_pointer())
+            .unwrap_or(std::ptr::null_mut());
 
-        let result = unsafe {
+        trace_plist("Instproxy restore options", options_ptr);
+        run_with_progress(progress, |callback, user_data| unsafe {
             unsafe_bindings::instproxy_restore(
                 self.pointer,
                 app_id_c_str.as_ptr(),
-                ptr,
-                None, // I feel like this will segfault. The bindings are probably wrong.
-                std::ptr::null_mut(),
+                options_ptr,
+                callback,
+                user_data,
             )
-        }
-        .into();
-        if result != InstProxyError::Success {
-            return Err(result);
-        }
-        Ok(())
+        })
     }
 
-    /// Removes an archive from the device
+    /// Removes an archive from the device, blocking until it is deleted
     /// # Arguments
     /// * `app_id` - The app bundle ID of the archive to remove
     /// * `client_options` - The options to use for removal. There are no known options, so pass None if unsure.
+    /// * `progress` - Called with the percent complete and the status name for each status update
     /// # Returns
     /// *none*
     ///
     /// ***Verified:*** False
-    pub fn remove_archive(
+    pub fn remove_archive<F: FnMut(u32, Option<String>)>(
         &self,
-        app_id: String,
+        app_id: &str,
         client_options: Option<Plist>,
+        progress: F,
     ) -> Result<(), InstProxyError> {
         info!("Instproxy remove archive");
-        let app_id_c_str = std::ffi::CString::new(app_id).unwrap();
-
-        let ptr = if client_options.is_some() {
-            client_options.unwrap().get_pointer()
-        } else {
-            std::ptr::null_mut()
+        let app_id_c_str = match CString::new(app_id) {
+            Ok(s) => s,
+            Err(_) => return Err(InstProxyError::InvalidArg),
         };
+        let options_ptr = client_options
+            .as_ref()
+            .map(|o| o.get_pointer())
+            .unwrap_or(std::ptr::null_mut());
 
-        let result = unsafe {
+        trace_plist("Instproxy remove archive options", options_ptr);
+        run_with_progress(progress, |callback, user_data| unsafe {
             unsafe_bindings::instproxy_remove_archive(
                 self.pointer,
                 app_id_c_str.as_ptr(),
-                ptr,
-                None, // I feel like this will segfault. The bindings are probably wrong.
-                std::ptr::null_mut(),
+                options_ptr,
+                callback,
+                user_data,
             )
-        }
-        .into();
-        if result != InstProxyError::Success {
-            return Err(result);
-        }
-        Ok(())
+        })
     }
 
     /// Check if the device has certain capabilities
@@ -475,35 +581,213 @@ impl InstProxyClient<'_> {
     /// # Arguments
     /// * `bundle_identifier` - The bundle identifier of the app
     /// # Returns
-    /// The path as a string
+    /// The path as a string, or `AppNotFound` if no app with that bundle ID is installed
     ///
     /// ***Verified:*** False
     pub fn get_path_for_bundle_identifier(
         &self,
         bundle_identifier: String,
     ) -> Result<String, InstProxyError> {
-        let bundle_id = std::ffi::CString::new(bundle_identifier).unwrap();
-        // This is kinda horrifying, could use a refractor
-        let to_fill = CString::new("").unwrap();
-        let mut to_fill_bytes = to_fill.into_raw();
-        let to_fill_ptr = &mut to_fill_bytes;
+        let bundle_id = match CString::new(bundle_identifier.as_str()) {
+            Ok(s) => s,
+            Err(_) => return Err(InstProxyError::InvalidArg),
+        };
+        let mut path_ptr: *mut c_char = std::ptr::null_mut();
 
         info!("Instproxy get_path_for_bundle_identifier");
         let result = unsafe {
             unsafe_bindings::instproxy_client_get_path_for_bundle_identifier(
                 self.pointer,
                 bundle_id.as_ptr(),
-                to_fill_ptr,
+                &mut path_ptr,
             )
         }
         .into();
 
+        if result == InstProxyError::OpFailed {
+            // libimobiledevice reports a missing app as a generic failure, so check for it
+            if let Ok(apps) = self.lookup_many(&[&bundle_identifier], None) {
+                if !apps.contains_key(&bundle_identifier) {
+                    return Err(InstProxyError::AppNotFound);
+                }
+            }
+        }
         if result != InstProxyError::Success {
             return Err(result);
         }
+        if path_ptr.is_null() {
+            return Err(InstProxyError::AppNotFound);
+        }
+
+        let path = unsafe { CStr::from_ptr(path_ptr) }
+            .to_string_lossy()
+            .into_owned();
+        unsafe { libc::free(path_ptr as *mut c_void) };
 
         info!("Instproxy get_path_for_bundle_identifier done");
-        Ok(unsafe { CString::from_raw(to_fill_bytes).into_string().unwrap() })
+        Ok(path)
+    }
+}
+
+/// Information about an installed app, as returned by `InstProxyClient::lookup_typed`
+#[derive(Debug, Clone)]
+pub struct AppInfo {
+    pub bundle_identifier: String,
+    values: HashMap<String, String>,
+}
+
+impl AppInfo {
+    const COMMON_ATTRIBUTES: [&'static str; 6] = [
+        "CFBundleIdentifier",
+        "CFBundleExecutable",
+        "Container",
+        "Path",
+        "CFBundleShortVersionString",
+        "CFBundleVersion",
+    ];
+
+    /// The name of the app's executable inside its bundle
+    pub fn bundle_executable(&self) -> Option<&str> {
+        self.get("CFBundleExecutable")
+    }
+
+    /// The path of the app's data container
+    pub fn container(&self) -> Option<&str> {
+        self.get("Container")
+    }
+
+    /// The path of the app's bundle
+    pub fn path(&self) -> Option<&str> {
+        self.get("Path")
+    }
+
+    /// The user facing version of the app, falling back to the build version
+    pub fn version(&self) -> Option<&str> {
+        self.get("CFBundleShortVersionString")
+            .or_else(|| self.get("CFBundleVersion"))
+    }
+
+    /// Gets any string attribute that was requested in the lookup
+    /// # Arguments
+    /// * `key` - The attribute name, such as `CFBundleDisplayName`
+    /// # Returns
+    /// The value if it was returned and is a string
+    ///
+    /// ***Verified:*** False
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.values.get(key).map(|v| v.as_str())
+    }
+}
+
+/// How long to wait between status updates before giving up on an operation
+const STATUS_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// A status update forwarded from the instproxy status callback
+struct StatusUpdate {
+    percent: Option<u32>,
+    name: Option<String>,
+    error: Option<InstProxyError>,
+}
+
+/// Starts an asynchronous instproxy operation and blocks until it completes,
+/// forwarding each status update to `progress` on the calling thread.
+fn run_with_progress<F, S>(mut progress: F, start: S) -> Result<(), InstProxyError>
+where
+    F: FnMut(u32, Option<String>),
+    S: FnOnce(
+        unsafe_bindings::instproxy_status_cb_t,
+        *mut c_void,
+    ) -> unsafe_bindings::instproxy_error_t,
+{
+    let (sender, receiver) = mpsc::channel::<StatusUpdate>();
+    let user_data = Box::into_raw(Box::new(sender));
+
+    let result: InstProxyError = start(Some(status_trampoline), user_data as *mut c_void).into();
+    if result != InstProxyError::Success {
+        // The operation never started, so the callback will not be called
+        unsafe { drop(Box::from_raw(user_data)) };
+        return Err(result);
+    }
+
+    let mut percent = 0;
+    loop {
+        let update = match receiver.recv_timeout(STATUS_TIMEOUT) {
+            Ok(update) => update,
+            // The callback still owns the sender, so it is left alone
+            Err(_) => return Err(InstProxyError::RecieveTimeout),
+        };
+        if let Some(error) = update.error {
+            return Err(error);
+        }
+        // Updates can arrive out of order, so progress never goes backwards
+        if let Some(p) = update.percent {
+            percent = percent.max(p);
+        }
+        let complete = update.name.as_deref() == Some("Complete");
+        if complete {
+            percent = 100;
+        }
+        progress(percent, update.name);
+        if complete {
+            return Ok(());
+        }
+    }
+}
+
+/// Called by libimobiledevice from its status thread for every status update.
+/// Takes back ownership of the sender once the operation is finished.
+unsafe extern "C" fn status_trampoline(
+    _command: unsafe_bindings::plist_t,
+    status: unsafe_bindings::plist_t,
+    user_data: *mut c_void,
+) {
+    let sender = user_data as *mut mpsc::Sender<StatusUpdate>;
+
+    let mut name_ptr: *mut c_char = std::ptr::null_mut();
+    unsafe_bindings::instproxy_status_get_name(status, &mut name_ptr);
+    let name = if name_ptr.is_null() {
+        None
+    } else {
+        let name = CStr::from_ptr(name_ptr).to_string_lossy().into_owned();
+        libc::free(name_ptr as *mut c_void);
+        Some(name)
+    };
+
+    let mut percent: c_int = -1;
+    unsafe_bindings::instproxy_status_get_percent_complete(status, &mut percent);
+
+    let mut error_name: *mut c_char = std::ptr::null_mut();
+    let error: InstProxyError = unsafe_bindings::instproxy_status_get_error(
+        status,
+        &mut error_name,
+        std::ptr::null_mut(),
+        std::ptr::null_mut(),
+    )
+    .into();
+    if !error_name.is_null() {
+        libc::free(error_name as *mut c_void);
+    }
+    let error = if error == InstProxyError::Success {
+        None
+    } else {
+        Some(error)
+    };
+
+    let finished = error.is_some() || name.as_deref() == Some("Complete");
+    let update = StatusUpdate {
+        percent: if percent < 0 {
+            None
+        } else {
+            Some(percent as u32)
+        },
+        name,
+        error,
+    };
+    // The receiver may have timed out and gone away, which is fine
+    let _ = (*sender).send(update);
+
+    if finished {
+        drop(Box::from_raw(sender));
     }
 }
 
@@ -539,6 +823,80 @@ impl From<BrowseOption> for Plist {
     }
 }
 
+/// Builds the client options plist accepted by browse, lookup and install requests
+#[derive(Debug, Clone, Default)]
+pub struct InstProxyOptions {
+    application_type: Option<String>,
+    return_attributes: Vec<String>,
+    bundle_ids: Vec<String>,
+    meta_data_only: Option<bool>,
+}
+
+impl InstProxyOptions {
+    /// Creates an empty set of options
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Only include apps of this type, such as `User`, `System`, `Internal` or `Any`
+    pub fn application_type(mut self, application_type: &str) -> Self {
+        self.application_type = Some(application_type.to_string());
+        self
+    }
+
+    /// Only return these attributes for each app, such as `CFBundleIdentifier`
+    pub fn return_attributes(mut self, attributes: &[&str]) -> Self {
+        self.return_attributes = attributes.iter().map(|a| a.to_string()).collect();
+        self
+    }
+
+    /// Only include apps with these bundle ID's
+    pub fn bundle_ids(mut self, bundle_ids: &[&str]) -> Self {
+        self.bundle_ids = bundle_ids.iter().map(|b| b.to_string()).collect();
+        self
+    }
+
+    /// Sets the `MetaDataOnly` option, which skips everything but the app metadata
+    pub fn meta_data_only(mut self, meta_data_only: bool) -> Self {
+        self.meta_data_only = Some(meta_data_only);
+        self
+    }
+
+    /// Creates the plist dictionary for the options that were set
+    pub fn build(self) -> Plist {
+        let mut options = Plist::new_dict();
+        if let Some(application_type) = self.application_type {
+            options
+                .dict_set_item("ApplicationType", Plist::new_string(&application_type))
+                .unwrap();
+        }
+        if !self.return_attributes.is_empty() {
+            options
+                .dict_set_item("ReturnAttributes", string_array(&self.return_attributes))
+                .unwrap();
+        }
+        if !self.bundle_ids.is_empty() {
+            options
+                .dict_set_item("BundleIDs", string_array(&self.bundle_ids))
+                .unwrap();
+        }
+        if let Some(meta_data_only) = self.meta_data_only {
+            options
+                .dict_set_item("MetaDataOnly", Plist::new_bool(meta_data_only))
+                .unwrap();
+        }
+        options
+    }
+}
+
+fn string_array(values: &[String]) -> Plist {
+    let mut array = Plist::new_array();
+    for value in values {
+        array.array_append_item(Plist::new_string(value)).unwrap();
+    }
+    array
+}
+
 impl Drop for InstProxyClient<'_> {
     fn drop(&mut self) {
         info!("Dropping instproxy client");
@@ -557,3 +915,209 @@ extern "C" {
         null: *const u8,
     );
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strings(array: Plist) -> Vec<String> {
+        array
+            .into_iter()
+            .map(|item| item.plist.get_string_val().unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn builds_options_with_expected_keys() {
+        let options = InstProxyOptions::new()
+            .application_type("User")
+            .return_attributes(&["CFBundleIdentifier", "CFBundleVersion"])
+            .bundle_ids(&["com.apple.Preferences"])
+            .meta_data_only(true)
+            .build();
+
+        assert_eq!(
+            options
+                .dict_get_item("ApplicationType")
+                .unwrap()
+                .get_string_val()
+                .unwrap(),
+            "User"
+        );
+        assert_eq!(
+            strings(options.dict_get_item("ReturnAttributes").unwrap()),
+            vec!["CFBundleIdentifier", "CFBundleVersion"]
+        );
+        assert_eq!(
+            strings(options.dict_get_item("BundleIDs").unwrap()),
+            vec!["com.apple.Preferences"]
+        );
+        assert!(options
+            .dict_get_item("MetaDataOnly")
+            .unwrap()
+            .get_bool_val()
+            .unwrap());
+    }
+
+    #[test]
+    fn leaves_out_unset_options() {
+        let options = InstProxyOptions::new().application_type("Any").build();
+        assert!(options.dict_get_item("ApplicationType").is_ok());
+        assert!(options.dict_get_item("ReturnAttributes").is_err());
+        assert!(options.dict_get_item("BundleIDs").is_err());
+        assert!(options.dict_get_item("MetaDataOnly").is_err());
+    }
+
+    /// Starts a fake operation that sends `updates` from another thread,
+    /// the way the status callback does
+    fn fake_operation(
+        updates: Vec<(Option<u32>, &'static str)>,
+    ) -> impl FnOnce(
+        unsafe_bindings::instproxy_status_cb_t,
+        *mut c_void,
+    ) -> unsafe_bindings::instproxy_error_t {
+        move |_callback, user_data| {
+            let sender = unsafe { Box::from_raw(user_data as *mut mpsc::Sender<StatusUpdate>) };
+            std::thread::spawn(move || {
+                for (percent, name) in updates {
+                    let _ = sender.send(StatusUpdate {
+                        percent,
+                        name: Some(name.to_string()),
+                        error: None,
+                    });
+                }
+            });
+            unsafe_bindings::instproxy_error_t_INSTPROXY_E_SUCCESS
+        }
+    }
+
+    #[test]
+    fn progress_never_decreases() {
+        let mut reports = Vec::new();
+        run_with_progress(
+            |percent, _| reports.push(percent),
+            fake_operation(vec![
+                (Some(10), "CreatingStagingDirectory"),
+                (Some(40), "ExtractingPackage"),
+                (Some(30), "InspectingPackage"),
+                (None, "PreflightingApplication"),
+                (Some(90), "GeneratingApplicationMap"),
+                (None, "Complete"),
+            ]),
+        )
+        .unwrap();
+
+        assert_eq!(reports, vec![10, 40, 40, 40, 90, 100]);
+        assert!(reports.windows(2).all(|pair| pair[0] <= pair[1]));
+    }
+
+    #[test]
+    #[cfg_attr(not(feature = "hardware"), ignore = "needs a connected device")]
+    fn browse_lists_system_apps() {
+        let device = crate::idevice::test_device();
+        let client = device
+            .new_instproxy_client("browse_lists_system_apps".to_string())
+            .unwrap();
+        let apps: Vec<String> = client
+            .browse(BrowseOption::System)
+            .unwrap()
+            .into_iter()
+            .filter_map(|app| app.plist.dict_get_item("CFBundleIdentifier").ok())
+            .map(|id| id.get_string_val().unwrap())
+            .collect();
+        assert!(apps.iter().any(|id| id == "com.apple.Preferences"));
+    }
+
+    #[test]
+    #[cfg_attr(not(feature = "hardware"), ignore = "needs a connected device")]
+    fn uninstalling_a_missing_app_errors() {
+        let device = crate::idevice::test_device();
+        let client = device
+            .new_instproxy_client("uninstalling_a_missing_app_errors".to_string())
+            .unwrap();
+        let result = client.uninstall("com.example.not-installed", None, |_, _| {});
+        assert!(result.is_err());
+    }
+
+    #[test]
+    #[cfg_attr(not(feature = "hardware"), ignore = "needs a connected device")]
+    fn archive_operations_finish_through_the_status_callback() {
+        let device = crate::idevice::test_device();
+        let client = device
+            .new_instproxy_client("archive_operations_finish".to_string())
+            .unwrap();
+        client.lookup_archives(None).unwrap();
+
+        // Returning at all means the final status reached the waiting thread
+        let mut reports = vec![];
+        let result = client.remove_archive("com.example.not-archived", None, |percent, _| {
+            reports.push(percent)
+        });
+        if result.is_ok() {
+            assert_eq!(reports.last(), Some(&100));
+        }
+        assert!(reports.windows(2).all(|pair| pair[0] <= pair[1]));
+    }
+
+    #[test]
+    #[cfg_attr(not(feature = "hardware"), ignore = "needs a connected device")]
+    fn looked_up_apps_have_containers() {
+        let device = crate::idevice::test_device();
+        let client = device
+            .new_instproxy_client("looked_up_apps_have_containers".to_string())
+            .unwrap();
+        let settings = client
+            .lookup_typed(&["com.apple.Preferences"], &["CFBundleDisplayName"])
+            .unwrap();
+        let settings = &settings["com.apple.Preferences"];
+        assert_eq!(settings.bundle_identifier, "com.apple.Preferences");
+        assert!(settings.path().is_some());
+        assert!(settings.bundle_executable().is_some());
+
+        // Not every system app has a data container, but some installed app does
+        let apps = client.lookup_typed(&[], &[]).unwrap();
+        assert!(apps
+            .values()
+            .filter_map(|app| app.container())
+            .any(|container| !container.is_empty()));
+    }
+
+    #[test]
+    #[cfg_attr(not(feature = "hardware"), ignore = "needs a connected device")]
+    fn lookup_many_keys_results_by_bundle_id() {
+        let device = crate::idevice::test_device();
+        let client = device
+            .new_instproxy_client("lookup_many_keys_results".to_string())
+            .unwrap();
+        let apps = client
+            .lookup_many(
+                &[
+                    "com.apple.Preferences",
+                    "com.apple.mobilesafari",
+                    "com.example.not-installed",
+                ],
+                None,
+            )
+            .unwrap();
+        assert!(apps.contains_key("com.apple.Preferences"));
+        assert!(apps.contains_key("com.apple.mobilesafari"));
+        assert!(!apps.contains_key("com.example.not-installed"));
+    }
+
+    #[test]
+    #[cfg_attr(not(feature = "hardware"), ignore = "needs a connected device")]
+    fn unknown_bundle_id_has_no_path() {
+        let device = crate::idevice::test_device();
+        let client = device
+            .new_instproxy_client("unknown_bundle_id_has_no_path".to_string())
+            .unwrap();
+        assert_eq!(
+            client.get_path_for_bundle_identifier("com.example.not-installed".to_string()),
+            Err(InstProxyError::AppNotFound)
+        );
+        let path = client
+            .get_path_for_bundle_identifier("com.apple.Preferences".to_string())
+            .unwrap();
+        assert!(path.ends_with("Preferences.app"));
+    }
+}
@@ -1,13 +1,14 @@
 // jkcoxson
 
-use std::ffi::CStr;
+use std::ffi::{CStr, CString};
 use std::os::raw::c_char;
 
 use crate::bindings as unsafe_bindings;
 use crate::error::LockdowndError;
 use crate::idevice::Device;
+use crate::services::trace_plist;
 
-use log::info;
+use log::{info, trace};
 use plist_plus::Plist;
 
 /// A jumping point for other services.
@@ -30,15 +31,77 @@ pub struct LockdowndPairRecord {
 unsafe impl Send for LockdowndClient<'_> {}
 unsafe impl Sync for LockdowndClient<'_> {}
 
+/// A service started by lockdownd, describing the port to connect to and whether to use SSL
 pub struct LockdowndService<'a> {
     pub(crate) pointer: unsafe_bindings::lockdownd_service_descriptor_t,
-    pub port: u32,
     pub(crate) phantom: std::marker::PhantomData<&'a LockdowndClient<'a>>,
 }
 
 unsafe impl Send for LockdowndService<'_> {}
 unsafe impl Sync for LockdowndService<'_> {}
 
+impl LockdowndService<'_> {
+    /// Creates a service descriptor for a service that was started elsewhere
+    /// # Arguments
+    /// * `port` - The port the service is listening on
+    /// * `ssl_enabled` - Whether the service expects an SSL connection
+    /// # Returns
+    /// A service that owns its descriptor
+    ///
+    /// ***Verified:*** False
+    pub fn new(port: u16, ssl_enabled: bool) -> Self {
+        // Allocated with malloc since lockdownd_service_descriptor_free releases it with free
+        let descriptor = unsafe {
+            libc::malloc(std::mem::size_of::<
+                unsafe_bindings::lockdownd_service_descriptor,
+            >()) as unsafe_bindings::lockdownd_service_descriptor_t
+        };
+        if descriptor.is_null() {
+            panic!("Failed to allocate a lockdownd service descriptor");
+        }
+        unsafe {
+            descriptor.write(unsafe_bindings::lockdownd_service_descriptor {
+                port,
+                ssl_enabled: ssl_enabled as u8,
+                identifier: std::ptr::null_mut(),
+            });
+        }
+        Self::from_descriptor(descriptor)
+    }
+
+    /// Takes ownership of a descriptor returned by libimobiledevice
+    pub(crate) fn from_descriptor(
+        descriptor: unsafe_bindings::lockdownd_service_descriptor_t,
+    ) -> Self {
+        LockdowndService {
+            pointer: descriptor,
+            phantom: std::marker::PhantomData,
+        }
+    }
+
+    /// The port the service is listening on
+    /// # Arguments
+    /// *none*
+    /// # Returns
+    /// The port from the service descriptor
+    ///
+    /// ***Verified:*** False
+    pub fn port(&self) -> u16 {
+        unsafe { (*self.pointer).port }
+    }
+
+    /// Whether the service expects the connection to be upgraded to SSL
+    /// # Arguments
+    /// *none*
+    /// # Returns
+    /// The SSL flag from the service descriptor
+    ///
+    /// ***Verified:*** False
+    pub fn ssl_enabled(&self) -> bool {
+        unsafe { (*self.pointer).ssl_enabled != 0 }
+    }
+}
+
 impl LockdowndClient<'_> {
     /// Creates a new lockdown service and starts initial handshake
     /// # Arguments
@@ -76,79 +139,72 @@ impl LockdowndClient<'_> {
 
     /// Gets a preference value from the lockdown service
     /// # Arguments
-    /// * `key` - The key of the value to fetch. Pass "" to query all keys.
-    /// * `domain` - The domain that the value exists in. Pass "" to query the gloabl domain.
+    /// * `domain` - The domain that the value exists in. Pass None to query the global domain.
+    /// * `key` - The key of the value to fetch. Pass None to query all keys.
     /// # Returns
     /// A plist containing the value
     ///
     /// ***Verified:*** False
-    pub fn get_value(&self, key: String, domain: String) -> Result<Plist, LockdowndError> {
-        let domain_c_str = std::ffi::CString::new(domain.clone()).unwrap();
-        let domain_c_str = if domain == "".to_string() {
-            std::ptr::null()
-        } else {
-            domain_c_str.as_ptr()
-        };
-        let key_c_str = std::ffi::CString::new(key.clone()).unwrap();
-        let key_c_str = if key == "".to_string() {
-            std::ptr::null()
-        } else {
-            key_c_str.as_ptr()
-        };
+    pub fn get_value(
+        &self,
+        domain: Option<&str>,
+        key: Option<&str>,
+    ) -> Result<Plist, LockdowndError> {
+        let domain_c_str = optional_c_string(domain)?;
+        let key_c_str = optional_c_string(key)?;
 
         let mut value: unsafe_bindings::plist_t = unsafe { std::mem::zeroed() };
 
-        info!("Getting value for {}", key);
+        info!("Getting value for {:?}", key);
         let result = unsafe {
-            unsafe_bindings::lockdownd_get_value(self.pointer, domain_c_str, key_c_str, &mut value)
+            unsafe_bindings::lockdownd_get_value(
+                self.pointer,
+                optional_c_string_ptr(&domain_c_str),
+                optional_c_string_ptr(&key_c_str),
+                &mut value,
+            )
         }
         .into();
 
         if result != LockdowndError::Success {
             return Err(result);
         }
+        trace_plist("Lockdownd get value", value);
 
         Ok(value.into())
     }
 
     /// Sets a preference value on the device
     /// # Arguments
+    /// * `domain` - The domain to set the value in. Pass None for the global domain.
     /// * `key` - The key of the value to set
-    /// * `domain` - The domain to set the value in. Pass "" for the global domain.
     /// * `value` - The value to set
     /// # Returns
     /// *none*
     ///
-    /// ***Verified:***
+    /// ***Verified:*** False
     pub fn set_value(
         &self,
-        key: String,
-        domain: String,
+        domain: Option<&str>,
+        key: &str,
         value: Plist,
     ) -> Result<(), LockdowndError> {
-        let domain_c_str = std::ffi::CString::new(domain.clone()).unwrap();
-        let domain_c_str = if domain == "".to_string() {
-            std::ptr::null()
-        } else {
-            domain_c_str.as_ptr()
-        };
-        let key_c_str = std::ffi::CString::new(key.clone()).unwrap();
-        let key_c_str = if key == "".to_string() {
-            std::ptr::null()
-        } else {
-            key_c_str.as_ptr()
-        };
+        let domain_c_str = optional_c_string(domain)?;
+        let key_c_str = optional_c_string(Some(key))?;
 
         info!("Setting value for {}", key);
+        trace_plist("Lockdownd set value", value.get_pointer());
         let result = unsafe {
             unsafe_bindings::lockdownd_set_value(
                 self.pointer,
-                domain_c_str,
-                key_c_str,
+                optional_c_string_ptr(&domain_c_str),
+                optional_c_string_ptr(&key_c_str),
                 value.get_pointer(),
             )
         }
         .into();
+        // lockdownd takes ownership of the value and frees it with the request
+        value.false_drop();
 
         if result != LockdowndError::Success {
             return Err(result);
@@ -195,39 +251,94 @@ impl LockdowndClient<'_> {
     /// Commands lockdownd to start a service.
     /// This will not have a Rust type, and will only be able to use basic methods.
     /// # Arguments
+    /// * `service` - The identifier of the service to start, such as `com.apple.afc`
+    /// # Returns
+    /// A raw lockdownd service with the port and SSL requirement of the service
+    ///
+    /// ***Verified:*** False
+    pub fn start_service(&self, service: &str) -> Result<LockdowndService<'_>, LockdowndError> {
+        self.start_service_inner(service, false)
+    }
+
+    /// Commands lockdownd to start a service, sending the escrow bag of the pair record.
+    /// Some services refuse to start without it, and a session must be started first with `start_session`.
+    /// Without a session this fails with `NoRunningSession`, and a device that hasn't been unlocked
+    /// since boot fails with `EscrowLocked`.
+    /// # Arguments
     /// * `service` - The identifier of the service to start
-    /// * `escrow_bag` - Whether to start the service with escrow_bag
     /// # Returns
-    /// A raw lockdownd service
+    /// A raw lockdownd service. Check `ssl_enabled` on it to see whether the service requires SSL.
     ///
     /// ***Verified:*** False
-    pub fn start_service(
-        &mut self,
-        service: String,
-        escrow_bag: bool,
-    ) -> Result<LockdowndService, LockdowndError> {
-        let label_c_str = std::ffi::CString::new(service.clone()).unwrap();
-        let label_c_str = if service == "".to_string() {
-            std::ptr::null()
-        } else {
-            label_c_str.as_ptr()
-        };
+    pub fn start_service_with_escrow_bag(
+        &self,
+        service: &str,
+    ) -> Result<LockdowndService<'_>, LockdowndError> {
+        self.start_service_inner(service, true)
+    }
+
+    /// Starts a service and returns the port lockdownd opened for it
+    /// # Arguments
+    /// * `service` - The identifier of the service, such as `com.apple.afc`
+    /// # Returns
+    /// The port of the service on the device
+    ///
+    /// ***Verified:*** False
+    pub fn get_service_port(&self, service: &str) -> Result<u16, LockdowndError> {
+        Ok(self.start_service(service)?.port())
+    }
 
-        let mut service: unsafe_bindings::lockdownd_service_descriptor_t =
-            unsafe { std::mem::zeroed() };
+    /// Finds which of the given services the device will start.
+    /// Lockdownd has no way to list its services, so each one is started to see if it succeeds.
+    ///
+    /// **This is not a read-only query.** Every service that starts is really launched on the
+    /// device, and lockdownd opens a port for it that stays open until the device times it out.
+    /// Only pass services that are safe to start without being used, and probe sparingly.
+    /// Services that need an escrow bag are reported as unavailable,
+    /// as are developer services while the developer image isn't mounted.
+    /// # Arguments
+    /// * `services` - The identifiers of the services to try, such as those in `KNOWN_SERVICES`
+    /// # Returns
+    /// The identifiers of the services that started
+    ///
+    /// ***Verified:*** False
+    pub fn available_services(&self, services: &[&str]) -> Result<Vec<String>, LockdowndError> {
+        let mut available = vec![];
+        for service in services {
+            match self.get_service_port(service) {
+                Ok(_) => available.push(service.to_string()),
+                // The connection to lockdownd is gone, so every other probe would fail too
+                Err(LockdowndError::MuxError) => return Err(LockdowndError::MuxError),
+                Err(e) => trace!("Service {} is unavailable: {:?}", service, e),
+            }
+        }
+        Ok(available)
+    }
 
-        info!("Starting lockdown service");
-        let result = if escrow_bag {
+    fn start_service_inner(
+        &self,
+        service: &str,
+        escrow_bag: bool,
+    ) -> Result<LockdowndService<'_>, LockdowndError> {
+        let service_c_str = optional_c_string(Some(service))?;
+        let mut descriptor: unsafe_bindings::lockdownd_service_descriptor_t = std::ptr::null_mut();
+
+        info!("Starting lockdown service {}", service);
+        let result: LockdowndError = if escrow_bag {
             unsafe {
-                unsafe_bindings::lockdownd_start_service(self.pointer, label_c_str, &mut service)
+                unsafe_bindings::lockdownd_start_service_with_escrow_bag(
+                    self.pointer,
+                    optional_c_string_ptr(&service_c_str),
+                    &mut descriptor,
+                )
             }
             .into()
         } else {
             unsafe {
-                unsafe_bindings::lockdownd_start_service_with_escrow_bag(
+                unsafe_bindings::lockdownd_start_service(
                     self.pointer,
-                    label_c_str,
-                    &mut service,
+                    optional_c_string_ptr(&service_c_str),
+                    &mut descriptor,
                 )
             }
             .into()
@@ -236,30 +347,32 @@ impl LockdowndClient<'_> {
         if result != LockdowndError::Success {
             return Err(result);
         }
+        if descriptor.is_null() {
+            return Err(LockdowndError::InvalidService);
+        }
 
-        Ok(LockdowndService {
-            pointer: service,
-            port: 0,
-            phantom: std::marker::PhantomData,
-        })
+        Ok(LockdowndService::from_descriptor(descriptor))
     }
 
-    /// Opens a session with lockdownd and switches to SSL if requested by the device
+    /// Opens a session with lockdownd and switches to SSL if requested by the device.
+    /// SSL is enabled on the underlying connection automatically, no further action is needed.
+    /// A session is required for some values and services, and needs a valid pair record.
     /// # Arguments
-    /// * `host_id` - The ID of the host
+    /// * `host_id` - The ID of the host, as found in the pair record
     /// # Returns
     /// The session ID and whether SSL was enabled
     ///
     /// ***Verified:*** False
-    pub fn start_session(&self, host_id: String) -> Result<(String, bool), LockdowndError> {
-        let host_id_c_str = std::ffi::CString::new(host_id.clone()).unwrap();
-        let mut session_id = unsafe { std::mem::zeroed() };
-        let mut ssl_enabled = unsafe { std::mem::zeroed() };
+    pub fn start_session(&self, host_id: &str) -> Result<(String, bool), LockdowndError> {
+        let host_id_c_str = optional_c_string(Some(host_id))?;
+        let mut session_id: *mut c_char = std::ptr::null_mut();
+        let mut ssl_enabled = 0;
 
+        info!("Starting lockdownd session");
         let result = unsafe {
             unsafe_bindings::lockdownd_start_session(
                 self.pointer,
-                host_id_c_str.as_ptr(),
+                optional_c_string_ptr(&host_id_c_str),
                 &mut session_id,
                 &mut ssl_enabled,
             )
@@ -270,34 +383,36 @@ impl LockdowndClient<'_> {
             return Err(result);
         }
 
-        unsafe {
-            Ok((
-                std::ffi::CStr::from_ptr(session_id)
-                    .to_string_lossy()
-                    .into_owned(),
-                ssl_enabled != 0,
-            ))
+        if session_id.is_null() {
+            return Err(LockdowndError::MissingSessionId);
         }
+        let session_id_str = unsafe { CStr::from_ptr(session_id) }
+            .to_string_lossy()
+            .into_owned();
+        unsafe { libc::free(session_id as *mut libc::c_void) };
+
+        Ok((session_id_str, ssl_enabled != 0))
     }
 
-    /// Stops a lockdownd session started by `start_session`
+    /// Stops a lockdownd session started by `start_session`.
+    /// SSL is disabled on the connection if it was enabled for the session.
     /// # Arguments
-    /// * `session` - The ID of the session created to stop
+    /// * `session_id` - The ID of the session created to stop
     /// # Returns
     /// *none*
     ///
     /// ***Verified:*** False
-    pub fn stop_session(&self, session_id: String) -> Result<(), LockdowndError> {
-        let session_id_c_str = std::ffi::CString::new(session_id.clone()).unwrap();
-        let session_id_c_str = if session_id == "".to_string() {
-            std::ptr::null()
-        } else {
-            session_id_c_str.as_ptr()
-        };
+    pub fn stop_session(&self, session_id: &str) -> Result<(), LockdowndError> {
+        let session_id_c_str = optional_c_string(Some(session_id))?;
 
-        let result =
-            unsafe { unsafe_bindings::lockdownd_stop_session(self.pointer, session_id_c_str) }
-                .into();
+        info!("Stopping lockdownd session");
+        let result = unsafe {
+            unsafe_bindings::lockdownd_stop_session(
+                self.pointer,
+                optional_c_string_ptr(&session_id_c_str),
+            )
+        }
+        .into();
 
         if result != LockdowndError::Success {
             return Err(result);
@@ -346,6 +461,8 @@ impl LockdowndClient<'_> {
 
     /// Attempts to pair with the device.
     /// This will only succeed on USB devices, and will add the pairing file to usbmuxd's pairing file storage.
+    /// Fails with `PasswordProtected` if the device is locked, `UserDeniedPairing` if the trust dialog was declined,
+    /// or `PairingDialogueRepsonsePending` while the dialog is still shown.
     /// # Arguments
     /// * `pair_record` - A pair record for the host. If None, usbmuxd will fetch the host's record.
     /// * `options` - The options for pairing
@@ -358,26 +475,26 @@ impl LockdowndClient<'_> {
         pairing_record: Option<LockdowndPairRecord>,
         options: Option<Plist>,
     ) -> Result<(), LockdowndError> {
-        let pair_ptr: unsafe_bindings::lockdownd_pair_record_t = if pairing_record.is_some() {
-            &mut pairing_record.unwrap().into()
-        } else {
-            std::ptr::null_mut()
-        };
-
-        let mut response = unsafe { std::mem::zeroed() };
-
-        let result = if options.is_none() {
-            unsafe { unsafe_bindings::lockdownd_pair(self.pointer, pair_ptr) }.into()
-        } else {
-            unsafe {
-                unsafe_bindings::lockdownd_pair_with_options(
-                    self.pointer,
-                    pair_ptr,
-                    options.unwrap().get_pointer(),
-                    &mut response,
-                )
+        info!("Pairing with device");
+        let result: LockdowndError = match options {
+            None => return self.pair_operation(pairing_record, unsafe_bindings::lockdownd_pair),
+            Some(options) => {
+                let mut raw_record = RawPairRecord::new(pairing_record)?;
+                let mut response: unsafe_bindings::plist_t = std::ptr::null_mut();
+                let result: LockdowndError = unsafe {
+                    unsafe_bindings::lockdownd_pair_with_options(
+                        self.pointer,
+                        raw_record.as_ptr(),
+                        options.get_pointer(),
+                        &mut response,
+                    )
+                }
+                .into();
+                if !response.is_null() {
+                    unsafe { unsafe_bindings::plist_free(response) };
+                }
+                result
             }
-            .into()
         };
 
         if result != LockdowndError::Success {
@@ -387,37 +504,45 @@ impl LockdowndClient<'_> {
         Ok(())
     }
 
-    /// Validates that the device is paired with a specified host
+    /// Validates that the device is paired with a specified host.
+    /// Fails with `InvalidHostId` if the device does not know the host.
     /// # Arguments
-    /// * `pairing_record` - The host pairing record
+    /// * `pairing_record` - The host pairing record. If None, usbmuxd will fetch the host's record.
     /// # Returns
     /// *none*
     ///
     /// ***Verified:*** False
-    pub fn validate_pair(&self, pairing_record: LockdowndPairRecord) -> Result<(), LockdowndError> {
-        let mut pairing_record = pairing_record.into();
-        let result =
-            unsafe { unsafe_bindings::lockdownd_validate_pair(self.pointer, &mut pairing_record) }
-                .into();
-
-        if result != LockdowndError::Success {
-            return Err(result);
-        }
-
-        Ok(())
+    pub fn validate_pair(
+        &self,
+        pairing_record: Option<LockdowndPairRecord>,
+    ) -> Result<(), LockdowndError> {
+        info!("Validating pair record");
+        self.pair_operation(pairing_record, unsafe_bindings::lockdownd_validate_pair)
     }
 
     /// Unpairs the device from the host
     /// # Arguments
-    /// * `pairing_record` - The host pairing record
+    /// * `pairing_record` - The host pairing record. If None, usbmuxd will fetch the host's record.
     /// # Returns
     /// *none*
     ///
     /// ***Verified:*** False
-    pub fn unpair(&self, pairing_record: LockdowndPairRecord) -> Result<(), LockdowndError> {
-        let mut pairing_record = pairing_record.into();
-        let result =
-            unsafe { unsafe_bindings::lockdownd_unpair(self.pointer, &mut pairing_record) }.into();
+    pub fn unpair(
+        &self,
+        pairing_record: Option<LockdowndPairRecord>,
+    ) -> Result<(), LockdowndError> {
+        info!("Unpairing device");
+        self.pair_operation(pairing_record, unsafe_bindings::lockdownd_unpair)
+    }
+
+    /// Runs a pairing call that only takes a pair record, keeping the record alive for the call
+    fn pair_operation(
+        &self,
+        pairing_record: Option<LockdowndPairRecord>,
+        operation: PairFn,
+    ) -> Result<(), LockdowndError> {
+        let mut raw_record = RawPairRecord::new(pairing_record)?;
+        let result = unsafe { operation(self.pointer, raw_record.as_ptr()) }.into();
 
         if result != LockdowndError::Success {
             return Err(result);
@@ -463,7 +588,9 @@ impl LockdowndClient<'_> {
         Ok(())
     }
 
-    /// Forces the device to enter recovery mode immediately
+    /// Forces the device to enter recovery mode immediately.
+    /// The device reboots, so this client and any services started from it become unusable.
+    /// They are still safe to drop afterwards.
     /// # Arguments
     /// *none*
     /// # Returns
@@ -471,6 +598,7 @@ impl LockdowndClient<'_> {
     ///
     /// ***Verified:*** False
     pub fn enter_recovery(&self) -> Result<(), LockdowndError> {
+        info!("Telling the device to enter recovery mode");
         let result = unsafe { unsafe_bindings::lockdownd_enter_recovery(self.pointer) }.into();
 
         if result != LockdowndError::Success {
@@ -480,7 +608,8 @@ impl LockdowndClient<'_> {
         Ok(())
     }
 
-    /// Sends a goodbye to lockdown, terminating the connection
+    /// Sends a goodbye to lockdown, terminating the connection.
+    /// This consumes the client so it cannot be used on the closed connection, and frees it on return.
     /// # Arguments
     /// *none*
     /// # Returns
@@ -523,46 +652,58 @@ impl LockdowndClient<'_> {
     ///
     /// ***Verified:*** False
     pub fn get_device_udid(&self) -> Result<String, LockdowndError> {
-        let mut udid_c_str = unsafe { std::mem::zeroed() };
+        self.device_udid()
+    }
 
+    /// Gets the device's name
+    /// # Arguments
+    /// *none*
+    /// # Returns
+    /// The name of the device
+    ///
+    /// ***Verified:*** False
+    pub fn get_device_name(&self) -> Result<String, LockdowndError> {
+        self.device_name()
+    }
+
+    /// Gets the UDID of the device without a session.
+    /// This is cheaper than looking up `UniqueDeviceID` with `get_value`.
+    /// # Arguments
+    /// *none*
+    /// # Returns
+    /// The UDID as a string
+    ///
+    /// ***Verified:*** False
+    pub fn device_udid(&self) -> Result<String, LockdowndError> {
+        let mut udid_c_str: *mut c_char = std::ptr::null_mut();
         let result =
             unsafe { unsafe_bindings::lockdownd_get_device_udid(self.pointer, &mut udid_c_str) }
                 .into();
-
         if result != LockdowndError::Success {
             return Err(result);
         }
 
-        Ok(unsafe {
-            std::ffi::CStr::from_ptr(udid_c_str)
-                .to_string_lossy()
-                .into_owned()
-        })
+        take_c_string(udid_c_str)
     }
 
-    /// Gets the device's name
+    /// Gets the name of the device without a session.
+    /// This is cheaper than looking up `DeviceName` with `get_value`.
     /// # Arguments
     /// *none*
     /// # Returns
-    /// *none*
+    /// The name of the device, such as "Jackson's iPhone"
     ///
     /// ***Verified:*** False
-    pub fn get_device_name(&self) -> Result<String, LockdowndError> {
-        let mut name_c_str = unsafe { std::mem::zeroed() };
-
+    pub fn device_name(&self) -> Result<String, LockdowndError> {
+        let mut name_c_str: *mut c_char = std::ptr::null_mut();
         let result =
             unsafe { unsafe_bindings::lockdownd_get_device_name(self.pointer, &mut name_c_str) }
                 .into();
-
         if result != LockdowndError::Success {
             return Err(result);
         }
 
-        Ok(unsafe {
-            std::ffi::CStr::from_ptr(name_c_str)
-                .to_string_lossy()
-                .into_owned()
-        })
+        take_c_string(name_c_str)
     }
 
     /// Get the data classes the device supports
@@ -620,15 +761,75 @@ impl LockdowndClient<'_> {
             return Err(result);
         }
 
+        if type_c_str.is_null() {
+            return Err(LockdowndError::InvalidResponse);
+        }
         let type_str = unsafe { std::ffi::CStr::from_ptr(type_c_str as *const c_char) }
-            .to_str()
-            .unwrap()
-            .to_string();
+            .to_string_lossy()
+            .into_owned();
+        unsafe { libc::free(type_c_str as *mut libc::c_void) };
 
         Ok(type_str)
     }
 }
 
+/// Services libimobiledevice has clients for, which can be passed to `LockdowndClient::available_services`.
+/// Probing starts each of them on the device.
+pub const KNOWN_SERVICES: [&str; 21] = [
+    "com.apple.afc",
+    "com.apple.companion_proxy",
+    "com.apple.debugserver",
+    "com.apple.debugserver.DVTSecureSocketProxy",
+    "com.apple.mobile.diagnostics_relay",
+    "com.apple.mobile.file_relay",
+    "com.apple.mobile.heartbeat",
+    "com.apple.mobile.house_arrest",
+    "com.apple.mobile.installation_proxy",
+    "com.apple.misagent",
+    "com.apple.mobile.mobile_image_mounter",
+    "com.apple.mobileactivationd",
+    "com.apple.mobilebackup",
+    "com.apple.mobilebackup2",
+    "com.apple.mobilesync",
+    "com.apple.mobile.notification_proxy",
+    "com.apple.preboardservice_v2",
+    "com.apple.springboardservices",
+    "com.apple.mobile.screenshotr",
+    "com.apple.syslog_relay",
+    "com.apple.webinspector",
+];
+
+/// Copies a C string allocated by libimobiledevice and frees it
+fn take_c_string(value: *mut c_char) -> Result<String, LockdowndError> {
+    if value.is_null() {
+        return Err(LockdowndError::InvalidResponse);
+    }
+    let string = unsafe { CStr::from_ptr(value) }
+        .to_string_lossy()
+        .into_owned();
+    unsafe { libc::free(value as *mut libc::c_void) };
+    Ok(string)
+}
+
+/// Converts an optional string into a C string, rejecting interior NUL bytes
+fn optional_c_string(value: Option<&str>) -> Result<Option<CString>, LockdowndError> {
+    match value {
+        Some(value) => match CString::new(value) {
+            Ok(value) => Ok(Some(value)),
+            Err(_) => Err(LockdowndError::InvalidArg),
+        },
+        None => Ok(None),
+    }
+}
+
+/// Gets a pointer to an optional C string, or null if there is none
+fn optional_c_string_ptr(value: &Option<CString>) -> *const c_char {
+    value
+        .as_ref()
+        .map(|v| v.as_ptr())
+        .unwrap_or(std::ptr::null())
+}
+
 impl From<*mut unsafe_bindings::lockdownd_pair_record> for LockdowndPairRecord {
     fn from(l: *mut unsafe_bindings::lockdownd_pair_record) -> Self {
         info!("Converting device certificate");
@@ -667,26 +868,64 @@ impl From<*mut unsafe_bindings::lockdownd_pair_record> for LockdowndPairRecord {
     }
 }
 
-impl From<LockdowndPairRecord> for unsafe_bindings::lockdownd_pair_record {
-    fn from(l: LockdowndPairRecord) -> Self {
-        info!("Converting device certificate");
-        let device_certificate = std::ffi::CString::new(l.device_certificate).unwrap();
-        info!("Converting host certificate");
-        let host_certificate = std::ffi::CString::new(l.host_certificate).unwrap();
-        info!("Converting root certificate");
-        let root_certificate = std::ffi::CString::new(l.root_certificate).unwrap();
-        info!("Converting host id");
-        let host_id = std::ffi::CString::new(l.host_id).unwrap();
-        info!("Converting system buid");
-        let system_buid = std::ffi::CString::new(l.system_buid).unwrap();
+/// A libimobiledevice call that takes only a client and a pair record
+type PairFn = unsafe extern "C" fn(
+    unsafe_bindings::lockdownd_client_t,
+    unsafe_bindings::lockdownd_pair_record_t,
+) -> unsafe_bindings::lockdownd_error_t;
+
+/// A pair record converted for libimobiledevice.
+/// Owns the C strings so the raw record stays valid for as long as this struct lives.
+struct RawPairRecord {
+    _strings: Vec<CString>,
+    record: Option<unsafe_bindings::lockdownd_pair_record>,
+}
 
-        info!("Setting device certificate");
-        Self {
-            device_certificate: device_certificate.as_ptr() as *mut c_char,
-            host_certificate: host_certificate.as_ptr() as *mut c_char,
-            root_certificate: root_certificate.as_ptr() as *mut c_char,
-            host_id: host_id.as_ptr() as *mut c_char,
-            system_buid: system_buid.as_ptr() as *mut c_char,
+impl RawPairRecord {
+    fn new(pair_record: Option<LockdowndPairRecord>) -> Result<Self, LockdowndError> {
+        let pair_record = match pair_record {
+            Some(pair_record) => pair_record,
+            None => {
+                return Ok(RawPairRecord {
+                    _strings: vec![],
+                    record: None,
+                })
+            }
+        };
+
+        let mut strings = vec![];
+        for value in [
+            pair_record.device_certificate,
+            pair_record.host_certificate,
+            pair_record.root_certificate,
+            pair_record.host_id,
+            pair_record.system_buid,
+        ] {
+            match CString::new(value) {
+                Ok(value) => strings.push(value),
+                Err(_) => return Err(LockdowndError::InvalidArg),
+            }
+        }
+
+        let record = unsafe_bindings::lockdownd_pair_record {
+            device_certificate: strings[0].as_ptr() as *mut c_char,
+            host_certificate: strings[1].as_ptr() as *mut c_char,
+            root_certificate: strings[2].as_ptr() as *mut c_char,
+            host_id: strings[3].as_ptr() as *mut c_char,
+            system_buid: strings[4].as_ptr() as *mut c_char,
+        };
+
+        Ok(RawPairRecord {
+            _strings: strings,
+            record: Some(record),
+        })
+    }
+
+    /// Gets a pointer to the record, or null to let usbmuxd supply one
+    fn as_ptr(&mut self) -> unsafe_bindings::lockdownd_pair_record_t {
+        match self.record.as_mut() {
+            Some(record) => record,
+            None => std::ptr::null_mut(),
         }
     }
 }
@@ -706,3 +945,267 @@ impl Drop for LockdowndService<'_> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::idevice;
+
+    #[test]
+    #[cfg_attr(not(feature = "hardware"), ignore = "needs a connected device")]
+    fn afc_is_available() {
+        let device = idevice::test_device();
+        let client = device
+            .new_lockdownd_client("afc_is_available".to_string())
+            .unwrap();
+        let available = client.available_services(&["com.apple.afc"]).unwrap();
+        assert_eq!(available, vec!["com.apple.afc".to_string()]);
+    }
+
+    fn unconnected_client() -> LockdowndClient<'static> {
+        LockdowndClient {
+            pointer: std::ptr::NonNull::dangling().as_ptr(),
+            phantom: std::marker::PhantomData,
+        }
+    }
+
+    unsafe extern "C" fn declined(
+        _client: unsafe_bindings::lockdownd_client_t,
+        _record: unsafe_bindings::lockdownd_pair_record_t,
+    ) -> unsafe_bindings::lockdownd_error_t {
+        unsafe_bindings::lockdownd_error_t_LOCKDOWN_E_USER_DENIED_PAIRING
+    }
+
+    unsafe extern "C" fn locked(
+        _client: unsafe_bindings::lockdownd_client_t,
+        _record: unsafe_bindings::lockdownd_pair_record_t,
+    ) -> unsafe_bindings::lockdownd_error_t {
+        unsafe_bindings::lockdownd_error_t_LOCKDOWN_E_PASSWORD_PROTECTED
+    }
+
+    /// Knows only the host `known-host`, and expects usbmuxd's record when none is given
+    unsafe extern "C" fn knows_one_host(
+        _client: unsafe_bindings::lockdownd_client_t,
+        record: unsafe_bindings::lockdownd_pair_record_t,
+    ) -> unsafe_bindings::lockdownd_error_t {
+        if record.is_null() || CStr::from_ptr((*record).host_id).to_bytes() == b"known-host" {
+            unsafe_bindings::lockdownd_error_t_LOCKDOWN_E_SUCCESS
+        } else {
+            unsafe_bindings::lockdownd_error_t_LOCKDOWN_E_INVALID_HOST_ID
+        }
+    }
+
+    fn pair_record(host_id: &str) -> LockdowndPairRecord {
+        LockdowndPairRecord {
+            device_certificate: "device".to_string(),
+            host_certificate: "host".to_string(),
+            root_certificate: "root".to_string(),
+            host_id: host_id.to_string(),
+            system_buid: "buid".to_string(),
+        }
+    }
+
+    #[test]
+    fn declined_pairing_errors() {
+        let client = unconnected_client();
+        assert_eq!(
+            client.pair_operation(None, declined),
+            Err(LockdowndError::UserDeniedPairing)
+        );
+        assert_eq!(
+            client.pair_operation(None, locked),
+            Err(LockdowndError::PasswordProtected)
+        );
+        assert_eq!(
+            LockdowndError::from(
+                unsafe_bindings::lockdownd_error_t_LOCKDOWN_E_PAIRING_DIALOG_RESPONSE_PENDING
+            ),
+            LockdowndError::PairingDialogueRepsonsePending
+        );
+        std::mem::forget(client);
+    }
+
+    #[test]
+    fn pair_record_reaches_the_device() {
+        let client = unconnected_client();
+        assert_eq!(
+            client.pair_operation(Some(pair_record("known-host")), knows_one_host),
+            Ok(())
+        );
+        assert_eq!(
+            client.pair_operation(Some(pair_record("other-host")), knows_one_host),
+            Err(LockdowndError::InvalidHostId)
+        );
+        assert_eq!(client.pair_operation(None, knows_one_host), Ok(()));
+        // A record that can't be passed to C never reaches the device
+        assert_eq!(
+            client.pair_operation(Some(pair_record("known\0host")), declined),
+            Err(LockdowndError::InvalidArg)
+        );
+        std::mem::forget(client);
+    }
+
+    #[test]
+    #[cfg_attr(not(feature = "hardware"), ignore = "needs a connected device")]
+    fn reads_product_version() {
+        let device = idevice::test_device();
+        let client = device
+            .new_lockdownd_client("reads_product_version".to_string())
+            .unwrap();
+        let version = client
+            .get_value(None, Some("ProductVersion"))
+            .unwrap()
+            .get_string_val()
+            .unwrap();
+        assert!(!version.is_empty());
+
+        // Without a key the whole domain comes back
+        let all = client.get_value(None, None).unwrap();
+        assert_eq!(
+            all.dict_get_item("ProductVersion")
+                .unwrap()
+                .get_string_val()
+                .unwrap(),
+            version
+        );
+    }
+
+    #[test]
+    #[cfg_attr(not(feature = "hardware"), ignore = "needs a connected device")]
+    fn device_name_round_trips() {
+        let device = idevice::test_device();
+        let client = device
+            .new_lockdownd_client("device_name_round_trips".to_string())
+            .unwrap();
+        let name = client
+            .get_value(None, Some("DeviceName"))
+            .unwrap()
+            .get_string_val()
+            .unwrap();
+        // Setting the name it already has leaves the device as it was
+        client
+            .set_value(None, "DeviceName", Plist::new_string(&name))
+            .unwrap();
+        let read_back = client
+            .get_value(None, Some("DeviceName"))
+            .unwrap()
+            .get_string_val()
+            .unwrap();
+        assert_eq!(read_back, name);
+    }
+
+    /// Reads the host ID from this host's pair record for the device
+    fn host_id(device: &Device) -> String {
+        let udid = std::ffi::CString::new(device.get_udid()).unwrap();
+        let mut record: unsafe_bindings::plist_t = std::ptr::null_mut();
+        let result =
+            unsafe { unsafe_bindings::userpref_read_pair_record(udid.as_ptr(), &mut record) };
+        assert_eq!(result, 0, "The device has no pair record on this host");
+        Plist::from(record)
+            .dict_get_item("HostID")
+            .unwrap()
+            .get_string_val()
+            .unwrap()
+    }
+
+    #[test]
+    #[cfg_attr(not(feature = "hardware"), ignore = "needs a connected device")]
+    fn session_starts_and_stops() {
+        let device = idevice::test_device();
+        let host_id = host_id(&device);
+        let client = device
+            .new_lockdownd_client("session_starts_and_stops".to_string())
+            .unwrap();
+        let (session_id, _ssl_enabled) = client.start_session(&host_id).unwrap();
+        assert!(!session_id.is_empty());
+        client.stop_session(&session_id).unwrap();
+
+        assert!(client.start_session("not-a-host").is_err());
+    }
+
+    #[test]
+    #[cfg_attr(not(feature = "hardware"), ignore = "needs a connected device")]
+    fn goodbye_closes_the_client() {
+        let device = idevice::test_device();
+        let client = device
+            .new_lockdownd_client("goodbye_closes_the_client".to_string())
+            .unwrap();
+        client.goodbye().unwrap();
+        // The device still accepts new connections afterwards
+        let client = device
+            .new_lockdownd_client("goodbye_closes_the_client".to_string())
+            .unwrap();
+        assert!(!client.query_type().unwrap().is_empty());
+    }
+
+    #[test]
+    #[cfg_attr(
+        not(all(feature = "hardware", feature = "dangerous")),
+        ignore = "reboots the connected device into recovery mode"
+    )]
+    fn enters_recovery() {
+        let device = idevice::test_device();
+        let client = device
+            .new_lockdownd_client("enters_recovery".to_string())
+            .unwrap();
+        client.enter_recovery().unwrap();
+        // Dropping the client after the device went away must not crash
+        drop(client);
+    }
+
+    #[test]
+    #[cfg_attr(not(feature = "hardware"), ignore = "needs a connected device")]
+    fn starts_afc() {
+        let device = idevice::test_device();
+        let client = device
+            .new_lockdownd_client("starts_afc".to_string())
+            .unwrap();
+        assert_eq!(client.query_type().unwrap(), "com.apple.mobile.lockdown");
+
+        let service = client.start_service("com.apple.afc").unwrap();
+        assert_ne!(service.port(), 0);
+        // The handshake started a session, so the escrow bag can be sent
+        let service = client
+            .start_service_with_escrow_bag("com.apple.afc")
+            .unwrap();
+        assert_ne!(service.port(), 0);
+        assert_ne!(client.get_service_port("com.apple.afc").unwrap(), 0);
+    }
+
+    #[test]
+    #[cfg_attr(not(feature = "hardware"), ignore = "needs a connected device")]
+    fn device_udid_matches_the_device() {
+        let device = idevice::test_device();
+        let client = device
+            .new_lockdownd_client("device_udid_matches".to_string())
+            .unwrap();
+        assert_eq!(client.device_udid().unwrap(), device.get_udid());
+        let name = client.device_name().unwrap();
+        assert!(!name.is_empty());
+        assert_eq!(
+            client
+                .get_value(None, Some("DeviceName"))
+                .unwrap()
+                .get_string_val()
+                .unwrap(),
+            name
+        );
+    }
+
+    #[test]
+    #[cfg_attr(not(feature = "hardware"), ignore = "needs a connected device")]
+    fn escrow_bag_service_starts_in_a_session() {
+        let device = idevice::test_device();
+        let client = device
+            .new_lockdownd_client("escrow_bag_service_starts".to_string())
+            .unwrap();
+        let (session_id, _) = client.start_session(&host_id(&device)).unwrap();
+
+        // Backups are protected by the escrow bag while the device is locked
+        let service = client
+            .start_service_with_escrow_bag("com.apple.mobilebackup2")
+            .unwrap();
+        assert_ne!(service.port(), 0);
+        client.stop_session(&session_id).unwrap();
+    }
+}
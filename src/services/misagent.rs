@@ -1,12 +1,13 @@
 // jkcoxson
 
-use std::os::raw::c_char;
+use std::{ffi::CString, os::raw::c_char};
 
 use crate::{
     bindings as unsafe_bindings, error::MisagentError, idevice::Device,
     services::lockdownd::LockdowndService,
 };
 
+use log::info;
 use plist_plus::Plist;
 
 /// Manges and checks provisioning profiles
@@ -15,6 +16,8 @@ pub struct MisagentClient<'a> {
     phantom: std::marker::PhantomData<&'a Device>,
 }
 
+unsafe impl Send for MisagentClient<'_> {}
+
 impl MisagentClient<'_> {
     /// Creates a new misagent service connection to the device
     /// The use of this function is unknown
@@ -49,12 +52,16 @@ impl MisagentClient<'_> {
     ///
     /// ***Verified:*** False
     pub fn start_service(device: &Device, label: String) -> Result<Self, MisagentError> {
+        let label_c_str = match CString::new(label) {
+            Ok(s) => s,
+            Err(_) => return Err(MisagentError::InvalidArg),
+        };
         let mut pointer = unsafe { std::mem::zeroed() };
         let result = unsafe {
             unsafe_bindings::misagent_client_start_service(
                 device.pointer,
                 &mut pointer,
-                label.as_ptr() as *const c_char,
+                label_c_str.as_ptr(),
             )
         }
         .into();
@@ -70,12 +77,16 @@ impl MisagentClient<'_> {
 
     /// Installs a provisioning profile on the device
     /// # Arguments
-    /// * `profile` - The profile as a plist
+    /// * `profile` - The contents of the .mobileprovision file
     /// # Returns
     /// *none*
     ///
     /// ***Verified:*** False
-    pub fn install(&self, profile: Plist) -> Result<(), MisagentError> {
+    pub fn install(&self, profile: &[u8]) -> Result<(), MisagentError> {
+        // misagent copies the node, so the wrapper frees ours when it drops
+        let profile = profile_plist(profile);
+
+        info!("Installing provisioning profile");
         let result =
             unsafe { unsafe_bindings::misagent_install(self.pointer, profile.get_pointer()) }
                 .into();
@@ -107,16 +118,47 @@ impl MisagentClient<'_> {
         Ok(plist.into())
     }
 
+    /// Retrieves every provisioning profile on the device.
+    /// Requires iOS 9.3 or newer, use `copy` for older devices.
+    /// # Arguments
+    /// *none*
+    /// # Returns
+    /// The contents of each profile, as they would appear in a .mobileprovision file
+    ///
+    /// ***Verified:*** False
+    pub fn copy_all(&self) -> Result<Vec<Vec<u8>>, MisagentError> {
+        let mut plist: unsafe_bindings::plist_t = std::ptr::null_mut();
+        let result: MisagentError =
+            unsafe { unsafe_bindings::misagent_copy_all(self.pointer, &mut plist) }.into();
+        if result != MisagentError::Success {
+            return Err(result);
+        }
+        if plist.is_null() {
+            return Err(MisagentError::PlistError);
+        }
+        // Frees the array once the profiles have been copied out
+        let profiles: Plist = plist.into();
+        let to_return = profiles_from_plist(&profiles)?;
+
+        info!("Copied {} provisioning profiles", to_return.len());
+        Ok(to_return)
+    }
+
     /// Removes a provisioning profile from the device
     /// # Arguments
-    /// * `id` - The ID of the provisioning profile
+    /// * `profile_id` - The UUID of the provisioning profile
     /// # Returns
     /// *none*
     ///
     /// ***Verified:*** False
-    pub fn remove(&self, id: String) -> Result<(), MisagentError> {
+    pub fn remove(&self, profile_id: &str) -> Result<(), MisagentError> {
+        let profile_id_c_str = match CString::new(profile_id) {
+            Ok(s) => s,
+            Err(_) => return Err(MisagentError::InvalidArg),
+        };
+        info!("Removing provisioning profile {}", profile_id);
         let result =
-            unsafe { unsafe_bindings::misagent_remove(self.pointer, id.as_ptr() as *const c_char) }
+            unsafe { unsafe_bindings::misagent_remove(self.pointer, profile_id_c_str.as_ptr()) }
                 .into();
         if result != MisagentError::Success {
             return Err(result);
@@ -140,10 +182,80 @@ impl MisagentClient<'_> {
     }
 }
 
+/// Wraps the contents of a .mobileprovision file in the data node misagent expects
+fn profile_plist(profile: &[u8]) -> Plist {
+    unsafe {
+        unsafe_bindings::plist_new_data(profile.as_ptr() as *const c_char, profile.len() as u64)
+    }
+    .into()
+}
+
+/// Copies the profiles out of the array misagent returns, skipping anything that isn't data
+fn profiles_from_plist(profiles: &Plist) -> Result<Vec<Vec<u8>>, MisagentError> {
+    let plist = profiles.get_pointer();
+    if unsafe { unsafe_bindings::plist_get_node_type(plist) }
+        != unsafe_bindings::plist_type_PLIST_ARRAY
+    {
+        return Err(MisagentError::PlistError);
+    }
+
+    let mut to_return = vec![];
+    let size = unsafe { unsafe_bindings::plist_array_get_size(plist) };
+    for i in 0..size {
+        let item = unsafe { unsafe_bindings::plist_array_get_item(plist, i) };
+        if unsafe { unsafe_bindings::plist_get_node_type(item) }
+            != unsafe_bindings::plist_type_PLIST_DATA
+        {
+            continue;
+        }
+        let mut data = std::ptr::null_mut();
+        let mut length = 0;
+        unsafe { unsafe_bindings::plist_get_data_val(item, &mut data, &mut length) };
+        if data.is_null() {
+            continue;
+        }
+        to_return.push(
+            unsafe { std::slice::from_raw_parts(data as *const u8, length as usize) }.to_vec(),
+        );
+        unsafe { libc::free(data as *mut libc::c_void) };
+    }
+    Ok(to_return)
+}
+
 impl Drop for MisagentClient<'_> {
     fn drop(&mut self) {
+        info!("Dropping misagent client");
         unsafe {
             unsafe_bindings::misagent_client_free(self.pointer);
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn profiles_round_trip() {
+        let first = b"<?xml version=\"1.0\"?><plist><dict/></plist>".to_vec();
+        let second = vec![0x30, 0x82, 0x00, 0x00, 0xff];
+
+        // Laid out the way misagent returns the profiles it has
+        let mut profiles = Plist::new_array();
+        profiles.array_append_item(profile_plist(&first)).unwrap();
+        profiles
+            .array_append_item(Plist::new_string("not a profile"))
+            .unwrap();
+        profiles.array_append_item(profile_plist(&second)).unwrap();
+
+        assert_eq!(profiles_from_plist(&profiles), Ok(vec![first, second]));
+    }
+
+    #[test]
+    fn profiles_must_be_an_array() {
+        assert_eq!(
+            profiles_from_plist(&Plist::new_dict()),
+            Err(MisagentError::PlistError)
+        );
+    }
+}
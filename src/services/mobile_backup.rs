@@ -1,6 +1,13 @@
 // jkcoxson
 
-use std::os::raw::{c_char, c_int, c_uint};
+use std::{
+    ffi::{CStr, CString},
+    fs::{self, File},
+    io::{self, Read, Write},
+    os::raw::{c_char, c_int, c_uint},
+    path::{Component, Path, PathBuf},
+    time::UNIX_EPOCH,
+};
 
 use crate::{
     bindings as unsafe_bindings,
@@ -9,6 +16,8 @@ use crate::{
     services::lockdownd::LockdowndService,
 };
 
+use libc::c_void;
+use log::{info, trace, warn};
 use plist_plus::Plist;
 
 /// Manages backups on older devices
@@ -21,6 +30,7 @@ pub struct MobileBackupClient<'a> {
 /// Manages backups on new devices
 pub struct MobileBackup2Client<'a> {
     pub(crate) pointer: unsafe_bindings::mobilebackup2_client_t,
+    udid: String,
     phantom: std::marker::PhantomData<&'a Device>,
 }
 
@@ -316,6 +326,7 @@ impl MobileBackup2Client<'_> {
 
         Ok(MobileBackup2Client {
             pointer: client,
+            udid: device.get_udid(),
             phantom: std::marker::PhantomData,
         })
     }
@@ -329,13 +340,14 @@ impl MobileBackup2Client<'_> {
     ///
     /// ***Verified:*** False
     pub fn start_service(device: &Device, label: String) -> Result<Self, MobileBackup2Error> {
+        let label_c_str = c_string(&label)?;
         let mut client = unsafe { std::mem::zeroed() };
 
         let result = unsafe {
             unsafe_bindings::mobilebackup2_client_start_service(
                 device.pointer,
                 &mut client,
-                label.as_ptr() as *const std::os::raw::c_char,
+                label_c_str.as_ptr(),
             )
         }
         .into();
@@ -346,6 +358,7 @@ impl MobileBackup2Client<'_> {
 
         Ok(MobileBackup2Client {
             pointer: client,
+            udid: device.get_udid(),
             phantom: std::marker::PhantomData,
         })
     }
@@ -360,16 +373,22 @@ impl MobileBackup2Client<'_> {
     /// ***Verified:*** False
     pub fn send_message(
         &self,
-        message: Option<String>,
-        options: Plist,
+        message: Option<&str>,
+        options: Option<Plist>,
     ) -> Result<(), MobileBackup2Error> {
-        let ptr = if message.is_some() {
-            message.unwrap().as_ptr() as *const c_char
-        } else {
-            std::ptr::null()
-        };
+        let message = message.map(c_string).transpose()?;
         let result = unsafe {
-            unsafe_bindings::mobilebackup2_send_message(self.pointer, ptr, options.get_pointer())
+            unsafe_bindings::mobilebackup2_send_message(
+                self.pointer,
+                message
+                    .as_ref()
+                    .map(|m| m.as_ptr())
+                    .unwrap_or(std::ptr::null()),
+                options
+                    .as_ref()
+                    .map(|p| p.get_pointer())
+                    .unwrap_or(std::ptr::null_mut()),
+            )
         }
         .into();
 
@@ -388,7 +407,7 @@ impl MobileBackup2Client<'_> {
     ///
     /// ***Verified:*** False
     pub fn receive_message(&self) -> Result<(String, Plist), MobileBackup2Error> {
-        let mut message = unsafe { std::mem::zeroed() };
+        let mut message: *mut c_char = std::ptr::null_mut();
         let mut options = unsafe { std::mem::zeroed() };
 
         let result = unsafe {
@@ -400,22 +419,28 @@ impl MobileBackup2Client<'_> {
             return Err(result);
         }
 
-        Ok((
-            unsafe { std::ffi::CStr::from_ptr(message) }
+        let message_name = if message.is_null() {
+            String::new()
+        } else {
+            let name = unsafe { CStr::from_ptr(message) }
                 .to_string_lossy()
-                .into_owned(),
-            options.into(),
-        ))
+                .into_owned();
+            unsafe { libc::free(message as *mut c_void) };
+            name
+        };
+        trace!("Received backup message {}", message_name);
+
+        Ok((message_name, options.into()))
     }
 
     /// Sends raw data through the service connection
     /// # Arguments
-    /// * `data` - A vector of bytes to send
+    /// * `data` - The bytes to send
     /// # Returns
     /// The bytes sent
     ///
     /// ***Verified:*** False
-    pub fn send_raw(&self, data: Vec<u8>) -> Result<u32, MobileBackup2Error> {
+    pub fn send_raw(&self, data: &[u8]) -> Result<u32, MobileBackup2Error> {
         let mut sent = 0;
         let result = unsafe {
             unsafe_bindings::mobilebackup2_send_raw(
@@ -436,17 +461,22 @@ impl MobileBackup2Client<'_> {
 
     /// Receives raw data from the connection
     /// # Arguments
-    /// * `len` - How many bytes to receive
+    /// * `len` - The most bytes to receive
     /// # Returns
-    /// A vector of bytes containing the received data
+    /// A vector of bytes containing the received data, which may be shorter than requested
     ///
     /// ***Verified:*** False
-    pub fn recieve_raw(&self, len: u32) -> Result<Vec<c_char>, MobileBackup2Error> {
-        let mut data = unsafe { std::mem::zeroed() };
+    pub fn receive_raw(&self, len: u32) -> Result<Vec<u8>, MobileBackup2Error> {
+        let mut data = vec![0u8; len as usize];
         let mut received = 0;
 
         let result = unsafe {
-            unsafe_bindings::mobilebackup2_receive_raw(self.pointer, &mut data, len, &mut received)
+            unsafe_bindings::mobilebackup2_receive_raw(
+                self.pointer,
+                data.as_mut_ptr() as *mut c_char,
+                len,
+                &mut received,
+            )
         }
         .into();
 
@@ -454,9 +484,8 @@ impl MobileBackup2Client<'_> {
             return Err(result);
         }
 
-        Ok(unsafe {
-            std::slice::from_raw_parts(&data as *const c_char, received as usize).to_vec()
-        })
+        data.truncate(received as usize);
+        Ok(data)
     }
 
     /// Exchanges version with the service
@@ -490,6 +519,7 @@ impl MobileBackup2Client<'_> {
     /// * `request` - The type of request to send
     /// * `target` - The UDID of the target device
     /// * `source` - The UDID of the source device
+    /// * `options` - Options for the request, such as `ForceFullBackup`
     /// # Returns
     /// *none*
     ///
@@ -497,17 +527,23 @@ impl MobileBackup2Client<'_> {
     pub fn send_request(
         &self,
         request: MobileBackupRequest,
-        target: String,
-        source: String,
-        options: Plist,
+        target: &str,
+        source: &str,
+        options: Option<Plist>,
     ) -> Result<(), MobileBackup2Error> {
+        let request_c_str = c_string(request.as_str())?;
+        let target_c_str = c_string(target)?;
+        let source_c_str = c_string(source)?;
         let result = unsafe {
             unsafe_bindings::mobilebackup2_send_request(
                 self.pointer,
-                request.into(),
-                target.as_ptr() as *const std::os::raw::c_char,
-                source.as_ptr() as *const std::os::raw::c_char,
-                options.get_pointer(),
+                request_c_str.as_ptr(),
+                target_c_str.as_ptr(),
+                source_c_str.as_ptr(),
+                options
+                    .as_ref()
+                    .map(|p| p.get_pointer())
+                    .unwrap_or(std::ptr::null_mut()),
             )
         }
         .into();
@@ -521,25 +557,32 @@ impl MobileBackup2Client<'_> {
 
     /// Sends a status response to the service
     /// # Arguments
-    /// * `code` - The status code to send
+    /// * `code` - The status code to send, 0 for success
     /// * `status_string` - The string for the status
     /// * `status_plist` - The plist containing status data
+    /// # Returns
+    /// *none*
+    ///
+    /// ***Verified:*** False
     pub fn send_status_response(
         &self,
         code: c_int,
-        status_string: Option<String>,
+        status_string: Option<&str>,
         status_plist: Option<Plist>,
     ) -> Result<(), MobileBackup2Error> {
+        let status_string = status_string.map(c_string).transpose()?;
         let result = unsafe {
             unsafe_bindings::mobilebackup2_send_status_response(
                 self.pointer,
                 code,
                 status_string
-                    .map(|s| s.as_ptr() as *const std::os::raw::c_char)
+                    .as_ref()
+                    .map(|s| s.as_ptr())
                     .unwrap_or(std::ptr::null()),
                 status_plist
+                    .as_ref()
                     .map(|p| p.get_pointer())
-                    .unwrap_or(0 as *mut std::os::raw::c_void), // idk
+                    .unwrap_or(std::ptr::null_mut()),
             )
         }
         .into();
@@ -550,6 +593,362 @@ impl MobileBackup2Client<'_> {
 
         Ok(())
     }
+
+    /// Runs a full backup of the device into a directory on the host.
+    /// The backup is written to `base_dir/<udid>`, in the same layout iTunes and Finder use.
+    /// The call blocks until the device reports that the backup has finished.
+    /// # Arguments
+    /// * `base_dir` - The directory to hold the backup
+    /// * `progress` - Called with the overall progress of the backup, from 0 to 100
+    /// # Returns
+    /// *none*
+    ///
+    /// ***Verified:*** False
+    pub fn backup<F: FnMut(f64)>(
+        &self,
+        base_dir: &Path,
+        mut progress: F,
+    ) -> Result<(), MobileBackup2Error> {
+        if let Err(e) = fs::create_dir_all(base_dir.join(&self.udid)) {
+            warn!("Unable to create the backup directory: {}", e);
+            return Err(MobileBackup2Error::InvalidArg);
+        }
+
+        let version = self.version_exchange(PROTOCOL_VERSIONS.to_vec())?;
+        info!("Using backup protocol version {}", version);
+        self.send_request(MobileBackupRequest::Backup, &self.udid, &self.udid, None)?;
+
+        loop {
+            let (name, message_plist) = match self.receive_message() {
+                Ok(message) => message,
+                // The device can take a while to prepare the backup
+                Err(MobileBackup2Error::RecieveTimeout) => continue,
+                Err(e) => return Err(e),
+            };
+            let message = message_plist.get_pointer();
+
+            if let Some(percent) = message_progress(message) {
+                progress(percent);
+            }
+
+            match DlMessage::from(name.as_str()) {
+                DlMessage::DownloadFiles => self.handle_download_files(base_dir, message)?,
+                DlMessage::UploadFiles => self.handle_upload_files(base_dir)?,
+                DlMessage::GetFreeDiskSpace => {
+                    let free_space: Plist =
+                        unsafe { unsafe_bindings::plist_new_uint(free_disk_space(base_dir)) }
+                            .into();
+                    self.send_status_response(0, None, Some(free_space))?
+                }
+                DlMessage::PurgeDiskSpace => self.send_status_response(
+                    -1,
+                    Some("Operation not supported"),
+                    Some(Plist::new_dict()),
+                )?,
+                DlMessage::ContentsOfDirectory => {
+                    let path = array_item(message, 1).and_then(node_string);
+                    let contents = match path {
+                        Some(path) => directory_contents(&device_path(base_dir, &path)),
+                        None => Plist::new_dict(),
+                    };
+                    self.send_status_response(0, None, Some(contents))?
+                }
+                DlMessage::CreateDirectory => {
+                    let result = match array_item(message, 1).and_then(node_string) {
+                        Some(path) => fs::create_dir_all(device_path(base_dir, &path)),
+                        None => Ok(()),
+                    };
+                    self.send_io_result(result)?
+                }
+                DlMessage::MoveFiles => {
+                    let mut result = Ok(());
+                    let moves = array_item(message, 1).map(dict_entries).unwrap_or_default();
+                    for (from, to) in moves {
+                        let to = match node_string(to) {
+                            Some(to) => to,
+                            None => continue,
+                        };
+                        let to = device_path(base_dir, &to);
+                        let _ = remove_path(&to);
+                        if let Err(e) = fs::rename(device_path(base_dir, &from), &to) {
+                            result = Err(e);
+                        }
+                    }
+                    self.send_io_result(result)?
+                }
+                DlMessage::RemoveFiles => {
+                    let mut result = Ok(());
+                    let paths = array_item(message, 1)
+                        .map(array_strings)
+                        .unwrap_or_default();
+                    for path in paths {
+                        match remove_path(&device_path(base_dir, &path)) {
+                            Err(e) if e.kind() != io::ErrorKind::NotFound => result = Err(e),
+                            _ => {}
+                        }
+                    }
+                    self.send_io_result(result)?
+                }
+                DlMessage::CopyItem => {
+                    let from = array_item(message, 1).and_then(node_string);
+                    let to = array_item(message, 2).and_then(node_string);
+                    let result = match (from, to) {
+                        (Some(from), Some(to)) => {
+                            copy_path(&device_path(base_dir, &from), &device_path(base_dir, &to))
+                        }
+                        _ => Ok(()),
+                    };
+                    self.send_io_result(result)?
+                }
+                DlMessage::ProcessMessage => {
+                    let result = array_item(message, 1).unwrap_or(std::ptr::null_mut());
+                    let error_code = dict_item(result, "ErrorCode")
+                        .and_then(node_uint)
+                        .unwrap_or(0);
+                    if error_code != 0 {
+                        let description = dict_item(result, "ErrorDescription")
+                            .and_then(node_string)
+                            .unwrap_or_default();
+                        warn!("Backup failed with code {}: {}", error_code, description);
+                        return Err(MobileBackup2Error::ReplyNotOk);
+                    }
+                    info!("Backup finished");
+                    progress(100.0);
+                    return Ok(());
+                }
+                DlMessage::Disconnect => {
+                    info!("Device disconnected from the backup");
+                    return Ok(());
+                }
+                DlMessage::Unknown(name) => warn!("Unhandled backup message {}", name),
+            }
+        }
+    }
+
+    /// Sends the files the device asked for with `DLMessageDownloadFiles`
+    fn handle_download_files(
+        &self,
+        base_dir: &Path,
+        message: unsafe_bindings::plist_t,
+    ) -> Result<(), MobileBackup2Error> {
+        let files = array_item(message, 1)
+            .map(array_strings)
+            .unwrap_or_default();
+        let mut errors = vec![];
+        for file in files {
+            if let Some(e) = self.send_file(base_dir, &file)? {
+                errors.push((file, e));
+            }
+        }
+        // A zero length marks the end of the files
+        self.send_all(&0u32.to_be_bytes())?;
+
+        if errors.is_empty() {
+            return self.send_status_response(0, None, Some(Plist::new_dict()));
+        }
+        let error_dict = unsafe { unsafe_bindings::plist_new_dict() };
+        for (file, error) in errors {
+            let entry = unsafe { unsafe_bindings::plist_new_dict() };
+            dict_set(entry, "DLFileErrorString", new_string(&error.to_string()));
+            dict_set(entry, "DLFileErrorCode", unsafe {
+                unsafe_bindings::plist_new_uint(device_error_code(&error) as u64)
+            });
+            dict_set(error_dict, &file, entry);
+        }
+        self.send_status_response(-13, Some("Multi status"), Some(error_dict.into()))
+    }
+
+    /// Streams a single file to the device.
+    /// Errors reading the file are reported to the device and returned in the Ok variant,
+    /// since the backup can carry on without the file.
+    fn send_file(
+        &self,
+        base_dir: &Path,
+        file: &str,
+    ) -> Result<Option<io::Error>, MobileBackup2Error> {
+        self.send_all(&(file.len() as u32).to_be_bytes())?;
+        self.send_all(file.as_bytes())?;
+
+        trace!("Sending {} to the device", file);
+        let mut error = None;
+        match File::open(device_path(base_dir, file)) {
+            Ok(mut handle) => {
+                let mut buffer = vec![0u8; FILE_CHUNK_SIZE];
+                loop {
+                    match handle.read(&mut buffer) {
+                        Ok(0) => break,
+                        Ok(read) => self.send_file_block(CODE_FILE_DATA, &buffer[..read])?,
+                        Err(e) => {
+                            error = Some(e);
+                            break;
+                        }
+                    }
+                }
+            }
+            Err(e) => error = Some(e),
+        }
+
+        match &error {
+            None => self.send_file_block(CODE_SUCCESS, &[])?,
+            Some(e) => self.send_file_block(CODE_ERROR_LOCAL, e.to_string().as_bytes())?,
+        }
+        Ok(error)
+    }
+
+    /// Writes the files the device sends with `DLMessageUploadFiles`
+    fn handle_upload_files(&self, base_dir: &Path) -> Result<(), MobileBackup2Error> {
+        loop {
+            let name_len = self.receive_u32()?;
+            if name_len == 0 {
+                break;
+            }
+            // The first name is the path on the device, which isn't needed
+            self.receive_exact(name_len)?;
+            let name_len = self.receive_u32()?;
+            let file_name = String::from_utf8_lossy(&self.receive_exact(name_len)?).into_owned();
+
+            let mut block_len = self.receive_u32()?;
+            if block_len == 0 {
+                break;
+            }
+            let mut code = self.receive_exact(1)?[0];
+
+            trace!("Receiving {} from the device", file_name);
+            let path = device_path(base_dir, &file_name);
+            let _ = fs::remove_file(&path);
+            let mut file = match File::create(&path) {
+                Ok(file) => Some(file),
+                Err(e) => {
+                    warn!("Unable to create {}: {}", path.display(), e);
+                    None
+                }
+            };
+
+            while code == CODE_FILE_DATA {
+                let data = self.receive_exact(block_len - 1)?;
+                if let Some(handle) = &mut file {
+                    if let Err(e) = handle.write_all(&data) {
+                        warn!("Unable to write {}: {}", path.display(), e);
+                        file = None;
+                    }
+                }
+                block_len = self.receive_u32()?;
+                if block_len == 0 {
+                    break;
+                }
+                code = self.receive_exact(1)?[0];
+            }
+            if block_len == 0 {
+                break;
+            }
+
+            if block_len > 1 {
+                let payload = self.receive_exact(block_len - 1)?;
+                if code == CODE_ERROR_REMOTE {
+                    warn!(
+                        "Device failed to send {}: {}",
+                        file_name,
+                        String::from_utf8_lossy(&payload)
+                    );
+                }
+            }
+        }
+
+        self.send_status_response(0, None, Some(Plist::new_dict()))
+    }
+
+    /// Replies to a file operation with its result
+    fn send_io_result(&self, result: Result<(), io::Error>) -> Result<(), MobileBackup2Error> {
+        match result {
+            Ok(()) => self.send_status_response(0, None, Some(Plist::new_dict())),
+            Err(e) => {
+                warn!("Backup file operation failed: {}", e);
+                self.send_status_response(
+                    device_error_code(&e),
+                    Some(&e.to_string()),
+                    Some(Plist::new_dict()),
+                )
+            }
+        }
+    }
+
+    /// Sends a length prefixed block of a file transfer
+    fn send_file_block(&self, code: u8, data: &[u8]) -> Result<(), MobileBackup2Error> {
+        let mut header = ((data.len() + 1) as u32).to_be_bytes().to_vec();
+        header.push(code);
+        self.send_all(&header)?;
+        if !data.is_empty() {
+            self.send_all(data)?;
+        }
+        Ok(())
+    }
+
+    fn send_all(&self, mut data: &[u8]) -> Result<(), MobileBackup2Error> {
+        while !data.is_empty() {
+            let sent = self.send_raw(data)?;
+            if sent == 0 {
+                return Err(MobileBackup2Error::MuxError);
+            }
+            data = &data[sent as usize..];
+        }
+        Ok(())
+    }
+
+    fn receive_exact(&self, len: u32) -> Result<Vec<u8>, MobileBackup2Error> {
+        let mut data = Vec::with_capacity(len as usize);
+        while data.len() < len as usize {
+            let chunk = self.receive_raw(len - data.len() as u32)?;
+            if chunk.is_empty() {
+                return Err(MobileBackup2Error::RecieveTimeout);
+            }
+            data.extend(chunk);
+        }
+        Ok(data)
+    }
+
+    fn receive_u32(&self) -> Result<u32, MobileBackup2Error> {
+        let data = self.receive_exact(4)?;
+        Ok(u32::from_be_bytes([data[0], data[1], data[2], data[3]]))
+    }
+}
+
+/// The kinds of request the device makes of the host during a backup
+#[derive(Debug, Clone, PartialEq)]
+pub enum DlMessage {
+    /// The device wants files sent from the host
+    DownloadFiles,
+    /// The device is sending files to the host
+    UploadFiles,
+    GetFreeDiskSpace,
+    PurgeDiskSpace,
+    ContentsOfDirectory,
+    CreateDirectory,
+    MoveFiles,
+    RemoveFiles,
+    CopyItem,
+    /// The operation has finished, successfully or not
+    ProcessMessage,
+    Disconnect,
+    Unknown(String),
+}
+
+impl From<&str> for DlMessage {
+    fn from(name: &str) -> Self {
+        match name {
+            "DLMessageDownloadFiles" => DlMessage::DownloadFiles,
+            "DLMessageUploadFiles" => DlMessage::UploadFiles,
+            "DLMessageGetFreeDiskSpace" => DlMessage::GetFreeDiskSpace,
+            "DLMessagePurgeDiskSpace" => DlMessage::PurgeDiskSpace,
+            "DLContentsOfDirectory" => DlMessage::ContentsOfDirectory,
+            "DLMessageCreateDirectory" => DlMessage::CreateDirectory,
+            "DLMessageMoveFiles" | "DLMessageMoveItems" => DlMessage::MoveFiles,
+            "DLMessageRemoveFiles" | "DLMessageRemoveItems" => DlMessage::RemoveFiles,
+            "DLMessageCopyItem" => DlMessage::CopyItem,
+            "DLMessageProcessMessage" => DlMessage::ProcessMessage,
+            "DLMessageDisconnect" => DlMessage::Disconnect,
+            _ => DlMessage::Unknown(name.to_string()),
+        }
+    }
 }
 
 pub enum MobileBackupRequest {
@@ -559,6 +958,18 @@ pub enum MobileBackupRequest {
     List,
 }
 
+impl MobileBackupRequest {
+    /// The name of the request as the service expects it
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            MobileBackupRequest::Backup => "Backup",
+            MobileBackupRequest::Restore => "Restore",
+            MobileBackupRequest::Info => "Info",
+            MobileBackupRequest::List => "List",
+        }
+    }
+}
+
 /// Choose what to restore
 pub enum MobileBackupRestoreFlags {
     /// Show a restore screen on the device
@@ -579,17 +990,6 @@ impl From<MobileBackupRestoreFlags> for c_uint {
     }
 }
 
-impl From<MobileBackupRequest> for *const c_char {
-    fn from(request: MobileBackupRequest) -> Self {
-        match request {
-            MobileBackupRequest::Backup => "Backup".as_ptr() as *const c_char,
-            MobileBackupRequest::Restore => "Restore".as_ptr() as *const c_char,
-            MobileBackupRequest::Info => "Info".as_ptr() as *const c_char,
-            MobileBackupRequest::List => "List".as_ptr() as *const c_char,
-        }
-    }
-}
-
 impl Drop for MobileBackupClient<'_> {
     fn drop(&mut self) {
         unsafe {
@@ -605,3 +1005,310 @@ impl Drop for MobileBackup2Client<'_> {
         }
     }
 }
+
+/// Versions of the backup protocol understood by `backup`
+const PROTOCOL_VERSIONS: [f64; 2] = [2.0, 2.1];
+/// How much of a file to send to the device at a time
+const FILE_CHUNK_SIZE: usize = 0x10000;
+/// Seconds between the Unix epoch and the plist epoch of 2001-01-01
+const PLIST_EPOCH_OFFSET: u64 = 978_307_200;
+
+// Codes that start each block of a file transfer
+const CODE_SUCCESS: u8 = 0x00;
+const CODE_ERROR_LOCAL: u8 = 0x06;
+const CODE_ERROR_REMOTE: u8 = 0x0b;
+const CODE_FILE_DATA: u8 = 0x0c;
+
+fn c_string(value: &str) -> Result<CString, MobileBackup2Error> {
+    match CString::new(value) {
+        Ok(s) => Ok(s),
+        Err(_) => Err(MobileBackup2Error::InvalidArg),
+    }
+}
+
+/// Resolves a path sent by the device against the backup directory,
+/// dropping any components that would escape it
+fn device_path(base_dir: &Path, path: &str) -> PathBuf {
+    let mut resolved = base_dir.to_path_buf();
+    for component in Path::new(path).components() {
+        if let Component::Normal(part) = component {
+            resolved.push(part);
+        }
+    }
+    resolved
+}
+
+/// Converts a host error to the errno style codes the device expects
+fn device_error_code(error: &io::Error) -> c_int {
+    match error.kind() {
+        io::ErrorKind::NotFound => -6,
+        io::ErrorKind::AlreadyExists => -7,
+        _ => -1,
+    }
+}
+
+fn remove_path(path: &Path) -> Result<(), io::Error> {
+    if fs::symlink_metadata(path)?.is_dir() {
+        fs::remove_dir_all(path)
+    } else {
+        fs::remove_file(path)
+    }
+}
+
+fn copy_path(from: &Path, to: &Path) -> Result<(), io::Error> {
+    if !fs::metadata(from)?.is_dir() {
+        return fs::copy(from, to).map(|_| ());
+    }
+    fs::create_dir_all(to)?;
+    for entry in fs::read_dir(from)? {
+        let entry = entry?;
+        copy_path(&entry.path(), &to.join(entry.file_name()))?;
+    }
+    Ok(())
+}
+
+/// Lists a directory for `DLContentsOfDirectory`
+fn directory_contents(path: &Path) -> Plist {
+    let contents = unsafe { unsafe_bindings::plist_new_dict() };
+    if let Ok(entries) = fs::read_dir(path) {
+        for entry in entries.flatten() {
+            let metadata = match entry.metadata() {
+                Ok(metadata) => metadata,
+                Err(_) => continue,
+            };
+            let file_type = if metadata.is_dir() {
+                "DLFileTypeDirectory"
+            } else if metadata.is_file() {
+                "DLFileTypeRegular"
+            } else {
+                "DLFileTypeUnknown"
+            };
+            let modified = metadata
+                .modified()
+                .ok()
+                .and_then(|m| m.duration_since(UNIX_EPOCH).ok())
+                .map(|d| d.as_secs().saturating_sub(PLIST_EPOCH_OFFSET))
+                .unwrap_or(0);
+
+            let info = unsafe { unsafe_bindings::plist_new_dict() };
+            dict_set(info, "DLFileType", new_string(file_type));
+            dict_set(info, "DLFileSize", unsafe {
+                unsafe_bindings::plist_new_uint(metadata.len())
+            });
+            dict_set(info, "DLFileModificationDate", unsafe {
+                unsafe_bindings::plist_new_date(modified as i32, 0)
+            });
+            dict_set(contents, &entry.file_name().to_string_lossy(), info);
+        }
+    }
+    contents.into()
+}
+
+#[cfg(unix)]
+fn free_disk_space(path: &Path) -> u64 {
+    use std::os::unix::ffi::OsStrExt;
+
+    let path = match CString::new(path.as_os_str().as_bytes()) {
+        Ok(path) => path,
+        Err(_) => return 0,
+    };
+    let mut stats: libc::statvfs = unsafe { std::mem::zeroed() };
+    if unsafe { libc::statvfs(path.as_ptr(), &mut stats) } != 0 {
+        return 0;
+    }
+    stats.f_bavail as u64 * stats.f_frsize as u64
+}
+
+#[cfg(not(unix))]
+fn free_disk_space(_path: &Path) -> u64 {
+    0
+}
+
+/// Finds the overall progress the device attaches to some messages
+fn message_progress(message: unsafe_bindings::plist_t) -> Option<f64> {
+    let mut index = 1;
+    let mut progress = None;
+    while let Some(item) = array_item(message, index) {
+        if let Some(value) = node_real(item) {
+            progress = Some(value);
+        }
+        index += 1;
+    }
+    progress
+}
+
+// The messages from the device are nested arrays and dictionaries,
+// so these read them in place without taking ownership of any node.
+
+fn node_type(node: unsafe_bindings::plist_t) -> Option<unsafe_bindings::plist_type> {
+    if node.is_null() {
+        return None;
+    }
+    Some(unsafe { unsafe_bindings::plist_get_node_type(node) })
+}
+
+fn array_item(node: unsafe_bindings::plist_t, index: u32) -> Option<unsafe_bindings::plist_t> {
+    if node_type(node)? != unsafe_bindings::plist_type_PLIST_ARRAY
+        || index >= unsafe { unsafe_bindings::plist_array_get_size(node) }
+    {
+        return None;
+    }
+    let item = unsafe { unsafe_bindings::plist_array_get_item(node, index) };
+    if item.is_null() {
+        return None;
+    }
+    Some(item)
+}
+
+fn array_strings(node: unsafe_bindings::plist_t) -> Vec<String> {
+    let mut strings = vec![];
+    let mut index = 0;
+    while let Some(item) = array_item(node, index) {
+        if let Some(value) = node_string(item) {
+            strings.push(value);
+        }
+        index += 1;
+    }
+    strings
+}
+
+fn dict_item(node: unsafe_bindings::plist_t, key: &str) -> Option<unsafe_bindings::plist_t> {
+    if node_type(node)? != unsafe_bindings::plist_type_PLIST_DICT {
+        return None;
+    }
+    let key = CString::new(key).ok()?;
+    let item = unsafe { unsafe_bindings::plist_dict_get_item(node, key.as_ptr()) };
+    if item.is_null() {
+        return None;
+    }
+    Some(item)
+}
+
+fn dict_entries(node: unsafe_bindings::plist_t) -> Vec<(String, unsafe_bindings::plist_t)> {
+    let mut entries = vec![];
+    if node_type(node) != Some(unsafe_bindings::plist_type_PLIST_DICT) {
+        return entries;
+    }
+    let mut iter: unsafe_bindings::plist_dict_iter = std::ptr::null_mut();
+    unsafe { unsafe_bindings::plist_dict_new_iter(node, &mut iter) };
+    loop {
+        let mut key: *mut c_char = std::ptr::null_mut();
+        let mut value: unsafe_bindings::plist_t = std::ptr::null_mut();
+        unsafe { unsafe_bindings::plist_dict_next_item(node, iter, &mut key, &mut value) };
+        if key.is_null() {
+            break;
+        }
+        entries.push((
+            unsafe { CStr::from_ptr(key) }
+                .to_string_lossy()
+                .into_owned(),
+            value,
+        ));
+        unsafe { libc::free(key as *mut c_void) };
+    }
+    unsafe { libc::free(iter) };
+    entries
+}
+
+fn node_string(node: unsafe_bindings::plist_t) -> Option<String> {
+    if node_type(node)? != unsafe_bindings::plist_type_PLIST_STRING {
+        return None;
+    }
+    let mut value: *mut c_char = std::ptr::null_mut();
+    unsafe { unsafe_bindings::plist_get_string_val(node, &mut value) };
+    if value.is_null() {
+        return None;
+    }
+    let string = unsafe { CStr::from_ptr(value) }
+        .to_string_lossy()
+        .into_owned();
+    unsafe { libc::free(value as *mut c_void) };
+    Some(string)
+}
+
+fn node_real(node: unsafe_bindings::plist_t) -> Option<f64> {
+    if node_type(node)? != unsafe_bindings::plist_type_PLIST_REAL {
+        return None;
+    }
+    let mut value = 0.0;
+    unsafe { unsafe_bindings::plist_get_real_val(node, &mut value) };
+    Some(value)
+}
+
+fn node_uint(node: unsafe_bindings::plist_t) -> Option<u64> {
+    if node_type(node)? != unsafe_bindings::plist_type_PLIST_UINT {
+        return None;
+    }
+    let mut value = 0;
+    unsafe { unsafe_bindings::plist_get_uint_val(node, &mut value) };
+    Some(value)
+}
+
+fn new_string(value: &str) -> unsafe_bindings::plist_t {
+    let value = CString::new(value.replace('\0', "")).unwrap_or_default();
+    unsafe { unsafe_bindings::plist_new_string(value.as_ptr()) }
+}
+
+/// Adds an item to a dictionary, which takes ownership of it
+fn dict_set(node: unsafe_bindings::plist_t, key: &str, item: unsafe_bindings::plist_t) {
+    let key = CString::new(key.replace('\0', "")).unwrap_or_default();
+    unsafe { unsafe_bindings::plist_dict_set_item(node, key.as_ptr(), item) };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dispatches_dl_messages() {
+        let canned = [
+            ("DLMessageDownloadFiles", DlMessage::DownloadFiles),
+            ("DLMessageUploadFiles", DlMessage::UploadFiles),
+            ("DLMessageGetFreeDiskSpace", DlMessage::GetFreeDiskSpace),
+            ("DLMessagePurgeDiskSpace", DlMessage::PurgeDiskSpace),
+            ("DLContentsOfDirectory", DlMessage::ContentsOfDirectory),
+            ("DLMessageCreateDirectory", DlMessage::CreateDirectory),
+            ("DLMessageMoveFiles", DlMessage::MoveFiles),
+            ("DLMessageMoveItems", DlMessage::MoveFiles),
+            ("DLMessageRemoveFiles", DlMessage::RemoveFiles),
+            ("DLMessageRemoveItems", DlMessage::RemoveFiles),
+            ("DLMessageCopyItem", DlMessage::CopyItem),
+            ("DLMessageProcessMessage", DlMessage::ProcessMessage),
+            ("DLMessageDisconnect", DlMessage::Disconnect),
+        ];
+        for (name, message) in canned {
+            assert_eq!(DlMessage::from(name), message);
+        }
+        assert_eq!(
+            DlMessage::from("DLMessageSomethingNew"),
+            DlMessage::Unknown("DLMessageSomethingNew".to_string())
+        );
+    }
+
+    #[test]
+    fn device_paths_stay_in_the_backup_dir() {
+        let base = Path::new("/backups");
+        assert_eq!(
+            device_path(base, "00008030/Manifest.db"),
+            base.join("00008030").join("Manifest.db")
+        );
+        assert_eq!(
+            device_path(base, "/00008030/Status.plist"),
+            base.join("00008030").join("Status.plist")
+        );
+        assert_eq!(
+            device_path(base, "../../etc/passwd"),
+            base.join("etc").join("passwd")
+        );
+        assert_eq!(device_path(base, "./a/../b"), base.join("a").join("b"));
+        assert_eq!(device_path(base, ""), base.to_path_buf());
+    }
+
+    #[test]
+    fn host_errors_map_to_device_codes() {
+        let code = |kind| device_error_code(&io::Error::new(kind, "test"));
+        assert_eq!(code(io::ErrorKind::NotFound), -6);
+        assert_eq!(code(io::ErrorKind::AlreadyExists), -7);
+        assert_eq!(code(io::ErrorKind::PermissionDenied), -1);
+    }
+}
@@ -1,13 +1,16 @@
 // jkcoxson
 
 use std::{
+    ffi::CString,
+    fs::File,
     io::Read,
-    os::raw::{c_char, c_long, c_ulong},
+    os::raw::c_char,
+    path::{Path, PathBuf},
 };
 
 use libc::c_void;
-use log::{info, trace};
-use plist_plus::Plist;
+use log::{info, trace, warn};
+use plist_plus::{Plist, PlistType};
 
 use super::lockdownd::LockdowndService;
 use crate::{bindings as unsafe_bindings, error::MobileImageMounterError, idevice::Device};
@@ -85,66 +88,79 @@ impl MobileImageMounter<'_> {
         })
     }
 
-    /// Uploads an image from a path to the device
+    /// Uploads an image from a path to the device.
+    /// The image is streamed from disk in chunks as the service requests it,
+    /// and ends up in the staging directory on the device.
     /// # Arguments
-    /// * `image_path` - The path on the host to the image. Cannot contain spaces. TODO: fix this
-    /// * `image_type` - The type of the image to upload, usually "Developer"
-    /// * `signature_path` - The path to the signature
+    /// * `image_path` - The path on the host to the image
+    /// * `image_type` - The type of the image to upload, usually "Developer". Pass "" for none.
+    /// * `signature_path` - The path on the host to the image's signature
     /// # Returns
     /// *none*
     ///
     /// ***Verified:*** False
     pub fn upload_image(
         &self,
-        image_path: String,
-        image_type: String,
-        signature_path: String,
+        image_path: &Path,
+        image_type: &str,
+        signature_path: &Path,
     ) -> Result<(), MobileImageMounterError> {
-        // Determine if files exist
-        let dmg_size = match std::fs::File::open(image_path.clone()) {
-            Ok(mut file) => {
-                let mut temp_buf = vec![];
-                file.read_to_end(&mut temp_buf).unwrap();
-                temp_buf.len()
-            }
-            Err(_) => return Err(MobileImageMounterError::DmgNotFound),
+        self.upload_image_with_progress(image_path, image_type, signature_path, |_, _| {})
+    }
+
+    /// Uploads an image from a path to the device, reporting progress as it is streamed.
+    /// # Arguments
+    /// * `image_path` - The path on the host to the image
+    /// * `image_type` - The type of the image to upload, usually "Developer". Pass "" for none.
+    /// * `signature_path` - The path on the host to the image's signature
+    /// * `progress` - Called with the bytes sent and the image size as each chunk is fed to the device
+    /// # Returns
+    /// *none*
+    ///
+    /// ***Verified:*** False
+    pub fn upload_image_with_progress(
+        &self,
+        image_path: &Path,
+        image_type: &str,
+        signature_path: &Path,
+        mut progress: impl FnMut(u64, u64),
+    ) -> Result<(), MobileImageMounterError> {
+        let dmg_size = match std::fs::metadata(image_path) {
+            Ok(metadata) if metadata.is_file() => metadata.len(),
+            _ => return Err(MobileImageMounterError::DmgNotFound),
         };
-        let signature_size = match std::fs::File::open(signature_path.clone()) {
-            Ok(mut file) => {
-                let mut temp_buf = vec![];
-                file.read_to_end(&mut temp_buf).unwrap();
-                temp_buf.len()
-            }
+        let signature = match std::fs::read(signature_path) {
+            Ok(signature) => signature,
             Err(_) => return Err(MobileImageMounterError::SignatureNotFound),
         };
-        // Read the image into a buffer
-        let image_path_c_str = &mut std::ffi::CString::new(image_path.clone()).unwrap();
-        let mode_c_str = &mut std::ffi::CString::new("rb").unwrap();
+        if signature.len() > u16::MAX as usize {
+            return Err(MobileImageMounterError::InvalidArg);
+        }
+
         info!("Opening image file");
-        let image_buffer = unsafe { libc::fopen(image_path_c_str.as_ptr(), mode_c_str.as_ptr()) };
-        // Read the signature into a buffer
-        let signature_path_c_str = &mut std::ffi::CString::new(signature_path.clone()).unwrap();
-        info!("Reading signature file");
-        let signature_buffer =
-            unsafe { libc::fopen(signature_path_c_str.as_ptr(), mode_c_str.as_ptr()) };
-
-        let image_type_c_str = std::ffi::CString::new(image_type.clone()).unwrap();
-        let image_type_c_str = if image_type == "".to_string() {
-            std::ptr::null()
-        } else {
-            image_type_c_str.as_ptr()
+        let image_file = match File::open(image_path) {
+            Ok(file) => file,
+            Err(_) => return Err(MobileImageMounterError::DmgNotFound),
         };
+        let mut upload = ImageUpload {
+            file: image_file,
+            sent: 0,
+            total: dmg_size,
+            progress: &mut progress,
+        };
+
+        let image_type_c_str = optional_c_string(image_type)?;
 
         info!("Uploading image");
         let result = unsafe {
             unsafe_bindings::mobile_image_mounter_upload_image(
                 self.pointer,
-                image_type_c_str,
-                dmg_size as c_ulong,
-                signature_buffer as *const c_char,
-                signature_size as u16,
+                optional_c_string_ptr(&image_type_c_str),
+                dmg_size as unsafe_bindings::size_t,
+                signature.as_ptr() as *const c_char,
+                signature.len() as u16,
                 Some(image_mounter_callback),
-                image_buffer as *mut c_void,
+                &mut upload as *mut ImageUpload as *mut c_void,
             )
         }
         .into();
@@ -156,49 +172,30 @@ impl MobileImageMounter<'_> {
         Ok(())
     }
 
-    /// Mounts the image on the device
+    /// Mounts an image that has already been uploaded to the device
     /// # Arguments
-    /// * `image_path` - The path on the host to the image. Cannot contain spaces. TODO: fix this
-    /// * `image_type` - The type of the image to upload, usually "Developer"
-    /// * `signature_path` - The path to the signature
+    /// * `image_path_on_device` - The path to the image on the device. Images sent with `upload_image`
+    ///   are placed at `/private/var/mobile/Media/PublicStaging/staging.dimage`
+    /// * `signature` - The contents of the image's signature file
+    /// * `image_type` - The type of the image to mount, usually "Developer". Pass "" for none.
     /// # Returns
-    /// *none*
+    /// A plist containing the result from the device
     ///
     /// ***Verified:*** False
     pub fn mount_image(
         &self,
-        image_path: String,
-        image_type: String,
-        signature_path: String,
+        image_path_on_device: &str,
+        signature: &[u8],
+        image_type: &str,
     ) -> Result<Plist, MobileImageMounterError> {
-        // Read the image into a buffer
-        let mut image_buffer = Vec::new();
-        let file = match std::fs::File::open(image_path.clone()) {
-            Ok(file) => file,
-            Err(_) => return Err(MobileImageMounterError::DmgNotFound),
-        };
-        let mut reader = std::io::BufReader::new(file);
-        match reader.read_to_end(&mut image_buffer) {
-            Ok(_) => (),
-            Err(_) => return Err(MobileImageMounterError::DmgNotFound),
-        };
-        // Read the signature into a buffer
-        let mut signature_buffer = Vec::new();
-        let file = match std::fs::File::open(signature_path) {
-            Ok(file) => file,
-            Err(_) => return Err(MobileImageMounterError::SignatureNotFound),
-        };
-        let mut reader = std::io::BufReader::new(file);
-        match reader.read_to_end(&mut signature_buffer) {
-            Ok(_) => (),
-            Err(_) => return Err(MobileImageMounterError::SignatureNotFound),
-        };
-        let image_type_c_str = std::ffi::CString::new(image_type.clone()).unwrap();
-        let image_type_c_str = if image_type == "".to_string() {
-            std::ptr::null()
-        } else {
-            image_type_c_str.as_ptr()
+        if signature.len() > u16::MAX as usize {
+            return Err(MobileImageMounterError::InvalidArg);
+        }
+        let image_path_c_str = match CString::new(image_path_on_device) {
+            Ok(s) => s,
+            Err(_) => return Err(MobileImageMounterError::InvalidArg),
         };
+        let image_type_c_str = optional_c_string(image_type)?;
 
         let mut plist: unsafe_bindings::plist_t = unsafe { std::mem::zeroed() };
 
@@ -206,10 +203,10 @@ impl MobileImageMounter<'_> {
         let result = unsafe {
             unsafe_bindings::mobile_image_mounter_mount_image(
                 self.pointer,
-                image_path.as_ptr() as *const c_char,
-                signature_buffer.as_ptr() as *const c_char,
-                signature_buffer.len() as u16,
-                image_type_c_str,
+                image_path_c_str.as_ptr(),
+                signature.as_ptr() as *const c_char,
+                signature.len() as u16,
+                optional_c_string_ptr(&image_type_c_str),
                 &mut plist,
             )
         }
@@ -228,13 +225,8 @@ impl MobileImageMounter<'_> {
     /// A plist containing the results. This may return Ok even if failed, check the plist.
     ///
     /// ***Verified:*** False
-    pub fn lookup_image(&self, image_type: String) -> Result<Plist, MobileImageMounterError> {
-        let image_type_c_str = std::ffi::CString::new(image_type.clone()).unwrap();
-        let image_type_c_str = if image_type == "".to_string() {
-            std::ptr::null()
-        } else {
-            image_type_c_str.as_ptr()
-        };
+    pub fn lookup_image(&self, image_type: &str) -> Result<Plist, MobileImageMounterError> {
+        let image_type_c_str = optional_c_string(image_type)?;
 
         let mut plist: unsafe_bindings::plist_t = unsafe { std::mem::zeroed() };
 
@@ -242,7 +234,7 @@ impl MobileImageMounter<'_> {
         let result = unsafe {
             unsafe_bindings::mobile_image_mounter_lookup_image(
                 self.pointer,
-                image_type_c_str,
+                optional_c_string_ptr(&image_type_c_str),
                 &mut plist,
             )
         }
@@ -253,11 +245,282 @@ impl MobileImageMounter<'_> {
         }
         Ok(plist.into())
     }
+
+    /// Checks whether a developer image is already mounted on the device
+    /// # Arguments
+    /// *none*
+    /// # Returns
+    /// True if the device reports a signature for a mounted developer image
+    ///
+    /// ***Verified:*** False
+    pub fn is_developer_image_mounted(&self) -> Result<bool, MobileImageMounterError> {
+        let lookup = self.lookup_image("Developer")?;
+        Ok(has_image_signature(&lookup))
+    }
+
+    /// Unmounts an image from the device. Only supported on iOS 14 and newer.
+    /// Needs the `unmount-image` feature, as older libimobiledevice releases can't unmount.
+    /// # Arguments
+    /// * `mount_path` - The path the image is mounted at, usually "/Developer"
+    /// # Returns
+    /// *none*, or `NotSupported` if the device is too old to unmount images
+    /// or the crate was built without the `unmount-image` feature
+    ///
+    /// ***Verified:*** False
+    pub fn unmount_image(&self, mount_path: &str) -> Result<(), MobileImageMounterError> {
+        let mount_path_c_str = match CString::new(mount_path) {
+            Ok(s) => s,
+            Err(_) => return Err(MobileImageMounterError::InvalidArg),
+        };
+
+        #[cfg(feature = "unmount-image")]
+        {
+            info!("Unmounting image");
+            unmount_result(unsafe {
+                unsafe_bindings::mobile_image_mounter_unmount_image(
+                    self.pointer,
+                    mount_path_c_str.as_ptr(),
+                )
+            })
+        }
+        #[cfg(not(feature = "unmount-image"))]
+        {
+            warn!(
+                "Can't unmount {:?} without the unmount-image feature",
+                mount_path_c_str
+            );
+            Err(MobileImageMounterError::NotSupported)
+        }
+    }
+
+    /// Tells the service that we are done with it, so the device closes the connection
+    /// # Arguments
+    /// *none*
+    /// # Returns
+    /// *none*
+    ///
+    /// ***Verified:*** False
+    pub fn hangup(&self) -> Result<(), MobileImageMounterError> {
+        info!("Hanging up on the image mounter");
+        let result = unsafe { unsafe_bindings::mobile_image_mounter_hangup(self.pointer) }.into();
+
+        if result != MobileImageMounterError::Success {
+            return Err(result);
+        }
+
+        Ok(())
+    }
 }
 
-extern "C" fn image_mounter_callback(a: *mut c_void, b: c_ulong, c: *mut c_void) -> c_long {
-    trace!("image_mounter_callback called");
-    return unsafe { libc::fread(a, 1, b as usize, c as *mut libc::FILE) } as c_long;
+/// The path on the device that `upload_image` places images at
+pub const STAGING_IMAGE_PATH: &str = "/private/var/mobile/Media/PublicStaging/staging.dimage";
+
+/// Mounts the developer disk image matching the device's iOS version, if one isn't mounted already.
+/// `image_dir` is laid out like Xcode's DeviceSupport folder, with one directory per version
+/// (such as `15.4` or `15.4 (19E5235a)`) containing `DeveloperDiskImage.dmg` and
+/// `DeveloperDiskImage.dmg.signature`. If there is no directory for the exact version,
+/// the nearest lower version is used.
+/// # Arguments
+/// * `device` - The device to mount the image on
+/// * `image_dir` - The directory containing the per-version image directories
+/// # Returns
+/// *none*
+///
+/// ***Verified:*** False
+pub fn mount_developer_image(
+    device: &Device,
+    image_dir: &Path,
+) -> Result<(), MobileImageMounterError> {
+    let lockdown_client = match device.new_lockdownd_client("rusty_libimobiledevice".to_string()) {
+        Ok(client) => client,
+        Err(e) => {
+            warn!(
+                "Unable to start lockdownd to mount the developer image: {:?}",
+                e
+            );
+            return Err(MobileImageMounterError::ConnFailed);
+        }
+    };
+    let product_version = match lockdown_client
+        .get_value(None, Some("ProductVersion"))
+        .map(|v| v.get_string_val())
+    {
+        Ok(Ok(version)) => version,
+        _ => {
+            warn!("Unable to read the product version of the device");
+            return Err(MobileImageMounterError::ConnFailed);
+        }
+    };
+    let version = match parse_version(&product_version) {
+        Some(version) => version,
+        None => return Err(MobileImageMounterError::UnknownError),
+    };
+
+    let version_dir = match find_image_dir(image_dir, version) {
+        Some(dir) => dir,
+        None => {
+            warn!("No developer image found for iOS {}", product_version);
+            return Err(MobileImageMounterError::DmgNotFound);
+        }
+    };
+    info!("Using developer image from {}", version_dir.display());
+    let image_path = version_dir.join("DeveloperDiskImage.dmg");
+    let signature_path = version_dir.join("DeveloperDiskImage.dmg.signature");
+
+    let service = match lockdown_client.start_service("com.apple.mobile.mobile_image_mounter") {
+        Ok(service) => service,
+        Err(e) => {
+            warn!("Unable to start the image mounter service: {:?}", e);
+            return Err(MobileImageMounterError::ConnFailed);
+        }
+    };
+    let mounter = device.new_mobile_image_mounter(&service)?;
+
+    if mounter.is_developer_image_mounted()? {
+        info!("Developer image is already mounted");
+        return Ok(());
+    }
+
+    mounter.upload_image(&image_path, "Developer", &signature_path)?;
+    let signature = match std::fs::read(&signature_path) {
+        Ok(signature) => signature,
+        Err(_) => return Err(MobileImageMounterError::SignatureNotFound),
+    };
+    let result = mounter.mount_image(STAGING_IMAGE_PATH, &signature, "Developer")?;
+    if let Ok(error) = result.dict_get_item("Error") {
+        warn!(
+            "Mounting the developer image failed: {:?}",
+            error.get_string_val()
+        );
+        return Err(MobileImageMounterError::CommandFailed);
+    }
+
+    Ok(())
+}
+
+/// Converts the status of an unmount request.
+/// libimobiledevice reports a device too old to know the unmount command as `NotSupported`.
+#[cfg_attr(not(feature = "unmount-image"), allow(dead_code))]
+fn unmount_result(
+    status: unsafe_bindings::mobile_image_mounter_error_t,
+) -> Result<(), MobileImageMounterError> {
+    match status.into() {
+        MobileImageMounterError::Success => Ok(()),
+        e => Err(e),
+    }
+}
+
+/// Parses the major and minor parts of a version such as `15.4.1` or `15.4 (19E5235a)`
+fn parse_version(version: &str) -> Option<(u32, u32)> {
+    let version = version.split_whitespace().next()?;
+    let mut parts = version.split('.');
+    let major = parts.next()?.parse().ok()?;
+    let minor = match parts.next() {
+        Some(minor) => minor.parse().ok()?,
+        None => 0,
+    };
+    Some((major, minor))
+}
+
+/// Finds the image directory for a version, falling back to the nearest lower version
+fn find_image_dir(image_dir: &Path, version: (u32, u32)) -> Option<PathBuf> {
+    let mut best: Option<((u32, u32), PathBuf)> = None;
+    for entry in std::fs::read_dir(image_dir).ok()?.flatten() {
+        let path = entry.path();
+        if !path.join("DeveloperDiskImage.dmg").is_file() {
+            continue;
+        }
+        let dir_version = match path
+            .file_name()
+            .and_then(|n| n.to_str())
+            .and_then(parse_version)
+        {
+            Some(v) => v,
+            None => continue,
+        };
+        if dir_version > version {
+            continue;
+        }
+        match &best {
+            Some((best_version, _)) if *best_version >= dir_version => {}
+            _ => best = Some((dir_version, path)),
+        }
+    }
+    best.map(|(_, path)| path)
+}
+
+/// Checks a lookup result for a non-empty `ImageSignature` array
+fn has_image_signature(lookup: &Plist) -> bool {
+    match lookup.dict_get_item("ImageSignature") {
+        Ok(signatures) => {
+            signatures.plist_type == PlistType::Array && signatures.into_iter().next().is_some()
+        }
+        Err(_) => false,
+    }
+}
+
+/// Converts an image type to a C string, treating "" as no type
+fn optional_c_string(value: &str) -> Result<Option<CString>, MobileImageMounterError> {
+    if value.is_empty() {
+        return Ok(None);
+    }
+    match CString::new(value) {
+        Ok(s) => Ok(Some(s)),
+        Err(_) => Err(MobileImageMounterError::InvalidArg),
+    }
+}
+
+fn optional_c_string_ptr(value: &Option<CString>) -> *const c_char {
+    match value {
+        Some(s) => s.as_ptr(),
+        None => std::ptr::null(),
+    }
+}
+
+/// The state of an image upload, passed to `image_mounter_callback`
+struct ImageUpload<'a> {
+    file: File,
+    sent: u64,
+    total: u64,
+    progress: &'a mut dyn FnMut(u64, u64),
+}
+
+/// Fills the buffer requested by the service with the next chunk of the image.
+/// `user_data` is the `ImageUpload` in progress.
+unsafe extern "C" fn image_mounter_callback(
+    buffer: *mut c_void,
+    length: unsafe_bindings::size_t,
+    user_data: *mut c_void,
+) -> unsafe_bindings::ssize_t {
+    trace!("image_mounter_callback called for {} bytes", length);
+    if buffer.is_null() || user_data.is_null() {
+        return -1;
+    }
+    let upload = &mut *(user_data as *mut ImageUpload);
+    let buffer = std::slice::from_raw_parts_mut(buffer as *mut u8, length as usize);
+    loop {
+        match upload.file.read(buffer) {
+            // The service keeps asking until it has the announced size, so a short file is an error
+            Ok(0) if upload.sent < upload.total => {
+                warn!(
+                    "Image ended after {} of {} bytes",
+                    upload.sent, upload.total
+                );
+                return -1;
+            }
+            Ok(0) => return 0,
+            Ok(read) => {
+                upload.sent += read as u64;
+                (upload.progress)(upload.sent, upload.total);
+                return read as unsafe_bindings::ssize_t;
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+            Err(e) => {
+                warn!("Failed to read image for upload: {}", e);
+                return -1;
+            }
+        }
+    }
 }
 
 impl Drop for MobileImageMounter<'_> {
@@ -268,3 +531,165 @@ impl Drop for MobileImageMounter<'_> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unmount_on_old_version_is_not_supported() {
+        // The status libimobiledevice returns when the device rejects the unmount command
+        let old_version_status: unsafe_bindings::mobile_image_mounter_error_t = -6;
+        assert_eq!(
+            unmount_result(old_version_status),
+            Err(MobileImageMounterError::NotSupported)
+        );
+        assert_eq!(unmount_result(0), Ok(()));
+        assert_eq!(
+            unmount_result(-5),
+            Err(MobileImageMounterError::DeviceLocked)
+        );
+    }
+
+    #[cfg(not(feature = "unmount-image"))]
+    #[test]
+    fn unmount_without_feature_is_not_supported() {
+        // The client is never used, as nothing is sent without the feature
+        let mounter = MobileImageMounter {
+            pointer: std::ptr::null_mut(),
+            phantom: std::marker::PhantomData,
+        };
+        assert_eq!(
+            mounter.unmount_image("/Developer"),
+            Err(MobileImageMounterError::NotSupported)
+        );
+    }
+
+    #[test]
+    fn detects_image_signature() {
+        let mut signatures = Plist::new_array();
+        signatures
+            .array_append_item(Plist::new_string("signature"))
+            .unwrap();
+        let mut lookup = Plist::new_dict();
+        lookup.dict_set_item("ImageSignature", signatures).unwrap();
+        assert!(has_image_signature(&lookup));
+    }
+
+    #[test]
+    fn no_signature_without_mounted_image() {
+        let mut lookup = Plist::new_dict();
+        lookup
+            .dict_set_item("ImageSignature", Plist::new_array())
+            .unwrap();
+        assert!(!has_image_signature(&lookup));
+
+        let mut lookup = Plist::new_dict();
+        lookup
+            .dict_set_item("Status", Plist::new_string("Complete"))
+            .unwrap();
+        assert!(!has_image_signature(&lookup));
+    }
+
+    fn upload_file(name: &str, contents: &[u8]) -> (PathBuf, File) {
+        let path = std::env::temp_dir().join(format!("{}_{}", name, std::process::id()));
+        std::fs::write(&path, contents).unwrap();
+        let file = File::open(&path).unwrap();
+        (path, file)
+    }
+
+    #[test]
+    fn upload_reports_increasing_progress() {
+        let (path, file) = upload_file("image_upload", &[7u8; 10]);
+        let mut reports = vec![];
+        let mut progress = |sent, total| reports.push((sent, total));
+        let mut upload = ImageUpload {
+            file,
+            sent: 0,
+            total: 10,
+            progress: &mut progress,
+        };
+        let user_data = &mut upload as *mut ImageUpload as *mut c_void;
+
+        let mut buffer = [0u8; 4];
+        let mut chunks = vec![];
+        loop {
+            let read =
+                unsafe { image_mounter_callback(buffer.as_mut_ptr() as *mut c_void, 4, user_data) };
+            assert!(read >= 0 && chunks.len() < 10);
+            if read == 0 {
+                break;
+            }
+            chunks.push(read);
+        }
+        drop(upload);
+
+        assert_eq!(chunks, vec![4, 4, 2]);
+        assert_eq!(reports, vec![(4, 10), (8, 10), (10, 10)]);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn upload_of_short_file_fails() {
+        let (path, file) = upload_file("short_image_upload", &[7u8; 3]);
+        let mut progress = |_, _| {};
+        let mut upload = ImageUpload {
+            file,
+            sent: 0,
+            total: 10,
+            progress: &mut progress,
+        };
+        let user_data = &mut upload as *mut ImageUpload as *mut c_void;
+
+        let mut buffer = [0u8; 8];
+        let buffer = buffer.as_mut_ptr() as *mut c_void;
+        assert_eq!(unsafe { image_mounter_callback(buffer, 8, user_data) }, 3);
+        assert_eq!(unsafe { image_mounter_callback(buffer, 8, user_data) }, -1);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn parses_versions() {
+        assert_eq!(parse_version("15.4.1"), Some((15, 4)));
+        assert_eq!(parse_version("15.4 (19E5235a)"), Some((15, 4)));
+        assert_eq!(parse_version("16"), Some((16, 0)));
+        assert_eq!(parse_version("beta"), None);
+        assert_eq!(parse_version(""), None);
+    }
+
+    #[test]
+    fn finds_nearest_lower_image_dir() {
+        let root = std::env::temp_dir().join(format!("image_dirs_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        for (name, has_image) in [
+            ("14.8", true),
+            ("15.0", true),
+            ("15.2 (19C51)", true),
+            ("15.4", false),
+            ("16.0", true),
+            ("Notes", true),
+        ] {
+            let dir = root.join(name);
+            std::fs::create_dir_all(&dir).unwrap();
+            if has_image {
+                std::fs::write(dir.join("DeveloperDiskImage.dmg"), b"").unwrap();
+            }
+        }
+
+        assert_eq!(
+            find_image_dir(&root, (15, 2)),
+            Some(root.join("15.2 (19C51)"))
+        );
+        // 15.4 has no image, so the nearest lower version is used
+        assert_eq!(
+            find_image_dir(&root, (15, 4)),
+            Some(root.join("15.2 (19C51)"))
+        );
+        assert_eq!(find_image_dir(&root, (15, 1)), Some(root.join("15.0")));
+        assert_eq!(find_image_dir(&root, (17, 0)), Some(root.join("16.0")));
+        assert_eq!(find_image_dir(&root, (13, 0)), None);
+        assert_eq!(find_image_dir(&root.join("missing"), (15, 0)), None);
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -1,16 +1,37 @@
 // jkcoxson
 
+use std::{
+    collections::HashSet,
+    ffi::{CStr, CString},
+    os::raw::{c_char, c_void},
+};
+
 use crate::bindings as unsafe_bindings;
-use crate::error::NpError;
+use crate::error::{InstProxyError, NpError};
 use crate::idevice::Device;
+use crate::services::instproxy::InstProxyClient;
 use crate::services::lockdownd::LockdowndService;
 
+use log::{info, warn};
+
+/// Posted by the device after an app is installed or updated
+pub const APP_INSTALLED_NOTIFICATION: &str = "com.apple.mobile.application_installed";
+/// Posted by the device after an app is removed
+pub const APP_UNINSTALLED_NOTIFICATION: &str = "com.apple.mobile.application_uninstalled";
+
+/// The callback run for each observed notification
+type NotifyCallback = Box<dyn FnMut(&str) + Send>;
+
 /// A service to proxy notifications to the device
 pub struct NotificationProxyClient<'a> {
     pub(crate) pointer: unsafe_bindings::np_client_t,
+    callback: Option<Box<NotifyCallback>>,
     phantom: std::marker::PhantomData<&'a Device>,
 }
 
+unsafe impl Send for NotificationProxyClient<'_> {}
+unsafe impl Sync for NotificationProxyClient<'_> {}
+
 impl NotificationProxyClient<'_> {
     /// Creates a new notification proxy from a lockdown service
     /// # Arguments
@@ -33,6 +54,7 @@ impl NotificationProxyClient<'_> {
 
         Ok(Self {
             pointer,
+            callback: None,
             phantom: std::marker::PhantomData,
         })
     }
@@ -46,12 +68,13 @@ impl NotificationProxyClient<'_> {
     ///
     /// ***Verified:*** False
     pub fn start_service(device: &Device, label: String) -> Result<Self, NpError> {
+        let label_c_str = c_string(&label)?;
         let mut pointer = std::ptr::null_mut();
         let result = unsafe {
             unsafe_bindings::np_client_start_service(
                 device.pointer,
                 &mut pointer,
-                label.as_ptr() as *const std::os::raw::c_char,
+                label_c_str.as_ptr(),
             )
         }
         .into();
@@ -62,23 +85,22 @@ impl NotificationProxyClient<'_> {
 
         Ok(Self {
             pointer,
+            callback: None,
             phantom: std::marker::PhantomData,
         })
     }
 
     /// Sends a notification to the device
     /// # Arguments
-    /// * `notification` - The contents of the notification
+    /// * `notification` - The name of the notification
     /// # Returns
     /// *none*
     ///
     /// ***Verified:*** False
     pub fn post_notification(&self, notification: &str) -> Result<(), NpError> {
+        let notification_c_str = c_string(notification)?;
         let result = unsafe {
-            unsafe_bindings::np_post_notification(
-                self.pointer,
-                notification.as_ptr() as *const std::os::raw::c_char,
-            )
+            unsafe_bindings::np_post_notification(self.pointer, notification_c_str.as_ptr())
         }
         .into();
 
@@ -89,19 +111,18 @@ impl NotificationProxyClient<'_> {
         Ok(())
     }
 
-    /// Tells the proxy to send a notification when an event occurs
+    /// Tells the proxy to send a notification when an event occurs.
+    /// Notifications can be observed before or after the callback is set.
     /// # Arguments
-    /// * `notification` - The contents of the notification
+    /// * `notification` - The name of the notification, such as "com.apple.mobile.application_installed"
     /// # Returns
     /// *none*
     ///
     /// ***Verified:*** False
     pub fn observe_notification(&self, notification: &str) -> Result<(), NpError> {
+        let notification_c_str = c_string(notification)?;
         let result = unsafe {
-            unsafe_bindings::np_observe_notification(
-                self.pointer,
-                notification.as_ptr() as *const std::os::raw::c_char,
-            )
+            unsafe_bindings::np_observe_notification(self.pointer, notification_c_str.as_ptr())
         }
         .into();
 
@@ -114,16 +135,19 @@ impl NotificationProxyClient<'_> {
 
     /// Tells the proxy to send notifications when an event occurs
     /// # Arguments
-    /// * `notifications` - The contents of the notifications
+    /// * `notifications` - The names of the notifications
     /// # Returns
     /// *none*
     ///
     /// ***Verified:*** False
     pub fn observe_notifications(&self, notifications: Vec<&str>) -> Result<(), NpError> {
-        let mut not_ptrs = Vec::new();
-        for notification in notifications {
-            not_ptrs.push(notification.as_ptr() as *const std::os::raw::c_char);
-        }
+        let c_strings = notifications
+            .into_iter()
+            .map(c_string)
+            .collect::<Result<Vec<CString>, NpError>>()?;
+        let mut not_ptrs: Vec<*const c_char> = c_strings.iter().map(|s| s.as_ptr()).collect();
+        // The list is terminated by a null pointer
+        not_ptrs.push(std::ptr::null());
 
         let result = unsafe {
             unsafe_bindings::np_observe_notifications(self.pointer, not_ptrs.as_mut_ptr())
@@ -136,12 +160,238 @@ impl NotificationProxyClient<'_> {
 
         Ok(())
     }
+
+    /// Sets the callback run for each observed notification.
+    /// The callback runs on a thread owned by libimobiledevice, replacing any previous callback.
+    /// # Arguments
+    /// * `callback` - Called with the name of each notification received
+    /// # Returns
+    /// *none*
+    ///
+    /// ***Verified:*** False
+    pub fn set_notify_callback<F>(&mut self, callback: F) -> Result<(), NpError>
+    where
+        F: FnMut(&str) + Send + 'static,
+    {
+        self.clear_notify_callback()?;
+
+        let mut callback: Box<NotifyCallback> = Box::new(Box::new(callback));
+        let user_data = &mut *callback as *mut NotifyCallback as *mut c_void;
+
+        info!("Setting notification callback");
+        let result = unsafe {
+            unsafe_bindings::np_set_notify_callback(self.pointer, Some(notify_callback), user_data)
+        }
+        .into();
+
+        if result != NpError::Success {
+            return Err(result);
+        }
+
+        self.callback = Some(callback);
+        Ok(())
+    }
+
+    /// Removes the callback set with `set_notify_callback`, stopping its thread
+    /// # Arguments
+    /// *none*
+    /// # Returns
+    /// *none*
+    ///
+    /// ***Verified:*** False
+    pub fn clear_notify_callback(&mut self) -> Result<(), NpError> {
+        if self.callback.is_none() {
+            return Ok(());
+        }
+
+        info!("Clearing notification callback");
+        let result = unsafe {
+            unsafe_bindings::np_set_notify_callback(self.pointer, None, std::ptr::null_mut())
+        }
+        .into();
+
+        if result != NpError::Success {
+            return Err(result);
+        }
+
+        // The notification thread has been joined, so the callback can be freed
+        self.callback = None;
+        Ok(())
+    }
+}
+
+unsafe extern "C" fn notify_callback(notification: *const c_char, user_data: *mut c_void) {
+    if notification.is_null() || user_data.is_null() {
+        return;
+    }
+    let callback = &mut *(user_data as *mut NotifyCallback);
+    let notification = CStr::from_ptr(notification).to_string_lossy();
+    callback(&notification);
+}
+
+/// A change to the apps installed on the device, reported by `watch_installs`
+#[derive(Debug, Clone, PartialEq)]
+pub enum InstallEvent {
+    /// An app with this bundle id was installed
+    Installed(String),
+    /// The app with this bundle id was removed
+    Uninstalled(String),
+}
+
+/// Keeps `watch_installs` running. Dropping it stops the watcher.
+pub struct InstallWatcher {
+    // Dropped before the device it was created from
+    _proxy: NotificationProxyClient<'static>,
+    _device: Device,
+}
+
+/// Watches the device for apps being installed and removed.
+/// The install notifications don't name the app, so the installed apps are looked up with
+/// instproxy after each one and compared against the previous lookup.
+/// Updates to an app that was already installed aren't reported.
+/// # Arguments
+/// * `device` - The device to watch
+/// * `callback` - Called on a background thread with each install or removal
+/// # Returns
+/// A guard that keeps the watcher running until it is dropped
+///
+/// ***Verified:*** False
+pub fn watch_installs<F>(device: &Device, mut callback: F) -> Result<InstallWatcher, NpError>
+where
+    F: FnMut(InstallEvent) + Send + 'static,
+{
+    let device = device.clone();
+    let lookup_device = device.clone();
+    let mut installed = match installed_bundle_ids(&lookup_device) {
+        Ok(installed) => installed,
+        Err(e) => {
+            warn!("Unable to list installed apps to watch: {:?}", e);
+            return Err(NpError::ConnFailed);
+        }
+    };
+
+    let mut proxy = NotificationProxyClient::start_service(&device, "watch_installs".to_string())?;
+    proxy.observe_notifications(vec![
+        APP_INSTALLED_NOTIFICATION,
+        APP_UNINSTALLED_NOTIFICATION,
+    ])?;
+    proxy.set_notify_callback(move |notification| {
+        if notification != APP_INSTALLED_NOTIFICATION
+            && notification != APP_UNINSTALLED_NOTIFICATION
+        {
+            return;
+        }
+        let current = match installed_bundle_ids(&lookup_device) {
+            Ok(current) => current,
+            Err(e) => {
+                warn!(
+                    "Unable to list installed apps after {}: {:?}",
+                    notification, e
+                );
+                return;
+            }
+        };
+        for event in install_events(&installed, &current) {
+            callback(event);
+        }
+        installed = current;
+    })?;
+
+    Ok(InstallWatcher {
+        _proxy: proxy,
+        _device: device,
+    })
+}
+
+/// Compares two lookups of the installed apps.
+/// Installs are listed before removals, each sorted by bundle id.
+fn install_events(before: &HashSet<String>, after: &HashSet<String>) -> Vec<InstallEvent> {
+    let mut installed: Vec<&String> = after.difference(before).collect();
+    let mut uninstalled: Vec<&String> = before.difference(after).collect();
+    installed.sort();
+    uninstalled.sort();
+    installed
+        .into_iter()
+        .map(|bundle_id| InstallEvent::Installed(bundle_id.clone()))
+        .chain(
+            uninstalled
+                .into_iter()
+                .map(|bundle_id| InstallEvent::Uninstalled(bundle_id.clone())),
+        )
+        .collect()
+}
+
+/// Lists the bundle ids of every app on the device
+fn installed_bundle_ids(device: &Device) -> Result<HashSet<String>, InstProxyError> {
+    let client = InstProxyClient::new(device, "watch_installs".to_string())?;
+    Ok(client.lookup_typed(&[], &[])?.into_keys().collect())
+}
+
+fn c_string(value: &str) -> Result<CString, NpError> {
+    match CString::new(value) {
+        Ok(s) => Ok(s),
+        Err(_) => Err(NpError::InvalidArg),
+    }
 }
 
 impl Drop for NotificationProxyClient<'_> {
     fn drop(&mut self) {
+        info!("Dropping notification proxy client");
+        let _ = self.clear_notify_callback();
         unsafe {
             unsafe_bindings::np_client_free(self.pointer);
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bundle_ids(ids: &[&str]) -> HashSet<String> {
+        ids.iter().map(|id| id.to_string()).collect()
+    }
+
+    #[test]
+    fn reports_installed_and_removed_apps() {
+        let before = bundle_ids(&["com.apple.mobilesafari", "com.example.old"]);
+        let after = bundle_ids(&["com.apple.mobilesafari", "com.example.b", "com.example.a"]);
+        assert_eq!(
+            install_events(&before, &after),
+            vec![
+                InstallEvent::Installed("com.example.a".to_string()),
+                InstallEvent::Installed("com.example.b".to_string()),
+                InstallEvent::Uninstalled("com.example.old".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn unchanged_apps_report_nothing() {
+        let apps = bundle_ids(&["com.apple.mobilesafari", "com.example.app"]);
+        assert!(install_events(&apps, &apps).is_empty());
+        assert!(install_events(&HashSet::new(), &HashSet::new()).is_empty());
+    }
+
+    #[test]
+    fn callback_receives_the_posted_name() {
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let mut callback: Box<NotifyCallback> = Box::new(Box::new(move |name: &str| {
+            sender.send(name.to_string()).unwrap();
+        }));
+        let user_data = &mut *callback as *mut NotifyCallback as *mut c_void;
+
+        // What libimobiledevice's notification thread does for each notification
+        let name = CString::new(APP_INSTALLED_NOTIFICATION).unwrap();
+        unsafe {
+            notify_callback(name.as_ptr(), user_data);
+            notify_callback(std::ptr::null(), user_data);
+            notify_callback(name.as_ptr(), std::ptr::null_mut());
+        }
+
+        assert_eq!(
+            receiver.try_iter().collect::<Vec<_>>(),
+            vec![APP_INSTALLED_NOTIFICATION.to_string()]
+        );
+    }
+}
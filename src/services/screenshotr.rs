@@ -1,19 +1,26 @@
 // jkcoxson
 
-use std::os::raw::c_char;
+use std::ffi::CString;
 
 use crate::{
     bindings as unsafe_bindings, error::ScreenshotrError, idevice::Device,
     services::lockdownd::LockdowndService,
 };
 
+use log::info;
+
+/// Takes screenshots of the device's screen.
+/// The service is only available once the Developer Disk Image is mounted,
+/// see `mobile_image_mounter::mount_developer_image`.
 pub struct ScreenshotrClient<'a> {
     pub(crate) pointer: unsafe_bindings::screenshotr_client_t,
     phantom: std::marker::PhantomData<&'a Device>,
 }
 
+unsafe impl Send for ScreenshotrClient<'_> {}
+
 impl ScreenshotrClient<'_> {
-    /// Creates a preboard client from a screenshotr service
+    /// Creates a screenshotr client from a lockdown service
     /// # Arguments
     /// * `device` - The device to connect to
     /// * `descriptor` - The lockdown service to connect on
@@ -51,12 +58,16 @@ impl ScreenshotrClient<'_> {
     ///
     /// ***Verified:*** False
     pub fn start_service(device: &Device, label: String) -> Result<Self, ScreenshotrError> {
+        let label_c_str = match CString::new(label) {
+            Ok(s) => s,
+            Err(_) => return Err(ScreenshotrError::InvalidArg),
+        };
         let mut pointer = std::ptr::null_mut();
         let result = unsafe {
             unsafe_bindings::screenshotr_client_start_service(
                 device.pointer,
                 &mut pointer,
-                label.as_ptr() as *const c_char,
+                label_c_str.as_ptr(),
             )
         }
         .into();
@@ -75,12 +86,13 @@ impl ScreenshotrClient<'_> {
     /// # Arguments
     /// *none*
     /// # Returns
-    /// A vector of bytes containing a .png
+    /// The bytes of the image. Older devices return a TIFF, newer ones a PNG.
     ///
     /// ***Verified:*** False
-    pub fn take_screenshot(&self) -> Result<Vec<c_char>, ScreenshotrError> {
+    pub fn take_screenshot(&self) -> Result<Vec<u8>, ScreenshotrError> {
         let mut data = std::ptr::null_mut();
         let mut size = 0;
+        info!("Taking screenshot");
         let result = unsafe {
             unsafe_bindings::screenshotr_take_screenshot(self.pointer, &mut data, &mut size)
         }
@@ -90,10 +102,12 @@ impl ScreenshotrClient<'_> {
             return Err(result);
         }
 
-        let mut buffer = Vec::with_capacity(size as usize);
-        unsafe {
-            std::ptr::copy_nonoverlapping(data, buffer.as_mut_ptr(), size as usize);
+        if data.is_null() {
+            return Err(ScreenshotrError::UnknownError);
         }
+        let buffer =
+            unsafe { std::slice::from_raw_parts(data as *const u8, size as usize) }.to_vec();
+        unsafe { libc::free(data as *mut libc::c_void) };
 
         Ok(buffer)
     }
@@ -101,8 +115,33 @@ impl ScreenshotrClient<'_> {
 
 impl Drop for ScreenshotrClient<'_> {
     fn drop(&mut self) {
+        info!("Dropping screenshotr client");
         unsafe {
             unsafe_bindings::screenshotr_client_free(self.pointer);
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::idevice;
+
+    #[test]
+    #[cfg_attr(not(feature = "hardware"), ignore = "needs a connected device")]
+    fn screenshot_is_an_image() {
+        // The developer disk image has to be mounted already
+        let device = idevice::test_device();
+        let client = ScreenshotrClient::start_service(&device, "screenshot_test".to_string())
+            .expect("Is the developer disk image mounted?");
+        let image = client.take_screenshot().unwrap();
+
+        let is_png = image.starts_with(b"\x89PNG\r\n\x1a\n");
+        let is_tiff = image.starts_with(b"MM\0*") || image.starts_with(b"II*\0");
+        assert!(
+            is_png || is_tiff,
+            "unknown header {:02x?}",
+            &image[..image.len().min(8)]
+        );
+    }
+}
@@ -7,6 +7,7 @@ use crate::{
     services::lockdownd::LockdowndService,
 };
 
+use log::warn;
 use plist_plus::Plist;
 
 /// A service to manage Springboard on iOS
@@ -15,6 +16,10 @@ pub struct SpringboardServicesClient<'a> {
     phantom: std::marker::PhantomData<&'a Device>,
 }
 
+// libimobiledevice locks a mutex around every springboard request
+unsafe impl Send for SpringboardServicesClient<'_> {}
+unsafe impl Sync for SpringboardServicesClient<'_> {}
+
 impl SpringboardServicesClient<'_> {
     /// Creates a preboard client from a springboard service
     /// # Arguments
@@ -24,7 +29,7 @@ impl SpringboardServicesClient<'_> {
     /// A struct containing the handle to the connection
     ///
     /// ***Verified:*** False
-    pub fn new(device: &Device, descriptor: LockdowndService) -> Result<Self, SbservicesError> {
+    pub fn new(device: &Device, descriptor: &LockdowndService) -> Result<Self, SbservicesError> {
         let mut pointer = std::ptr::null_mut();
         let result = unsafe {
             unsafe_bindings::sbservices_client_new(device.pointer, descriptor.pointer, &mut pointer)
@@ -77,16 +82,17 @@ impl SpringboardServicesClient<'_> {
     /// A plist with the icon state
     ///
     /// ***Verified:*** False
-    pub fn get_icon_state(&self, format_version: Option<String>) -> Result<Plist, SbservicesError> {
+    pub fn get_icon_state(&self, format_version: Option<&str>) -> Result<Plist, SbservicesError> {
+        let format_version_c_str = format_version_c_string(format_version)?;
         let mut plist = std::ptr::null_mut();
         let result = unsafe {
             unsafe_bindings::sbservices_get_icon_state(
                 self.pointer,
                 &mut plist,
-                format_version
+                format_version_c_str
                     .as_ref()
                     .map(|s| s.as_ptr())
-                    .unwrap_or(std::ptr::null()) as *mut c_char,
+                    .unwrap_or(std::ptr::null()),
             )
         }
         .into();
@@ -122,16 +128,20 @@ impl SpringboardServicesClient<'_> {
     /// # Arguments
     /// * `bundle_id` - The bundle ID of the app to take the icon from
     /// # Returns
-    /// A vector of bytes containing the .png
+    /// The raw bytes of the .png
     ///
     /// ***Verified:*** False
-    pub fn get_icon_png_data(&self, bundle_id: String) -> Result<Vec<c_char>, SbservicesError> {
-        let mut data = std::ptr::null_mut();
+    pub fn get_icon_png_data(&self, bundle_id: String) -> Result<Vec<u8>, SbservicesError> {
+        let bundle_id_c_str = match std::ffi::CString::new(bundle_id) {
+            Ok(s) => s,
+            Err(_) => return Err(SbservicesError::InvalidArg),
+        };
+        let mut data: *mut c_char = std::ptr::null_mut();
         let mut size = 0;
         let result = unsafe {
             unsafe_bindings::sbservices_get_icon_pngdata(
                 self.pointer,
-                bundle_id.as_ptr() as *const c_char,
+                bundle_id_c_str.as_ptr(),
                 &mut data,
                 &mut size,
             )
@@ -142,12 +152,7 @@ impl SpringboardServicesClient<'_> {
             return Err(result);
         }
 
-        let mut vec = Vec::with_capacity(size as usize);
-        unsafe {
-            std::ptr::copy_nonoverlapping(data, vec.as_mut_ptr(), size as usize);
-        }
-
-        Ok(vec)
+        Ok(unsafe { png_data_to_vec(data, size) })
     }
 
     /// Gets the orientation of the device
@@ -168,18 +173,18 @@ impl SpringboardServicesClient<'_> {
             return Err(result);
         }
 
-        Ok(orientation.into())
+        orientation.try_into()
     }
 
     /// Gets the wallpaper of the homescreen
     /// # Arguments
     /// *none*
     /// # Returns
-    /// A vector of bytes containing the .png
+    /// The raw bytes of the .png
     ///
     /// ***Verified:*** False
-    pub fn get_home_screen_wallpaper_pngdata(&self) -> Result<Vec<c_char>, SbservicesError> {
-        let mut data = std::ptr::null_mut();
+    pub fn get_home_screen_wallpaper_pngdata(&self) -> Result<Vec<u8>, SbservicesError> {
+        let mut data: *mut c_char = std::ptr::null_mut();
         let mut size = 0;
         let result = unsafe {
             unsafe_bindings::sbservices_get_home_screen_wallpaper_pngdata(
@@ -194,13 +199,57 @@ impl SpringboardServicesClient<'_> {
             return Err(result);
         }
 
-        let mut vec = Vec::with_capacity(size as usize);
-        unsafe {
-            std::ptr::copy_nonoverlapping(data, vec.as_mut_ptr(), size as usize);
+        Ok(unsafe { png_data_to_vec(data, size) })
+    }
+
+    /// Get the icon of an app as a decoded image
+    /// # Arguments
+    /// * `bundle_id` - The bundle ID of the app to take the icon from
+    /// # Returns
+    /// The decoded icon
+    ///
+    /// ***Verified:*** False
+    #[cfg(feature = "image")]
+    pub fn get_icon(&self, bundle_id: &str) -> Result<image::DynamicImage, SbservicesError> {
+        let data = self.get_icon_png_data(bundle_id.to_string())?;
+        decode_png(&data)
+    }
+
+    /// Gets the wallpaper of the homescreen as a decoded image
+    /// # Arguments
+    /// *none*
+    /// # Returns
+    /// The decoded wallpaper
+    ///
+    /// ***Verified:*** False
+    #[cfg(feature = "image")]
+    pub fn get_wallpaper(&self) -> Result<image::DynamicImage, SbservicesError> {
+        let data = self.get_home_screen_wallpaper_pngdata()?;
+        decode_png(&data)
+    }
+}
+
+#[cfg(feature = "image")]
+fn decode_png(data: &[u8]) -> Result<image::DynamicImage, SbservicesError> {
+    match image::load_from_memory_with_format(data, image::ImageFormat::Png) {
+        Ok(image) => Ok(image),
+        Err(e) => {
+            warn!("Unable to decode png data: {}", e);
+            Err(SbservicesError::UnknownError)
         }
+    }
+}
 
-        Ok(vec)
+/// Copies a PNG buffer allocated by libimobiledevice into a byte vector and frees it
+/// # Safety
+/// `data` must be null or a buffer of `size` bytes allocated with malloc
+unsafe fn png_data_to_vec(data: *mut c_char, size: u64) -> Vec<u8> {
+    if data.is_null() {
+        return Vec::new();
     }
+    let vec = std::slice::from_raw_parts(data as *const u8, size as usize).to_vec();
+    libc::free(data as *mut libc::c_void);
+    vec
 }
 
 /// A device orientation
@@ -224,19 +273,35 @@ impl From<Orientation> for c_uint {
     }
 }
 
-impl From<c_uint> for Orientation {
-    fn from(orientation: c_uint) -> Self {
+impl TryFrom<c_uint> for Orientation {
+    type Error = SbservicesError;
+
+    fn try_from(orientation: c_uint) -> Result<Self, Self::Error> {
         match orientation {
-            0 => Orientation::Unknown,
-            1 => Orientation::Portrait,
-            2 => Orientation::PortraitUpsideDown,
-            3 => Orientation::LandscapeRight,
-            4 => Orientation::LandscapeLeft,
-            _ => panic!("Unknown orientation: {}", orientation),
+            0 => Ok(Orientation::Unknown),
+            1 => Ok(Orientation::Portrait),
+            2 => Ok(Orientation::PortraitUpsideDown),
+            3 => Ok(Orientation::LandscapeRight),
+            4 => Ok(Orientation::LandscapeLeft),
+            _ => {
+                warn!(
+                    "Unknown orientation reported by the device: {}",
+                    orientation
+                );
+                Err(SbservicesError::UnknownError)
+            }
         }
     }
 }
 
+impl std::fmt::Debug for SpringboardServicesClient<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("SpringboardServicesClient")
+            .field("connected", &!self.pointer.is_null())
+            .finish()
+    }
+}
+
 impl Drop for SpringboardServicesClient<'_> {
     fn drop(&mut self) {
         unsafe {
@@ -244,3 +309,180 @@ impl Drop for SpringboardServicesClient<'_> {
         }
     }
 }
+
+/// Converts the optional format version into a C string, which is left out when there is none
+fn format_version_c_string(
+    format_version: Option<&str>,
+) -> Result<Option<std::ffi::CString>, SbservicesError> {
+    match format_version.map(std::ffi::CString::new) {
+        Some(Ok(s)) => Ok(Some(s)),
+        Some(Err(_)) => Err(SbservicesError::InvalidArg),
+        None => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn orientation_round_trips() {
+        for value in 0..=4 {
+            let orientation = Orientation::try_from(value).unwrap();
+            assert_eq!(c_uint::from(orientation), value);
+        }
+        assert!(matches!(
+            Orientation::try_from(3),
+            Ok(Orientation::LandscapeRight)
+        ));
+    }
+
+    #[test]
+    fn unknown_orientation_is_an_error() {
+        assert!(matches!(
+            Orientation::try_from(99),
+            Err(SbservicesError::UnknownError)
+        ));
+    }
+
+    #[test]
+    fn format_version_is_nul_terminated() {
+        let version = format_version_c_string(Some("2")).unwrap().unwrap();
+        assert_eq!(version.as_bytes_with_nul(), b"2\0");
+        assert!(format_version_c_string(None).unwrap().is_none());
+        assert!(matches!(
+            format_version_c_string(Some("2\0")),
+            Err(SbservicesError::InvalidArg)
+        ));
+    }
+
+    #[test]
+    #[cfg_attr(not(feature = "hardware"), ignore = "needs a connected device")]
+    fn icon_state_with_and_without_format_version() {
+        let device = crate::idevice::test_device();
+        let client =
+            SpringboardServicesClient::start_service(&device, "springboard-test".to_string())
+                .unwrap();
+        client.get_icon_state(Some("2")).unwrap();
+        client.get_icon_state(None).unwrap();
+    }
+
+    #[test]
+    fn debug_shows_connection_without_dereferencing() {
+        let client = SpringboardServicesClient {
+            pointer: std::ptr::null_mut(),
+            phantom: std::marker::PhantomData,
+        };
+        assert_eq!(
+            format!("{:?}", client),
+            "SpringboardServicesClient { connected: false }"
+        );
+        std::mem::forget(client);
+
+        let client = SpringboardServicesClient {
+            pointer: std::ptr::NonNull::dangling().as_ptr(),
+            phantom: std::marker::PhantomData,
+        };
+        assert_eq!(
+            format!("{:?}", client),
+            "SpringboardServicesClient { connected: true }"
+        );
+        std::mem::forget(client);
+    }
+
+    const PNG_MAGIC: &[u8] = b"\x89PNG\r\n\x1a\n";
+    const SETTINGS_BUNDLE_ID: &str = "com.apple.Preferences";
+
+    #[test]
+    #[cfg_attr(not(feature = "hardware"), ignore = "needs a connected device")]
+    fn icon_is_a_png() {
+        let device = crate::idevice::test_device();
+        let client =
+            SpringboardServicesClient::start_service(&device, "springboard-test".to_string())
+                .unwrap();
+        let icon = client
+            .get_icon_png_data(SETTINGS_BUNDLE_ID.to_string())
+            .unwrap();
+        assert!(icon.starts_with(PNG_MAGIC));
+    }
+
+    #[test]
+    #[cfg_attr(not(feature = "hardware"), ignore = "needs a connected device")]
+    fn wallpaper_is_a_png() {
+        let device = crate::idevice::test_device();
+        let client =
+            SpringboardServicesClient::start_service(&device, "springboard-test".to_string())
+                .unwrap();
+        let wallpaper = client.get_home_screen_wallpaper_pngdata().unwrap();
+        assert!(wallpaper.len() > PNG_MAGIC.len());
+        assert!(wallpaper.starts_with(PNG_MAGIC));
+    }
+
+    #[test]
+    #[cfg_attr(not(feature = "hardware"), ignore = "needs a connected device")]
+    fn icon_bytes_write_a_png_file() {
+        let device = crate::idevice::test_device();
+        let client =
+            SpringboardServicesClient::start_service(&device, "springboard-test".to_string())
+                .unwrap();
+        let icon: Vec<u8> = client
+            .get_icon_png_data(SETTINGS_BUNDLE_ID.to_string())
+            .unwrap();
+        let path = std::env::temp_dir().join(format!("sbservices_icon_{}.png", std::process::id()));
+        std::fs::write(&path, &icon).unwrap();
+        let written = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        // The IHDR chunk comes first, holding the big endian width and height
+        assert!(written.starts_with(PNG_MAGIC));
+        assert_eq!(&written[12..16], b"IHDR");
+        let width = u32::from_be_bytes(written[16..20].try_into().unwrap());
+        let height = u32::from_be_bytes(written[20..24].try_into().unwrap());
+        assert!(width > 0 && height > 0);
+    }
+
+    #[test]
+    #[cfg(feature = "image")]
+    fn decodes_png_data() {
+        let mut png = std::io::Cursor::new(vec![]);
+        image::DynamicImage::new_rgba8(3, 2)
+            .write_to(&mut png, image::ImageOutputFormat::Png)
+            .unwrap();
+        let image = decode_png(png.get_ref()).unwrap();
+        assert_eq!((image.width(), image.height()), (3, 2));
+        assert!(matches!(
+            decode_png(b"not a png"),
+            Err(SbservicesError::UnknownError)
+        ));
+    }
+
+    #[test]
+    #[cfg(feature = "image")]
+    #[cfg_attr(not(feature = "hardware"), ignore = "needs a connected device")]
+    fn decoded_icon_has_dimensions() {
+        let device = crate::idevice::test_device();
+        let client =
+            SpringboardServicesClient::start_service(&device, "springboard-test".to_string())
+                .unwrap();
+        let icon = client.get_icon(SETTINGS_BUNDLE_ID).unwrap();
+        assert!(icon.width() > 0 && icon.height() > 0);
+    }
+
+    #[test]
+    #[cfg_attr(not(feature = "hardware"), ignore = "needs a connected device")]
+    fn one_descriptor_serves_two_clients() {
+        let device = crate::idevice::test_device();
+        let lockdownd = device
+            .new_lockdownd_client("one_descriptor_serves_two_clients".to_string())
+            .unwrap();
+        let descriptor = lockdownd
+            .start_service("com.apple.springboardservices")
+            .unwrap();
+
+        let first = SpringboardServicesClient::new(&device, &descriptor).unwrap();
+        let second = SpringboardServicesClient::new(&device, &descriptor).unwrap();
+        first.get_interface_orientation().unwrap();
+        second.get_interface_orientation().unwrap();
+        assert_ne!(descriptor.port(), 0);
+    }
+}
@@ -0,0 +1,404 @@
+// jkcoxson
+
+use std::{
+    ffi::CString,
+    os::raw::{c_char, c_void},
+};
+
+use crate::{
+    bindings as unsafe_bindings, error::SyslogRelayError, idevice::Device,
+    services::lockdownd::LockdowndService,
+};
+
+use log::{info, trace};
+
+/// How many bytes to ask the service for at a time when reading lines manually
+const RECEIVE_CHUNK_SIZE: usize = 4096;
+
+/// Relays the device's system log to the host.
+/// Lines can either be pushed to a callback with `start_capture`
+/// or pulled one at a time with `receive_line`, but not both at once.
+pub struct SyslogRelayClient<'a> {
+    pub(crate) pointer: unsafe_bindings::syslog_relay_client_t,
+    capture: Option<Box<CaptureState>>,
+    pending: Vec<u8>,
+    phantom: std::marker::PhantomData<&'a Device>,
+}
+
+unsafe impl Send for SyslogRelayClient<'_> {}
+unsafe impl Sync for SyslogRelayClient<'_> {}
+
+/// The state handed to the capture thread while a capture is running
+struct CaptureState {
+    line: Vec<u8>,
+    filter: SyslogFilter,
+    // Whether the last line with a recognizable prefix passed the filter,
+    // so the rest of a multi-line message follows the same decision
+    last_matched: bool,
+    callback: Box<dyn FnMut(&str) + Send>,
+}
+
+/// Chooses which syslog lines are passed to the capture callback.
+/// The default filter passes every line.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SyslogFilter {
+    process: Option<String>,
+}
+
+impl SyslogFilter {
+    /// Creates a filter that passes every line
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Only passes lines whose process name contains `name`.
+    /// Lines without a recognizable prefix are treated as a continuation of the previous message.
+    pub fn process(name: &str) -> Self {
+        SyslogFilter {
+            process: Some(name.to_string()),
+        }
+    }
+
+    /// Checks a line against the filter
+    /// # Arguments
+    /// * `line` - A line of the log
+    /// # Returns
+    /// Whether the line passes, or None if the line has no `<timestamp> <device> <process>[pid]:` prefix
+    pub fn matches(&self, line: &str) -> Option<bool> {
+        let process = parse_process(line)?;
+        Some(match &self.process {
+            Some(name) => process.contains(name.as_str()),
+            None => true,
+        })
+    }
+}
+
+impl SyslogRelayClient<'_> {
+    /// Creates a syslog relay client from a lockdown service
+    /// # Arguments
+    /// * `device` - The device to connect to
+    /// * `descriptor` - The lockdown service to connect on
+    /// # Returns
+    /// A struct containing the handle to the connection
+    ///
+    /// ***Verified:*** False
+    pub fn new(device: &Device, descriptor: LockdowndService) -> Result<Self, SyslogRelayError> {
+        let mut pointer = std::ptr::null_mut();
+        let result = unsafe {
+            unsafe_bindings::syslog_relay_client_new(
+                device.pointer,
+                descriptor.pointer,
+                &mut pointer,
+            )
+        }
+        .into();
+
+        if result != SyslogRelayError::Success {
+            return Err(result);
+        }
+
+        Ok(SyslogRelayClient {
+            pointer,
+            capture: None,
+            pending: Vec::new(),
+            phantom: std::marker::PhantomData,
+        })
+    }
+
+    /// Starts a new connection and adds a syslog relay client to it
+    /// # Arguments
+    /// * `device` - The device to connect to
+    /// * `label` - The label for the connection
+    /// # Returns
+    /// A struct containing the handle to the connection
+    ///
+    /// ***Verified:*** False
+    pub fn start_service(device: &Device, label: &str) -> Result<Self, SyslogRelayError> {
+        let label_c_str = match CString::new(label) {
+            Ok(s) => s,
+            Err(_) => return Err(SyslogRelayError::InvalidArg),
+        };
+        let mut pointer = std::ptr::null_mut();
+        let result = unsafe {
+            unsafe_bindings::syslog_relay_client_start_service(
+                device.pointer,
+                &mut pointer,
+                label_c_str.as_ptr(),
+            )
+        }
+        .into();
+
+        if result != SyslogRelayError::Success {
+            return Err(result);
+        }
+
+        Ok(SyslogRelayClient {
+            pointer,
+            capture: None,
+            pending: Vec::new(),
+            phantom: std::marker::PhantomData,
+        })
+    }
+
+    /// Starts sending each log line to a callback.
+    /// The callback runs on a thread owned by libimobiledevice until the capture is stopped.
+    /// Any capture that is already running is stopped first.
+    /// # Arguments
+    /// * `filter` - Which lines to pass to the callback. Use `SyslogFilter::new()` for every line
+    /// * `callback` - Called with each line of the log, without the trailing newline
+    /// # Returns
+    /// *none*
+    ///
+    /// ***Verified:*** False
+    pub fn start_capture<F>(
+        &mut self,
+        filter: SyslogFilter,
+        callback: F,
+    ) -> Result<(), SyslogRelayError>
+    where
+        F: FnMut(&str) + Send + 'static,
+    {
+        self.stop_capture()?;
+
+        let mut state = Box::new(CaptureState {
+            line: Vec::new(),
+            last_matched: filter.process.is_none(),
+            filter,
+            callback: Box::new(callback),
+        });
+        let user_data = &mut *state as *mut CaptureState as *mut c_void;
+
+        info!("Starting syslog capture");
+        let result = unsafe {
+            unsafe_bindings::syslog_relay_start_capture(
+                self.pointer,
+                Some(capture_callback),
+                user_data,
+            )
+        }
+        .into();
+
+        if result != SyslogRelayError::Success {
+            return Err(result);
+        }
+
+        // The box's allocation doesn't move, so the pointer given to the thread stays valid
+        self.capture = Some(state);
+        Ok(())
+    }
+
+    /// Stops a capture started with `start_capture`.
+    /// Does nothing if no capture is running.
+    /// # Arguments
+    /// *none*
+    /// # Returns
+    /// *none*
+    ///
+    /// ***Verified:*** False
+    pub fn stop_capture(&mut self) -> Result<(), SyslogRelayError> {
+        if self.capture.is_none() {
+            return Ok(());
+        }
+
+        info!("Stopping syslog capture");
+        let result = unsafe { unsafe_bindings::syslog_relay_stop_capture(self.pointer) }.into();
+
+        if result != SyslogRelayError::Success {
+            return Err(result);
+        }
+
+        // The capture thread has been joined, so nothing refers to the state anymore
+        self.capture = None;
+        Ok(())
+    }
+
+    /// Blocks until a full line of the log has been received
+    /// # Arguments
+    /// * `timeout_ms` - How long to wait for each read from the service in milliseconds
+    /// # Returns
+    /// The line without the trailing newline, or a Timeout error if the device sent nothing
+    ///
+    /// ***Verified:*** False
+    pub fn receive_line(&mut self, timeout_ms: u32) -> Result<String, SyslogRelayError> {
+        if self.capture.is_some() {
+            return Err(SyslogRelayError::InvalidArg);
+        }
+
+        loop {
+            if let Some(end) = self.pending.iter().position(|c| *c == b'\n') {
+                let line: Vec<u8> = self.pending.drain(..=end).collect();
+                return Ok(line_to_string(&line[..line.len() - 1]));
+            }
+
+            let mut buffer = vec![0u8; RECEIVE_CHUNK_SIZE];
+            let mut received = 0;
+            let result = unsafe {
+                unsafe_bindings::syslog_relay_receive_with_timeout(
+                    self.pointer,
+                    buffer.as_mut_ptr() as *mut c_char,
+                    buffer.len() as u32,
+                    &mut received,
+                    timeout_ms,
+                )
+            }
+            .into();
+
+            if result != SyslogRelayError::Success {
+                return Err(result);
+            }
+            if received == 0 {
+                return Err(SyslogRelayError::Timeout);
+            }
+
+            trace!("Received {} bytes of syslog", received);
+            // Entries are separated by NUL bytes, which aren't part of the text
+            self.pending.extend(
+                buffer[..received as usize]
+                    .iter()
+                    .filter(|c| **c != 0)
+                    .copied(),
+            );
+        }
+    }
+}
+
+/// Collects the characters sent by the capture thread into lines
+unsafe extern "C" fn capture_callback(c: c_char, user_data: *mut c_void) {
+    if user_data.is_null() {
+        return;
+    }
+    let state = &mut *(user_data as *mut CaptureState);
+    match c as u8 {
+        0 => {}
+        b'\n' => {
+            let line = line_to_string(&state.line);
+            state.line.clear();
+            let matched = match state.filter.matches(&line) {
+                Some(matched) => matched,
+                None => state.last_matched,
+            };
+            state.last_matched = matched;
+            if matched {
+                (state.callback)(&line);
+            }
+        }
+        c => state.line.push(c),
+    }
+}
+
+/// Finds the process name in a line like `Oct 17 12:34:56 iPhone SpringBoard(FrontBoard)[58] <Notice>: ...`
+fn parse_process(line: &str) -> Option<&str> {
+    let mut parts = line.split_whitespace();
+    let _month = parts.next()?;
+    let day = parts.next()?;
+    let time = parts.next()?;
+    let _device = parts.next()?;
+    let process = parts.next()?;
+    if !day.chars().all(|c| c.is_ascii_digit()) || time.matches(':').count() != 2 {
+        return None;
+    }
+
+    let pid_start = process.find('[')?;
+    if !process.trim_end_matches(':').ends_with(']') {
+        return None;
+    }
+    let name = &process[..pid_start];
+    // Strip the library the message came from, as in `SpringBoard(FrontBoard)`
+    Some(match name.find('(') {
+        Some(library_start) => &name[..library_start],
+        None => name,
+    })
+}
+
+fn line_to_string(line: &[u8]) -> String {
+    let line = match line.last() {
+        Some(b'\r') => &line[..line.len() - 1],
+        _ => line,
+    };
+    String::from_utf8_lossy(line).into_owned()
+}
+
+impl Drop for SyslogRelayClient<'_> {
+    fn drop(&mut self) {
+        info!("Dropping syslog relay client");
+        let _ = self.stop_capture();
+        unsafe {
+            unsafe_bindings::syslog_relay_client_free(self.pointer);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+
+    const SPRINGBOARD_LINE: &str =
+        "Oct 17 12:34:56 iPhone SpringBoard(FrontBoard)[58] <Notice>: Scene created";
+    const KERNEL_LINE: &str = "Oct 17 12:34:57 iPhone kernel[0] <Notice>: wifi up";
+
+    /// Feeds text through the capture callback, returning the lines passed on
+    fn capture(filter: SyslogFilter, text: &str) -> Vec<String> {
+        let lines = Arc::new(Mutex::new(vec![]));
+        let captured = lines.clone();
+        let mut state = CaptureState {
+            line: Vec::new(),
+            last_matched: filter.process.is_none(),
+            filter,
+            callback: Box::new(move |line| captured.lock().unwrap().push(line.to_string())),
+        };
+        for c in text.bytes() {
+            unsafe {
+                capture_callback(c as c_char, &mut state as *mut CaptureState as *mut c_void)
+            };
+        }
+        drop(state);
+        Arc::try_unwrap(lines).unwrap().into_inner().unwrap()
+    }
+
+    #[test]
+    fn parses_process_names() {
+        assert_eq!(parse_process(SPRINGBOARD_LINE), Some("SpringBoard"));
+        assert_eq!(parse_process(KERNEL_LINE), Some("kernel"));
+        assert_eq!(parse_process("    at frame 3"), None);
+        assert_eq!(
+            parse_process("Oct 17 12:34:56 iPhone SpringBoard <Notice>:"),
+            None
+        );
+    }
+
+    #[test]
+    fn filter_matches_process_substring() {
+        let filter = SyslogFilter::process("Spring");
+        assert_eq!(filter.matches(SPRINGBOARD_LINE), Some(true));
+        assert_eq!(filter.matches(KERNEL_LINE), Some(false));
+        assert_eq!(filter.matches("no prefix here"), None);
+        assert_eq!(SyslogFilter::new().matches(KERNEL_LINE), Some(true));
+    }
+
+    #[test]
+    fn continuation_lines_follow_their_message() {
+        let text = format!(
+            "{}\n  continued\n{}\n  also continued\n",
+            SPRINGBOARD_LINE, KERNEL_LINE
+        );
+        assert_eq!(
+            capture(SyslogFilter::process("SpringBoard"), &text),
+            vec![SPRINGBOARD_LINE.to_string(), "  continued".to_string()]
+        );
+    }
+
+    #[test]
+    fn unfiltered_capture_passes_every_line() {
+        let text = format!("stray line\n{}\n  continued\n", KERNEL_LINE);
+        assert_eq!(
+            capture(SyslogFilter::new(), &text),
+            vec![
+                "stray line".to_string(),
+                KERNEL_LINE.to_string(),
+                "  continued".to_string()
+            ]
+        );
+    }
+}
@@ -3,6 +3,36 @@
 use rusty_libimobiledevice::idevice;
 
 fn main() {
+    // Parse arguments
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    for arg in &args {
+        match arg.as_str() {
+            "-h" | "--help" => {
+                println!("Usage: idevice_id [options]");
+                println!("");
+                println!("Options:");
+                println!("  -h, --help           : display this help message");
+                println!("  -v, --version        : display the native library versions");
+                return;
+            }
+            "-v" | "--version" => {
+                println!(
+                    "libimobiledevice {}",
+                    idevice::library_version().unwrap_or("unknown".to_string())
+                );
+                println!(
+                    "libusbmuxd {}",
+                    idevice::usbmuxd_version().unwrap_or("unknown".to_string())
+                );
+                return;
+            }
+            _ => {
+                println!("Unknown flag: {}", arg);
+                return;
+            }
+        }
+    }
+
     // Get all devices attatched
     let devices = match idevice::get_devices() {
         Ok(devices) => devices,
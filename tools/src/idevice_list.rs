@@ -0,0 +1,119 @@
+// jkcoxson
+
+use rusty_libimobiledevice::idevice;
+
+struct DeviceDetails {
+    udid: String,
+    connection: &'static str,
+    name: String,
+    product_version: String,
+}
+
+fn main() {
+    const VERSION: &str = "0.1.0";
+    let mut json = false;
+
+    // Parse arguments
+    let mut args: Vec<String> = std::env::args().collect();
+    args.remove(0);
+    for arg in &args {
+        match arg.as_str() {
+            "-j" | "--json" => {
+                json = true;
+            }
+            "-h" | "--help" => {
+                println!("Usage: idevice_list [options]");
+                println!("");
+                println!("Options:");
+                println!("  -j, --json           : print the devices as a JSON array");
+                println!("  -h, --help           : display this help message");
+                println!("  -v, --version        : display version");
+                return;
+            }
+            "-v" | "--version" => {
+                println!("v{}", VERSION);
+                return;
+            }
+            _ => {
+                println!("Unknown flag: {}", arg);
+                return;
+            }
+        }
+    }
+
+    // Get all devices attatched
+    let devices = match idevice::get_devices() {
+        Ok(devices) => devices,
+        Err(e) => {
+            println!("Error getting devices: {:?}", e);
+            return;
+        }
+    };
+
+    // Devices that can't be reached over lockdownd are skipped instead of ending the listing
+    let mut details = vec![];
+    for device in &devices {
+        let name = match device.get_name() {
+            Ok(name) => name,
+            Err(e) => {
+                eprintln!("Skipping {}: {:?}", device.get_udid(), e);
+                continue;
+            }
+        };
+        let product_version = match device.get_product_version() {
+            Ok(version) => version,
+            Err(e) => {
+                eprintln!("Skipping {}: {:?}", device.get_udid(), e);
+                continue;
+            }
+        };
+        details.push(DeviceDetails {
+            udid: device.get_udid(),
+            connection: match device.get_network() {
+                true => "Network",
+                false => "USB",
+            },
+            name,
+            product_version,
+        });
+    }
+
+    if json {
+        let entries: Vec<String> = details
+            .iter()
+            .map(|d| {
+                format!(
+                    "{{\"udid\":\"{}\",\"connection\":\"{}\",\"name\":\"{}\",\"product_version\":\"{}\"}}",
+                    json_escape(&d.udid),
+                    d.connection,
+                    json_escape(&d.name),
+                    json_escape(&d.product_version)
+                )
+            })
+            .collect();
+        println!("[{}]", entries.join(","));
+    } else {
+        for d in &details {
+            println!(
+                "{} ({}) {} - iOS {}",
+                d.udid, d.connection, d.name, d.product_version
+            );
+        }
+    }
+}
+
+fn json_escape(value: &str) -> String {
+    let mut escaped = String::new();
+    for c in value.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            c if (c as u32) < 0x20 => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
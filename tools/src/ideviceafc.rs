@@ -0,0 +1,174 @@
+// jkcoxson
+
+use std::{
+    fs,
+    io::{self, BufReader, BufWriter, Write},
+    path::Path,
+};
+
+use rusty_libimobiledevice::{
+    idevice,
+    services::afc::{AfcClient, AfcFileMode},
+};
+
+fn main() {
+    const VERSION: &str = "0.1.0";
+
+    let mut udid = "".to_string();
+    let mut command: Vec<String> = vec![];
+
+    // Parse arguments
+    let mut args: Vec<String> = std::env::args().collect();
+    args.remove(0);
+    let mut i = 0;
+    while i < args.len() {
+        match args[i].as_str() {
+            "-u" | "--udid" => {
+                if i + 1 >= args.len() {
+                    println!("Error: -u requires a udid");
+                    return;
+                }
+                udid = args[i + 1].clone();
+                i += 1;
+            }
+            "-h" | "--help" => {
+                print_usage();
+                return;
+            }
+            "-v" | "--version" => {
+                println!("v{}", VERSION);
+                return;
+            }
+            _ => {
+                if args[i].starts_with("-") {
+                    println!("Unknown flag: {}", args[i]);
+                    return;
+                }
+                command.push(args[i].clone());
+            }
+        }
+        i += 1;
+    }
+    if command.is_empty() {
+        print_usage();
+        return;
+    }
+
+    // Get the device, defaulting to the first one found
+    let device = if udid == "" {
+        let devices = match idevice::get_devices() {
+            Ok(devices) => devices,
+            Err(e) => {
+                println!("Error: {:?}", e);
+                return;
+            }
+        };
+        match devices.into_iter().next() {
+            Some(device) => device,
+            None => {
+                println!("Error: No devices found.");
+                return;
+            }
+        }
+    } else {
+        match idevice::get_device(udid) {
+            Ok(device) => device,
+            Err(e) => {
+                println!("Error: Could not find device: {:?}", e);
+                return;
+            }
+        }
+    };
+
+    let afc = match AfcClient::start_service(&device, "ideviceafc") {
+        Ok(afc) => afc,
+        Err(e) => {
+            println!("Error starting afc: {:?}", e);
+            return;
+        }
+    };
+
+    let result = match (command[0].as_str(), &command[1..]) {
+        ("ls", [path]) => list(&afc, path),
+        ("ls", []) => list(&afc, "/"),
+        ("get", [remote, local]) => download(&afc, remote, Path::new(local)),
+        ("put", [local, remote]) => upload(&afc, Path::new(local), remote),
+        ("rm", [path]) => afc.remove_path(path.to_string()).map_err(|e| e.into()),
+        ("mkdir", [path]) => afc.make_directory(path.to_string()).map_err(|e| e.into()),
+        _ => {
+            print_usage();
+            return;
+        }
+    };
+    if let Err(e) = result {
+        println!("Error: {}", e);
+        std::process::exit(1);
+    }
+}
+
+fn print_usage() {
+    println!("Usage: ideviceafc [options] <command> [args]");
+    println!("");
+    println!("Commands:");
+    println!("  ls [path]              : list a directory on the device");
+    println!("  get <remote> <local>   : download a file or directory from the device");
+    println!("  put <local> <remote>   : upload a file or directory to the device");
+    println!("  rm <path>              : remove a file or empty directory on the device");
+    println!("  mkdir <path>           : create a directory on the device");
+    println!("");
+    println!("Options:");
+    println!("  -u, --udid <udid>    : udid of the device, defaults to the first device found");
+    println!("  -h, --help           : display this help message");
+    println!("  -v, --version        : display version");
+}
+
+fn list(afc: &AfcClient, path: &str) -> Result<(), String> {
+    for entry in afc.read_directory(path.to_string())? {
+        println!("{}", entry);
+    }
+    Ok(())
+}
+
+fn download(afc: &AfcClient, remote: &str, local: &Path) -> Result<(), String> {
+    if afc.metadata(remote)?.is_dir() {
+        fs::create_dir_all(local).map_err(|e| e.to_string())?;
+        for entry in afc.read_directory(remote.to_string())? {
+            let remote_entry = format!("{}/{}", remote.trim_end_matches('/'), entry);
+            download(afc, &remote_entry, &local.join(&entry))?;
+        }
+        return Ok(());
+    }
+
+    // Streamed a chunk at a time so large files don't have to fit in memory
+    println!("{} -> {}", remote, local.display());
+    let file = afc.open(remote, AfcFileMode::ReadOnly)?;
+    let mut local_file = fs::File::create(local).map_err(|e| e.to_string())?;
+    io::copy(
+        &mut BufReader::with_capacity(afc.chunk_size(), file),
+        &mut local_file,
+    )
+    .map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+fn upload(afc: &AfcClient, local: &Path, remote: &str) -> Result<(), String> {
+    if local.is_dir() {
+        afc.make_directory_all(remote)?;
+        for entry in fs::read_dir(local).map_err(|e| e.to_string())? {
+            let entry = entry.map_err(|e| e.to_string())?;
+            let name = entry.file_name().to_string_lossy().into_owned();
+            let remote_entry = format!("{}/{}", remote.trim_end_matches('/'), name);
+            upload(afc, &entry.path(), &remote_entry)?;
+        }
+        return Ok(());
+    }
+
+    println!("{} -> {}", local.display(), remote);
+    let mut local_file = fs::File::open(local).map_err(|e| e.to_string())?;
+    let mut file =
+        BufWriter::with_capacity(afc.chunk_size(), afc.open(remote, AfcFileMode::WriteOnly)?);
+    io::copy(&mut local_file, &mut file).map_err(|e| e.to_string())?;
+    file.flush().map_err(|e| e.to_string())?;
+    let file = file.into_inner().map_err(|e| e.to_string())?;
+    file.close().map_err(|e| e.into())
+}
@@ -1,8 +1,6 @@
 // jkcoxson
 
-use plist_plus::Plist;
-use rusty_libimobiledevice::idevice;
-use rusty_libimobiledevice::services::instproxy::InstProxyClient;
+use rusty_libimobiledevice::{error::InstProxyError, idevice};
 
 fn main() {
     const VERSION: &str = "0.1.0";
@@ -68,15 +66,7 @@ fn main() {
         }
     };
 
-    let client_opts = InstProxyClient::create_return_attributes(
-        vec![("ApplicationType".to_string(), Plist::new_string("Any"))],
-        vec![
-            "CFBundleIdentifier".to_string(),
-            "CFBundleExecutable".to_string(),
-            "Container".to_string(),
-        ],
-    );
-    let lookup_results = match instproxy_client.lookup(vec![app.clone()], Some(client_opts)) {
+    let lookup_results = match instproxy_client.lookup_typed(&[&app], &[]) {
         Ok(apps) => {
             println!("Successfully looked up apps");
             apps
@@ -86,30 +76,25 @@ fn main() {
             return;
         }
     };
-    let lookup_results = lookup_results.dict_get_item(&app).unwrap();
 
-    let working_directory = match lookup_results.dict_get_item("Container") {
-        Ok(p) => p,
-        Err(_) => {
-            println!("App not found");
-            return;
-        }
-    };
-
-    let working_directory = match working_directory.get_string_val() {
-        Ok(p) => p,
-        Err(_) => {
+    let working_directory = match lookup_results.get(&app).and_then(|a| a.container()) {
+        Some(p) => p.to_string(),
+        None => {
             println!("App not found");
             return;
         }
     };
     println!("Working directory: {}", working_directory);
 
-    let bundle_path = match instproxy_client.get_path_for_bundle_identifier(app) {
+    let bundle_path = match instproxy_client.get_path_for_bundle_identifier(app.clone()) {
         Ok(p) => {
             println!("Successfully found bundle path");
             p
         }
+        Err(InstProxyError::AppNotFound) => {
+            println!("Error: {} is not installed on the device", app);
+            return;
+        }
         Err(e) => {
             println!("Error getting path for bundle identifier: {:?}", e);
             return;
@@ -130,40 +115,32 @@ fn main() {
         }
     };
 
-    match debug_server.send_command("QSetMaxPacketSize: 1024".into()) {
-        Ok(res) => println!("Successfully set max packet size: {:?}", res),
+    match debug_server.set_max_packet_size(1024) {
+        Ok(()) => println!("Successfully set max packet size"),
         Err(e) => {
             println!("Error setting max packet size: {:?}", e);
             return;
         }
     }
 
-    match debug_server.send_command(format!("QSetWorkingDir: {}", working_directory).into()) {
-        Ok(res) => println!("Successfully set working directory: {:?}", res),
+    match debug_server.set_working_directory(&working_directory) {
+        Ok(()) => println!("Successfully set working directory"),
         Err(e) => {
             println!("Error setting working directory: {:?}", e);
             return;
         }
     }
 
-    match debug_server.set_argv(vec![bundle_path.clone(), bundle_path.clone()]) {
-        Ok(res) => println!("Successfully set argv: {:?}", res),
-        Err(e) => {
-            println!("Error setting argv: {:?}", e);
-            return;
-        }
-    }
-
-    match debug_server.send_command("qLaunchSuccess".into()) {
-        Ok(res) => println!("Got launch response: {:?}", res),
+    match debug_server.launch(&[&bundle_path, &bundle_path]) {
+        Ok(()) => println!("Successfully launched app"),
         Err(e) => {
-            println!("Error checking if app launched: {:?}", e);
+            println!("Error launching app: {:?}", e);
             return;
         }
     }
 
     match debug_server.send_command("D".into()) {
-        Ok(res) => println!("Detaching: {:?}", res),
+        Ok(res) => println!("Detaching: {}", res.to_string_lossy()),
         Err(e) => {
             println!("Error detaching: {:?}", e);
             return;
@@ -1,6 +1,8 @@
 // jkcoxson
 
-use rusty_libimobiledevice::idevice;
+use std::path::Path;
+
+use rusty_libimobiledevice::{idevice, services::mobile_image_mounter};
 
 fn main() {
     const VERSION: &str = "0.1.0";
@@ -73,7 +75,7 @@ fn main() {
         }
     };
 
-    let mut lockdown_client = match device.new_lockdownd_client("ideviceimagemounter".to_string()) {
+    let lockdown_client = match device.new_lockdownd_client("ideviceimagemounter".to_string()) {
         Ok(lckd) => {
             println!("Successfully connected to lockdownd");
             lckd
@@ -84,8 +86,7 @@ fn main() {
         }
     };
 
-    let ios_version = match lockdown_client.get_value("ProductVersion".to_string(), "".to_string())
-    {
+    let ios_version = match lockdown_client.get_value(None, Some("ProductVersion")) {
         Ok(ios_version) => ios_version.get_string_val().unwrap(),
         Err(e) => {
             println!("Error getting iOS version: {:?}", e);
@@ -104,9 +105,7 @@ fn main() {
         return;
     }
 
-    let service = match lockdown_client
-        .start_service("com.apple.mobile.mobile_image_mounter".to_string(), false)
-    {
+    let service = match lockdown_client.start_service("com.apple.mobile.mobile_image_mounter") {
         Ok(service) => {
             println!("Successfully started com.apple.mobile.mobile_image_mounter");
             service
@@ -132,7 +131,7 @@ fn main() {
     };
 
     if list_mode {
-        match mim.lookup_image(image_type.to_string()) {
+        match mim.lookup_image(&image_type) {
             Ok(plist) => {
                 println!("{:?}", plist.to_string());
             }
@@ -142,10 +141,11 @@ fn main() {
             }
         }
     } else {
+        let signature_path = format!("{}.signature", dmg_path);
         match mim.upload_image(
-            dmg_path.clone(),
-            image_type.clone(),
-            format!("{}.signature", dmg_path.clone()).to_string(),
+            Path::new(&dmg_path),
+            &image_type,
+            Path::new(&signature_path),
         ) {
             Ok(_) => {
                 println!("Successfully uploaded image");
@@ -155,10 +155,17 @@ fn main() {
                 return;
             }
         }
+        let signature = match std::fs::read(&signature_path) {
+            Ok(signature) => signature,
+            Err(e) => {
+                println!("Error reading signature: {:?}", e);
+                return;
+            }
+        };
         match mim.mount_image(
-            dmg_path.clone(),
-            image_type,
-            format!("{}.signature", dmg_path.clone()).to_string(),
+            mobile_image_mounter::STAGING_IMAGE_PATH,
+            &signature,
+            &image_type,
         ) {
             Ok(_) => {
                 println!("Successfully mounted image");
@@ -58,7 +58,7 @@ fn main() {
                 return;
             }
         };
-        let output = match lckd.get_value("".to_string(), "".to_string()) {
+        let output = match lckd.get_value(None, None) {
             Ok(output) => output,
             Err(e) => {
                 println!("Error: {:?}", e);
@@ -85,7 +85,7 @@ fn main() {
                 return;
             }
         };
-        let output = match lckd.get_value("".to_string(), "".to_string()) {
+        let output = match lckd.get_value(None, None) {
             Ok(output) => output,
             Err(e) => {
                 println!("Error: {:?}", e);
@@ -1,10 +1,19 @@
 // jkcoxson
 
-use rusty_libimobiledevice::idevice;
+use std::path::Path;
+
+use rusty_libimobiledevice::{
+    idevice,
+    services::{mobile_image_mounter, screenshotr::ScreenshotrClient},
+};
+
+const PNG_MAGIC: &[u8] = b"\x89PNG\r\n\x1a\n";
 
 fn main() {
     const VERSION: &str = "0.1.0";
     let mut udid = "".to_string();
+    let mut image_dir = "".to_string();
+    let mut output = "screenshot.png".to_string();
 
     // Collect options
     let mut i = 1;
@@ -13,46 +22,132 @@ fn main() {
     while i < arguments.len() {
         match arguments[i].as_str() {
             "-u" | "--udid" => {
+                if i + 1 >= arguments.len() {
+                    println!("Error: -u requires a udid");
+                    return;
+                }
                 i += 1;
                 udid = arguments[i].clone();
             }
+            "-d" | "--image-dir" => {
+                if i + 1 >= arguments.len() {
+                    println!("Error: -d requires a directory");
+                    return;
+                }
+                i += 1;
+                image_dir = arguments[i].clone();
+            }
             "-v" | "--version" => {
                 println!("v{}", VERSION);
                 return;
             }
             "-h" | "--help" => {
+                println!("Usage: idevicescreenshot [options] [output]");
+                println!("");
+                println!("Saves a screenshot of the device as a PNG, screenshot.png by default.");
+                println!("");
                 println!("Options:");
-                println!("  -u, --udid <udid>    Device UDID");
-                println!("  -n, --network        Use network connection");
-                println!("  -v, --version        Print version");
-                println!("  -h, --help           Show this help");
+                println!("  -u, --udid <udid>       Device UDID, defaults to the first device");
+                println!(
+                    "  -d, --image-dir <dir>   Directory to mount the Developer Disk Image from"
+                );
+                println!("  -v, --version           Print version");
+                println!("  -h, --help              Show this help");
                 return;
             }
             _ => {
-                panic!("Unknown argument: {}", &arguments[i]);
+                if arguments[i].starts_with("-") {
+                    println!("Unknown flag: {}", arguments[i]);
+                    return;
+                }
+                output = arguments[i].clone();
             }
         }
         i += 1;
     }
-    if udid == "".to_string() {
-        panic!("No device UDID specified");
-    }
 
-    let device = match idevice::get_device(udid) {
-        Ok(d) => d,
-        Err(e) => {
-            println!("Error: {:?}", e);
-            return;
+    // Get the device, defaulting to the first one found
+    let device = if udid == "" {
+        let devices = match idevice::get_devices() {
+            Ok(devices) => devices,
+            Err(e) => {
+                println!("Error: {:?}", e);
+                return;
+            }
+        };
+        match devices.into_iter().next() {
+            Some(device) => device,
+            None => {
+                println!("Error: No devices found.");
+                return;
+            }
+        }
+    } else {
+        match idevice::get_device(udid) {
+            Ok(d) => d,
+            Err(e) => {
+                println!("Error: {:?}", e);
+                return;
+            }
         }
     };
 
-    match device.new_lockdownd_client("yeet".to_string()) {
-        Ok(_) => {}
+    // The screenshot service only exists once the Developer Disk Image is mounted
+    if image_dir != "" {
+        if let Err(e) = mobile_image_mounter::mount_developer_image(&device, Path::new(&image_dir))
+        {
+            println!("Error mounting the Developer Disk Image: {:?}", e);
+            return;
+        }
+    } else if !developer_image_mounted(&device) {
+        print_mount_help();
+        return;
+    }
+
+    let screenshotr =
+        match ScreenshotrClient::start_service(&device, "idevicescreenshot".to_string()) {
+            Ok(s) => s,
+            Err(e) => {
+                println!("Error starting the screenshot service: {:?}", e);
+                print_mount_help();
+                return;
+            }
+        };
+    let data = match screenshotr.take_screenshot() {
+        Ok(data) => data,
         Err(e) => {
-            println!("Error starting lockdown service: {:?}", e);
+            println!("Error taking screenshot: {:?}", e);
             return;
         }
+    };
+
+    // Older devices send a TIFF, which is converted so the output is always a PNG
+    let result = if data.starts_with(PNG_MAGIC) {
+        std::fs::write(&output, &data).map_err(|e| e.to_string())
+    } else {
+        image::load_from_memory(&data)
+            .and_then(|image| image.save_with_format(&output, image::ImageFormat::Png))
+            .map_err(|e| e.to_string())
+    };
+    match result {
+        Ok(()) => println!("Screenshot saved to {}", output),
+        Err(e) => println!("Error saving screenshot: {}", e),
     }
+}
+
+fn developer_image_mounted(device: &idevice::Device) -> bool {
+    let mounter = match mobile_image_mounter::MobileImageMounter::start_service(
+        device,
+        "idevicescreenshot".to_string(),
+    ) {
+        Ok(mounter) => mounter,
+        Err(_) => return false,
+    };
+    mounter.is_developer_image_mounted().unwrap_or(false)
+}
 
-    todo!();
+fn print_mount_help() {
+    println!("The Developer Disk Image does not appear to be mounted on the device.");
+    println!("Pass the DeviceSupport directory with -d to mount it automatically,");
+    println!("or mount it with ideviceimagemounter first.");
 }
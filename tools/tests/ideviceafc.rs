@@ -0,0 +1,73 @@
+// jkcoxson
+// Runs ideviceafc against the first connected device
+
+#![cfg(feature = "hardware")]
+
+use std::fs;
+use std::path::PathBuf;
+use std::process::{Command, Output};
+
+const REMOTE_DIR: &str = "/ideviceafc_test";
+
+fn ideviceafc(args: &[&str]) -> Output {
+    let output = Command::new(env!("CARGO_BIN_EXE_ideviceafc"))
+        .args(args)
+        .output()
+        .expect("Failed to run ideviceafc");
+    assert!(
+        output.status.success(),
+        "ideviceafc {:?} failed: {}",
+        args,
+        String::from_utf8_lossy(&output.stdout)
+    );
+    output
+}
+
+fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("ideviceafc_{}_{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+#[test]
+fn put_then_get_round_trips() {
+    let dir = temp_dir("round_trip");
+    let local = dir.join("upload.bin");
+    // Larger than the default chunk size so the transfer takes several chunks
+    let data: Vec<u8> = (0..200 * 1024).map(|i| (i % 251) as u8).collect();
+    fs::write(&local, &data).unwrap();
+
+    let remote = format!("{}/upload.bin", REMOTE_DIR);
+    let _ = Command::new(env!("CARGO_BIN_EXE_ideviceafc"))
+        .args(["mkdir", REMOTE_DIR])
+        .output();
+    ideviceafc(&["put", local.to_str().unwrap(), &remote]);
+
+    let listing = ideviceafc(&["ls", REMOTE_DIR]);
+    assert!(String::from_utf8_lossy(&listing.stdout).contains("upload.bin"));
+
+    let downloaded = dir.join("download.bin");
+    ideviceafc(&["get", &remote, downloaded.to_str().unwrap()]);
+    assert_eq!(fs::read(&downloaded).unwrap(), data);
+
+    ideviceafc(&["rm", &remote]);
+    ideviceafc(&["rm", REMOTE_DIR]);
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn get_missing_file_fails() {
+    let dir = temp_dir("missing");
+    let output = Command::new(env!("CARGO_BIN_EXE_ideviceafc"))
+        .args([
+            "get",
+            "/ideviceafc_missing",
+            dir.join("out").to_str().unwrap(),
+        ])
+        .output()
+        .expect("Failed to run ideviceafc");
+    assert!(!output.status.success());
+    assert!(!dir.join("out").exists());
+    fs::remove_dir_all(&dir).unwrap();
+}
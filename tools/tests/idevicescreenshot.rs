@@ -0,0 +1,60 @@
+// jkcoxson
+// Runs idevicescreenshot against the first connected device.
+// The Developer Disk Image must already be mounted, or its directory given in IDEVICESCREENSHOT_IMAGE_DIR.
+
+#![cfg(feature = "hardware")]
+
+use std::fs;
+use std::path::PathBuf;
+use std::process::{Command, Output};
+
+const PNG_MAGIC: &[u8] = b"\x89PNG\r\n\x1a\n";
+
+fn idevicescreenshot(args: &[&str]) -> Output {
+    let mut command = Command::new(env!("CARGO_BIN_EXE_idevicescreenshot"));
+    if let Ok(image_dir) = std::env::var("IDEVICESCREENSHOT_IMAGE_DIR") {
+        command.args(["-d", &image_dir]);
+    }
+    let output = command
+        .args(args)
+        .output()
+        .expect("Failed to run idevicescreenshot");
+    // The tool reports errors on stdout rather than through its exit status
+    assert!(output.status.success());
+    output
+}
+
+fn temp_dir(name: &str) -> PathBuf {
+    let dir =
+        std::env::temp_dir().join(format!("idevicescreenshot_{}_{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+#[test]
+fn saves_a_png() {
+    let dir = temp_dir("png");
+    let path = dir.join("screen.png");
+
+    let output = idevicescreenshot(&[path.to_str().unwrap()]);
+    assert!(
+        String::from_utf8_lossy(&output.stdout).contains("Screenshot saved to"),
+        "idevicescreenshot failed: {}",
+        String::from_utf8_lossy(&output.stdout)
+    );
+    // Devices that send a TIFF are converted, so the file is always a PNG
+    assert!(fs::read(&path).unwrap().starts_with(PNG_MAGIC));
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn unknown_udid_saves_nothing() {
+    let dir = temp_dir("unknown_udid");
+    let path = dir.join("screen.png");
+
+    let output = idevicescreenshot(&["-u", "not-a-device", path.to_str().unwrap()]);
+    assert!(String::from_utf8_lossy(&output.stdout).starts_with("Error"));
+    assert!(!path.exists());
+    fs::remove_dir_all(&dir).unwrap();
+}